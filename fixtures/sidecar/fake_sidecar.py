@@ -0,0 +1,86 @@
+#!/usr/bin/env python3
+"""测试用的假侧车 - 实现与 sidecar/mlx_sidecar.py 相同的协议，不依赖 mlx。
+
+  - ready 帧附带 pid，测试据此检查进程是否退出
+  - load：model_path 包含 "bad" 时返回 error
+  - generate：按空格切分 prompt（未提供时为最后一条消息），逐词返回 token（每词间隔 20ms）；
+    消息包含 "crash" 时进程立即退出
+  - 设置 FAKE_SIDECAR_EXIT 时不监听 socket 直接退出，用于测试重启退避
+"""
+
+import json
+import os
+import socket
+import struct
+import sys
+import threading
+import time
+
+if os.environ.get("FAKE_SIDECAR_EXIT"):
+    sys.exit(1)
+
+server = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+server.bind(os.environ["SILO_SIDECAR_SOCKET"])
+server.listen(1)
+conn, _ = server.accept()
+send_lock = threading.Lock()
+cancelled = set()
+model = None
+
+
+def read_exact(n):
+    buf = b""
+    while len(buf) < n:
+        chunk = conn.recv(n - len(buf))
+        if not chunk:
+            sys.exit(0)
+        buf += chunk
+    return buf
+
+
+def read():
+    (length,) = struct.unpack(">I", read_exact(4))
+    return json.loads(read_exact(length))
+
+
+def send(frame):
+    body = json.dumps(frame).encode("utf-8")
+    with send_lock:
+        conn.sendall(struct.pack(">I", len(body)) + body)
+
+
+def generate(frame):
+    text = frame["messages"][-1]["content"]
+    if "crash" in text:
+        os._exit(3)
+    words = (frame.get("prompt") or text).split()
+    for i, word in enumerate(words):
+        if frame["id"] in cancelled:
+            send({"type": "done", "id": frame["id"], "finish_reason": "cancelled",
+                  "usage": {"prompt_tokens": len(words), "completion_tokens": i}})
+            return
+        send({"type": "token", "id": frame["id"], "text": word + " "})
+        time.sleep(0.02)
+    send({"type": "done", "id": frame["id"], "finish_reason": "stop",
+          "usage": {"prompt_tokens": len(words), "completion_tokens": len(words)}})
+
+
+hello = read()
+assert hello["type"] == "hello", hello
+send({"type": "ready", "protocol": hello["protocol"], "pid": os.getpid()})
+
+while True:
+    frame = read()
+    if frame["type"] == "load":
+        if "bad" in frame["model_path"]:
+            send({"type": "error", "id": frame["id"], "message": "cannot load model"})
+            continue
+        model = frame["model_path"]
+        send({"type": "loaded", "id": frame["id"]})
+    elif frame["type"] == "cancel":
+        cancelled.add(frame["id"])
+    elif frame["type"] == "generate":
+        if model is None:
+            send({"type": "error", "id": frame["id"], "message": "no model loaded"})
+            continue
+        threading.Thread(target=generate, args=(frame,), daemon=True).start()
@@ -0,0 +1,82 @@
+/*
+ * Silo 推理后端插件 C ABI（版本 1）
+ *
+ * 插件是一个动态库（.so / .dylib / .dll），放在插件目录（默认 <data_dir>/silo/plugins，
+ * 可用 SILO_PLUGIN_DIR 覆盖）中，导出 silo_plugin_entry，返回静态的 SiloPluginV1 函数表。
+ * Silo 启动时扫描插件目录并动态加载，无需重新编译 Silo。
+ *
+ * 约定：
+ *   - 所有字符串为 UTF-8，以 NUL 结尾；JSON 参数的结构见各函数说明
+ *   - 函数返回 SILO_OK 表示成功，否则将错误信息写入 err（最多 err_len 字节，含 NUL）
+ *   - 同一个 SiloBackend 实例不会被并发调用，但可能在不同线程上调用
+ *   - 插件名为 "inferflow" 时作为 Inferflow 后端（NVIDIA GPU），其他插件作为独立后端加入降级链
+ */
+
+#ifndef SILO_PLUGIN_H
+#define SILO_PLUGIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define SILO_PLUGIN_ABI_VERSION 1
+
+#define SILO_OK 0
+#define SILO_ERR 1
+/* embed 的输出缓冲区不足，written 为所需长度 */
+#define SILO_ERR_BUFFER_TOO_SMALL 2
+
+/* 生成结束原因 */
+#define SILO_FINISH_STOP 0
+#define SILO_FINISH_LENGTH 1
+#define SILO_FINISH_STOP_SEQUENCE 2
+#define SILO_FINISH_CANCELLED 3
+#define SILO_FINISH_ERROR 4
+
+/* 插件实例（由插件定义） */
+typedef struct SiloBackend SiloBackend;
+
+/* 每生成一段文本回调一次；返回非 0 表示调用方已取消，插件应尽快停止并返回 SILO_FINISH_CANCELLED */
+typedef int32_t (*silo_token_callback)(const char *piece, size_t len, void *user_data);
+
+typedef struct {
+    uint32_t finish_reason;
+    uint32_t prompt_tokens;
+    uint32_t completion_tokens;
+} SiloGenerateResult;
+
+typedef struct {
+    /* 必须为 SILO_PLUGIN_ABI_VERSION */
+    uint32_t abi_version;
+    /* 插件名，如 "inferflow"、"openvino" */
+    const char *name;
+
+    /* 创建实例并加载模型。config_json: {"model_path": "...", "context_size": 4096}
+     * 失败时返回 NULL 并写入 err */
+    SiloBackend *(*init)(const char *config_json, char *err, size_t err_len);
+
+    /* 生成文本。request_json: {"messages": [{"role": "user", "content": "..."}], "params": {...}}
+     * params 与 Silo 的 GenerationParams 一致（max_tokens、temperature、top_p、stop 等）；
+     * params.constraint 为输出约束（JSON / JSON Schema / GBNF），插件可以忽略，由 Silo 校验输出并重试 */
+    int32_t (*generate)(SiloBackend *backend, const char *request_json, silo_token_callback callback,
+                        void *user_data, SiloGenerateResult *result, char *err, size_t err_len);
+
+    /* 计算文本向量，写入 out（最多 out_len 个 float），written 为向量维度；不支持时可为 NULL */
+    int32_t (*embed)(SiloBackend *backend, const char *text, float *out, size_t out_len, size_t *written,
+                     char *err, size_t err_len);
+
+    /* 释放实例 */
+    void (*free)(SiloBackend *backend);
+} SiloPluginV1;
+
+/* 插件入口 */
+const SiloPluginV1 *silo_plugin_entry(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SILO_PLUGIN_H */
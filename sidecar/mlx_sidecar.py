@@ -0,0 +1,200 @@
+#!/usr/bin/env python3
+"""Silo MLX 侧车 - 在 Apple Silicon 上用 mlx-lm 执行推理，由 Rust 端的 Sidecar 监管。
+
+协议（与 src/engine/sidecar.rs 一致）：
+  - 监听环境变量 SILO_SIDECAR_SOCKET 指定的 Unix Domain Socket，只接受一个连接
+  - 每帧为 4 字节大端长度 + UTF-8 JSON
+  - 握手：收到 {"type": "hello"} 后回复 {"type": "ready", ...}
+  - {"type": "load", "id", "model_path", "context_size"} -> {"type": "loaded", "id"}
+  - {"type": "generate", "id", "messages", "params"} -> 若干 {"type": "token", "id", "text"}，
+    最后 {"type": "done", "id", "finish_reason", "usage"}
+    可选的 "prompt" 为 Rust 端按模型对话模板渲染好的提示词，缺省时用分词器的 chat_template 渲染 messages
+  - {"type": "embed", "id", "texts"} -> {"type": "embeddings", "id", "embeddings"}
+  - {"type": "cancel", "id"} 停止对应的生成
+  - 出错时回复 {"type": "error", "id", "message"}
+"""
+
+import json
+import os
+import socket
+import struct
+import sys
+import threading
+
+PROTOCOL_VERSION = 1
+
+
+class Connection:
+    def __init__(self, sock):
+        self.sock = sock
+        self.lock = threading.Lock()
+
+    def read(self):
+        header = self._read_exact(4)
+        if header is None:
+            return None
+        (length,) = struct.unpack(">I", header)
+        body = self._read_exact(length)
+        if body is None:
+            return None
+        return json.loads(body)
+
+    def send(self, frame):
+        body = json.dumps(frame, ensure_ascii=False).encode("utf-8")
+        with self.lock:
+            self.sock.sendall(struct.pack(">I", len(body)) + body)
+
+    def _read_exact(self, n):
+        buf = b""
+        while len(buf) < n:
+            chunk = self.sock.recv(n - len(buf))
+            if not chunk:
+                return None
+            buf += chunk
+        return buf
+
+
+class Engine:
+    def __init__(self, conn):
+        self.conn = conn
+        self.model = None
+        self.tokenizer = None
+        self.context_size = 4096
+        self.cancelled = set()
+        # mlx 的计算不是线程安全的，同一时间只执行一个生成
+        self.generate_lock = threading.Lock()
+
+    def load(self, frame):
+        from mlx_lm import load
+
+        self.model, self.tokenizer = load(frame["model_path"])
+        self.context_size = frame.get("context_size") or self.context_size
+        self.conn.send({"type": "loaded", "id": frame["id"]})
+
+    def generate(self, frame):
+        from mlx_lm import stream_generate
+        from mlx_lm.sample_utils import make_sampler
+
+        request_id = frame["id"]
+        if self.model is None:
+            raise RuntimeError("No model loaded")
+        params = frame.get("params") or {}
+        if frame.get("prompt") is not None:
+            # 模板中已包含 BOS 等特殊 token，不再重复添加
+            prompt = self.tokenizer.encode(frame["prompt"], add_special_tokens=False)
+        else:
+            messages = [{"role": m["role"], "content": m["content"]} for m in frame["messages"]]
+            prompt = self.tokenizer.apply_chat_template(messages, add_generation_prompt=True)
+        max_tokens = params.get("max_tokens") or max(self.context_size - len(prompt), 1)
+        sampler = make_sampler(temp=params.get("temperature", 0.7), top_p=params.get("top_p", 1.0))
+        stops = [s for s in params.get("stop") or [] if s]
+
+        text = ""
+        sent = 0
+        completion_tokens = 0
+        finish_reason = "length"
+        with self.generate_lock:
+            for response in stream_generate(self.model, self.tokenizer, prompt, max_tokens=max_tokens, sampler=sampler):
+                if request_id in self.cancelled:
+                    finish_reason = "cancelled"
+                    break
+                completion_tokens += 1
+                text += response.text
+                hit = min((text.find(s) for s in stops if s in text), default=-1)
+                if hit >= 0:
+                    text = text[:hit]
+                    finish_reason = "stop_sequence"
+                # 暂不发送可能是停止序列前缀的尾部
+                hold = 0 if hit >= 0 else max((len(s) - 1 for s in stops), default=0)
+                if len(text) - hold > sent:
+                    self.conn.send({"type": "token", "id": request_id, "text": text[sent : len(text) - hold]})
+                    sent = len(text) - hold
+                if hit >= 0:
+                    break
+                if response.finish_reason is not None:
+                    finish_reason = "stop" if response.finish_reason == "stop" else "length"
+                    break
+        if finish_reason != "stop_sequence" and len(text) > sent:
+            self.conn.send({"type": "token", "id": request_id, "text": text[sent:]})
+        self.cancelled.discard(request_id)
+        self.conn.send(
+            {
+                "type": "done",
+                "id": request_id,
+                "finish_reason": finish_reason,
+                "usage": {"prompt_tokens": len(prompt), "completion_tokens": completion_tokens},
+            }
+        )
+
+    def embed(self, frame):
+        import mlx.core as mx
+
+        if self.model is None:
+            raise RuntimeError("No model loaded")
+        embeddings = []
+        with self.generate_lock:
+            for text in frame["texts"]:
+                tokens = self.tokenizer.encode(text)[: self.context_size] or [0]
+                # mlx-lm 的 model.model 返回经过最后一层 norm 的隐状态；取平均值并 L2 归一化
+                hidden = self.model.model(mx.array([tokens]))
+                pooled = hidden[0].mean(axis=0)
+                pooled = pooled / mx.maximum(mx.linalg.norm(pooled), 1e-12)
+                embeddings.append(pooled.astype(mx.float32).tolist())
+        self.conn.send({"type": "embeddings", "id": frame["id"], "embeddings": embeddings})
+
+    def handle(self, frame):
+        kind = frame.get("type")
+        if kind == "cancel":
+            self.cancelled.add(frame["id"])
+            return
+        handler = {"load": self.load, "generate": self.generate, "embed": self.embed}.get(kind)
+        if handler is None:
+            self.conn.send({"type": "error", "id": frame.get("id"), "message": f"unknown request type: {kind}"})
+            return
+
+        def run():
+            try:
+                handler(frame)
+            except Exception as e:  # noqa: BLE001 - 错误原样回传给 Rust 端
+                self.conn.send({"type": "error", "id": frame.get("id"), "message": str(e)})
+
+        # 加载与生成在线程中执行，主线程继续读取取消请求
+        threading.Thread(target=run, daemon=True).start()
+
+
+def main():
+    path = os.environ.get("SILO_SIDECAR_SOCKET")
+    if not path:
+        print("SILO_SIDECAR_SOCKET is not set", file=sys.stderr)
+        return 2
+    if os.path.exists(path):
+        os.unlink(path)
+    server = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+    server.bind(path)
+    server.listen(1)
+    sock, _ = server.accept()
+    conn = Connection(sock)
+
+    hello = conn.read()
+    if not hello or hello.get("type") != "hello":
+        return 1
+    try:
+        import mlx_lm
+
+        version = getattr(mlx_lm, "__version__", "unknown")
+    except ImportError as e:
+        print(f"mlx-lm is not installed: {e}", file=sys.stderr)
+        return 1
+    conn.send({"type": "ready", "protocol": PROTOCOL_VERSION, "name": "mlx", "version": version})
+
+    engine = Engine(conn)
+    while True:
+        frame = conn.read()
+        if frame is None:
+            # Rust 端关闭连接即退出
+            return 0
+        engine.handle(frame)
+
+
+if __name__ == "__main__":
+    sys.exit(main())
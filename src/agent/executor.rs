@@ -1,12 +1,92 @@
 // Agent 执行器实现
 
 use crate::agent::{AgentAction, AgentResponse, AgentTask, Artifact, extract_keywords, extract_search_query, extract_code_block};
-use crate::engine::EngineManager;
+use crate::engine::tokenizer::Tokenizer;
+use crate::engine::{ChatMessage, EngineManager, FinishReason, GenerationParams, InferenceResponse, OutputConstraint, Priority};
 use crate::sandbox::SandboxExecutor;
 use crate::vault::VaultDatabase;
 use anyhow::Result;
+use serde::Deserialize;
 use std::sync::Arc;
 use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+
+/// 约束解码得到的动作规划
+#[derive(Debug, Deserialize)]
+struct ActionPlan {
+    thought: String,
+    #[serde(default)]
+    actions: Vec<PlannedAction>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum PlannedAction {
+    CodeExecution { language: String, code: String },
+    SearchQuery { query: String },
+    FileOperation { path: String, operation: String },
+}
+
+impl From<PlannedAction> for AgentAction {
+    fn from(action: PlannedAction) -> Self {
+        match action {
+            PlannedAction::CodeExecution { language, code } => AgentAction::CodeExecution { code, language },
+            PlannedAction::SearchQuery { query } => AgentAction::SearchQuery { query },
+            PlannedAction::FileOperation { path, operation } => AgentAction::FileOperation { path, operation },
+        }
+    }
+}
+
+/// 提示词中每条检索上下文的预览长度（token）
+const CONTEXT_PREVIEW_TOKENS: usize = 128;
+/// 搜索结果的预览长度（token）
+const SEARCH_PREVIEW_TOKENS: usize = 96;
+
+/// 按 token 截取预览，截断位置总在字符边界上
+fn preview(content: &str, max_tokens: usize, tokenizer: &dyn Tokenizer) -> String {
+    let truncated = tokenizer.truncate_to_tokens(content, max_tokens);
+    if truncated.len() < content.len() {
+        format!("{}...", truncated)
+    } else {
+        truncated
+    }
+}
+
+/// 动作规划的 JSON Schema
+fn action_plan_constraint() -> OutputConstraint {
+    let action = |ty: &str, fields: serde_json::Value, required: &[&str]| {
+        let mut properties = serde_json::json!({ "type": { "const": ty } });
+        properties.as_object_mut().unwrap().extend(fields.as_object().cloned().unwrap_or_default());
+        let mut required: Vec<&str> = required.to_vec();
+        required.insert(0, "type");
+        serde_json::json!({ "type": "object", "properties": properties, "required": required })
+    };
+    OutputConstraint::JsonSchema {
+        schema: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "thought": { "type": "string" },
+                "actions": {
+                    "type": "array",
+                    "items": { "anyOf": [
+                        action("code_execution", serde_json::json!({
+                            "language": { "enum": ["python", "javascript"] },
+                            "code": { "type": "string" },
+                        }), &["language", "code"]),
+                        action("search_query", serde_json::json!({
+                            "query": { "type": "string" },
+                        }), &["query"]),
+                        action("file_operation", serde_json::json!({
+                            "path": { "type": "string" },
+                            "operation": { "type": "string" },
+                        }), &["path", "operation"]),
+                    ]},
+                },
+            },
+            "required": ["thought", "actions"],
+        }),
+    }
+}
 
 pub struct AgentExecutor {
     engine: Arc<RwLock<EngineManager>>,
@@ -27,8 +107,8 @@ impl AgentExecutor {
         }
     }
     
-    /// 执行 Agent 任务
-    pub async fn execute(&self, task: AgentTask) -> Result<AgentResponse> {
+    /// 执行 Agent 任务；cancel 被触发时中止推理并跳过尚未执行的动作
+    pub async fn execute(&self, task: AgentTask, cancel: CancellationToken) -> Result<AgentResponse> {
         // 1. 从 Vault 检索相关上下文（自动提取关键词）
         let context_query = if let Some(query) = &task.context {
             query.clone()
@@ -41,46 +121,100 @@ impl AgentExecutor {
         let context = vault.search(&context_query, 5).await?;
         drop(vault);
         
-        // 2. 构建增强提示词
-        let enhanced_prompt = self.build_enhanced_prompt(&task, &context);
+        // 2. 构建对话消息（系统提示词 + 历史对话 + 用户指令）
+        let tokenizer = self.engine.read().await.tokenizer();
+        let messages = self.build_messages(&task, &context, &*tokenizer);
         
-        // 3. 调用推理引擎
-        let engine = self.engine.read().await;
-        let response = engine.infer(&enhanced_prompt).await?;
-        let reasoning = response.tokens.join("");
+        // 3. 调用推理引擎（动作规划需要稳定输出，使用确定性参数）
+        let (response, plan) = self.plan(&messages, &cancel).await?;
+        let reasoning = match &plan {
+            Some(plan) => plan.thought.clone(),
+            None => response.tokens.join(""),
+        };
+        
+        if response.finish_reason == FinishReason::Cancelled {
+            tracing::info!("Agent task cancelled during inference");
+            return Ok(AgentResponse {
+                reasoning,
+                actions: vec![],
+                artifacts: vec![],
+                finish_reason: FinishReason::Cancelled,
+                metrics: response.metrics,
+            });
+        }
         
-        // 4. 解析 Agent 动作（改进的解析逻辑）
-        let actions = self.parse_actions(&reasoning, &task.instruction).await?;
+        // 4. 解析 Agent 动作：优先使用结构化的动作规划，否则退回关键词解析
+        let actions = match plan {
+            Some(plan) => plan.actions.into_iter().map(AgentAction::from).collect(),
+            None => self.parse_actions(&reasoning, &task.instruction).await?,
+        };
         
         // 5. 执行动作（需要用户确认）
-        let artifacts = self.execute_actions(actions.clone()).await?;
+        let artifacts = self.execute_actions(actions.clone(), &cancel).await?;
         
         Ok(AgentResponse {
             reasoning,
             actions,
             artifacts,
+            finish_reason: if cancel.is_cancelled() { FinishReason::Cancelled } else { response.finish_reason },
+            metrics: response.metrics,
         })
     }
     
     
-    fn build_enhanced_prompt(&self, task: &AgentTask, context: &[crate::vault::SearchResult]) -> String {
-        let mut prompt = format!("你是一个本地 AI Agent，名为 Silo。你的任务是帮助用户完成各种任务，同时确保所有操作都在本地完成，保护用户隐私。\n\n");
-        prompt.push_str(&format!("用户指令: {}\n\n", task.instruction));
-        
-        if !context.is_empty() {
-            prompt.push_str("相关上下文（来自本地知识库）:\n");
-            for (idx, result) in context.iter().enumerate() {
-                let preview = if result.document.content.len() > 200 {
-                    format!("{}...", &result.document.content[..200])
-                } else {
-                    result.document.content.clone()
-                };
-                prompt.push_str(&format!("[文档 {}] (相似度: {:.2})\n{}\n\n", idx + 1, result.similarity, preview));
+    /// 请求按 JSON Schema 约束输出动作规划；无法给出合法规划时退回自由文本推理。
+    /// 不支持约束解码的后端只尝试一次，不合格时直接退回，不再反复重试
+    async fn plan(&self, messages: &[ChatMessage], cancel: &CancellationToken) -> Result<(InferenceResponse, Option<ActionPlan>)> {
+        let params = GenerationParams::deterministic()
+            .with_priority(Priority::Agent)
+            .with_constraint(action_plan_constraint())
+            .with_constraint_attempts(1);
+        // 只在创建流时持有引擎读锁，读取输出期间不阻塞切换模型等写操作
+        let constrained = self.engine.read().await.chat_stream(messages, &params, cancel.clone()).await;
+        match constrained {
+            Ok(stream) => {
+                let response = stream.collect().await;
+                let plan = serde_json::from_str::<ActionPlan>(&response.tokens.concat());
+                if let Err(e) = &plan
+                    && response.finish_reason != FinishReason::Cancelled
+                {
+                    tracing::warn!("Failed to parse action plan, falling back to keyword parsing: {}", e);
+                }
+                Ok((response, plan.ok()))
+            }
+            Err(e) => {
+                tracing::warn!("Constrained action planning failed, falling back to free-form reasoning: {}", e);
+                let params = GenerationParams::deterministic().with_priority(Priority::Agent);
+                let stream = self.engine.read().await.chat_stream(messages, &params, cancel.clone()).await?;
+                Ok((stream.collect().await, None))
             }
         }
+    }
+    
+    fn build_messages(
+        &self,
+        task: &AgentTask,
+        context: &[crate::vault::SearchResult],
+        tokenizer: &dyn Tokenizer,
+    ) -> Vec<ChatMessage> {
+        let mut prompt = String::from("你是一个本地 AI Agent，名为 Silo。你的任务是帮助用户完成各种任务，同时确保所有操作都在本地完成，保护用户隐私。\n\n");
+        prompt.push_str("请分析任务并给出执行计划。如果需要执行代码、搜索文档或操作文件，请在 actions 中列出对应的动作。");
+        if !context.is_empty() {
+            prompt.push_str("\n\n指令之前的 vault 工具消息是从本地知识库检索到的相关上下文。");
+        }
         
-        prompt.push_str("请分析任务并给出执行计划。如果需要执行代码、搜索文档或操作文件，请明确说明。");
-        prompt
+        let mut messages = vec![ChatMessage::system(prompt)];
+        messages.extend(task.history.iter().cloned());
+        // 检索片段带相关度单独成条，超出上下文长度时可按相关度丢弃
+        for (idx, result) in context.iter().enumerate() {
+            let preview = preview(&result.document.content, CONTEXT_PREVIEW_TOKENS, tokenizer);
+            messages.push(ChatMessage::retrieved(
+                format!("[文档 {}] (相似度: {:.2})\n{}", idx + 1, result.similarity, preview),
+                result.similarity,
+            ));
+        }
+        messages.push(ChatMessage::user(&task.instruction));
+        messages
     }
     
     async fn parse_actions(&self, reasoning: &str, instruction: &str) -> Result<Vec<AgentAction>> {
@@ -121,10 +255,15 @@ impl AgentExecutor {
         Ok(actions)
     }
     
-    async fn execute_actions(&self, actions: Vec<AgentAction>) -> Result<Vec<Artifact>> {
+    async fn execute_actions(&self, actions: Vec<AgentAction>, cancel: &CancellationToken) -> Result<Vec<Artifact>> {
         let mut artifacts = vec![];
         
         for action in actions {
+            if cancel.is_cancelled() {
+                tracing::info!("Agent task cancelled, skipping remaining actions");
+                break;
+            }
+
             match action {
                 AgentAction::CodeExecution { code, language } => {
                     let sandbox = self.sandbox.read().await;
@@ -141,13 +280,10 @@ impl AgentExecutor {
                 AgentAction::SearchQuery { query } => {
                     let vault = self.vault.read().await;
                     let results = vault.search(&query, 10).await?;
+                    let tokenizer = self.engine.read().await.tokenizer();
                     let mut content = format!("找到 {} 个相关结果:\n\n", results.len());
                     for (idx, result) in results.iter().enumerate() {
-                        let preview = if result.document.content.len() > 150 {
-                            format!("{}...", &result.document.content[..150])
-                        } else {
-                            result.document.content.clone()
-                        };
+                        let preview = preview(&result.document.content, SEARCH_PREVIEW_TOKENS, &*tokenizer);
                         content.push_str(&format!("[{}] (相似度: {:.2})\n{}\n\n", 
                             idx + 1, result.similarity, preview));
                     }
@@ -1,5 +1,6 @@
 // Agent 执行器 - 协调推理、检索和执行
 
+use crate::engine::{ChatMessage, FinishReason, InferenceMetrics};
 use serde::{Deserialize, Serialize};
 
 pub mod executor;
@@ -12,6 +13,9 @@ pub use utils::*;
 pub struct AgentTask {
     pub instruction: String,
     pub context: Option<String>,
+    /// 之前的对话轮次（含工具结果），按时间顺序
+    #[serde(default)]
+    pub history: Vec<ChatMessage>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,6 +23,11 @@ pub struct AgentResponse {
     pub reasoning: String,
     pub actions: Vec<AgentAction>,
     pub artifacts: Vec<Artifact>,
+    /// 被取消时为 Cancelled，reasoning 为已生成的部分
+    pub finish_reason: FinishReason,
+    /// 本次推理的 token 用量与延迟
+    #[serde(default)]
+    pub metrics: InferenceMetrics,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
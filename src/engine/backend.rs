@@ -1,70 +1,250 @@
 // 推理后端抽象接口
 
-use crate::engine::{InferenceConfig, InferenceResponse};
+use crate::engine::gguf;
+use crate::engine::llama::LlamaModel;
+use crate::engine::prefix_cache::{self, PrefixCacheConfig, PrefixCacheStats};
+use crate::engine::scripted::ScriptedBackend;
+use crate::engine::sidecar::{Sidecar, SidecarConfig, SidecarState};
+use crate::engine::template::{ChatFormat, ChatTemplate};
+use crate::engine::{
+    last_user_message, ChatMessage, FinishReason, GenerationParams, InferenceConfig, InferenceMetrics, InferenceResponse,
+    InferenceStream, MetricsTimer, ModelDescriptor, OutputConstraint, StreamSender, TokenUsage,
+};
 use anyhow::Result;
 use async_trait::async_trait;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
 
 #[async_trait]
 pub trait InferenceBackend: Send + Sync {
     /// 初始化后端
     async fn initialize(&mut self, config: InferenceConfig) -> Result<()>;
     
-    /// 执行推理
-    async fn infer(&self, prompt: &str) -> Result<InferenceResponse>;
+    /// 多轮对话推理
+    async fn chat(&self, messages: &[ChatMessage], params: &GenerationParams) -> Result<InferenceResponse>;
     
-    /// 流式推理
-    async fn infer_stream(&self, prompt: &str) -> Result<tokio::sync::mpsc::Receiver<String>>;
+    /// 多轮对话流式推理；cancel 被触发或返回的流被丢弃时停止生成
+    async fn chat_stream(
+        &self,
+        messages: &[ChatMessage],
+        params: &GenerationParams,
+        cancel: CancellationToken,
+    ) -> Result<InferenceStream>;
+    
+    /// 执行推理（单条用户消息，默认生成参数）
+    async fn infer(&self, prompt: &str) -> Result<InferenceResponse> {
+        self.chat(&[ChatMessage::user(prompt)], &GenerationParams::default()).await
+    }
+    
+    /// 流式推理（单条用户消息，默认生成参数）
+    async fn infer_stream(&self, prompt: &str, cancel: CancellationToken) -> Result<InferenceStream> {
+        self.chat_stream(&[ChatMessage::user(prompt)], &GenerationParams::default(), cancel).await
+    }
     
     /// 获取后端类型
     fn backend_type(&self) -> crate::engine::BackendType;
     
     /// 检查后端是否可用
     fn is_available(&self) -> bool;
+    
+    /// 是否在生成时原生执行该输出约束；返回 false 时由引擎管理器校验输出并重试
+    fn supports_constraint(&self, _constraint: &OutputConstraint) -> bool {
+        false
+    }
+    
+    /// 当前模型的向量空间标识；不能生成文本向量的后端返回 None。
+    /// 标识不同的向量之间不可比较，调用方据此判断已存储的向量是否需要重新计算
+    fn embedding_model(&self) -> Option<String> {
+        None
+    }
+    
+    /// 批量计算文本向量，返回顺序与输入一致
+    async fn embed(&self, _texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        anyhow::bail!("{:?} does not support embeddings", self.backend_type())
+    }
+    
+    /// 健康检查（由降级链定期调用），默认等同于 is_available
+    async fn health_check(&self) -> bool {
+        self.is_available()
+    }
+    
+    /// 列出后端可用的模型（不支持多模型的后端返回空列表）
+    async fn list_models(&self) -> Result<Vec<ModelDescriptor>> {
+        Ok(vec![])
+    }
+    
+    /// 切换后续请求使用的模型
+    fn select_model(&mut self, model: &str) -> Result<()> {
+        anyhow::bail!("{:?} does not support model selection: {}", self.backend_type(), model)
+    }
+    
+    /// 提示词前缀 KV 缓存的命中情况；不支持保存/恢复推理状态的后端返回 None
+    fn prefix_cache_stats(&self) -> Option<PrefixCacheStats> {
+        None
+    }
+}
+
+/// 模拟流式输出：逐词发送，取消时立即停止
+fn spawn_mock_stream(text: String, sender: StreamSender) {
+    tokio::spawn(async move {
+        for word in text.split_whitespace() {
+            if !sender.send(format!("{} ", word)).await {
+                break;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(tokio::time::Duration::from_millis(50)) => {}
+                _ = sender.cancelled() => break,
+            }
+        }
+        sender.finish(FinishReason::Stop).await;
+    });
 }
 
 // MLX Sidecar 后端 (Mac 优化)
-// 暂未实现，使用模拟响应以便应用可运行
+// Rust 启动 Python MLX 侧车进程（sidecar/mlx_sidecar.py），通过 Unix Domain Socket 流式传输 token
 pub struct MlxBackend {
-    // MLX Python 侧车进程句柄
-    // 通过 gRPC 或 Unix Domain Socket 通信
+    sidecar_config: SidecarConfig,
+    sidecar: Option<Sidecar>,
+    model_path: Option<std::path::PathBuf>,
+    /// 模型目录中的对话模板；为 None 时由侧车用分词器自带的模板渲染
+    template: Option<ChatTemplate>,
+}
+
+impl MlxBackend {
+    /// 使用默认侧车：SILO_MLX_PYTHON（默认 python3）运行 SILO_MLX_SIDECAR 指定的脚本
+    pub fn new() -> Self {
+        let python = std::env::var("SILO_MLX_PYTHON").unwrap_or_else(|_| "python3".to_string());
+        let script = std::env::var("SILO_MLX_SIDECAR")
+            .map(std::path::PathBuf::from)
+            .unwrap_or_else(|_| default_mlx_script());
+        Self::with_sidecar(SidecarConfig::new("mlx", python).with_args([script.to_string_lossy().into_owned()]))
+    }
+
+    /// 使用自定义侧车程序（需实现同样的协议）
+    pub fn with_sidecar(config: SidecarConfig) -> Self {
+        Self {
+            sidecar_config: config,
+            sidecar: None,
+            model_path: None,
+            template: None,
+        }
+    }
+
+    fn sidecar(&self) -> Result<&Sidecar> {
+        self.sidecar
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("MLX sidecar is not initialized"))
+    }
+}
+
+impl Default for MlxBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 安装包中位于可执行文件旁的 sidecar 目录，开发时位于仓库根目录
+fn default_mlx_script() -> std::path::PathBuf {
+    let bundled = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join("sidecar").join("mlx_sidecar.py")));
+    match bundled {
+        Some(path) if path.exists() => path,
+        _ => std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("sidecar").join("mlx_sidecar.py"),
+    }
 }
 
 #[async_trait]
 impl InferenceBackend for MlxBackend {
-    async fn initialize(&mut self, _config: InferenceConfig) -> Result<()> {
-        // TODO: 启动 Python MLX 侧车进程
+    async fn initialize(&mut self, config: InferenceConfig) -> Result<()> {
+        if self.sidecar.as_ref().is_none_or(|s| !s.is_ready()) {
+            self.sidecar = Some(Sidecar::spawn(self.sidecar_config.clone()).await?);
+        }
+        let sidecar = self.sidecar()?;
+        let load = serde_json::json!({
+            "type": "load",
+            "model_path": config.model_path,
+            "context_size": config.context_size,
+        });
+        sidecar.call(load.clone()).await?;
+        // 侧车崩溃重启后自动重新加载模型
+        sidecar.set_init(Some(load));
+        tracing::info!("MlxBackend initialized: {:?} ({})", config.model_path, sidecar.info());
+        // 与本地 GGUF 后端使用同一套模板渲染提示词，模型缺少 Jinja 模板时按 model_type 选择内置格式
+        self.template = if config.model_path.is_dir() {
+            ChatTemplate::from_model_dir(&config.model_path)
+                .inspect_err(|e| tracing::warn!("Failed to read chat template from {:?}: {}", config.model_path, e))
+                .ok()
+        } else {
+            None
+        };
+        self.model_path = Some(config.model_path);
         Ok(())
     }
     
-    async fn infer(&self, prompt: &str) -> Result<InferenceResponse> {
-        // TODO: 通过 IPC 调用 MLX，目前返回模拟响应
-        let response_text = format!(
-            "我理解你的指令：\"{}\"。\n\n（注意：当前运行在模拟模式下。MLX 后端尚未完成集成，请后续配置模型文件。）",
-            prompt
-        );
-        let tokens: Vec<String> = response_text
-            .split_whitespace()
-            .map(|s| s.to_string())
-            .collect();
-        Ok(InferenceResponse {
-            tokens,
-            finish_reason: "stop".to_string(),
-        })
+    async fn chat(&self, messages: &[ChatMessage], params: &GenerationParams) -> Result<InferenceResponse> {
+        let response = self.chat_stream(messages, params, CancellationToken::new()).await?.collect().await;
+        if response.finish_reason == FinishReason::Error {
+            anyhow::bail!("MLX sidecar generation failed");
+        }
+        Ok(response)
     }
     
-    async fn infer_stream(&self, prompt: &str) -> Result<tokio::sync::mpsc::Receiver<String>> {
-        // TODO: 流式调用 MLX
-        let (tx, rx) = tokio::sync::mpsc::channel(100);
-        let text = format!("处理中：{}...", prompt);
+    async fn chat_stream(
+        &self,
+        messages: &[ChatMessage],
+        params: &GenerationParams,
+        cancel: CancellationToken,
+    ) -> Result<InferenceStream> {
+        let sidecar = self.sidecar()?;
+        let mut request = serde_json::json!({
+            "type": "generate",
+            "messages": messages,
+            "params": params,
+        });
+        if let Some(template) = &self.template {
+            request["prompt"] = serde_json::json!(template.render(messages, true));
+        }
+        let (id, mut frames) = sidecar.request(request).await?;
+        let (mut sender, stream) = InferenceStream::channel(cancel);
+        let (shared, cancel_id) = (sidecar.clone(), id);
         tokio::spawn(async move {
-            for word in text.split_whitespace() {
-                if tx.send(format!("{} ", word)).await.is_err() {
-                    break;
+            let reason = loop {
+                let frame = tokio::select! {
+                    frame = frames.recv() => frame,
+                    _ = sender.cancelled() => {
+                        shared.cancel(cancel_id).await;
+                        break FinishReason::Cancelled;
+                    }
+                };
+                let Some(frame) = frame else {
+                    break FinishReason::Error;
+                };
+                match frame["type"].as_str() {
+                    Some("token") => {
+                        let text = frame["text"].as_str().unwrap_or_default().to_string();
+                        if !sender.send(text).await {
+                            shared.cancel(cancel_id).await;
+                            break FinishReason::Cancelled;
+                        }
+                    }
+                    Some("done") => {
+                        if let Ok(usage) = serde_json::from_value::<TokenUsage>(frame["usage"].clone()) {
+                            sender.set_usage(usage);
+                        }
+                        break FinishReason::from_server(frame["finish_reason"].as_str().unwrap_or("stop"));
+                    }
+                    Some("error") => {
+                        tracing::error!("MLX sidecar generation failed: {}", frame["message"]);
+                        break FinishReason::Error;
+                    }
+                    _ => tracing::debug!("Ignoring MLX sidecar frame: {}", frame),
                 }
-                tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
-            }
+            };
+            shared.finish(cancel_id);
+            sender.finish(reason).await;
         });
-        Ok(rx)
+        Ok(stream)
     }
     
     fn backend_type(&self) -> crate::engine::BackendType {
@@ -72,8 +252,33 @@ impl InferenceBackend for MlxBackend {
     }
     
     fn is_available(&self) -> bool {
-        // 检查是否为 macOS 且为 Apple Silicon
-        cfg!(target_os = "macos")
+        // 检查是否为 macOS 且为 Apple Silicon，且侧车没有因反复崩溃被放弃
+        cfg!(target_os = "macos") && self.sidecar.as_ref().is_none_or(|s| s.state() != SidecarState::Failed)
+    }
+    
+    fn embedding_model(&self) -> Option<String> {
+        self.sidecar.as_ref()?;
+        self.model_path.as_ref().map(|path| format!("mlx:{}", path.display()))
+    }
+    
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let reply = self
+            .sidecar()?
+            .call(serde_json::json!({ "type": "embed", "texts": texts }))
+            .await?;
+        let embeddings: Vec<Vec<f32>> = serde_json::from_value(reply["embeddings"].clone())
+            .map_err(|e| anyhow::anyhow!("Malformed MLX embeddings reply: {}", e))?;
+        if embeddings.len() != texts.len() {
+            anyhow::bail!("Expected {} embeddings, got {}", texts.len(), embeddings.len());
+        }
+        Ok(embeddings)
+    }
+    
+    async fn health_check(&self) -> bool {
+        match &self.sidecar {
+            Some(sidecar) => sidecar.is_ready(),
+            None => self.is_available(),
+        }
     }
 }
 
@@ -83,6 +288,12 @@ pub struct InferflowBackend {
     // Inferflow C++ 库绑定
 }
 
+impl InferflowBackend {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
 #[async_trait]
 impl InferenceBackend for InferflowBackend {
     async fn initialize(&mut self, _config: InferenceConfig) -> Result<()> {
@@ -90,8 +301,10 @@ impl InferenceBackend for InferflowBackend {
         Ok(())
     }
     
-    async fn infer(&self, prompt: &str) -> Result<InferenceResponse> {
+    async fn chat(&self, messages: &[ChatMessage], _params: &GenerationParams) -> Result<InferenceResponse> {
         // TODO: 调用 Inferflow 推理，目前返回模拟响应
+        let timer = MetricsTimer::start();
+        let prompt = last_user_message(messages);
         let response_text = format!(
             "我理解你的指令：\"{}\"。\n\n（注意：当前运行在模拟模式下。Inferflow 后端尚未完成集成。）",
             prompt
@@ -100,25 +313,24 @@ impl InferenceBackend for InferflowBackend {
             .split_whitespace()
             .map(|s| s.to_string())
             .collect();
+        let metrics = timer.finish(None, tokens.len());
         Ok(InferenceResponse {
             tokens,
-            finish_reason: "stop".to_string(),
+            finish_reason: FinishReason::Stop,
+            metrics,
         })
     }
     
-    async fn infer_stream(&self, prompt: &str) -> Result<tokio::sync::mpsc::Receiver<String>> {
+    async fn chat_stream(
+        &self,
+        messages: &[ChatMessage],
+        _params: &GenerationParams,
+        cancel: CancellationToken,
+    ) -> Result<InferenceStream> {
         // TODO: 流式调用 Inferflow
-        let (tx, rx) = tokio::sync::mpsc::channel(100);
-        let text = format!("处理中：{}...", prompt);
-        tokio::spawn(async move {
-            for word in text.split_whitespace() {
-                if tx.send(format!("{} ", word)).await.is_err() {
-                    break;
-                }
-                tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
-            }
-        });
-        Ok(rx)
+        let (sender, stream) = InferenceStream::channel(cancel);
+        spawn_mock_stream(format!("处理中：{}...", last_user_message(messages)), sender);
+        Ok(stream)
     }
     
     fn backend_type(&self) -> crate::engine::BackendType {
@@ -126,17 +338,27 @@ impl InferenceBackend for InferflowBackend {
     }
     
     fn is_available(&self) -> bool {
-        // 检查是否有 NVIDIA GPU
-        // TODO: 使用 sysinfo 或 nvidia-smi 检测
+        // 尚未接入 Inferflow 库，上面只是模拟输出，不能排在真实的 CPU 后端之前；
+        // NVIDIA GPU 上的推理由名为 inferflow 的插件提供
         false
     }
 }
 
 // Llama.cpp 后端 (通用 CPU)
-// 目前使用模拟实现，后续可集成实际的 llama.cpp
+// 使用纯 Rust GGUF 运行时在 CPU 上执行模型；未配置模型文件时回放演示脚本（fixtures/demo.json）
 pub struct LlamaCppBackend {
     initialized: bool,
     model_path: Option<std::path::PathBuf>,
+    model: Option<Arc<LlamaModel>>,
+    template: ChatTemplate,
+    context_size: usize,
+    demo: ScriptedBackend,
+    /// 提示词前缀 KV 缓存配置，None 表示不缓存
+    prefix_cache: Option<PrefixCacheConfig>,
+    /// 作为专用嵌入后端加载
+    embedding_slot: bool,
+    /// 当前模型可以生成文本向量：专用嵌入后端，或 GGUF 元数据声明了池化方式
+    embeds: bool,
 }
 
 impl LlamaCppBackend {
@@ -144,95 +366,124 @@ impl LlamaCppBackend {
         Self {
             initialized: false,
             model_path: None,
+            model: None,
+            template: ChatTemplate::builtin(ChatFormat::ChatMl),
+            context_size: 2048,
+            demo: ScriptedBackend::demo(),
+            prefix_cache: None,
+            embedding_slot: false,
+            embeds: false,
         }
     }
+    
+    /// 作为专用嵌入后端使用，无论模型元数据如何都提供文本向量
+    pub fn for_embeddings(mut self) -> Self {
+        self.embedding_slot = true;
+        self
+    }
+    
+    /// 缓存提示词前缀的 KV 状态，相同前缀的后续请求跳过这部分预填充
+    pub fn with_prefix_cache(mut self, config: PrefixCacheConfig) -> Self {
+        self.prefix_cache = Some(config).filter(PrefixCacheConfig::is_enabled);
+        self
+    }
 }
 
 #[async_trait]
 impl InferenceBackend for LlamaCppBackend {
     async fn initialize(&mut self, config: InferenceConfig) -> Result<()> {
-        // 检查模型文件是否存在
+        // 模型文件缺失时直接报错，不再静默退回模拟模式
         if !config.model_path.exists() {
-            tracing::warn!("Model file not found: {:?}, using mock mode", config.model_path);
-            self.initialized = true;
-            return Ok(());
+            anyhow::bail!("Model file not found: {:?}", config.model_path);
+        }
+        
+        let path = config.model_path.clone();
+        let cache_config = self.prefix_cache.clone();
+        let (model, info) = tokio::task::spawn_blocking(move || -> Result<_> {
+            let mut model = LlamaModel::load(&path)?;
+            // 缓存不可用（如目录无法写入）时照常推理
+            if let Some(cache_config) = cache_config
+                && let Err(e) = model.enable_prefix_cache(prefix_cache::model_namespace(&path), cache_config)
+            {
+                tracing::warn!("Prefix cache disabled: {}", e);
+            }
+            Ok((model, gguf::inspect(&path)?))
+        })
+        .await??;
+        let n_ctx_train = model.hparams().n_ctx_train;
+        if config.context_size > n_ctx_train {
+            tracing::warn!("context_size {} exceeds model training context {}, clamping", config.context_size, n_ctx_train);
         }
         
+        self.context_size = config.context_size.min(n_ctx_train);
+        self.template = ChatTemplate::from_model_info(&info, model.tokenizer().bos_token(), model.tokenizer().eos_token());
+        // 生成模型的隐状态不是可用的向量空间，只有嵌入模型才向调用方报告
+        self.embeds = self.embedding_slot || info.is_embedding_model();
+        self.model = Some(Arc::new(model));
         self.model_path = Some(config.model_path);
         self.initialized = true;
-        tracing::info!("LlamaCppBackend initialized (mock mode)");
+        tracing::info!(
+            "LlamaCppBackend initialized: {:?} (n_ctx: {}, template: {:?}{})",
+            self.model_path,
+            self.context_size,
+            self.template.format,
+            if self.template.jinja.is_some() { " + jinja" } else { "" }
+        );
         Ok(())
     }
     
-    async fn infer(&self, prompt: &str) -> Result<InferenceResponse> {
-        // 模拟模式：根据用户指令返回可执行的结构化推理，便于 parse_actions 解析并真正执行
-        // 仅匹配用户指令部分（"用户指令: xxx" 之后），避免被系统提示词中的"帮助"等词误触发
-        let user_instruction = prompt
-            .split("用户指令:")
-            .nth(1)
-            .and_then(|s| s.split('\n').next())
-            .map(str::trim)
-            .unwrap_or(prompt);
-
-        let response_text: String = if user_instruction.contains("你好") || user_instruction.contains("hello") {
-            "你好！我是 Silo AI，一个隐私优先的本地 Agent 操作系统。我可以帮助你完成各种任务，同时确保你的数据完全保留在本地。".to_string()
-        } else if user_instruction.contains("介绍") || user_instruction.contains("你是谁") {
-            "你好！我是 Silo AI，一个隐私优先的本地 Agent 操作系统。我可以帮助你完成各种任务，同时确保你的数据完全保留在本地。".to_string()
-        } else if user_instruction.contains("列出") && (user_instruction.contains("目录") || user_instruction.contains("文件")) {
-            // 返回包含代码块的结构化推理，便于 parse_actions 提取并执行
-            r#"用户需要列出当前目录下的文件，我将执行以下 Python 代码完成任务：
-
-```python
-import os
-for name in os.listdir('.'):
-    print(name)
-```
-"#.to_string()
-        } else if user_instruction.contains("扫描") && user_instruction.contains("PDF") {
-            r#"用户需要扫描目录查找 PDF 文件，我将执行以下 Python 代码：
-
-```python
-import os
-import glob
-home = os.path.expanduser('~')
-for path in glob.glob(os.path.join(home, 'Downloads', '**', '*.pdf'), recursive=True):
-    print(path)
-```
-"#.to_string()
-        } else {
-            format!("我理解你的指令：\"{}\"。\n\n（注意：当前运行在模拟模式下。要使用真实的 AI 模型，请配置模型文件路径并集成 llama.cpp。）", user_instruction)
+    async fn chat(&self, messages: &[ChatMessage], params: &GenerationParams) -> Result<InferenceResponse> {
+        let Some(model) = self.model.clone() else {
+            return self.demo.chat(messages, params).await;
         };
+        let mut timer = MetricsTimer::start();
         
-        // 模拟 tokens：保持完整文本，避免 split_whitespace 破坏代码块换行
-        let tokens: Vec<String> = vec![response_text];
+        let prompt = self.template.render(messages, true);
+        let (n_ctx, params) = (self.context_size, params.clone());
+        let (output, timer) = tokio::task::spawn_blocking(move || {
+            let output = model.generate(&prompt, n_ctx, &params, |_| {
+                timer.mark_token();
+                true
+            })?;
+            anyhow::Ok((output, timer))
+        })
+        .await??;
         
         Ok(InferenceResponse {
-            tokens,
-            finish_reason: "stop".to_string(),
+            metrics: timer.finish(Some(output.usage.prompt_tokens), output.usage.completion_tokens),
+            tokens: output.pieces,
+            finish_reason: output.finish_reason,
         })
     }
     
-    async fn infer_stream(&self, prompt: &str) -> Result<tokio::sync::mpsc::Receiver<String>> {
-        // 创建通道用于流式输出
-        let (tx, rx) = tokio::sync::mpsc::channel(100);
-        
-        // 异步发送模拟的流式响应（需要拥有字符串）
-        let response_text = if prompt.contains("你好") || prompt.contains("hello") {
-            "你好！我是 Silo AI...".to_string()
-        } else {
-            format!("处理中：{}...", prompt)
+    async fn chat_stream(
+        &self,
+        messages: &[ChatMessage],
+        params: &GenerationParams,
+        cancel: CancellationToken,
+    ) -> Result<InferenceStream> {
+        let Some(model) = self.model.clone() else {
+            return self.demo.chat_stream(messages, params, cancel).await;
         };
         
-        tokio::spawn(async move {
-            for word in response_text.split_whitespace() {
-                if tx.send(format!("{} ", word)).await.is_err() {
-                    break;
+        let (mut sender, stream) = InferenceStream::channel(cancel);
+        let prompt = self.template.render(messages, true);
+        let (n_ctx, params) = (self.context_size, params.clone());
+        // 推理是 CPU 密集型任务，放到阻塞线程池；取消或接收端丢弃时在下一个 token 前停止
+        tokio::task::spawn_blocking(move || {
+            match model.generate(&prompt, n_ctx, &params, |piece| sender.blocking_send(piece.to_string())) {
+                Ok(output) => {
+                    sender.set_usage(output.usage);
+                    sender.blocking_finish(output.finish_reason);
+                }
+                Err(e) => {
+                    tracing::error!("LlamaCppBackend generation failed: {}", e);
+                    sender.blocking_finish(FinishReason::Error);
                 }
-                tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
             }
         });
         
-        Ok(rx)
+        Ok(stream)
     }
     
     fn backend_type(&self) -> crate::engine::BackendType {
@@ -243,6 +494,31 @@ for path in glob.glob(os.path.join(home, 'Downloads', '**', '*.pdf'), recursive=
         // CPU 后端总是可用
         true
     }
+    
+    fn supports_constraint(&self, _constraint: &OutputConstraint) -> bool {
+        // 采样时按语法屏蔽 token；模拟模式下由引擎管理器校验
+        self.model.is_some()
+    }
+    
+    fn embedding_model(&self) -> Option<String> {
+        if !self.embeds {
+            return None;
+        }
+        self.model.as_ref()?;
+        self.model_path.as_ref().map(|path| format!("llama:{}", path.display()))
+    }
+    
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let Some(model) = self.model.clone() else {
+            anyhow::bail!("LlamaCppBackend has no model loaded");
+        };
+        let texts = texts.to_vec();
+        tokio::task::spawn_blocking(move || texts.iter().map(|text| model.embed(text)).collect()).await?
+    }
+    
+    fn prefix_cache_stats(&self) -> Option<PrefixCacheStats> {
+        self.model.as_ref()?.prefix_cache_stats()
+    }
 }
 
 impl Default for LlamaCppBackend {
@@ -250,3 +526,1074 @@ impl Default for LlamaCppBackend {
         Self::new()
     }
 }
+
+// OpenAI 兼容 HTTP 后端 (llama-server / vLLM / LM Studio)
+// 通过 /v1/chat/completions 调用本地推理服务，流式输出基于 SSE
+pub struct OpenAiCompatBackend {
+    base_url: String,
+    api_key: Option<String>,
+    model: Option<String>,
+    /// 专用的嵌入模型；未设置时不提供文本向量
+    embedding_model: Option<String>,
+    client: reqwest::Client,
+    available: bool,
+}
+
+impl OpenAiCompatBackend {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: None,
+            model: None,
+            embedding_model: None,
+            client: reqwest::Client::new(),
+            available: false,
+        }
+    }
+    
+    /// 设置 API Key（部分服务要求 Bearer 鉴权）
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+    
+    /// 指定请求使用的模型名，未指定时从 /v1/models 中选取第一个
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+    
+    /// 指定 /v1/embeddings 使用的嵌入模型（如 bge-m3）
+    pub fn with_embedding_model(mut self, model: impl Into<String>) -> Self {
+        self.embedding_model = Some(model.into());
+        self
+    }
+    
+    fn url(&self, path: &str) -> String {
+        // 兼容用户配置为 http://host:port 或 http://host:port/v1 两种形式
+        let base = self.base_url.trim_end_matches("/v1");
+        format!("{}/v1{}", base, path)
+    }
+    
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let builder = self.client.request(method, self.url(path));
+        match &self.api_key {
+            Some(key) => builder.bearer_auth(key),
+            None => builder,
+        }
+    }
+    
+    /// 健康检查：服务能在超时内返回模型列表即视为可用
+    pub async fn probe(&mut self) -> bool {
+        let result = self
+            .request(reqwest::Method::GET, "/models")
+            .timeout(std::time::Duration::from_secs(2))
+            .send()
+            .await;
+        
+        self.available = match result {
+            Ok(resp) if resp.status().is_success() => {
+                if self.model.is_none() {
+                    self.model = resp
+                        .json::<serde_json::Value>()
+                        .await
+                        .ok()
+                        .and_then(|v| v["data"][0]["id"].as_str().map(str::to_string));
+                }
+                true
+            }
+            Ok(resp) => {
+                tracing::warn!("OpenAI-compatible endpoint {} returned {}", self.base_url, resp.status());
+                false
+            }
+            Err(e) => {
+                tracing::debug!("OpenAI-compatible endpoint {} unreachable: {}", self.base_url, e);
+                false
+            }
+        };
+        self.available
+    }
+    
+    fn chat_body(&self, messages: &[ChatMessage], params: &GenerationParams, stream: bool) -> serde_json::Value {
+        let mut body = serde_json::json!({
+            "model": self.model.clone().unwrap_or_default(),
+            "messages": messages,
+            "temperature": params.temperature,
+            "top_p": params.top_p,
+            "stream": stream,
+        });
+        if let Some(max_tokens) = params.max_tokens {
+            body["max_tokens"] = serde_json::json!(max_tokens);
+        }
+        // top_k / repetition_penalty 是 llama-server、vLLM 支持的扩展字段
+        if let Some(top_k) = params.top_k {
+            body["top_k"] = serde_json::json!(top_k);
+        }
+        if params.repetition_penalty != 1.0 {
+            body["repetition_penalty"] = serde_json::json!(params.repetition_penalty);
+        }
+        if let Some(seed) = params.seed {
+            body["seed"] = serde_json::json!(seed);
+        }
+        if !params.stop.is_empty() {
+            body["stop"] = serde_json::json!(params.stop);
+        }
+        if stream {
+            // 让服务在最后一个事件中返回 token 用量
+            body["stream_options"] = serde_json::json!({ "include_usage": true });
+        }
+        if !params.logit_bias.is_empty() {
+            let bias: serde_json::Map<String, serde_json::Value> = params
+                .logit_bias
+                .iter()
+                .map(|(id, bias)| (id.to_string(), serde_json::json!(bias)))
+                .collect();
+            body["logit_bias"] = serde_json::Value::Object(bias);
+        }
+        match &params.constraint {
+            Some(OutputConstraint::Json) => {
+                body["response_format"] = serde_json::json!({ "type": "json_object" });
+            }
+            Some(OutputConstraint::JsonSchema { schema }) => {
+                body["response_format"] = serde_json::json!({
+                    "type": "json_schema",
+                    "json_schema": { "name": "output", "schema": schema, "strict": true },
+                });
+            }
+            // grammar 是 llama-server 的扩展字段，其他服务会忽略，由引擎管理器校验输出
+            Some(OutputConstraint::Grammar { gbnf }) => {
+                body["grammar"] = serde_json::json!(gbnf);
+            }
+            None => {}
+        }
+        body
+    }
+}
+
+/// SSE 中的一个数据事件
+struct SseEvent {
+    delta: Option<String>,
+    /// 仅最后一个 choice 事件携带
+    finish_reason: Option<FinishReason>,
+    /// 开启 include_usage 时在 [DONE] 之前单独发送
+    usage: Option<TokenUsage>,
+}
+
+/// 解析一行 SSE 数据；非数据行返回 None
+fn parse_sse_event(line: &str) -> Option<SseEvent> {
+    let data = line.strip_prefix("data:")?.trim();
+    if data.is_empty() || data == "[DONE]" {
+        return None;
+    }
+    let value: serde_json::Value = serde_json::from_str(data).ok()?;
+    let choice = &value["choices"][0];
+    let delta = choice["delta"]["content"]
+        .as_str()
+        .filter(|s| !s.is_empty())
+        .map(str::to_string);
+    let finish_reason = choice["finish_reason"].as_str().map(FinishReason::from_server);
+    Some(SseEvent {
+        delta,
+        finish_reason,
+        usage: openai_usage(&value),
+    })
+}
+
+fn openai_usage(value: &serde_json::Value) -> Option<TokenUsage> {
+    let usage = &value["usage"];
+    Some(TokenUsage {
+        prompt_tokens: usage["prompt_tokens"].as_u64()? as usize,
+        completion_tokens: usage["completion_tokens"].as_u64()? as usize,
+    })
+}
+
+#[async_trait]
+impl InferenceBackend for OpenAiCompatBackend {
+    async fn initialize(&mut self, _config: InferenceConfig) -> Result<()> {
+        if !self.probe().await {
+            anyhow::bail!("OpenAI-compatible endpoint not reachable: {}", self.base_url);
+        }
+        tracing::info!("OpenAiCompatBackend initialized: {} (model: {:?})", self.base_url, self.model);
+        Ok(())
+    }
+    
+    async fn chat(&self, messages: &[ChatMessage], params: &GenerationParams) -> Result<InferenceResponse> {
+        let timer = MetricsTimer::start();
+        let resp = self
+            .request(reqwest::Method::POST, "/chat/completions")
+            .json(&self.chat_body(messages, params, false))
+            .send()
+            .await?
+            .error_for_status()?;
+        let value: serde_json::Value = resp.json().await?;
+        
+        let choice = &value["choices"][0];
+        let content = choice["message"]["content"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Malformed chat completion response: {}", value))?
+            .to_string();
+        let finish_reason = FinishReason::from_server(choice["finish_reason"].as_str().unwrap_or("stop"));
+        // 非流式请求无法观测首 token 时间
+        let usage = openai_usage(&value);
+        let metrics = timer.finish(usage.map(|u| u.prompt_tokens), usage.map_or(1, |u| u.completion_tokens));
+        
+        Ok(InferenceResponse {
+            tokens: vec![content],
+            finish_reason,
+            metrics,
+        })
+    }
+    
+    async fn chat_stream(
+        &self,
+        messages: &[ChatMessage],
+        params: &GenerationParams,
+        cancel: CancellationToken,
+    ) -> Result<InferenceStream> {
+        use futures::StreamExt;
+        
+        // 先创建流再发请求，首 token 延迟包含服务端排队与预填充时间
+        let (mut sender, stream) = InferenceStream::channel(cancel);
+        let resp = self
+            .request(reqwest::Method::POST, "/chat/completions")
+            .json(&self.chat_body(messages, params, true))
+            .send()
+            .await?
+            .error_for_status()?;
+        
+        let mut body = resp.bytes_stream();
+        tokio::spawn(async move {
+            // SSE 事件（以及多字节 UTF-8 字符）可能跨多个 chunk，按字节行缓冲后再解析
+            let mut buffer: Vec<u8> = Vec::new();
+            let mut finish_reason = FinishReason::Stop;
+            'read: loop {
+                // 取消时丢弃响应体，连接关闭后服务端会停止生成
+                let chunk = tokio::select! {
+                    chunk = body.next() => chunk,
+                    _ = sender.cancelled() => break,
+                };
+                let chunk = match chunk {
+                    Some(Ok(chunk)) => chunk,
+                    Some(Err(e)) => {
+                        tracing::warn!("SSE stream interrupted: {}", e);
+                        finish_reason = FinishReason::Error;
+                        break;
+                    }
+                    None => break,
+                };
+                buffer.extend_from_slice(&chunk);
+                while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..=pos).collect();
+                    let line = String::from_utf8_lossy(&line);
+                    let Some(event) = parse_sse_event(line.trim_end()) else {
+                        continue;
+                    };
+                    if let Some(reason) = event.finish_reason {
+                        finish_reason = reason;
+                    }
+                    if let Some(usage) = event.usage {
+                        sender.set_usage(usage);
+                    }
+                    if let Some(delta) = event.delta
+                        && !sender.send(delta).await
+                    {
+                        break 'read;
+                    }
+                }
+            }
+            sender.finish(finish_reason).await;
+        });
+        
+        Ok(stream)
+    }
+    
+    fn backend_type(&self) -> crate::engine::BackendType {
+        crate::engine::BackendType::OpenAiCompat
+    }
+    
+    fn is_available(&self) -> bool {
+        self.available
+    }
+    
+    fn supports_constraint(&self, constraint: &OutputConstraint) -> bool {
+        !matches!(constraint, OutputConstraint::Grammar { .. })
+    }
+    
+    fn embedding_model(&self) -> Option<String> {
+        // 对话模型不作为向量空间；只有显式配置的嵌入模型才提供文本向量
+        self.embedding_model.as_ref().map(|model| format!("openai:{}/{}", self.base_url, model))
+    }
+    
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let Some(model) = &self.embedding_model else {
+            anyhow::bail!("No embedding model configured for {}", self.base_url);
+        };
+        let value: serde_json::Value = self
+            .request(reqwest::Method::POST, "/embeddings")
+            .json(&serde_json::json!({
+                "model": model,
+                "input": texts,
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let data = value["data"]
+            .as_array()
+            .ok_or_else(|| anyhow::anyhow!("Malformed embeddings response: {}", value))?;
+        // 规范允许乱序返回，按 index 排列
+        let mut embeddings: Vec<(usize, Vec<f32>)> = data
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                let index = item["index"].as_u64().map_or(i, |v| v as usize);
+                (index, parse_vector(&item["embedding"]))
+            })
+            .collect();
+        embeddings.sort_by_key(|(index, _)| *index);
+        if embeddings.len() != texts.len() {
+            anyhow::bail!("Expected {} embeddings, got {}", texts.len(), embeddings.len());
+        }
+        Ok(embeddings.into_iter().map(|(_, v)| v).collect())
+    }
+    
+    async fn health_check(&self) -> bool {
+        self.request(reqwest::Method::GET, "/models")
+            .timeout(std::time::Duration::from_secs(2))
+            .send()
+            .await
+            .is_ok_and(|resp| resp.status().is_success())
+    }
+    
+    async fn list_models(&self) -> Result<Vec<ModelDescriptor>> {
+        let value: serde_json::Value = self
+            .request(reqwest::Method::GET, "/models")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let models = value["data"]
+            .as_array()
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|m| m["id"].as_str())
+                    .map(|id| ModelDescriptor {
+                        name: id.to_string(),
+                        size_bytes: None,
+                        family: None,
+                        parameter_size: None,
+                        quantization: None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(models)
+    }
+    
+    fn select_model(&mut self, model: &str) -> Result<()> {
+        self.model = Some(model.to_string());
+        Ok(())
+    }
+}
+
+// Ollama 原生 API 后端
+// 使用 /api/chat（NDJSON 流式）、/api/tags（模型列表）与 /api/embeddings（文本向量）
+pub struct OllamaBackend {
+    base_url: String,
+    model: Option<String>,
+    /// 专用的嵌入模型；未设置时不提供文本向量
+    embedding_model: Option<String>,
+    client: reqwest::Client,
+    context_size: Option<usize>,
+    available: bool,
+}
+
+impl OllamaBackend {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            model: None,
+            embedding_model: None,
+            client: reqwest::Client::new(),
+            context_size: None,
+            available: false,
+        }
+    }
+    
+    /// 指定请求使用的模型名（如 llama3:8b），未指定时选用第一个已安装模型
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+    
+    /// 指定 /api/embeddings 使用的嵌入模型（如 nomic-embed-text）
+    pub fn with_embedding_model(mut self, model: impl Into<String>) -> Self {
+        self.embedding_model = Some(model.into());
+        self
+    }
+    
+    fn url(&self, path: &str) -> String {
+        format!("{}/api{}", self.base_url, path)
+    }
+    
+    async fn fetch_tags(&self, timeout: Option<std::time::Duration>) -> Result<Vec<ModelDescriptor>> {
+        let mut request = self.client.get(self.url("/tags"));
+        if let Some(timeout) = timeout {
+            request = request.timeout(timeout);
+        }
+        let value: serde_json::Value = request.send().await?.error_for_status()?.json().await?;
+        
+        let models = value["models"]
+            .as_array()
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|m| {
+                        let details = &m["details"];
+                        Some(ModelDescriptor {
+                            name: m["name"].as_str()?.to_string(),
+                            size_bytes: m["size"].as_u64(),
+                            family: details["family"].as_str().map(str::to_string),
+                            parameter_size: details["parameter_size"].as_str().map(str::to_string),
+                            quantization: details["quantization_level"].as_str().map(str::to_string),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(models)
+    }
+    
+    /// 健康检查：/api/tags 可访问即视为可用，同时补全默认模型
+    pub async fn probe(&mut self) -> bool {
+        self.available = match self.fetch_tags(Some(std::time::Duration::from_secs(2))).await {
+            Ok(models) => {
+                if self.model.is_none() {
+                    self.model = models.first().map(|m| m.name.clone());
+                }
+                true
+            }
+            Err(e) => {
+                tracing::debug!("Ollama endpoint {} unreachable: {}", self.base_url, e);
+                false
+            }
+        };
+        self.available
+    }
+    
+    fn model_name(&self) -> Result<String> {
+        self.model
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("No Ollama model selected (run `ollama pull <model>` first)"))
+    }
+    
+    fn chat_body(&self, messages: &[ChatMessage], params: &GenerationParams, stream: bool) -> Result<serde_json::Value> {
+        let mut options = serde_json::json!({
+            "temperature": params.temperature,
+            "top_p": params.top_p,
+            "repeat_penalty": params.repetition_penalty,
+        });
+        if let Some(num_ctx) = self.context_size {
+            options["num_ctx"] = serde_json::json!(num_ctx);
+        }
+        if let Some(max_tokens) = params.max_tokens {
+            options["num_predict"] = serde_json::json!(max_tokens);
+        }
+        if let Some(top_k) = params.top_k {
+            options["top_k"] = serde_json::json!(top_k);
+        }
+        if let Some(seed) = params.seed {
+            options["seed"] = serde_json::json!(seed);
+        }
+        if !params.stop.is_empty() {
+            options["stop"] = serde_json::json!(params.stop);
+        }
+        if !params.logit_bias.is_empty() {
+            tracing::debug!("Ollama does not support logit_bias, ignoring {} entries", params.logit_bias.len());
+        }
+        let mut body = serde_json::json!({
+            "model": self.model_name()?,
+            "messages": messages,
+            "stream": stream,
+            "options": options,
+        });
+        match &params.constraint {
+            Some(OutputConstraint::Json) => body["format"] = serde_json::json!("json"),
+            Some(OutputConstraint::JsonSchema { schema }) => body["format"] = schema.clone(),
+            Some(OutputConstraint::Grammar { .. }) => {
+                tracing::debug!("Ollama does not support GBNF grammars, validating output instead");
+            }
+            None => {}
+        }
+        Ok(body)
+    }
+}
+
+/// 解析 JSON 数组形式的向量
+fn parse_vector(value: &serde_json::Value) -> Vec<f32> {
+    value
+        .as_array()
+        .map(|items| items.iter().filter_map(|v| v.as_f64().map(|f| f as f32)).collect())
+        .unwrap_or_default()
+}
+
+/// 最后一个响应对象中的 token 用量；提示词命中缓存时 Ollama 可能省略 prompt_eval_count
+fn ollama_usage(value: &serde_json::Value) -> Option<TokenUsage> {
+    Some(TokenUsage {
+        prompt_tokens: value["prompt_eval_count"].as_u64().unwrap_or(0) as usize,
+        completion_tokens: value["eval_count"].as_u64()? as usize,
+    })
+}
+
+#[async_trait]
+impl InferenceBackend for OllamaBackend {
+    async fn initialize(&mut self, config: InferenceConfig) -> Result<()> {
+        self.context_size = Some(config.context_size);
+        if !self.probe().await {
+            anyhow::bail!("Ollama endpoint not reachable: {}", self.base_url);
+        }
+        tracing::info!("OllamaBackend initialized: {} (model: {:?})", self.base_url, self.model);
+        Ok(())
+    }
+    
+    async fn chat(&self, messages: &[ChatMessage], params: &GenerationParams) -> Result<InferenceResponse> {
+        let timer = MetricsTimer::start();
+        let value: serde_json::Value = self
+            .client
+            .post(self.url("/chat"))
+            .json(&self.chat_body(messages, params, false)?)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        
+        let content = value["message"]["content"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Malformed Ollama chat response: {}", value))?
+            .to_string();
+        let finish_reason = FinishReason::from_server(value["done_reason"].as_str().unwrap_or("stop"));
+        // 非流式请求由服务端耗时推算首 token 时间：模型加载 + 提示词处理
+        let usage = ollama_usage(&value);
+        let nanos = |key: &str| value[key].as_u64().map(std::time::Duration::from_nanos);
+        let time_to_first_token = match (nanos("load_duration"), nanos("prompt_eval_duration")) {
+            (None, None) => None,
+            (load, prompt_eval) => Some(load.unwrap_or_default() + prompt_eval.unwrap_or_default()),
+        };
+        let metrics = InferenceMetrics::from_durations(
+            usage.map(|u| u.prompt_tokens),
+            usage.map_or(1, |u| u.completion_tokens),
+            time_to_first_token,
+            timer.elapsed(),
+        );
+        
+        Ok(InferenceResponse {
+            tokens: vec![content],
+            finish_reason,
+            metrics,
+        })
+    }
+    
+    async fn chat_stream(
+        &self,
+        messages: &[ChatMessage],
+        params: &GenerationParams,
+        cancel: CancellationToken,
+    ) -> Result<InferenceStream> {
+        use futures::StreamExt;
+        
+        let (mut sender, stream) = InferenceStream::channel(cancel);
+        let resp = self
+            .client
+            .post(self.url("/chat"))
+            .json(&self.chat_body(messages, params, true)?)
+            .send()
+            .await?
+            .error_for_status()?;
+        
+        let mut body = resp.bytes_stream();
+        tokio::spawn(async move {
+            // 每行一个 JSON 对象，按字节行缓冲避免截断多字节字符
+            let mut buffer: Vec<u8> = Vec::new();
+            let mut finish_reason = FinishReason::Stop;
+            'read: loop {
+                // 取消时丢弃响应体，Ollama 检测到连接关闭后停止生成
+                let chunk = tokio::select! {
+                    chunk = body.next() => chunk,
+                    _ = sender.cancelled() => break,
+                };
+                let chunk = match chunk {
+                    Some(Ok(chunk)) => chunk,
+                    Some(Err(e)) => {
+                        tracing::warn!("Ollama stream interrupted: {}", e);
+                        finish_reason = FinishReason::Error;
+                        break;
+                    }
+                    None => break,
+                };
+                buffer.extend_from_slice(&chunk);
+                while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..=pos).collect();
+                    let Ok(value) = serde_json::from_slice::<serde_json::Value>(&line) else {
+                        continue;
+                    };
+                    if let Some(content) = value["message"]["content"].as_str().filter(|s| !s.is_empty())
+                        && !sender.send(content.to_string()).await
+                    {
+                        break 'read;
+                    }
+                    if value["done"].as_bool() == Some(true) {
+                        if let Some(usage) = ollama_usage(&value) {
+                            sender.set_usage(usage);
+                        }
+                        finish_reason = FinishReason::from_server(value["done_reason"].as_str().unwrap_or("stop"));
+                        break 'read;
+                    }
+                }
+            }
+            sender.finish(finish_reason).await;
+        });
+        
+        Ok(stream)
+    }
+    
+    fn backend_type(&self) -> crate::engine::BackendType {
+        crate::engine::BackendType::Ollama
+    }
+    
+    fn is_available(&self) -> bool {
+        self.available
+    }
+    
+    fn supports_constraint(&self, constraint: &OutputConstraint) -> bool {
+        !matches!(constraint, OutputConstraint::Grammar { .. })
+    }
+    
+    fn embedding_model(&self) -> Option<String> {
+        self.embedding_model.as_ref().map(|model| format!("ollama:{}/{}", self.base_url, model))
+    }
+    
+    /// /api/embeddings 每次请求只接受一条文本，按顺序逐条请求
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let Some(model) = &self.embedding_model else {
+            anyhow::bail!("No Ollama embedding model configured");
+        };
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for text in texts {
+            let value: serde_json::Value = self
+                .client
+                .post(self.url("/embeddings"))
+                .json(&serde_json::json!({ "model": model, "prompt": text }))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            let embedding = parse_vector(&value["embedding"]);
+            if embedding.is_empty() {
+                anyhow::bail!("Malformed embeddings response: {}", value);
+            }
+            embeddings.push(embedding);
+        }
+        Ok(embeddings)
+    }
+    
+    async fn health_check(&self) -> bool {
+        self.fetch_tags(Some(std::time::Duration::from_secs(2))).await.is_ok()
+    }
+    
+    async fn list_models(&self) -> Result<Vec<ModelDescriptor>> {
+        self.fetch_tags(None).await
+    }
+    
+    fn select_model(&mut self, model: &str) -> Result<()> {
+        self.model = Some(model.to_string());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// 读取一个完整的 HTTP 请求（请求头 + Content-Length 指定的请求体）
+    async fn read_request(socket: &mut TcpStream) -> String {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            let n = socket.read(&mut chunk).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+            let text = String::from_utf8_lossy(&buf);
+            if let Some(head_end) = text.find("\r\n\r\n") {
+                let content_length = text[..head_end]
+                    .lines()
+                    .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().to_string()))
+                    .and_then(|v| v.parse::<usize>().ok())
+                    .unwrap_or(0);
+                if buf.len() >= head_end + 4 + content_length {
+                    break;
+                }
+            }
+        }
+        String::from_utf8_lossy(&buf).into_owned()
+    }
+
+    /// 本地桩服务：模型列表、非流式补全，以及把 SSE 事件拆到多个 TCP 分块中的流式补全
+    async fn stub_server(requests: Arc<Mutex<Vec<String>>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let requests = requests.clone();
+                tokio::spawn(async move {
+                    let request = read_request(&mut socket).await;
+                    requests.lock().unwrap().push(request.clone());
+                    if request.starts_with("GET /v1/models") {
+                        let body = r#"{"data":[{"id":"first-model"},{"id":"second-model"}]}"#;
+                        let resp = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            body.len(),
+                            body
+                        );
+                        socket.write_all(resp.as_bytes()).await.unwrap();
+                    } else if request.contains("\"stream\":true") {
+                        socket
+                            .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nTransfer-Encoding: chunked\r\n\r\n")
+                            .await
+                            .unwrap();
+                        let events = concat!(
+                            "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+                            "data: {\"choices\":[{\"delta\":{\"content\":\"你\"}}]}\n\n",
+                            "data: {\"choices\":[{\"delta\":{\"content\":\"好 wor\"}}]}\n\n",
+                            "data: {\"choices\":[{\"delta\":{\"content\":\"ld\"},\"finish_reason\":\"length\"}]}\n\n",
+                            "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":3}}\n\n",
+                            "data: [DONE]\n\n",
+                        );
+                        // 在事件中间（包括多字节字符内部）切分，每块单独发送
+                        let bytes = events.as_bytes();
+                        for piece in bytes.chunks(13) {
+                            let mut frame = format!("{:x}\r\n", piece.len()).into_bytes();
+                            frame.extend_from_slice(piece);
+                            frame.extend_from_slice(b"\r\n");
+                            socket.write_all(&frame).await.unwrap();
+                            socket.flush().await.unwrap();
+                            tokio::time::sleep(Duration::from_millis(2)).await;
+                        }
+                        socket.write_all(b"0\r\n\r\n").await.unwrap();
+                    } else {
+                        let body = r#"{"choices":[{"message":{"role":"assistant","content":"hi there"},"finish_reason":"length"}],"usage":{"prompt_tokens":7,"completion_tokens":2}}"#;
+                        let resp = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            body.len(),
+                            body
+                        );
+                        socket.write_all(resp.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        format!("http://{}/v1", addr)
+    }
+
+    fn body_of(request: &str) -> serde_json::Value {
+        let (_, body) = request.split_once("\r\n\r\n").unwrap();
+        serde_json::from_str(body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn openai_chat_against_stub() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let url = stub_server(requests.clone()).await;
+        let mut backend = OpenAiCompatBackend::new(url).with_api_key("secret").with_model("second-model");
+        assert!(backend.probe().await);
+
+        let messages = vec![ChatMessage::user("hello")];
+        let resp = backend.chat(&messages, &GenerationParams::default()).await.unwrap();
+        assert_eq!(resp.tokens.join(""), "hi there");
+        assert_eq!(resp.finish_reason, FinishReason::Length);
+        assert_eq!(resp.metrics.prompt_tokens, Some(7));
+        assert_eq!(resp.metrics.completion_tokens, 2);
+
+        let requests = requests.lock().unwrap();
+        assert!(requests.iter().all(|r| r.to_ascii_lowercase().contains("authorization: bearer secret")));
+        let chat = requests.iter().find(|r| r.starts_with("POST /v1/chat/completions")).unwrap();
+        // 显式配置的模型优先于 /v1/models 中的第一个
+        assert_eq!(body_of(chat)["model"], "second-model");
+        assert_eq!(body_of(chat)["stream"], false);
+    }
+
+    #[tokio::test]
+    async fn openai_stream_reassembles_split_sse_frames() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let url = stub_server(requests.clone()).await;
+        let mut backend = OpenAiCompatBackend::new(url);
+        assert!(backend.probe().await);
+
+        let messages = vec![ChatMessage::user("hello")];
+        let mut stream = backend
+            .chat_stream(&messages, &GenerationParams::default(), CancellationToken::new())
+            .await
+            .unwrap();
+        let mut output = String::new();
+        while let Some(token) = stream.next_token().await {
+            output.push_str(&token);
+        }
+        assert_eq!(output, "你好 world");
+        assert_eq!(stream.finish_reason(), Some(FinishReason::Length));
+        let metrics = stream.metrics().unwrap();
+        assert_eq!(metrics.prompt_tokens, Some(5));
+        assert_eq!(metrics.completion_tokens, 3);
+
+        let requests = requests.lock().unwrap();
+        assert!(requests.iter().all(|r| !r.to_ascii_lowercase().contains("authorization:")));
+        let chat = requests.iter().find(|r| r.starts_with("POST /v1/chat/completions")).unwrap();
+        // 未配置模型时使用 /v1/models 返回的第一个
+        assert_eq!(body_of(chat)["model"], "first-model");
+        assert_eq!(body_of(chat)["stream_options"]["include_usage"], true);
+    }
+
+    fn http_json(body: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    }
+
+    /// Ollama 桩服务：/api/tags、/api/embeddings，以及按行切分到多个 TCP 分块中的 NDJSON 流式对话
+    async fn ollama_stub(requests: Arc<Mutex<Vec<String>>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let requests = requests.clone();
+                tokio::spawn(async move {
+                    let request = read_request(&mut socket).await;
+                    requests.lock().unwrap().push(request.clone());
+                    if request.starts_with("GET /api/tags") {
+                        let body = r#"{"models":[{"name":"llama3:8b","size":4661224676,"details":{"family":"llama","parameter_size":"8.0B","quantization_level":"Q4_0"}},{"name":"qwen2:7b"}]}"#;
+                        socket.write_all(http_json(body).as_bytes()).await.unwrap();
+                    } else if request.starts_with("POST /api/embeddings") {
+                        let prompt = body_of(&request)["prompt"].as_str().unwrap().chars().count();
+                        let body = format!(r#"{{"embedding":[{},0.5]}}"#, prompt);
+                        socket.write_all(http_json(&body).as_bytes()).await.unwrap();
+                    } else if request.contains("\"stream\":true") {
+                        socket
+                            .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nTransfer-Encoding: chunked\r\n\r\n")
+                            .await
+                            .unwrap();
+                        let lines = concat!(
+                            "{\"message\":{\"role\":\"assistant\",\"content\":\"你\"},\"done\":false}\n",
+                            "{\"message\":{\"role\":\"assistant\",\"content\":\"好 wor\"},\"done\":false}\n",
+                            "{\"message\":{\"role\":\"assistant\",\"content\":\"ld\"},\"done\":false}\n",
+                            "{\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true,\"done_reason\":\"length\",\"prompt_eval_count\":5,\"eval_count\":3}\n",
+                        );
+                        // 在行中间（包括多字节字符内部）切分，每块单独发送
+                        for piece in lines.as_bytes().chunks(11) {
+                            let mut frame = format!("{:x}\r\n", piece.len()).into_bytes();
+                            frame.extend_from_slice(piece);
+                            frame.extend_from_slice(b"\r\n");
+                            socket.write_all(&frame).await.unwrap();
+                            socket.flush().await.unwrap();
+                            tokio::time::sleep(Duration::from_millis(2)).await;
+                        }
+                        socket.write_all(b"0\r\n\r\n").await.unwrap();
+                    } else {
+                        let body = r#"{"message":{"role":"assistant","content":"hi there"},"done":true,"done_reason":"stop","prompt_eval_count":7,"eval_count":2,"load_duration":1000000,"prompt_eval_duration":2000000}"#;
+                        socket.write_all(http_json(body).as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn ollama_lists_tags_and_chats_against_stub() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let url = ollama_stub(requests.clone()).await;
+        let mut backend = OllamaBackend::new(format!("{}/", url));
+        backend
+            .initialize(InferenceConfig {
+                model_path: Default::default(),
+                backend: crate::engine::BackendType::Ollama,
+                context_size: 4096,
+            })
+            .await
+            .unwrap();
+        assert!(backend.is_available());
+
+        let models = backend.list_models().await.unwrap();
+        assert_eq!(models.len(), 2);
+        assert_eq!(models[0].name, "llama3:8b");
+        assert_eq!(models[0].size_bytes, Some(4661224676));
+        assert_eq!(models[0].family.as_deref(), Some("llama"));
+        assert_eq!(models[0].parameter_size.as_deref(), Some("8.0B"));
+        assert_eq!(models[0].quantization.as_deref(), Some("Q4_0"));
+        assert_eq!(models[1].family, None);
+
+        let params = GenerationParams {
+            max_tokens: Some(16),
+            constraint: Some(OutputConstraint::Json),
+            ..GenerationParams::default()
+        };
+        let resp = backend.chat(&[ChatMessage::user("hello")], &params).await.unwrap();
+        assert_eq!(resp.tokens, ["hi there"]);
+        assert_eq!(resp.finish_reason, FinishReason::Stop);
+        assert_eq!(resp.metrics.prompt_tokens, Some(7));
+        assert_eq!(resp.metrics.completion_tokens, 2);
+        // 首 token 时间 = 模型加载 + 提示词处理
+        assert_eq!(resp.metrics.time_to_first_token_ms, Some(3.0));
+
+        let requests = requests.lock().unwrap();
+        let chat = body_of(requests.iter().find(|r| r.starts_with("POST /api/chat")).unwrap());
+        // 未配置模型时使用 /api/tags 返回的第一个
+        assert_eq!(chat["model"], "llama3:8b");
+        assert_eq!(chat["stream"], false);
+        assert_eq!(chat["format"], "json");
+        assert_eq!(chat["options"]["num_ctx"], 4096);
+        assert_eq!(chat["options"]["num_predict"], 16);
+    }
+
+    #[tokio::test]
+    async fn ollama_stream_reassembles_split_ndjson_lines() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let url = ollama_stub(requests.clone()).await;
+        let mut backend = OllamaBackend::new(url).with_model("qwen2:7b");
+        assert!(backend.probe().await);
+
+        let mut stream = backend
+            .chat_stream(&[ChatMessage::user("hello")], &GenerationParams::default(), CancellationToken::new())
+            .await
+            .unwrap();
+        let mut output = String::new();
+        while let Some(token) = stream.next_token().await {
+            output.push_str(&token);
+        }
+        assert_eq!(output, "你好 world");
+        assert_eq!(stream.finish_reason(), Some(FinishReason::Length));
+        let metrics = stream.metrics().unwrap();
+        assert_eq!(metrics.prompt_tokens, Some(5));
+        assert_eq!(metrics.completion_tokens, 3);
+
+        let requests = requests.lock().unwrap();
+        let chat = body_of(requests.iter().find(|r| r.starts_with("POST /api/chat")).unwrap());
+        assert_eq!(chat["model"], "qwen2:7b");
+        assert_eq!(chat["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn ollama_embeds_one_prompt_per_request() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let url = ollama_stub(requests.clone()).await;
+        let backend = OllamaBackend::new(url.clone());
+        assert_eq!(backend.embedding_model(), None);
+        assert!(backend.embed(&["a".to_string()]).await.is_err());
+
+        let backend = backend.with_embedding_model("nomic-embed-text");
+        assert_eq!(backend.embedding_model(), Some(format!("ollama:{}/nomic-embed-text", url)));
+        let vectors = backend.embed(&["a".to_string(), "你好吗".to_string()]).await.unwrap();
+        assert_eq!(vectors, [vec![1.0, 0.5], vec![3.0, 0.5]]);
+
+        let requests = requests.lock().unwrap();
+        let bodies: Vec<serde_json::Value> = requests
+            .iter()
+            .filter(|r| r.starts_with("POST /api/embeddings"))
+            .map(|r| body_of(r))
+            .collect();
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(bodies[1], serde_json::json!({ "model": "nomic-embed-text", "prompt": "你好吗" }));
+    }
+
+    #[cfg(target_os = "linux")]
+    fn fake_mlx_backend() -> MlxBackend {
+        let script = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/sidecar/fake_sidecar.py");
+        let mut config = SidecarConfig::new("fake-mlx", "python3").with_args([script]);
+        config.ready_timeout = std::time::Duration::from_secs(5);
+        MlxBackend::with_sidecar(config)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn mlx_config(model_path: &str) -> InferenceConfig {
+        InferenceConfig {
+            model_path: model_path.into(),
+            backend: crate::engine::BackendType::MlxSidecar,
+            context_size: 512,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn mlx_backend_streams_from_sidecar() {
+        let mut backend = fake_mlx_backend();
+        assert!(backend.initialize(mlx_config("/models/bad")).await.is_err());
+        backend.initialize(mlx_config("/models/a")).await.unwrap();
+        assert!(backend.health_check().await);
+
+        let messages = vec![ChatMessage::user("one two three")];
+        let mut stream = backend
+            .chat_stream(&messages, &GenerationParams::default(), CancellationToken::new())
+            .await
+            .unwrap();
+        let mut tokens = Vec::new();
+        while let Some(token) = stream.next_token().await {
+            tokens.push(token);
+        }
+        assert_eq!(tokens, ["one ", "two ", "three "]);
+        assert_eq!(stream.finish_reason(), Some(FinishReason::Stop));
+        assert_eq!(stream.metrics().unwrap().prompt_tokens, Some(3));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn mlx_backend_renders_model_dir_template() {
+        let dir = std::env::temp_dir().join(format!("silo-mlx-model-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("config.json"), r#"{"model_type": "qwen2", "vocab_size": 151936}"#).unwrap();
+
+        let mut backend = fake_mlx_backend();
+        backend.initialize(mlx_config(dir.to_str().unwrap())).await.unwrap();
+        let resp = backend.chat(&[ChatMessage::user("hi")], &GenerationParams::default()).await.unwrap();
+        // 假侧车逐词回显提示词：没有 Jinja 模板时按 model_type 使用 Qwen 内置格式
+        let prompt = ChatFormat::Qwen.render(&[ChatMessage::user("hi")], true);
+        assert_eq!(resp.tokens.concat().split_whitespace().collect::<Vec<_>>(), prompt.split_whitespace().collect::<Vec<_>>());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn mlx_backend_cancels_generation() {
+        let mut backend = fake_mlx_backend();
+        backend.initialize(mlx_config("/models/a")).await.unwrap();
+
+        let words: Vec<String> = (0..100).map(|i| format!("w{}", i)).collect();
+        let messages = vec![ChatMessage::user(words.join(" "))];
+        let cancel = CancellationToken::new();
+        let mut stream = backend
+            .chat_stream(&messages, &GenerationParams::default(), cancel.clone())
+            .await
+            .unwrap();
+        assert_eq!(stream.next_token().await.as_deref(), Some("w0 "));
+        cancel.cancel();
+        let mut rest = 0;
+        while stream.next_token().await.is_some() {
+            rest += 1;
+        }
+        assert!(rest < 10, "generation kept running after cancel");
+        assert_eq!(stream.finish_reason(), Some(FinishReason::Cancelled));
+
+        // 侧车仍可处理后续请求
+        let resp = backend.chat(&[ChatMessage::user("still here")], &GenerationParams::default()).await.unwrap();
+        assert_eq!(resp.tokens.join(""), "still here ");
+    }
+}
@@ -0,0 +1,514 @@
+// 推理基准测试 - 用标准提示词集在本机实测各后端与模型的 TTFT、预填充与解码速度和内存峰值
+// 结果按机器保存为 JSON / Markdown 报告，检测后端时优先依据实测结果而不是按硬件型号推测
+
+use crate::engine::backend::InferenceBackend;
+use crate::engine::context::message_tokens;
+use crate::engine::hardware::{BackendSelection, HardwareProfile};
+use crate::engine::prefix_cache::{fnv1a, FNV_OFFSET};
+use crate::engine::tokenizer::Tokenizer;
+use crate::engine::{BackendType, ChatMessage, FinishReason, GenerationParams, InferenceMetrics, Priority};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sysinfo::{Pid, System};
+use tokio_util::sync::CancellationToken;
+
+/// 内存采样间隔
+const RSS_SAMPLE_INTERVAL: Duration = Duration::from_millis(50);
+/// 为对话模板（角色标记、特殊 token）预留的 token 数，避免提示词加生成超出上下文长度
+const TEMPLATE_MARGIN_TOKENS: usize = 64;
+
+/// 标准提示词：填充到目标长度的资料 + 一条指令
+#[derive(Debug, Clone, Copy)]
+pub struct BenchmarkPrompt {
+    pub name: &'static str,
+    pub instruction: &'static str,
+}
+
+/// 覆盖知识库问答、摘要与代码生成三类常见负载
+pub const STANDARD_PROMPTS: &[BenchmarkPrompt] = &[
+    BenchmarkPrompt {
+        name: "qa",
+        instruction: "根据以上资料回答：文中描述的流程包括哪些步骤？",
+    },
+    BenchmarkPrompt {
+        name: "summarize",
+        instruction: "请用三句话总结以上资料。",
+    },
+    BenchmarkPrompt {
+        name: "code",
+        instruction: "根据以上资料，用 Rust 写一个实现其中流程的函数。",
+    },
+];
+
+/// 填充资料的段落，循环使用直到达到目标长度
+const FILLER_PARAGRAPHS: &[&str] = &[
+    "系统启动时先读取配置文件，校验各项参数的取值范围，并为缺省项填入默认值。配置无效时记录错误并退出，避免带着错误的参数运行。",
+    "数据导入分为三个阶段：扫描目录中的文件，按类型解析为纯文本，再切分成固定长度的片段并计算向量，写入本地数据库。",
+    "检索时先把问题转换为向量，在数据库中找出最相似的若干片段，按相关度排序后与问题一起交给模型生成回答。",
+    "每个任务在执行前都会估算所需的内存与时间，超出限制的任务被拒绝，并向用户说明原因和可行的替代方案。",
+    "The scheduler keeps separate queues for interactive, agent and background work, so long-running batch jobs never delay a user who is waiting for an answer.",
+    "When a backend fails repeatedly it is disabled for a cooldown period, requests fall back to the next backend in the chain, and a periodic health check re-enables it once it recovers.",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BenchmarkConfig {
+    /// 目标上下文长度（提示词 + 生成），超出后端上下文长度的跳过
+    pub context_lengths: Vec<usize>,
+    /// 每次生成的 token 数
+    pub max_tokens: usize,
+    /// 使用的标准提示词（按名称），为空时全部使用
+    pub prompts: Vec<String>,
+}
+
+impl Default for BenchmarkConfig {
+    fn default() -> Self {
+        Self {
+            context_lengths: vec![512, 2048, 4096],
+            max_tokens: 64,
+            prompts: vec![],
+        }
+    }
+}
+
+impl BenchmarkConfig {
+    pub fn prompts(&self) -> Vec<BenchmarkPrompt> {
+        STANDARD_PROMPTS
+            .iter()
+            .filter(|p| self.prompts.is_empty() || self.prompts.iter().any(|name| name == p.name))
+            .copied()
+            .collect()
+    }
+}
+
+/// 被测对象：后端及其加载的模型
+#[derive(Debug, Clone)]
+pub struct BenchmarkTarget {
+    pub backend: BackendType,
+    pub model: String,
+    /// 后端的上下文长度
+    pub context_size: usize,
+}
+
+/// 一次测量的结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkResult {
+    pub backend: BackendType,
+    pub model: String,
+    pub prompt: String,
+    pub context_length: usize,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub ttft_ms: Option<f64>,
+    /// 提示词 token 数 / 首 token 延迟
+    pub prefill_tokens_per_second: Option<f64>,
+    pub decode_tokens_per_second: f64,
+    /// Silo 进程的常驻内存峰值；远程后端（Ollama / OpenAI 兼容服务）不含服务本身
+    pub peak_rss_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BenchmarkResult {
+    pub fn failed(target: &BenchmarkTarget, prompt: &BenchmarkPrompt, context_length: usize, error: impl ToString) -> Self {
+        Self {
+            backend: target.backend.clone(),
+            model: target.model.clone(),
+            prompt: prompt.name.to_string(),
+            context_length,
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            ttft_ms: None,
+            prefill_tokens_per_second: None,
+            decode_tokens_per_second: 0.0,
+            peak_rss_bytes: None,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// 一台机器上的基准测试报告
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkReport {
+    pub machine_id: String,
+    pub created_at: DateTime<Utc>,
+    pub hardware: HardwareProfile,
+    pub config: BenchmarkConfig,
+    pub results: Vec<BenchmarkResult>,
+}
+
+impl BenchmarkReport {
+    pub fn new(hardware: HardwareProfile, config: BenchmarkConfig, results: Vec<BenchmarkResult>) -> Self {
+        Self {
+            machine_id: machine_id(&hardware),
+            created_at: Utc::now(),
+            hardware,
+            config,
+            results,
+        }
+    }
+
+    /// 默认位置：dirs::data_dir()/silo/benchmarks
+    pub fn default_dir() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("silo")
+            .join("benchmarks")
+    }
+
+    /// 读取本机的报告（按机器标识命名），没有时返回 None
+    pub fn load(dir: &Path, profile: &HardwareProfile) -> Result<Option<Self>> {
+        let path = dir.join(format!("{}.json", machine_id(profile)));
+        if !path.exists() {
+            return Ok(None);
+        }
+        let report: Self = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+        Ok(Some(report))
+    }
+
+    /// 写入 <machine_id>.json 与 <machine_id>.md，覆盖本机之前的报告，返回 JSON 路径
+    pub fn save(&self, dir: &Path) -> Result<PathBuf> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.json", self.machine_id));
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        std::fs::write(path.with_extension("md"), self.to_markdown())?;
+        tracing::info!("Benchmark report saved to {:?}", path);
+        Ok(path)
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let hw = &self.hardware;
+        let gpus = if hw.gpus.is_empty() {
+            "无".to_string()
+        } else {
+            hw.gpus.iter().map(|g| g.name.as_str()).collect::<Vec<_>>().join("、")
+        };
+        let mut out = format!(
+            "# Silo 推理基准测试\n\n- 时间：{}\n- 机器：{}，{} 线程，{:.1} GiB 内存，GPU：{}\n- 机器标识：`{}`\n\n",
+            self.created_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            hw.cpu_brand,
+            hw.logical_cores,
+            hw.total_memory_bytes as f64 / (1024.0 * 1024.0 * 1024.0),
+            gpus,
+            self.machine_id
+        );
+        out.push_str("| 后端 | 模型 | 提示词 | 上下文 | 提示词 token | TTFT (ms) | 预填充 (tok/s) | 解码 (tok/s) | 峰值 RSS (MiB) |\n");
+        out.push_str("|---|---|---|---:|---:|---:|---:|---:|---:|\n");
+        let optional = |v: Option<f64>| v.map(|v| format!("{:.1}", v)).unwrap_or_else(|| "-".to_string());
+        for r in &self.results {
+            let row = match &r.error {
+                Some(error) => format!(
+                    "| {:?} | {} | {} | {} | 失败：{} | | | | |\n",
+                    r.backend,
+                    r.model,
+                    r.prompt,
+                    r.context_length,
+                    brief(error)
+                ),
+                None => format!(
+                    "| {:?} | {} | {} | {} | {} | {} | {} | {:.1} | {} |\n",
+                    r.backend,
+                    r.model,
+                    r.prompt,
+                    r.context_length,
+                    r.prompt_tokens,
+                    optional(r.ttft_ms),
+                    optional(r.prefill_tokens_per_second),
+                    r.decode_tokens_per_second,
+                    r.peak_rss_bytes.map(|b| (b / (1024 * 1024)).to_string()).unwrap_or_else(|| "-".to_string())
+                ),
+            };
+            out.push_str(&row);
+        }
+        out
+    }
+}
+
+/// 表格中的错误信息只保留第一行的开头
+fn brief(error: &str) -> String {
+    const MAX_CHARS: usize = 80;
+    let line = error.lines().next().unwrap_or_default();
+    let mut brief: String = line.chars().take(MAX_CHARS).collect();
+    if brief.len() < line.len() {
+        brief.push_str("...");
+    }
+    brief.replace('|', "\\|")
+}
+
+/// 机器标识：CPU、线程数、内存容量（GiB）、GPU 与系统架构的哈希；可用内存、系统版本等易变信息不计入
+pub fn machine_id(profile: &HardwareProfile) -> String {
+    let total_gib = profile.total_memory_bytes / (1024 * 1024 * 1024);
+    let mut hash = fnv1a(FNV_OFFSET, profile.os.as_bytes());
+    hash = fnv1a(hash, profile.arch.as_bytes());
+    hash = fnv1a(hash, profile.cpu_brand.as_bytes());
+    hash = fnv1a(hash, &(profile.logical_cores as u64).to_le_bytes());
+    hash = fnv1a(hash, &total_gib.to_le_bytes());
+    for gpu in &profile.gpus {
+        hash = fnv1a(hash, gpu.name.as_bytes());
+    }
+    format!("{:016x}", hash)
+}
+
+/// 按本机的基准测试结果选择加速后端：同一模型上实测解码速度最快的后端
+/// 只比较本机硬件可用的本地后端；报告不属于本机或比较的后端不足两个时返回 None（沿用按硬件的选择）
+pub fn select_backend(report: &BenchmarkReport, profile: &HardwareProfile) -> Option<BackendSelection> {
+    if report.machine_id != machine_id(profile) {
+        return None;
+    }
+    let eligible = |backend: &BackendType| match backend {
+        BackendType::MlxSidecar => profile.is_apple_silicon(),
+        BackendType::InferflowCpp => profile.best_nvidia_gpu().is_some(),
+        BackendType::LlamaCppCpu => true,
+        _ => false,
+    };
+    // 模型 → 后端 → (解码速度之和, 次数)
+    let mut by_model: HashMap<&str, HashMap<&BackendType, (f64, usize)>> = HashMap::new();
+    for r in report.results.iter().filter(|r| r.error.is_none() && eligible(&r.backend)) {
+        let entry = by_model.entry(&r.model).or_default().entry(&r.backend).or_default();
+        entry.0 += r.decode_tokens_per_second;
+        entry.1 += 1;
+    }
+    // 测过的后端最多的模型；数量相同时取名称靠前的，结果不随哈希顺序变化
+    let (model, backends) = by_model
+        .into_iter()
+        .max_by(|a, b| a.1.len().cmp(&b.1.len()).then_with(|| b.0.cmp(a.0)))?;
+    if backends.len() < 2 {
+        return None;
+    }
+    let (backend, speed) = backends
+        .into_iter()
+        .map(|(backend, (sum, n))| (backend, sum / n as f64))
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    Some(BackendSelection {
+        backend: backend.clone(),
+        reason: format!("benchmark on this machine: {:.1} tok/s decoding {} ({})", speed, model, report.created_at.date_naive()),
+    })
+}
+
+/// 构造约 prompt_tokens 个 token 的提示词
+/// 系统消息以随机标识开头，避免命中提示词前缀缓存而测不出预填充速度
+pub fn build_messages(prompt: &BenchmarkPrompt, prompt_tokens: usize, tokenizer: &dyn Tokenizer) -> Vec<ChatMessage> {
+    let system = ChatMessage::system(format!("基准测试 {}。请简洁地回答。", uuid::Uuid::new_v4().simple()));
+    let overhead = message_tokens(&system, tokenizer) + message_tokens(&ChatMessage::user(prompt.instruction), tokenizer) + 2;
+    let budget = prompt_tokens.saturating_sub(overhead);
+    let mut document = String::new();
+    let mut tokens = 0;
+    for (i, paragraph) in FILLER_PARAGRAPHS.iter().cycle().enumerate() {
+        if tokens >= budget {
+            break;
+        }
+        let paragraph = format!("第 {} 段：{}\n", i + 1, paragraph);
+        tokens += tokenizer.count_tokens(&paragraph);
+        document.push_str(&paragraph);
+    }
+    let document = tokenizer.truncate_to_tokens(&document, budget);
+    vec![system, ChatMessage::user(format!("{}\n\n{}", document.trim_end(), prompt.instruction))]
+}
+
+/// 运行一次测量：贪心解码生成 max_tokens 个 token，期间采样进程内存
+pub async fn run(
+    backend: &dyn InferenceBackend,
+    target: &BenchmarkTarget,
+    prompt: &BenchmarkPrompt,
+    context_length: usize,
+    max_tokens: usize,
+    tokenizer: &dyn Tokenizer,
+    cancel: CancellationToken,
+) -> BenchmarkResult {
+    let messages = build_messages(prompt, context_length.saturating_sub(max_tokens + TEMPLATE_MARGIN_TOKENS), tokenizer);
+    let params = GenerationParams {
+        max_tokens: Some(max_tokens),
+        priority: Priority::Background,
+        ..GenerationParams::deterministic()
+    };
+    let sampler = RssSampler::start();
+    let started = Instant::now();
+    let measured = async {
+        let mut stream = backend.chat_stream(&messages, &params, cancel).await?;
+        let mut first_token = None;
+        while stream.next_token().await.is_some() {
+            first_token.get_or_insert_with(|| started.elapsed());
+        }
+        match stream.finish_reason() {
+            Some(FinishReason::Error) | None => anyhow::bail!("Generation failed"),
+            Some(FinishReason::Cancelled) => anyhow::bail!("Benchmark cancelled"),
+            _ => {}
+        }
+        Ok((first_token, started.elapsed(), stream.metrics().cloned().unwrap_or_default()))
+    }
+    .await;
+    let peak_rss_bytes = sampler.finish().await;
+
+    let (first_token, total, metrics) = match measured {
+        Ok(measured) => measured,
+        Err(e) => return BenchmarkResult::failed(target, prompt, context_length, e),
+    };
+    let prompt_tokens = metrics
+        .prompt_tokens
+        .unwrap_or_else(|| messages.iter().map(|m| message_tokens(m, tokenizer)).sum());
+    // 统一用本地计时计算速度，各后端自报的指标口径不一
+    let timing = InferenceMetrics::from_durations(Some(prompt_tokens), metrics.completion_tokens, first_token, total);
+    BenchmarkResult {
+        backend: target.backend.clone(),
+        model: target.model.clone(),
+        prompt: prompt.name.to_string(),
+        context_length,
+        prompt_tokens,
+        completion_tokens: metrics.completion_tokens,
+        ttft_ms: timing.time_to_first_token_ms,
+        prefill_tokens_per_second: first_token
+            .filter(|t| !t.is_zero())
+            .map(|t| prompt_tokens as f64 / t.as_secs_f64()),
+        decode_tokens_per_second: timing.tokens_per_second,
+        peak_rss_bytes,
+        error: None,
+    }
+}
+
+/// 后台定期采样本进程的常驻内存，记录峰值
+struct RssSampler {
+    peak: Arc<AtomicU64>,
+    stop: CancellationToken,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl RssSampler {
+    fn start() -> Self {
+        let peak = Arc::new(AtomicU64::new(0));
+        let stop = CancellationToken::new();
+        let task = {
+            let peak = peak.clone();
+            let stop = stop.clone();
+            tokio::spawn(async move {
+                let pid = Pid::from_u32(std::process::id());
+                let mut sys = System::new();
+                loop {
+                    if sys.refresh_process(pid)
+                        && let Some(process) = sys.process(pid)
+                    {
+                        peak.fetch_max(process.memory(), Ordering::Relaxed);
+                    }
+                    tokio::select! {
+                        _ = stop.cancelled() => break,
+                        _ = tokio::time::sleep(RSS_SAMPLE_INTERVAL) => {}
+                    }
+                }
+            })
+        };
+        Self { peak, stop, task }
+    }
+
+    /// 停止采样；平台不支持读取进程内存时返回 None
+    async fn finish(self) -> Option<u64> {
+        self.stop.cancel();
+        let _ = self.task.await;
+        Some(self.peak.load(Ordering::Relaxed)).filter(|&peak| peak > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(json: &str) -> HardwareProfile {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn nvidia() -> HardwareProfile {
+        fixture(include_str!("../../fixtures/hardware/nvidia_rtx4090.json"))
+    }
+
+    fn result(backend: BackendType, model: &str, decode_tokens_per_second: f64) -> BenchmarkResult {
+        BenchmarkResult {
+            decode_tokens_per_second,
+            error: None,
+            ..BenchmarkResult::failed(
+                &BenchmarkTarget {
+                    backend,
+                    model: model.to_string(),
+                    context_size: 4096,
+                },
+                &STANDARD_PROMPTS[0],
+                512,
+                "",
+            )
+        }
+    }
+
+    #[test]
+    fn machine_id_ignores_volatile_fields() {
+        let profile = nvidia();
+        let id = machine_id(&profile);
+        assert_eq!(id.len(), 16);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+
+        let mut same = profile.clone();
+        same.available_memory_bytes = 1;
+        same.os_version = None;
+        same.total_memory_bytes += 1024 * 1024;
+        assert_eq!(machine_id(&same), id);
+
+        let mut other = profile.clone();
+        other.gpus.clear();
+        assert_ne!(machine_id(&other), id);
+        let mut other = profile;
+        other.total_memory_bytes *= 2;
+        assert_ne!(machine_id(&other), id);
+    }
+
+    #[test]
+    fn selects_fastest_eligible_backend_from_report() {
+        let profile = nvidia();
+        let mut failed = result(BackendType::InferflowCpp, "m", 500.0);
+        failed.error = Some("out of memory".to_string());
+        let report = BenchmarkReport::new(
+            profile.clone(),
+            BenchmarkConfig::default(),
+            vec![
+                result(BackendType::LlamaCppCpu, "m", 10.0),
+                result(BackendType::LlamaCppCpu, "m", 12.0),
+                result(BackendType::InferflowCpp, "m", 30.0),
+                // 失败的测量与本机不可用的后端不参与比较
+                failed,
+                result(BackendType::MlxSidecar, "m", 100.0),
+                result(BackendType::Ollama, "m", 200.0),
+            ],
+        );
+        let selection = select_backend(&report, &profile).unwrap();
+        assert_eq!(selection.backend, BackendType::InferflowCpp);
+        assert!(selection.reason.contains("30.0 tok/s decoding m"), "{}", selection.reason);
+
+        // 没有 NVIDIA GPU 的机器上只剩一个可比较的后端
+        let cpu_only = fixture(include_str!("../../fixtures/hardware/cpu_only.json"));
+        let report = BenchmarkReport { hardware: cpu_only.clone(), machine_id: machine_id(&cpu_only), ..report };
+        assert!(select_backend(&report, &cpu_only).is_none());
+
+        // 其他机器的报告不采用
+        assert!(select_backend(&report, &profile).is_none());
+    }
+
+    #[test]
+    fn prefers_model_with_most_backends_deterministically() {
+        let profile = nvidia();
+        let report = BenchmarkReport::new(
+            profile.clone(),
+            BenchmarkConfig::default(),
+            vec![
+                result(BackendType::LlamaCppCpu, "b", 50.0),
+                result(BackendType::InferflowCpp, "b", 10.0),
+                result(BackendType::LlamaCppCpu, "a", 10.0),
+                result(BackendType::InferflowCpp, "a", 50.0),
+                result(BackendType::LlamaCppCpu, "c", 99.0),
+            ],
+        );
+        for _ in 0..20 {
+            assert_eq!(select_backend(&report, &profile).unwrap().backend, BackendType::InferflowCpp);
+        }
+    }
+}
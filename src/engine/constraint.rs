@@ -0,0 +1,313 @@
+// 输出约束 - 把 OutputConstraint 转换为 GBNF 语法并校验生成结果
+// 格式要求总是写入系统提示词；支持约束解码的后端在采样时直接排除不合法的 token，
+// 其他后端校验输出，失败时把错误反馈给模型重试
+
+use crate::engine::backend::InferenceBackend;
+use crate::engine::grammar::Grammar;
+use crate::engine::json_schema;
+use crate::engine::{
+    ChatMessage, ChatRole, FinishReason, GenerationParams, InferenceResponse, InferenceStream, OutputConstraint,
+    TokenUsage,
+};
+use anyhow::Result;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+/// 不支持约束解码的后端默认最多生成几次（见 GenerationParams::constraint_attempts）
+pub const MAX_CONSTRAINED_ATTEMPTS: usize = 3;
+
+impl OutputConstraint {
+    /// 等价的 GBNF 语法
+    pub fn to_gbnf(&self) -> Result<String> {
+        match self {
+            OutputConstraint::Json => Ok(json_schema::json_gbnf()),
+            OutputConstraint::JsonSchema { schema } => json_schema::schema_to_gbnf(schema),
+            OutputConstraint::Grammar { gbnf } => Ok(gbnf.clone()),
+        }
+    }
+
+    pub fn grammar(&self) -> Result<Arc<Grammar>> {
+        Grammar::parse(&self.to_gbnf()?)
+    }
+
+    /// 校验完整输出，返回规范化后的文本（JSON 约束会去掉 Markdown 代码块围栏与首尾空白）
+    pub fn validate(&self, text: &str) -> Result<String> {
+        match self {
+            OutputConstraint::Json | OutputConstraint::JsonSchema { .. } => {
+                let json = strip_code_fence(text);
+                let value: serde_json::Value =
+                    serde_json::from_str(json).map_err(|e| anyhow::anyhow!("Output is not valid JSON: {}", e))?;
+                if let OutputConstraint::JsonSchema { schema } = self {
+                    json_schema::validate(schema, &value)
+                        .map_err(|e| anyhow::anyhow!("Output does not match the JSON schema: {}", e))?;
+                }
+                Ok(json.to_string())
+            }
+            OutputConstraint::Grammar { .. } => {
+                let grammar = self.grammar()?;
+                [text, text.trim()]
+                    .into_iter()
+                    .find(|candidate| grammar.matches(candidate))
+                    .map(str::to_string)
+                    .ok_or_else(|| anyhow::anyhow!("Output does not match the grammar"))
+            }
+        }
+    }
+
+    /// 格式说明（追加到系统提示词）；约束解码只保证输出合法，模型仍需知道该输出什么
+    pub fn instruction(&self) -> String {
+        match self {
+            OutputConstraint::Json => "只输出一个合法的 JSON 值，不要输出任何解释或 Markdown 代码块。".to_string(),
+            OutputConstraint::JsonSchema { schema } => format!(
+                "只输出一个符合以下 JSON Schema 的 JSON 值，不要输出任何解释或 Markdown 代码块：\n{}",
+                serde_json::to_string_pretty(schema).unwrap_or_default()
+            ),
+            OutputConstraint::Grammar { gbnf } => {
+                format!("输出必须严格符合以下 GBNF 语法（入口规则为 root），不要输出任何其他内容：\n{}", gbnf)
+            }
+        }
+    }
+}
+
+/// 去掉包裹输出的 ```json 代码块围栏
+fn strip_code_fence(text: &str) -> &str {
+    let text = text.trim();
+    let Some(inner) = text.strip_prefix("```") else { return text };
+    let inner = inner.trim_start_matches(|c: char| c.is_ascii_alphanumeric());
+    inner.strip_suffix("```").unwrap_or(inner).trim()
+}
+
+/// 在系统提示词中追加格式说明
+fn with_instruction(messages: &[ChatMessage], constraint: &OutputConstraint) -> Vec<ChatMessage> {
+    let mut messages = messages.to_vec();
+    match messages.first_mut() {
+        Some(first) if first.role == ChatRole::System => {
+            first.content = format!("{}\n\n{}", first.content, constraint.instruction());
+        }
+        _ => messages.insert(0, ChatMessage::system(constraint.instruction())),
+    }
+    messages
+}
+
+/// 带输出约束的对话：后端支持约束解码时直接生成，否则校验输出并在失败时带上错误信息重试
+pub async fn chat(
+    backend: &dyn InferenceBackend,
+    messages: &[ChatMessage],
+    params: &GenerationParams,
+) -> Result<InferenceResponse> {
+    let Some(constraint) = &params.constraint else {
+        return backend.chat(messages, params).await;
+    };
+    // 原生约束也校验一次：推理服务可能忽略 response_format，输出也可能被 max_tokens 截断
+    let native = backend.supports_constraint(constraint);
+    let mut messages = with_instruction(messages, constraint);
+
+    let attempts = if native { 1 } else { params.constraint_attempts.max(1) };
+    let mut last_error = None;
+    for attempt in 1..=attempts {
+        let response = backend.chat(&messages, params).await?;
+        if response.finish_reason == FinishReason::Cancelled {
+            return Ok(response);
+        }
+        let text = response.tokens.concat();
+        match constraint.validate(&text) {
+            Ok(output) => {
+                return Ok(InferenceResponse {
+                    tokens: vec![output],
+                    ..response
+                });
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Constrained output from {:?} rejected (attempt {}/{}): {}",
+                    backend.backend_type(),
+                    attempt,
+                    attempts,
+                    e
+                );
+                messages.push(ChatMessage::assistant(text));
+                messages.push(ChatMessage::user(format!(
+                    "上面的输出不符合格式要求：{}。请重新输出，只包含符合要求的内容。",
+                    e
+                )));
+                last_error = Some(e);
+            }
+        }
+    }
+    let error = last_error.unwrap_or_else(|| anyhow::anyhow!("no output"));
+    anyhow::bail!("Constrained generation failed after {} attempt(s): {}", attempts, error)
+}
+
+/// 带输出约束的流式对话：后端支持约束解码时直接流式输出；
+/// 否则需要先拿到完整输出才能校验，校验通过后一次性发送
+pub async fn chat_stream(
+    backend: &dyn InferenceBackend,
+    messages: &[ChatMessage],
+    params: &GenerationParams,
+    cancel: CancellationToken,
+) -> Result<InferenceStream> {
+    match &params.constraint {
+        None => return backend.chat_stream(messages, params, cancel).await,
+        Some(constraint) if backend.supports_constraint(constraint) => {
+            return backend.chat_stream(&with_instruction(messages, constraint), params, cancel).await;
+        }
+        Some(_) => {}
+    }
+
+    let (mut sender, stream) = InferenceStream::channel(cancel.clone());
+    let response = tokio::select! {
+        response = chat(backend, messages, params) => response?,
+        _ = cancel.cancelled() => {
+            sender.finish(FinishReason::Cancelled).await;
+            return Ok(stream);
+        }
+    };
+    if let Some(prompt_tokens) = response.metrics.prompt_tokens {
+        sender.set_usage(TokenUsage {
+            prompt_tokens,
+            completion_tokens: response.metrics.completion_tokens,
+        });
+    }
+    sender.send(response.tokens.concat()).await;
+    sender.finish(response.finish_reason).await;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{BackendType, InferenceConfig};
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    /// 依次返回预设回复，并记录每次收到的对话
+    struct FakeBackend {
+        replies: Mutex<Vec<&'static str>>,
+        requests: Mutex<Vec<Vec<ChatMessage>>>,
+        native: bool,
+    }
+
+    impl FakeBackend {
+        fn new(replies: &[&'static str], native: bool) -> Self {
+            Self {
+                replies: Mutex::new(replies.to_vec()),
+                requests: Mutex::new(Vec::new()),
+                native,
+            }
+        }
+
+        fn calls(&self) -> usize {
+            self.requests.lock().unwrap().len()
+        }
+    }
+
+    #[async_trait]
+    impl InferenceBackend for FakeBackend {
+        async fn initialize(&mut self, _config: InferenceConfig) -> Result<()> {
+            Ok(())
+        }
+
+        async fn chat(&self, messages: &[ChatMessage], _params: &GenerationParams) -> Result<InferenceResponse> {
+            self.requests.lock().unwrap().push(messages.to_vec());
+            let reply = self.replies.lock().unwrap().remove(0);
+            Ok(InferenceResponse {
+                tokens: vec![reply.to_string()],
+                finish_reason: FinishReason::Stop,
+                metrics: Default::default(),
+            })
+        }
+
+        async fn chat_stream(
+            &self,
+            _messages: &[ChatMessage],
+            _params: &GenerationParams,
+            _cancel: CancellationToken,
+        ) -> Result<InferenceStream> {
+            anyhow::bail!("not used")
+        }
+
+        fn backend_type(&self) -> BackendType {
+            BackendType::Scripted
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+
+        fn supports_constraint(&self, _constraint: &OutputConstraint) -> bool {
+            self.native
+        }
+    }
+
+    fn params(attempts: usize) -> GenerationParams {
+        GenerationParams {
+            constraint: Some(OutputConstraint::Json),
+            constraint_attempts: attempts,
+            ..GenerationParams::default()
+        }
+    }
+
+    #[test]
+    fn validate_normalizes_output() {
+        assert_eq!(OutputConstraint::Json.validate("```json\n{\"a\": 1}\n```").unwrap(), "{\"a\": 1}");
+        assert!(OutputConstraint::Json.validate("{a: 1}").is_err());
+        let schema = OutputConstraint::JsonSchema {
+            schema: serde_json::json!({ "type": "object", "required": ["a"] }),
+        };
+        let err = schema.validate("{}").unwrap_err().to_string();
+        assert!(err.contains("missing required property \"a\""), "{}", err);
+        let grammar = OutputConstraint::Grammar { gbnf: r#"root ::= "yes" | "no""#.into() };
+        assert_eq!(grammar.validate(" yes\n").unwrap(), "yes");
+        assert!(grammar.validate("maybe").is_err());
+    }
+
+    #[tokio::test]
+    async fn retries_with_feedback_until_output_is_valid() {
+        let backend = FakeBackend::new(&["not json", "{\"ok\": true}"], false);
+        let messages = [ChatMessage::system("你是助手"), ChatMessage::user("hi")];
+        let response = chat(&backend, &messages, &params(3)).await.unwrap();
+        assert_eq!(response.tokens, ["{\"ok\": true}"]);
+        assert_eq!(backend.calls(), 2);
+
+        let requests = backend.requests.lock().unwrap();
+        // 格式说明追加到已有的系统提示词，重试时带上上一次的输出与错误
+        assert_eq!(requests[0].len(), 2);
+        assert!(requests[0][0].content.starts_with("你是助手\n\n"));
+        let retry = &requests[1];
+        assert_eq!(retry.len(), 4);
+        assert_eq!(retry[2].content, "not json");
+        assert!(retry[3].content.contains("Output is not valid JSON"), "{}", retry[3].content);
+    }
+
+    #[tokio::test]
+    async fn stops_after_constraint_attempts() {
+        let backend = FakeBackend::new(&["a", "b", "c", "d"], false);
+        let err = chat(&backend, &[ChatMessage::user("hi")], &params(2)).await.unwrap_err();
+        assert!(err.to_string().contains("after 2 attempt(s)"), "{}", err);
+        assert_eq!(backend.calls(), 2);
+
+        // 0 次按 1 次处理
+        let backend = FakeBackend::new(&["a", "b"], false);
+        assert!(chat(&backend, &[ChatMessage::user("hi")], &params(0)).await.is_err());
+        assert_eq!(backend.calls(), 1);
+
+        // 原生约束只生成一次，仍然校验输出
+        let backend = FakeBackend::new(&["a", "b"], true);
+        let err = chat(&backend, &[ChatMessage::user("hi")], &params(3)).await.unwrap_err();
+        assert!(err.to_string().contains("after 1 attempt(s)"), "{}", err);
+        assert_eq!(backend.calls(), 1);
+        // 插入的系统提示词包含格式说明
+        assert_eq!(backend.requests.lock().unwrap()[0][0].role, ChatRole::System);
+    }
+
+    #[tokio::test]
+    async fn stream_sends_validated_output_at_once() {
+        let backend = FakeBackend::new(&["```\n[1, 2]\n```"], false);
+        let stream = chat_stream(&backend, &[ChatMessage::user("hi")], &params(3), CancellationToken::new())
+            .await
+            .unwrap();
+        let response = stream.collect().await;
+        assert_eq!(response.tokens, ["[1, 2]"]);
+        assert_eq!(response.finish_reason, FinishReason::Stop);
+    }
+}
@@ -0,0 +1,460 @@
+// 上下文窗口管理 - 提示词与历史对话超出模型上下文长度时按策略裁剪，而不是交给后端报错
+// 裁剪结果附带报告，让用户知道助手为什么"忘记"了之前的内容
+
+use crate::engine::tokenizer::Tokenizer;
+use crate::engine::{ChatMessage, ChatRole};
+use serde::{Deserialize, Serialize};
+
+/// 每条消息在对话模板中的额外开销（角色标记、分隔符等）
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+/// 报告中被裁剪内容的预览长度
+const PREVIEW_TOKENS: usize = 32;
+
+/// 超出上下文长度时的处理策略
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextPolicy {
+    /// 不裁剪，超长时由后端报错
+    Disabled,
+    /// 从最早的对话轮次开始丢弃
+    #[default]
+    DropOldest,
+    /// 较早的对话轮次由模型压缩成摘要
+    Summarize,
+    /// 先丢弃相关度最低的检索片段，仍超出时再丢弃最早的轮次
+    EvictRetrieved,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContextConfig {
+    pub policy: ContextPolicy,
+    /// 请求未指定 max_tokens 时为生成预留的 token 数
+    pub reserve_tokens: usize,
+    /// 摘要最多占用的 token 数
+    pub summary_tokens: usize,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            policy: ContextPolicy::DropOldest,
+            reserve_tokens: 512,
+            summary_tokens: 256,
+        }
+    }
+}
+
+/// 消息被裁剪的原因
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DropReason {
+    /// 最早的对话轮次
+    OldestTurn,
+    /// 相关度最低的检索片段
+    LowRelevance { score: f32 },
+    /// 已压缩进摘要
+    Summarized,
+    /// 单条消息本身超出上限，只保留开头
+    Truncated { kept_tokens: usize },
+}
+
+/// 被裁剪的消息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DroppedMessage {
+    pub role: ChatRole,
+    /// 被裁掉的 token 数
+    pub tokens: usize,
+    /// 内容开头，便于用户辨认
+    pub preview: String,
+    pub reason: DropReason,
+}
+
+/// 一次裁剪的报告
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContextReport {
+    pub policy: ContextPolicy,
+    /// 提示词可用的 token 数（上下文长度减去生成预留）
+    pub budget: usize,
+    pub tokens_before: usize,
+    pub tokens_after: usize,
+    pub dropped: Vec<DroppedMessage>,
+    /// 代替较早轮次的摘要
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+impl ContextReport {
+    pub fn is_trimmed(&self) -> bool {
+        !self.dropped.is_empty()
+    }
+
+    /// 面向用户的说明
+    pub fn describe(&self) -> String {
+        let count = |f: fn(&DropReason) -> bool| self.dropped.iter().filter(|d| f(&d.reason)).count();
+        let mut parts = vec![];
+        let summarized = count(|r| matches!(r, DropReason::Summarized));
+        if summarized > 0 {
+            parts.push(format!("{} 条较早的消息已压缩为摘要", summarized));
+        }
+        let oldest = count(|r| matches!(r, DropReason::OldestTurn));
+        if oldest > 0 {
+            parts.push(format!("省略了 {} 条较早的消息", oldest));
+        }
+        let retrieved = count(|r| matches!(r, DropReason::LowRelevance { .. }));
+        if retrieved > 0 {
+            parts.push(format!("省略了 {} 个相关度较低的检索片段", retrieved));
+        }
+        let truncated = count(|r| matches!(r, DropReason::Truncated { .. }));
+        if truncated > 0 {
+            parts.push(format!("截断了 {} 条过长的消息", truncated));
+        }
+        format!(
+            "对话超出模型上下文长度（{} / {} token），{}",
+            self.tokens_before,
+            self.budget,
+            parts.join("，")
+        )
+    }
+}
+
+/// 裁剪结果
+#[derive(Debug, Clone)]
+pub struct ContextFit {
+    pub messages: Vec<ChatMessage>,
+    pub report: ContextReport,
+    /// Summarize 策略下需要压缩成摘要的消息（按原顺序）
+    pub summarized: Vec<ChatMessage>,
+}
+
+impl ContextFit {
+    /// 插入摘要：并入第一条系统消息，没有系统消息时作为新的系统消息放在最前面
+    /// 摘要生成失败（None）时这些消息视为直接丢弃
+    pub fn apply_summary(&mut self, summary: Option<String>, tokenizer: &dyn Tokenizer) {
+        let Some(summary) = summary.map(|s| s.trim().to_string()).filter(|s| !s.is_empty()) else {
+            for dropped in &mut self.report.dropped {
+                if dropped.reason == DropReason::Summarized {
+                    dropped.reason = DropReason::OldestTurn;
+                }
+            }
+            return;
+        };
+        let text = format!("此前对话的摘要：\n{}", summary);
+        match self.messages.first_mut().filter(|m| m.role == ChatRole::System) {
+            Some(system) => {
+                self.report.tokens_after += tokenizer.count_tokens(&text);
+                system.content = format!("{}\n\n{}", system.content, text);
+            }
+            None => {
+                let message = ChatMessage::system(text);
+                self.report.tokens_after += message_tokens(&message, tokenizer);
+                self.messages.insert(0, message);
+            }
+        }
+        self.report.summary = Some(summary);
+    }
+}
+
+/// 消息在提示词中占用的 token 数
+pub fn message_tokens(message: &ChatMessage, tokenizer: &dyn Tokenizer) -> usize {
+    tokenizer.count_tokens(&message.content) + MESSAGE_OVERHEAD_TOKENS
+}
+
+/// 将消息裁剪到 budget 以内
+///
+/// 开头的系统消息与最后一条用户消息始终保留；其余消息按策略丢弃，
+/// 都丢弃后仍超出时截断保留消息中最长的一条。
+/// Summarize 策略为摘要预留 summary_tokens，摘要由调用方生成后通过 apply_summary 插入
+pub fn fit(
+    messages: &[ChatMessage],
+    budget: usize,
+    policy: ContextPolicy,
+    summary_tokens: usize,
+    tokenizer: &dyn Tokenizer,
+) -> ContextFit {
+    let tokens: Vec<usize> = messages.iter().map(|m| message_tokens(m, tokenizer)).collect();
+    let total: usize = tokens.iter().sum();
+    let report = ContextReport {
+        policy,
+        budget,
+        tokens_before: total,
+        tokens_after: total,
+        dropped: vec![],
+        summary: None,
+    };
+    if total <= budget || policy == ContextPolicy::Disabled {
+        return ContextFit {
+            messages: messages.to_vec(),
+            report,
+            summarized: vec![],
+        };
+    }
+
+    let mut trim = Trim {
+        messages,
+        tokens,
+        keep: vec![true; messages.len()],
+        used: total,
+        report,
+        tokenizer,
+    };
+
+    // 检索片段按相关度从低到高排列
+    let mut retrieved: Vec<(usize, f32)> = messages
+        .iter()
+        .enumerate()
+        .filter_map(|(i, m)| Some((i, m.relevance?)))
+        .collect();
+    retrieved.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+    if policy == ContextPolicy::EvictRetrieved {
+        trim.evict_retrieved(&retrieved, budget);
+    }
+
+    // 按轮次丢弃最早的对话；Summarize 为摘要留出空间
+    let (turn_budget, turn_reason) = match policy {
+        ContextPolicy::Summarize => (budget.saturating_sub(summary_tokens), DropReason::Summarized),
+        _ => (budget, DropReason::OldestTurn),
+    };
+    let mut summarized = vec![];
+    for turn in turns(messages) {
+        if trim.used <= turn_budget {
+            break;
+        }
+        for i in turn {
+            if turn_reason == DropReason::Summarized {
+                summarized.push(messages[i].clone());
+            }
+            trim.discard(i, turn_reason);
+        }
+    }
+
+    trim.evict_retrieved(&retrieved, budget);
+
+    let Trim { keep, mut used, mut report, .. } = trim;
+    let mut kept: Vec<ChatMessage> = messages
+        .iter()
+        .zip(&keep)
+        .filter(|(_, keep)| **keep)
+        .map(|(m, _)| m.clone())
+        .collect();
+
+    // 保留的消息本身就超出上限：截断最长的一条
+    if used > budget
+        && let Some(message) = kept.iter_mut().max_by_key(|m| m.content.len())
+    {
+        let before = message_tokens(message, tokenizer);
+        let max_tokens = before.saturating_sub(used - budget).saturating_sub(MESSAGE_OVERHEAD_TOKENS);
+        let original = std::mem::take(&mut message.content);
+        message.content = tokenizer.truncate_to_tokens(&original, max_tokens);
+        let after = message_tokens(message, tokenizer);
+        used = used - before + after;
+        report.dropped.push(DroppedMessage {
+            role: message.role,
+            tokens: before - after,
+            preview: preview(&original[message.content.len()..], tokenizer),
+            reason: DropReason::Truncated { kept_tokens: after },
+        });
+    }
+
+    report.tokens_after = used;
+    ContextFit {
+        messages: kept,
+        report,
+        summarized,
+    }
+}
+
+/// 裁剪过程中的状态
+struct Trim<'a> {
+    messages: &'a [ChatMessage],
+    tokens: Vec<usize>,
+    keep: Vec<bool>,
+    used: usize,
+    report: ContextReport,
+    tokenizer: &'a dyn Tokenizer,
+}
+
+impl Trim<'_> {
+    fn discard(&mut self, index: usize, reason: DropReason) {
+        if !self.keep[index] {
+            return;
+        }
+        self.keep[index] = false;
+        self.used -= self.tokens[index];
+        self.report.dropped.push(DroppedMessage {
+            role: self.messages[index].role,
+            tokens: self.tokens[index],
+            preview: preview(&self.messages[index].content, self.tokenizer),
+            reason,
+        });
+    }
+
+    /// 按相关度从低到高丢弃检索片段，直到不超出 budget
+    fn evict_retrieved(&mut self, retrieved: &[(usize, f32)], budget: usize) {
+        for &(index, score) in retrieved {
+            if self.used <= budget {
+                break;
+            }
+            self.discard(index, DropReason::LowRelevance { score });
+        }
+    }
+}
+
+/// 可丢弃的对话轮次（从旧到新）：每轮从一条用户消息开始，包括其后的回复与工具输出
+/// 开头的系统消息、最后一条用户消息及其之后的消息、检索片段不在其中
+fn turns(messages: &[ChatMessage]) -> Vec<Vec<usize>> {
+    let start = messages.iter().take_while(|m| m.role == ChatRole::System).count();
+    let end = messages.iter().rposition(|m| m.role == ChatRole::User).unwrap_or(messages.len());
+    let mut turns: Vec<Vec<usize>> = vec![];
+    for (i, message) in messages.iter().enumerate().take(end).skip(start) {
+        if message.relevance.is_some() {
+            continue;
+        }
+        match turns.last_mut() {
+            Some(turn) if message.role != ChatRole::User => turn.push(i),
+            _ => turns.push(vec![i]),
+        }
+    }
+    turns
+}
+
+/// 生成摘要的请求；对话内容超出 max_input_tokens 时保留开头
+pub fn summary_request(messages: &[ChatMessage], max_input_tokens: usize, tokenizer: &dyn Tokenizer) -> Vec<ChatMessage> {
+    let transcript = messages
+        .iter()
+        .map(|m| {
+            let speaker = match m.role {
+                ChatRole::System => "系统",
+                ChatRole::User => "用户",
+                ChatRole::Assistant => "助手",
+                ChatRole::Tool => "工具",
+            };
+            format!("{}：{}", speaker, m.content)
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    vec![
+        ChatMessage::system("请将以下对话压缩成简短的摘要，保留事实、结论、用户的偏好和尚未完成的事项，不要添加对话中没有的内容。"),
+        ChatMessage::user(tokenizer.truncate_to_tokens(&transcript, max_input_tokens)),
+    ]
+}
+
+fn preview(content: &str, tokenizer: &dyn Tokenizer) -> String {
+    let preview = tokenizer.truncate_to_tokens(content.trim(), PREVIEW_TOKENS);
+    if preview.len() < content.trim().len() {
+        format!("{}...", preview)
+    } else {
+        preview
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 按空白分词，每个词一个 token
+    struct WordTokenizer;
+
+    impl Tokenizer for WordTokenizer {
+        fn count_tokens(&self, text: &str) -> usize {
+            text.split_whitespace().count()
+        }
+    }
+
+    /// 加上每条消息的开销后：系统 6、两条旧轮次各 8、两个检索片段各 6、最后的提问 6，共 40
+    fn conversation() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::system("s s"),
+            ChatMessage::user("a a a a"),
+            ChatMessage::assistant("b b b b"),
+            ChatMessage::retrieved("low low", 0.1),
+            ChatMessage::retrieved("high high", 0.9),
+            ChatMessage::user("q q"),
+        ]
+    }
+
+    fn contents(fit: &ContextFit) -> Vec<&str> {
+        fit.messages.iter().map(|m| m.content.as_str()).collect()
+    }
+
+    fn reasons(fit: &ContextFit) -> Vec<DropReason> {
+        fit.report.dropped.iter().map(|d| d.reason).collect()
+    }
+
+    #[test]
+    fn keeps_everything_within_budget_or_when_disabled() {
+        let fit = fit(&conversation(), 40, ContextPolicy::DropOldest, 0, &WordTokenizer);
+        assert_eq!(fit.messages.len(), 6);
+        assert!(!fit.report.is_trimmed());
+
+        let fit = super::fit(&conversation(), 10, ContextPolicy::Disabled, 0, &WordTokenizer);
+        assert_eq!(fit.messages.len(), 6);
+        assert_eq!(fit.report.tokens_after, 40);
+    }
+
+    #[test]
+    fn drop_oldest_removes_whole_turns() {
+        let fit = fit(&conversation(), 30, ContextPolicy::DropOldest, 0, &WordTokenizer);
+        assert_eq!(contents(&fit), ["s s", "low low", "high high", "q q"]);
+        assert_eq!(reasons(&fit), [DropReason::OldestTurn, DropReason::OldestTurn]);
+        assert_eq!((fit.report.tokens_before, fit.report.tokens_after), (40, 24));
+        assert_eq!(fit.report.dropped[1].role, ChatRole::Assistant);
+        assert_eq!(fit.report.dropped[1].preview, "b b b b");
+        assert_eq!(fit.report.describe(), "对话超出模型上下文长度（40 / 30 token），省略了 2 条较早的消息");
+    }
+
+    #[test]
+    fn evict_retrieved_drops_least_relevant_first() {
+        let fit = fit(&conversation(), 34, ContextPolicy::EvictRetrieved, 0, &WordTokenizer);
+        assert_eq!(contents(&fit), ["s s", "a a a a", "b b b b", "high high", "q q"]);
+        assert_eq!(reasons(&fit), [DropReason::LowRelevance { score: 0.1 }]);
+
+        // 检索片段都丢弃后仍超出时再丢弃最早的轮次
+        let fit = super::fit(&conversation(), 20, ContextPolicy::EvictRetrieved, 0, &WordTokenizer);
+        assert_eq!(contents(&fit), ["s s", "q q"]);
+        assert_eq!(fit.report.tokens_after, 12);
+        assert!(fit.report.describe().contains("省略了 2 个相关度较低的检索片段"));
+    }
+
+    #[test]
+    fn summarize_reserves_room_and_applies_summary() {
+        let mut fit = fit(&conversation(), 30, ContextPolicy::Summarize, 6, &WordTokenizer);
+        assert_eq!(fit.summarized.iter().map(|m| m.content.as_str()).collect::<Vec<_>>(), ["a a a a", "b b b b"]);
+        assert_eq!(reasons(&fit), [DropReason::Summarized, DropReason::Summarized]);
+        assert_eq!(fit.report.tokens_after, 24);
+
+        // 摘要并入系统消息
+        fit.apply_summary(Some(" 用户 在 问 q ".to_string()), &WordTokenizer);
+        assert_eq!(fit.messages[0].content, "s s\n\n此前对话的摘要：\n用户 在 问 q");
+        assert_eq!(fit.report.tokens_after, 29);
+        assert_eq!(fit.report.summary.as_deref(), Some("用户 在 问 q"));
+        assert!(fit.report.describe().contains("2 条较早的消息已压缩为摘要"));
+
+        // 没有系统消息时作为新的系统消息插入
+        let messages = &conversation()[1..];
+        let mut fit = super::fit(messages, 24, ContextPolicy::Summarize, 6, &WordTokenizer);
+        fit.apply_summary(Some("摘要".to_string()), &WordTokenizer);
+        assert_eq!(fit.messages[0].role, ChatRole::System);
+        assert_eq!(fit.messages.len(), 4);
+
+        // 摘要生成失败时视为直接丢弃
+        let mut fit = super::fit(&conversation(), 30, ContextPolicy::Summarize, 6, &WordTokenizer);
+        fit.apply_summary(None, &WordTokenizer);
+        assert_eq!(reasons(&fit), [DropReason::OldestTurn, DropReason::OldestTurn]);
+        assert_eq!(fit.messages.len(), 4);
+    }
+
+    #[test]
+    fn truncates_oversized_message_that_must_be_kept() {
+        let question = (0..50).map(|i| format!("w{}", i)).collect::<Vec<_>>().join(" ");
+        let fit = fit(&[ChatMessage::user(question)], 20, ContextPolicy::DropOldest, 0, &WordTokenizer);
+        assert_eq!(fit.messages[0].content.split_whitespace().count(), 16);
+        assert!(fit.messages[0].content.trim_end().ends_with("w15"));
+        assert_eq!(fit.report.tokens_after, 20);
+        assert_eq!(reasons(&fit), [DropReason::Truncated { kept_tokens: 20 }]);
+        assert_eq!(fit.report.dropped[0].tokens, 34);
+        assert!(fit.report.dropped[0].preview.starts_with("w16 w17"));
+        assert!(fit.report.describe().ends_with("截断了 1 条过长的消息"));
+    }
+}
@@ -0,0 +1,261 @@
+// 后端降级链 - 熔断器、健康状态与请求路由事件
+// 侧车进程退出、本地服务断开时按顺序切换到下一个后端，反复失败的后端暂时熔断
+
+use crate::engine::BackendType;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// 连续失败多少次后熔断
+const FAILURE_THRESHOLD: u32 = 3;
+/// 首次熔断时长，之后每次重新熔断翻倍
+const BASE_COOLDOWN: Duration = Duration::from_secs(30);
+const MAX_COOLDOWN: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    /// 正常接受请求
+    Closed,
+    /// 熔断中，冷却结束前跳过该后端
+    Open,
+    /// 冷却结束，放行试探请求，成功则恢复
+    HalfOpen,
+}
+
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    state: BreakerState,
+    consecutive_failures: u32,
+    /// 连续熔断次数，用于计算冷却时长（抖动的后端冷却越来越久）
+    trips: u32,
+    open_until: Option<Instant>,
+    closed_since: Instant,
+    last_error: Option<String>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            state: BreakerState::Closed,
+            consecutive_failures: 0,
+            trips: 0,
+            open_until: None,
+            closed_since: Instant::now(),
+            last_error: None,
+        }
+    }
+}
+
+impl CircuitBreaker {
+    /// 是否允许请求；冷却结束时转为半开
+    pub fn allow(&mut self) -> bool {
+        match self.state {
+            BreakerState::Closed | BreakerState::HalfOpen => true,
+            BreakerState::Open => {
+                if self.open_until.is_some_and(|until| Instant::now() >= until) {
+                    self.state = BreakerState::HalfOpen;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.state
+    }
+
+    /// 返回 true 表示熔断器由此恢复
+    pub fn record_success(&mut self) -> bool {
+        let recovered = self.state != BreakerState::Closed;
+        self.consecutive_failures = 0;
+        if recovered {
+            self.state = BreakerState::Closed;
+            self.open_until = None;
+            self.closed_since = Instant::now();
+        }
+        recovered
+    }
+
+    /// 返回 Some(冷却时长) 表示熔断器由此打开
+    pub fn record_failure(&mut self, error: impl Into<String>) -> Option<Duration> {
+        self.consecutive_failures += 1;
+        self.last_error = Some(error.into());
+        let should_open = match self.state {
+            // 半开状态下试探失败，立即重新熔断
+            BreakerState::HalfOpen => true,
+            BreakerState::Closed => self.consecutive_failures >= FAILURE_THRESHOLD,
+            BreakerState::Open => false,
+        };
+        if !should_open {
+            return None;
+        }
+        // 稳定运行足够久后不再计入之前的熔断次数
+        if self.state == BreakerState::Closed && self.closed_since.elapsed() > MAX_COOLDOWN {
+            self.trips = 0;
+        }
+        let cooldown = BASE_COOLDOWN.saturating_mul(1 << self.trips.min(8)).min(MAX_COOLDOWN);
+        self.trips += 1;
+        self.state = BreakerState::Open;
+        self.open_until = Some(Instant::now() + cooldown);
+        Some(cooldown)
+    }
+
+    /// 立即熔断（如启动时探测失败）
+    pub fn trip(&mut self, error: impl Into<String>) {
+        self.last_error = Some(error.into());
+        self.state = BreakerState::Open;
+        self.open_until = Some(Instant::now() + BASE_COOLDOWN);
+    }
+
+    pub fn health(&self, backend: BackendType, position: usize) -> BackendHealth {
+        BackendHealth {
+            backend,
+            position,
+            state: self.state,
+            consecutive_failures: self.consecutive_failures,
+            retry_in_secs: self
+                .open_until
+                .filter(|_| self.state == BreakerState::Open)
+                .map(|until| until.saturating_duration_since(Instant::now()).as_secs()),
+            last_error: self.last_error.clone(),
+        }
+    }
+}
+
+/// 降级链中单个后端的健康状态
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackendHealth {
+    pub backend: BackendType,
+    /// 在降级链中的位置，0 为首选
+    pub position: usize,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    /// 熔断剩余时间
+    pub retry_in_secs: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+/// 后端路由事件，通过 EngineManager::subscribe_events 订阅
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum BackendEvent {
+    /// 请求由该后端完成；fallback_from 为首选后端（发生降级时）
+    Served {
+        backend: BackendType,
+        fallback_from: Option<BackendType>,
+    },
+    /// 初始化或请求失败，将尝试下一个后端
+    Failed { backend: BackendType, error: String },
+    /// 连续失败后熔断
+    CircuitOpened { backend: BackendType, cooldown_secs: u64 },
+    /// 健康检查或试探请求成功，恢复使用
+    Recovered { backend: BackendType },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 冷却时间到期（不真正等待）
+    fn expire(breaker: &mut CircuitBreaker) {
+        breaker.open_until = Some(Instant::now());
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let mut breaker = CircuitBreaker::default();
+        assert_eq!(breaker.record_failure("timeout"), None);
+        assert_eq!(breaker.record_failure("timeout"), None);
+        // 成功清零连续失败次数
+        assert!(!breaker.record_success());
+        assert_eq!(breaker.record_failure("timeout"), None);
+        assert_eq!(breaker.record_failure("timeout"), None);
+        assert!(breaker.allow());
+
+        assert_eq!(breaker.record_failure("refused"), Some(BASE_COOLDOWN));
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(!breaker.allow());
+        // 熔断中的失败不重新计算冷却
+        assert_eq!(breaker.record_failure("refused"), None);
+
+        let health = breaker.health(BackendType::Ollama, 1);
+        assert_eq!(health.state, BreakerState::Open);
+        assert_eq!(health.consecutive_failures, 4);
+        assert!(health.retry_in_secs.is_some_and(|s| s <= BASE_COOLDOWN.as_secs()));
+        assert_eq!(health.last_error.as_deref(), Some("refused"));
+    }
+
+    #[test]
+    fn half_open_probe_recovers_or_reopens() {
+        let mut breaker = CircuitBreaker::default();
+        for _ in 0..FAILURE_THRESHOLD {
+            breaker.record_failure("down");
+        }
+        expire(&mut breaker);
+        assert!(breaker.allow());
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+
+        // 试探失败立即重新熔断，冷却翻倍
+        assert_eq!(breaker.record_failure("still down"), Some(BASE_COOLDOWN * 2));
+        assert!(!breaker.allow());
+
+        expire(&mut breaker);
+        assert!(breaker.allow());
+        assert!(breaker.record_success());
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert!(!breaker.record_success());
+        assert_eq!(breaker.health(BackendType::Ollama, 0).retry_in_secs, None);
+    }
+
+    #[test]
+    fn cooldown_is_capped_and_resets_after_stable_period() {
+        let mut breaker = CircuitBreaker::default();
+        for _ in 0..FAILURE_THRESHOLD {
+            breaker.record_failure("flaky");
+        }
+        let mut cooldowns = vec![];
+        for _ in 0..5 {
+            expire(&mut breaker);
+            assert!(breaker.allow());
+            cooldowns.push(breaker.record_failure("flaky").unwrap().as_secs());
+        }
+        assert_eq!(cooldowns, [60, 120, 240, 300, 300]);
+
+        // 恢复后很快再次熔断，沿用之前的熔断次数
+        expire(&mut breaker);
+        breaker.allow();
+        breaker.record_success();
+        for _ in 0..FAILURE_THRESHOLD - 1 {
+            breaker.record_failure("flaky");
+        }
+        assert_eq!(breaker.record_failure("flaky"), Some(MAX_COOLDOWN));
+
+        // 稳定运行超过最长冷却后重新从基础冷却开始
+        expire(&mut breaker);
+        breaker.allow();
+        breaker.record_success();
+        breaker.closed_since = Instant::now() - MAX_COOLDOWN - Duration::from_secs(1);
+        for _ in 0..FAILURE_THRESHOLD - 1 {
+            breaker.record_failure("flaky");
+        }
+        assert_eq!(breaker.record_failure("flaky"), Some(BASE_COOLDOWN));
+    }
+
+    #[test]
+    fn trip_opens_without_counting_failures() {
+        let mut breaker = CircuitBreaker::default();
+        breaker.trip("did not respond to probe");
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(!breaker.allow());
+        let health = breaker.health(BackendType::OpenAiCompat, 0);
+        assert_eq!(health.consecutive_failures, 0);
+        assert_eq!(health.last_error.as_deref(), Some("did not respond to probe"));
+
+        // 探测失败不计入熔断次数，试探失败按基础冷却重新熔断
+        expire(&mut breaker);
+        assert!(breaker.allow());
+        assert_eq!(breaker.record_failure("down"), Some(BASE_COOLDOWN));
+    }
+}
@@ -0,0 +1,639 @@
+// GGUF 文件解析 - 读取文件头、键值元数据与张量索引（不加载权重）
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufReader, Read, Seek};
+use std::path::{Path, PathBuf};
+
+const GGUF_MAGIC: u32 = 0x4655_4747; // "GGUF" 小端序
+const DEFAULT_ALIGNMENT: u64 = 32;
+
+/// GGUF 元数据值
+#[derive(Debug, Clone)]
+pub enum GgufValue {
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    F32(f32),
+    Bool(bool),
+    String(String),
+    Array(Vec<GgufValue>),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+}
+
+impl GgufValue {
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            GgufValue::U8(v) => Some(v as u64),
+            GgufValue::U16(v) => Some(v as u64),
+            GgufValue::U32(v) => Some(v as u64),
+            GgufValue::U64(v) => Some(v),
+            GgufValue::I8(v) if v >= 0 => Some(v as u64),
+            GgufValue::I16(v) if v >= 0 => Some(v as u64),
+            GgufValue::I32(v) if v >= 0 => Some(v as u64),
+            GgufValue::I64(v) if v >= 0 => Some(v as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<f32> {
+        match *self {
+            GgufValue::F32(v) => Some(v),
+            GgufValue::F64(v) => Some(v as f32),
+            _ => self.as_u64().map(|v| v as f32),
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            GgufValue::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            GgufValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[GgufValue]> {
+        match self {
+            GgufValue::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// ggml 张量数据类型（仅列出 GGUF 模型中常见的类型）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GgmlType {
+    F32,
+    F16,
+    Q4_0,
+    Q4_1,
+    Q5_0,
+    Q5_1,
+    Q8_0,
+    Q2K,
+    Q3K,
+    Q4K,
+    Q5K,
+    Q6K,
+    BF16,
+    /// 尚不支持的类型，保留原始编号以便报错
+    Unknown(u32),
+}
+
+impl GgmlType {
+    pub fn from_u32(value: u32) -> Self {
+        match value {
+            0 => GgmlType::F32,
+            1 => GgmlType::F16,
+            2 => GgmlType::Q4_0,
+            3 => GgmlType::Q4_1,
+            6 => GgmlType::Q5_0,
+            7 => GgmlType::Q5_1,
+            8 => GgmlType::Q8_0,
+            10 => GgmlType::Q2K,
+            11 => GgmlType::Q3K,
+            12 => GgmlType::Q4K,
+            13 => GgmlType::Q5K,
+            14 => GgmlType::Q6K,
+            30 => GgmlType::BF16,
+            other => GgmlType::Unknown(other),
+        }
+    }
+
+    /// 每个量化块包含的元素数
+    pub fn block_size(&self) -> usize {
+        match self {
+            GgmlType::F32 | GgmlType::F16 | GgmlType::BF16 => 1,
+            GgmlType::Q4_0 | GgmlType::Q4_1 | GgmlType::Q5_0 | GgmlType::Q5_1 | GgmlType::Q8_0 => 32,
+            GgmlType::Q2K | GgmlType::Q3K | GgmlType::Q4K | GgmlType::Q5K | GgmlType::Q6K => 256,
+            GgmlType::Unknown(_) => 1,
+        }
+    }
+
+    /// 每个量化块占用的字节数（未知类型返回 None）
+    pub fn type_size(&self) -> Option<usize> {
+        Some(match self {
+            GgmlType::F32 => 4,
+            GgmlType::F16 | GgmlType::BF16 => 2,
+            GgmlType::Q4_0 => 18,
+            GgmlType::Q4_1 => 20,
+            GgmlType::Q5_0 => 22,
+            GgmlType::Q5_1 => 24,
+            GgmlType::Q8_0 => 34,
+            GgmlType::Q2K => 84,
+            GgmlType::Q3K => 110,
+            GgmlType::Q4K => 144,
+            GgmlType::Q5K => 176,
+            GgmlType::Q6K => 210,
+            GgmlType::Unknown(_) => return None,
+        })
+    }
+}
+
+impl std::fmt::Display for GgmlType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GgmlType::F32 => write!(f, "F32"),
+            GgmlType::F16 => write!(f, "F16"),
+            GgmlType::Q4_0 => write!(f, "Q4_0"),
+            GgmlType::Q4_1 => write!(f, "Q4_1"),
+            GgmlType::Q5_0 => write!(f, "Q5_0"),
+            GgmlType::Q5_1 => write!(f, "Q5_1"),
+            GgmlType::Q8_0 => write!(f, "Q8_0"),
+            GgmlType::Q2K => write!(f, "Q2_K"),
+            GgmlType::Q3K => write!(f, "Q3_K"),
+            GgmlType::Q4K => write!(f, "Q4_K"),
+            GgmlType::Q5K => write!(f, "Q5_K"),
+            GgmlType::Q6K => write!(f, "Q6_K"),
+            GgmlType::BF16 => write!(f, "BF16"),
+            GgmlType::Unknown(t) => write!(f, "ggml_type({})", t),
+        }
+    }
+}
+
+/// 张量索引信息
+#[derive(Debug, Clone)]
+pub struct GgufTensorInfo {
+    pub name: String,
+    /// 维度，dims[0] 为最内层（行内元素数）
+    pub dims: Vec<u64>,
+    pub ggml_type: GgmlType,
+    /// 相对于数据区起点的偏移
+    pub offset: u64,
+}
+
+impl GgufTensorInfo {
+    pub fn element_count(&self) -> u64 {
+        self.dims.iter().product()
+    }
+
+    /// 张量数据字节数
+    pub fn byte_size(&self) -> Option<u64> {
+        let type_size = self.ggml_type.type_size()? as u64;
+        Some(self.element_count() / self.ggml_type.block_size() as u64 * type_size)
+    }
+}
+
+/// 已解析的 GGUF 文件头
+#[derive(Debug, Clone)]
+pub struct GgufFile {
+    pub version: u32,
+    pub metadata: BTreeMap<String, GgufValue>,
+    pub tensors: Vec<GgufTensorInfo>,
+    /// 张量数据区在文件中的绝对偏移
+    pub data_offset: u64,
+}
+
+impl GgufFile {
+    /// 读取文件头与元数据，权重数据不会被读入内存
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open GGUF file: {:?}", path))?;
+        let mut reader = BufReader::new(file);
+        Self::read(&mut reader).with_context(|| format!("Invalid GGUF file: {:?}", path))
+    }
+
+    fn read<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let magic = read_u32(reader)?;
+        if magic != GGUF_MAGIC {
+            anyhow::bail!("bad magic 0x{:08x}, not a GGUF file", magic);
+        }
+        let version = read_u32(reader)?;
+        if !(2..=3).contains(&version) {
+            anyhow::bail!("unsupported GGUF version {}", version);
+        }
+
+        let tensor_count = read_u64(reader)?;
+        let kv_count = read_u64(reader)?;
+
+        let mut metadata = BTreeMap::new();
+        for _ in 0..kv_count {
+            let key = read_string(reader)?;
+            let value_type = read_u32(reader)?;
+            let value = read_value(reader, value_type)?;
+            metadata.insert(key, value);
+        }
+
+        let mut tensors = Vec::with_capacity(tensor_count as usize);
+        for _ in 0..tensor_count {
+            let name = read_string(reader)?;
+            let n_dims = read_u32(reader)?;
+            if n_dims > 4 {
+                anyhow::bail!("tensor {} has {} dimensions", name, n_dims);
+            }
+            let dims = (0..n_dims).map(|_| read_u64(reader)).collect::<Result<Vec<_>>>()?;
+            let ggml_type = GgmlType::from_u32(read_u32(reader)?);
+            let offset = read_u64(reader)?;
+            tensors.push(GgufTensorInfo { name, dims, ggml_type, offset });
+        }
+
+        let alignment = metadata
+            .get("general.alignment")
+            .and_then(GgufValue::as_u64)
+            .filter(|a| *a > 0)
+            .unwrap_or(DEFAULT_ALIGNMENT);
+        let position = reader.stream_position()?;
+        let data_offset = position.div_ceil(alignment) * alignment;
+
+        Ok(Self {
+            version,
+            metadata,
+            tensors,
+            data_offset,
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&GgufValue> {
+        self.metadata.get(key)
+    }
+
+    pub fn get_u64(&self, key: &str) -> Option<u64> {
+        self.get(key).and_then(GgufValue::as_u64)
+    }
+
+    pub fn get_f32(&self, key: &str) -> Option<f32> {
+        self.get(key).and_then(GgufValue::as_f32)
+    }
+
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(GgufValue::as_str)
+    }
+
+    /// 模型架构（general.architecture），如 llama、qwen2
+    pub fn architecture(&self) -> Option<&str> {
+        self.get_str("general.architecture")
+    }
+
+    /// 读取带架构前缀的键，如 llama.context_length
+    pub fn get_arch_u64(&self, key: &str) -> Option<u64> {
+        let arch = self.architecture()?;
+        self.get_u64(&format!("{}.{}", arch, key))
+    }
+
+    pub fn get_arch_f32(&self, key: &str) -> Option<f32> {
+        let arch = self.architecture()?;
+        self.get_f32(&format!("{}.{}", arch, key))
+    }
+
+    pub fn tensor(&self, name: &str) -> Option<&GgufTensorInfo> {
+        self.tensors.iter().find(|t| t.name == name)
+    }
+}
+
+/// 模型文件概要信息，供 UI 展示与加载前校验
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GgufModelInfo {
+    pub path: PathBuf,
+    pub file_size: u64,
+    pub gguf_version: u32,
+    pub name: Option<String>,
+    pub architecture: String,
+    pub parameter_count: u64,
+    /// 量化类型（如 Q4_K_M），来自 general.file_type 或按张量类型推断
+    pub quantization: String,
+    pub context_length: Option<u64>,
+    pub embedding_length: Option<u64>,
+    pub block_count: Option<u64>,
+    pub head_count: Option<u64>,
+    pub head_count_kv: Option<u64>,
+    pub chat_template: Option<String>,
+    /// 内嵌分词器类型（tokenizer.ggml.model），如 llama、gpt2
+    pub tokenizer_model: Option<String>,
+    pub vocab_size: Option<usize>,
+    /// 文件中出现的张量数据类型
+    pub tensor_types: Vec<String>,
+    /// 嵌入模型声明的池化方式（{arch}.pooling_type），生成模型没有该字段
+    #[serde(default)]
+    pub pooling_type: Option<u64>,
+}
+
+impl GgufModelInfo {
+    /// 元数据声明了 mean / cls / last 池化，即为嵌入模型（rank 为重排序模型，不算）
+    pub fn is_embedding_model(&self) -> bool {
+        matches!(self.pooling_type, Some(1..=3))
+    }
+}
+
+/// 读取 GGUF 文件头并汇总模型信息（不加载权重）
+pub fn inspect(path: &Path) -> Result<GgufModelInfo> {
+    let gguf = GgufFile::open(path)?;
+    let file_size = std::fs::metadata(path)?.len();
+
+    let architecture = gguf
+        .architecture()
+        .ok_or_else(|| anyhow::anyhow!("GGUF file has no general.architecture: {:?}", path))?
+        .to_string();
+
+    let parameter_count = gguf
+        .get_u64("general.parameter_count")
+        .unwrap_or_else(|| gguf.tensors.iter().map(GgufTensorInfo::element_count).sum());
+
+    let mut tensor_types: Vec<String> = gguf.tensors.iter().map(|t| t.ggml_type.to_string()).collect();
+    tensor_types.sort();
+    tensor_types.dedup();
+
+    let quantization = gguf
+        .get_u64("general.file_type")
+        .and_then(file_type_name)
+        .map(str::to_string)
+        .unwrap_or_else(|| dominant_tensor_type(&gguf));
+
+    Ok(GgufModelInfo {
+        path: path.to_path_buf(),
+        file_size,
+        gguf_version: gguf.version,
+        name: gguf.get_str("general.name").map(str::to_string),
+        parameter_count,
+        quantization,
+        context_length: gguf.get_arch_u64("context_length"),
+        embedding_length: gguf.get_arch_u64("embedding_length"),
+        block_count: gguf.get_arch_u64("block_count"),
+        head_count: gguf.get_arch_u64("attention.head_count"),
+        head_count_kv: gguf.get_arch_u64("attention.head_count_kv"),
+        chat_template: gguf.get_str("tokenizer.chat_template").map(str::to_string),
+        tokenizer_model: gguf.get_str("tokenizer.ggml.model").map(str::to_string),
+        vocab_size: gguf
+            .get("tokenizer.ggml.tokens")
+            .and_then(GgufValue::as_array)
+            .map(|tokens| tokens.len()),
+        tensor_types,
+        pooling_type: gguf.get_arch_u64("pooling_type"),
+        architecture,
+    })
+}
+
+/// llama.cpp 的 llama_ftype 编号到名称
+fn file_type_name(file_type: u64) -> Option<&'static str> {
+    Some(match file_type {
+        0 => "F32",
+        1 => "F16",
+        2 => "Q4_0",
+        3 => "Q4_1",
+        7 => "Q8_0",
+        8 => "Q5_0",
+        9 => "Q5_1",
+        10 => "Q2_K",
+        11 => "Q3_K_S",
+        12 => "Q3_K_M",
+        13 => "Q3_K_L",
+        14 => "Q4_K_S",
+        15 => "Q4_K_M",
+        16 => "Q5_K_S",
+        17 => "Q5_K_M",
+        18 => "Q6_K",
+        19 => "IQ2_XXS",
+        20 => "IQ2_XS",
+        21 => "Q2_K_S",
+        22 => "IQ3_XS",
+        23 => "IQ3_XXS",
+        24 => "IQ1_S",
+        25 => "IQ4_NL",
+        26 => "IQ3_S",
+        27 => "IQ3_M",
+        28 => "IQ2_S",
+        29 => "IQ2_M",
+        30 => "IQ4_XS",
+        31 => "IQ1_M",
+        32 => "BF16",
+        _ => return None,
+    })
+}
+
+/// 没有 general.file_type 时，以占用字节最多的张量类型作为量化类型
+fn dominant_tensor_type(gguf: &GgufFile) -> String {
+    let mut bytes_by_type: BTreeMap<String, u64> = BTreeMap::new();
+    for tensor in &gguf.tensors {
+        *bytes_by_type.entry(tensor.ggml_type.to_string()).or_insert(0) += tensor.byte_size().unwrap_or(0);
+    }
+    bytes_by_type
+        .into_iter()
+        .max_by_key(|(_, bytes)| *bytes)
+        .map(|(name, _)| name)
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_string<R: Read>(reader: &mut R) -> Result<String> {
+    let len = read_u64(reader)?;
+    if len > 64 * 1024 * 1024 {
+        anyhow::bail!("string length {} is implausibly large", len);
+    }
+    let mut buf = vec![0u8; len as usize];
+    reader.read_exact(&mut buf)?;
+    // 部分转换工具写出的词表包含非法 UTF-8，按有损方式读取
+    Ok(String::from_utf8(buf).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()))
+}
+
+fn read_value<R: Read>(reader: &mut R, value_type: u32) -> Result<GgufValue> {
+    Ok(match value_type {
+        0 => GgufValue::U8(read_u8(reader)?),
+        1 => GgufValue::I8(read_u8(reader)? as i8),
+        2 => GgufValue::U16(read_u16(reader)?),
+        3 => GgufValue::I16(read_u16(reader)? as i16),
+        4 => GgufValue::U32(read_u32(reader)?),
+        5 => GgufValue::I32(read_u32(reader)? as i32),
+        6 => GgufValue::F32(f32::from_bits(read_u32(reader)?)),
+        7 => GgufValue::Bool(read_u8(reader)? != 0),
+        8 => GgufValue::String(read_string(reader)?),
+        9 => {
+            let item_type = read_u32(reader)?;
+            let len = read_u64(reader)?;
+            if len > 16 * 1024 * 1024 {
+                anyhow::bail!("array length {} is implausibly large", len);
+            }
+            let items = (0..len)
+                .map(|_| read_value(reader, item_type))
+                .collect::<Result<Vec<_>>>()?;
+            GgufValue::Array(items)
+        }
+        10 => GgufValue::U64(read_u64(reader)?),
+        11 => GgufValue::I64(read_u64(reader)? as i64),
+        12 => GgufValue::F64(f64::from_bits(read_u64(reader)?)),
+        other => anyhow::bail!("unknown metadata value type {}", other),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// 按 GGUF v3 格式手工拼出文件头
+    #[derive(Default)]
+    struct Writer {
+        kvs: Vec<u8>,
+        kv_count: u64,
+        tensors: Vec<u8>,
+        tensor_count: u64,
+    }
+
+    fn string(out: &mut Vec<u8>, s: &str) {
+        out.extend((s.len() as u64).to_le_bytes());
+        out.extend(s.as_bytes());
+    }
+
+    impl Writer {
+        fn kv(&mut self, key: &str, value_type: u32, value: &[u8]) -> &mut Self {
+            string(&mut self.kvs, key);
+            self.kvs.extend(value_type.to_le_bytes());
+            self.kvs.extend(value);
+            self.kv_count += 1;
+            self
+        }
+
+        fn kv_str(&mut self, key: &str, value: &str) -> &mut Self {
+            let mut bytes = vec![];
+            string(&mut bytes, value);
+            self.kv(key, 8, &bytes)
+        }
+
+        fn tensor(&mut self, name: &str, dims: &[u64], ggml_type: u32, offset: u64) -> &mut Self {
+            string(&mut self.tensors, name);
+            self.tensors.extend((dims.len() as u32).to_le_bytes());
+            dims.iter().for_each(|d| self.tensors.extend(d.to_le_bytes()));
+            self.tensors.extend(ggml_type.to_le_bytes());
+            self.tensors.extend(offset.to_le_bytes());
+            self.tensor_count += 1;
+            self
+        }
+
+        fn finish(&self, version: u32) -> Vec<u8> {
+            let mut out = b"GGUF".to_vec();
+            out.extend(version.to_le_bytes());
+            out.extend(self.tensor_count.to_le_bytes());
+            out.extend(self.kv_count.to_le_bytes());
+            out.extend(&self.kvs);
+            out.extend(&self.tensors);
+            out
+        }
+    }
+
+    fn tiny_model() -> Writer {
+        let mut tokens = 8u32.to_le_bytes().to_vec();
+        tokens.extend(3u64.to_le_bytes());
+        ["<s>", "a", "b"].iter().for_each(|t| string(&mut tokens, t));
+        let mut w = Writer::default();
+        w.kv_str("general.architecture", "qwen2")
+            .kv_str("general.name", "tiny")
+            .kv("general.alignment", 4, &64u32.to_le_bytes())
+            .kv("qwen2.context_length", 4, &4096u32.to_le_bytes())
+            .kv("qwen2.embedding_length", 10, &896u64.to_le_bytes())
+            .kv("qwen2.block_count", 5, &24i32.to_le_bytes())
+            .kv("qwen2.attention.head_count", 2, &14u16.to_le_bytes())
+            .kv("qwen2.attention.head_count_kv", 0, &[2])
+            .kv("qwen2.rope.freq_base", 6, &1_000_000f32.to_le_bytes())
+            .kv("general.offset", 11, &(-1i64).to_le_bytes())
+            .kv("general.quantized", 7, &[1])
+            .kv_str("tokenizer.ggml.model", "gpt2")
+            .kv("tokenizer.ggml.tokens", 9, &tokens)
+            .kv_str("tokenizer.chat_template", "{{ messages }}")
+            .tensor("token_embd.weight", &[896, 3], 8, 0)
+            .tensor("blk.0.attn_q.weight", &[896, 896], 1, 2880);
+        w
+    }
+
+    #[test]
+    fn parses_handcrafted_header() {
+        let bytes = tiny_model().finish(3);
+        let gguf = GgufFile::read(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(gguf.version, 3);
+        assert_eq!(gguf.architecture(), Some("qwen2"));
+        assert_eq!(gguf.get_arch_u64("block_count"), Some(24));
+        assert_eq!(gguf.get_arch_u64("attention.head_count_kv"), Some(2));
+        assert_eq!(gguf.get_arch_f32("rope.freq_base"), Some(1_000_000.0));
+        // 负数不能当作无符号数读取
+        assert_eq!(gguf.get_u64("general.offset"), None);
+        assert_eq!(gguf.get("general.quantized").and_then(GgufValue::as_bool), Some(true));
+
+        let embd = gguf.tensor("token_embd.weight").unwrap();
+        assert_eq!(embd.ggml_type, GgmlType::Q8_0);
+        assert_eq!(embd.element_count(), 2688);
+        assert_eq!(embd.byte_size(), Some(2688 / 32 * 34));
+        assert_eq!(gguf.tensor("blk.0.attn_q.weight").unwrap().offset, 2880);
+        // 数据区按 general.alignment 对齐
+        assert_eq!(gguf.data_offset % 64, 0);
+        assert!(gguf.data_offset >= bytes.len() as u64 && gguf.data_offset < bytes.len() as u64 + 64);
+    }
+
+    #[test]
+    fn inspect_summarizes_model_info() {
+        let path = std::env::temp_dir().join(format!("silo-gguf-{}.gguf", std::process::id()));
+        let bytes = tiny_model().finish(2);
+        std::fs::write(&path, &bytes).unwrap();
+        let info = inspect(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(info.file_size, bytes.len() as u64);
+        assert_eq!(info.gguf_version, 2);
+        assert_eq!(info.name.as_deref(), Some("tiny"));
+        assert_eq!(info.architecture, "qwen2");
+        // 没有 general.parameter_count / general.file_type 时由张量推断
+        assert_eq!(info.parameter_count, 2688 + 896 * 896);
+        assert_eq!(info.quantization, "F16");
+        assert_eq!(info.tensor_types, ["F16", "Q8_0"]);
+        assert_eq!(info.context_length, Some(4096));
+        assert_eq!(info.embedding_length, Some(896));
+        assert_eq!((info.head_count, info.head_count_kv), (Some(14), Some(2)));
+        assert_eq!(info.tokenizer_model.as_deref(), Some("gpt2"));
+        assert_eq!(info.vocab_size, Some(3));
+        assert_eq!(info.chat_template.as_deref(), Some("{{ messages }}"));
+        assert!(!info.is_embedding_model());
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        let read = |bytes: Vec<u8>| GgufFile::read(&mut Cursor::new(bytes)).unwrap_err().to_string();
+
+        let mut bytes = tiny_model().finish(3);
+        bytes[..4].copy_from_slice(b"GGML");
+        assert!(read(bytes).contains("not a GGUF file"));
+        assert!(read(tiny_model().finish(1)).contains("unsupported GGUF version 1"));
+
+        // 文件在元数据中途截断
+        let bytes = tiny_model().finish(3);
+        assert!(GgufFile::read(&mut Cursor::new(&bytes[..100])).is_err());
+
+        let mut w = Writer::default();
+        w.kv("general.weird", 13, &[]);
+        assert!(read(w.finish(3)).contains("unknown metadata value type 13"));
+
+        let mut w = Writer::default();
+        w.tensor("t", &[1, 1, 1, 1, 1], 0, 0);
+        assert!(read(w.finish(3)).contains("tensor t has 5 dimensions"));
+    }
+}
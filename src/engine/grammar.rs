@@ -0,0 +1,587 @@
+// GBNF 语法 - 解析 llama.cpp 格式的 GBNF 语法，并逐字符匹配生成内容
+// 约束解码时，采样器用 GrammarMatcher 排除会使输出偏离语法的 token
+
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// 展开规则引用时的最大嵌套深度（防止左递归导致死循环）
+const MAX_EXPAND_DEPTH: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Element {
+    /// 匹配一个字符；negated 时匹配不在 ranges 中的字符
+    Chars { ranges: Vec<(char, char)>, negated: bool },
+    /// 引用另一条规则
+    Rule(usize),
+}
+
+impl Element {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Element::Chars { ranges, negated } => ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated,
+            Element::Rule(_) => false,
+        }
+    }
+
+    /// 是否可能匹配某个非 ASCII 字符（用于判断不完整的 UTF-8 序列能否继续）
+    fn may_match_non_ascii(&self) -> bool {
+        match self {
+            Element::Chars { ranges, negated } => *negated || ranges.iter().any(|&(_, hi)| hi as u32 >= 0x80),
+            Element::Rule(_) => false,
+        }
+    }
+}
+
+/// 解析后的语法：每条规则是若干备选，每个备选是元素序列
+#[derive(Debug)]
+pub struct Grammar {
+    rules: Vec<Vec<Vec<Element>>>,
+    root: usize,
+}
+
+impl Grammar {
+    /// 解析 GBNF 文本，入口规则为 root
+    pub fn parse(src: &str) -> Result<Arc<Self>> {
+        let mut parser = Parser {
+            src: src.chars().collect(),
+            pos: 0,
+            names: HashMap::new(),
+            rules: Vec::new(),
+            defined: Vec::new(),
+        };
+        parser.parse_rules()?;
+        let root = *parser
+            .names
+            .get("root")
+            .ok_or_else(|| anyhow::anyhow!("Grammar has no 'root' rule"))?;
+        for (name, &id) in &parser.names {
+            if !parser.defined[id] {
+                anyhow::bail!("Grammar rule '{}' is referenced but not defined", name);
+            }
+        }
+        Ok(Arc::new(Self {
+            rules: parser.rules,
+            root,
+        }))
+    }
+
+    /// 整段文本是否完整匹配语法
+    pub fn matches(self: &Arc<Self>, text: &str) -> bool {
+        let mut matcher = GrammarMatcher::new(self.clone());
+        text.chars().all(|c| matcher.accept_char(c)) && matcher.is_complete()
+    }
+}
+
+struct Parser {
+    src: Vec<char>,
+    pos: usize,
+    names: HashMap<String, usize>,
+    rules: Vec<Vec<Vec<Element>>>,
+    defined: Vec<bool>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.src.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<char> {
+        let c = self
+            .peek()
+            .ok_or_else(|| anyhow::anyhow!("Unexpected end of grammar"))?;
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn expect(&mut self, s: &str) -> Result<()> {
+        for expected in s.chars() {
+            let c = self.next()?;
+            if c != expected {
+                anyhow::bail!("Expected '{}' at offset {} in grammar, found '{}'", s, self.pos - 1, c);
+            }
+        }
+        Ok(())
+    }
+
+    /// 跳过空白（含换行）与 # 注释
+    fn skip_space(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '#' {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.pos += 1;
+                }
+            } else if c.is_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn is_name_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '-' || c == '_'
+    }
+
+    fn parse_name(&mut self) -> Result<String> {
+        let start = self.pos;
+        while self.peek().is_some_and(Self::is_name_char) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            anyhow::bail!("Expected rule name at offset {} in grammar", start);
+        }
+        Ok(self.src[start..self.pos].iter().collect())
+    }
+
+    /// 当前位置是否为新规则定义（name ::=），规则可以跨行书写，以此判断上一条规则结束
+    fn at_rule_definition(&self) -> bool {
+        let mut i = self.pos;
+        while self.src.get(i).copied().is_some_and(Self::is_name_char) {
+            i += 1;
+        }
+        if i == self.pos {
+            return false;
+        }
+        while self.src.get(i).is_some_and(|c| c.is_whitespace()) {
+            i += 1;
+        }
+        self.src[i..].starts_with(&[':', ':', '='])
+    }
+
+    fn rule_id(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.names.get(name) {
+            return id;
+        }
+        let id = self.new_rule(vec![]);
+        self.names.insert(name.to_string(), id);
+        self.defined[id] = false;
+        id
+    }
+
+    fn new_rule(&mut self, alternatives: Vec<Vec<Element>>) -> usize {
+        self.rules.push(alternatives);
+        self.defined.push(true);
+        self.rules.len() - 1
+    }
+
+    fn parse_rules(&mut self) -> Result<()> {
+        loop {
+            self.skip_space();
+            if self.peek().is_none() {
+                return Ok(());
+            }
+            let name = self.parse_name()?;
+            self.skip_space();
+            self.expect("::=")?;
+            let id = self.rule_id(&name);
+            if self.defined[id] {
+                anyhow::bail!("Grammar rule '{}' is defined twice", name);
+            }
+            self.rules[id] = self.parse_alternates()?;
+            self.defined[id] = true;
+        }
+    }
+
+    fn parse_alternates(&mut self) -> Result<Vec<Vec<Element>>> {
+        let mut alternatives = vec![self.parse_sequence()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alternatives.push(self.parse_sequence()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn parse_sequence(&mut self) -> Result<Vec<Element>> {
+        let mut seq: Vec<Element> = Vec::new();
+        // 最近一个原子在 seq 中的起始位置，重复运算符作用于它（字符串字面量可能包含多个元素）
+        let mut atom_start = None;
+        loop {
+            self.skip_space();
+            let Some(c) = self.peek() else { break };
+            match c {
+                '|' | ')' => break,
+                '"' => {
+                    self.pos += 1;
+                    atom_start = Some(seq.len());
+                    while self.peek() != Some('"') {
+                        let c = self.parse_char()?;
+                        seq.push(Element::Chars { ranges: vec![(c, c)], negated: false });
+                    }
+                    self.pos += 1;
+                }
+                '[' => {
+                    self.pos += 1;
+                    atom_start = Some(seq.len());
+                    seq.push(self.parse_class()?);
+                }
+                '.' => {
+                    self.pos += 1;
+                    atom_start = Some(seq.len());
+                    seq.push(Element::Chars { ranges: vec![], negated: true });
+                }
+                '(' => {
+                    self.pos += 1;
+                    let alternatives = self.parse_alternates()?;
+                    self.expect(")")?;
+                    let id = self.new_rule(alternatives);
+                    atom_start = Some(seq.len());
+                    seq.push(Element::Rule(id));
+                }
+                '*' | '+' | '?' | '{' => {
+                    let start = atom_start
+                        .take()
+                        .ok_or_else(|| anyhow::anyhow!("Repetition without a preceding item at offset {}", self.pos))?;
+                    let (min, max) = self.parse_repetition()?;
+                    let atom = seq.split_off(start);
+                    seq.extend(self.repeat(atom, min, max));
+                }
+                _ if self.at_rule_definition() => break,
+                _ if Self::is_name_char(c) => {
+                    let name = self.parse_name()?;
+                    let id = self.rule_id(&name);
+                    atom_start = Some(seq.len());
+                    seq.push(Element::Rule(id));
+                }
+                _ => anyhow::bail!("Unexpected '{}' at offset {} in grammar", c, self.pos),
+            }
+        }
+        Ok(seq)
+    }
+
+    fn parse_repetition(&mut self) -> Result<(usize, Option<usize>)> {
+        match self.next()? {
+            '*' => Ok((0, None)),
+            '+' => Ok((1, None)),
+            '?' => Ok((0, Some(1))),
+            _ => {
+                let number = |p: &mut Self| -> Option<usize> {
+                    p.skip_space();
+                    let start = p.pos;
+                    while p.peek().is_some_and(|c| c.is_ascii_digit()) {
+                        p.pos += 1;
+                    }
+                    p.src[start..p.pos].iter().collect::<String>().parse().ok()
+                };
+                let min = number(self).ok_or_else(|| anyhow::anyhow!("Expected number in repetition at offset {}", self.pos))?;
+                self.skip_space();
+                let max = if self.peek() == Some(',') {
+                    self.pos += 1;
+                    number(self)
+                } else {
+                    Some(min)
+                };
+                self.skip_space();
+                self.expect("}")?;
+                if max.is_some_and(|max| max < min) {
+                    anyhow::bail!("Invalid repetition {{{},{:?}}} in grammar", min, max);
+                }
+                Ok((min, max))
+            }
+        }
+    }
+
+    /// 把重复展开为规则：x{2,4} → x x R2，其中 R2 ::= x R1 | ε，R1 ::= x | ε；无上限时 R ::= x R | ε
+    fn repeat(&mut self, atom: Vec<Element>, min: usize, max: Option<usize>) -> Vec<Element> {
+        let mut seq: Vec<Element> = (0..min).flat_map(|_| atom.clone()).collect();
+        match max {
+            None => {
+                let id = self.new_rule(vec![]);
+                let mut recursive = atom;
+                recursive.push(Element::Rule(id));
+                self.rules[id] = vec![recursive, vec![]];
+                seq.push(Element::Rule(id));
+            }
+            Some(max) if max > min => {
+                let mut tail: Option<usize> = None;
+                for _ in min..max {
+                    let mut alternative = atom.clone();
+                    alternative.extend(tail.map(Element::Rule));
+                    tail = Some(self.new_rule(vec![alternative, vec![]]));
+                }
+                seq.extend(tail.map(Element::Rule));
+            }
+            Some(_) => {}
+        }
+        seq
+    }
+
+    fn parse_class(&mut self) -> Result<Element> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut ranges = Vec::new();
+        while self.peek() != Some(']') {
+            let lo = self.parse_char()?;
+            let hi = if self.peek() == Some('-') && self.src.get(self.pos + 1) != Some(&']') {
+                self.pos += 1;
+                self.parse_char()?
+            } else {
+                lo
+            };
+            ranges.push((lo, hi));
+        }
+        self.pos += 1;
+        Ok(Element::Chars { ranges, negated })
+    }
+
+    /// 读取一个字符，处理 \n、\xHH、\uHHHH 等转义
+    fn parse_char(&mut self) -> Result<char> {
+        let c = self.next()?;
+        if c != '\\' {
+            return Ok(c);
+        }
+        let escaped = self.next()?;
+        let hex_len = match escaped {
+            'n' => return Ok('\n'),
+            'r' => return Ok('\r'),
+            't' => return Ok('\t'),
+            'x' => 2,
+            'u' => 4,
+            'U' => 8,
+            other => return Ok(other),
+        };
+        let hex: String = (0..hex_len).map(|_| self.next()).collect::<Result<_>>()?;
+        u32::from_str_radix(&hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| anyhow::anyhow!("Invalid escape \\{}{} in grammar", escaped, hex))
+    }
+}
+
+/// 规则中的位置：第 rule 条规则的第 alt 个备选中，下一个待匹配的元素
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Pos {
+    rule: u32,
+    alt: u32,
+    elem: u32,
+}
+
+type Stack = Vec<Pos>;
+
+/// 增量匹配器：维护所有可能的解析栈，逐字符推进
+#[derive(Clone)]
+pub struct GrammarMatcher {
+    grammar: Arc<Grammar>,
+    /// 每个栈的栈顶都指向一个字符元素；空栈表示语法已完整匹配
+    stacks: Vec<Stack>,
+    /// 尚未凑齐的 UTF-8 字节
+    partial: Vec<u8>,
+}
+
+impl GrammarMatcher {
+    pub fn new(grammar: Arc<Grammar>) -> Self {
+        let root = grammar.root;
+        let mut matcher = Self {
+            grammar,
+            stacks: vec![],
+            partial: vec![],
+        };
+        let mut stacks = Vec::new();
+        let mut seen = HashSet::new();
+        for alt in 0..matcher.grammar.rules[root].len() {
+            let start = vec![Pos { rule: root as u32, alt: alt as u32, elem: 0 }];
+            matcher.expand(start, &mut stacks, &mut seen, 0);
+        }
+        matcher.stacks = stacks;
+        matcher
+    }
+
+    fn element(&self, pos: Pos) -> Option<&Element> {
+        self.grammar.rules[pos.rule as usize][pos.alt as usize].get(pos.elem as usize)
+    }
+
+    /// 展开栈顶的规则引用，直到栈顶为字符元素或栈为空
+    fn expand(&self, mut stack: Stack, out: &mut Vec<Stack>, seen: &mut HashSet<Stack>, depth: usize) {
+        if depth > MAX_EXPAND_DEPTH {
+            return;
+        }
+        while let Some(&top) = stack.last() {
+            match self.element(top) {
+                // 当前备选已匹配完，回到上一层
+                None => {
+                    stack.pop();
+                }
+                Some(Element::Chars { .. }) => break,
+                Some(&Element::Rule(rule)) => {
+                    stack.last_mut().unwrap().elem += 1;
+                    // 尾调用：上一层已无剩余元素时直接出栈，避免右递归规则使栈无限增长
+                    while stack.last().is_some_and(|&p| self.element(p).is_none()) {
+                        stack.pop();
+                    }
+                    for alt in 0..self.grammar.rules[rule].len() {
+                        let mut next = stack.clone();
+                        next.push(Pos { rule: rule as u32, alt: alt as u32, elem: 0 });
+                        self.expand(next, out, seen, depth + 1);
+                    }
+                    return;
+                }
+            }
+        }
+        if seen.insert(stack.clone()) {
+            out.push(stack);
+        }
+    }
+
+    fn advance(&self, stacks: &[Stack], c: char) -> Vec<Stack> {
+        let mut out = Vec::new();
+        let mut seen = HashSet::new();
+        for stack in stacks {
+            let Some(&top) = stack.last() else { continue };
+            if self.element(top).is_some_and(|e| e.matches(c)) {
+                let mut next = stack.clone();
+                next.last_mut().unwrap().elem += 1;
+                self.expand(next, &mut out, &mut seen, 0);
+            }
+        }
+        out
+    }
+
+    /// 接受一个字符；不符合语法时返回 false 且状态不变
+    pub fn accept_char(&mut self, c: char) -> bool {
+        let next = self.advance(&self.stacks, c);
+        if next.is_empty() {
+            return false;
+        }
+        self.stacks = next;
+        true
+    }
+
+    /// 推进一段字节（token 可能只包含多字节字符的一部分）；不符合语法时返回 false 且状态不变
+    pub fn accept_bytes(&mut self, bytes: &[u8]) -> bool {
+        match self.simulate(bytes) {
+            Some((stacks, partial)) => {
+                self.stacks = stacks;
+                self.partial = partial;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 这段字节是否可以接在当前输出之后（不改变状态）
+    pub fn allows(&self, bytes: &[u8]) -> bool {
+        self.simulate(bytes).is_some()
+    }
+
+    fn simulate(&self, bytes: &[u8]) -> Option<(Vec<Stack>, Vec<u8>)> {
+        let mut buf = self.partial.clone();
+        buf.extend_from_slice(bytes);
+        let (text, rest) = match std::str::from_utf8(&buf) {
+            Ok(text) => (text, &[][..]),
+            // 结尾是未凑齐的多字节字符，留到下一个 token
+            Err(e) if e.error_len().is_none() => {
+                let (valid, rest) = buf.split_at(e.valid_up_to());
+                (std::str::from_utf8(valid).ok()?, rest)
+            }
+            Err(_) => return None,
+        };
+        // 逐 token 检查整个词表时，大多数 token 在第一个字符就被拒绝，此时不复制当前状态
+        let mut advanced: Option<Vec<Stack>> = None;
+        for c in text.chars() {
+            let next = self.advance(advanced.as_deref().unwrap_or(&self.stacks), c);
+            if next.is_empty() {
+                return None;
+            }
+            advanced = Some(next);
+        }
+        let stacks = advanced.unwrap_or_else(|| self.stacks.clone());
+        if !rest.is_empty() && !stacks.iter().any(|s| s.last().and_then(|&p| self.element(p)).is_some_and(Element::may_match_non_ascii)) {
+            return None;
+        }
+        Some((stacks, rest.to_vec()))
+    }
+
+    /// 当前输出已完整匹配语法（可以结束生成）
+    pub fn is_complete(&self) -> bool {
+        self.partial.is_empty() && self.stacks.iter().any(|s| s.is_empty())
+    }
+
+    /// 语法是否还允许继续输出
+    pub fn can_continue(&self) -> bool {
+        self.stacks.iter().any(|s| !s.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_matches_gbnf() {
+        let grammar = Grammar::parse(
+            r#"
+            # 注释与跨行规则
+            root ::= greeting ws name ("!" | "?")
+            greeting ::= "hi" | "hello"
+            ws ::= [ \t]+
+            name ::= [a-zA-Z] [a-z]{1,3}
+                   | "你\x41"
+            "#,
+        )
+        .unwrap();
+        for text in ["hi Bob!", "hello\t amy?", "hi 你A!"] {
+            assert!(grammar.matches(text), "{:?}", text);
+        }
+        for text in ["hi B!", "hi Bobby!", "hey Bob!", "hi Bob", "hiBob!", "hi Bob!!"] {
+            assert!(!grammar.matches(text), "{:?}", text);
+        }
+
+        let negated = Grammar::parse(r#"root ::= "\"" [^"\\]* "\"" | . "x"?"#).unwrap();
+        assert!(negated.matches(r#""a b""#));
+        assert!(negated.matches("é"));
+        assert!(negated.matches("éx"));
+        assert!(!negated.matches(r#""a"b""#));
+    }
+
+    #[test]
+    fn rejects_invalid_grammars() {
+        for (src, message) in [
+            (r#"start ::= "a""#, "no 'root' rule"),
+            ("root ::= item", "'item' is referenced but not defined"),
+            (r#"root ::= "a"  root ::= "b""#, "defined twice"),
+            ("root ::= * \"a\"", "Repetition without a preceding item"),
+            (r#"root ::= "a"{3,1}"#, "Invalid repetition"),
+            (r#"root ::= "a"{x}"#, "Expected number"),
+            (r#"root ::= ("a" "b""#, "Unexpected end of grammar"),
+            (r#"root ::= ("a"] "#, "Unexpected ']'"),
+            (r#"root ::= "abc"#, "Unexpected end of grammar"),
+            (r#"root ::= "\xZZ""#, "Invalid escape"),
+            ("root = \"a\"", "Expected '::='"),
+            ("root ::= \"a\" ;", "Unexpected ';'"),
+        ] {
+            let err = Grammar::parse(src).unwrap_err().to_string();
+            assert!(err.contains(message), "{:?}: {}", src, err);
+        }
+    }
+
+    #[test]
+    fn matcher_accepts_partial_utf8_and_keeps_state_on_rejection() {
+        let grammar = Grammar::parse(r#"root ::= "你好" [0-9]*"#).unwrap();
+        let mut matcher = GrammarMatcher::new(grammar);
+        let bytes = "你好".as_bytes();
+        // 多字节字符可以跨 token 拆分
+        assert!(matcher.allows(&bytes[..2]));
+        assert!(matcher.accept_bytes(&bytes[..2]));
+        assert!(!matcher.is_complete());
+        assert!(!matcher.allows(b"1"));
+        assert!(matcher.accept_bytes(&bytes[2..]));
+        assert!(matcher.is_complete());
+        assert!(matcher.can_continue());
+
+        // 被拒绝的输入不改变状态
+        assert!(!matcher.accept_bytes(b"1a"));
+        assert!(!matcher.accept_char('x'));
+        assert!(matcher.accept_bytes(b"42"));
+        assert!(matcher.is_complete());
+        // 非法 UTF-8 直接拒绝
+        assert!(!matcher.allows(&[0xff]));
+
+        // 固定长度的语法匹配完后不能继续
+        let mut fixed = GrammarMatcher::new(Grammar::parse(r#"root ::= "ok""#).unwrap());
+        assert!(fixed.accept_bytes(b"ok"));
+        assert!(fixed.is_complete());
+        assert!(!fixed.can_continue());
+    }
+}
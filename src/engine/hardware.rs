@@ -0,0 +1,413 @@
+// 硬件画像 - 记录内存、CPU 指令集、GPU 等信息，作为后端选择的依据
+// 选择规则是纯函数，可用保存下来的画像（JSON）复现任意机器上的选择结果
+
+use crate::engine::BackendType;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use sysinfo::System;
+
+/// 选择 GPU 后端所需的最小显存
+const MIN_GPU_VRAM_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CpuVendor {
+    Intel,
+    Amd,
+    Apple,
+    Arm,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimdFeatures {
+    pub avx2: bool,
+    pub avx512f: bool,
+    pub fma: bool,
+    pub neon: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GpuVendor {
+    Nvidia,
+    Amd,
+    Intel,
+    Apple,
+    Unknown,
+}
+
+impl GpuVendor {
+    /// PCI 厂商 ID
+    fn from_pci_id(id: u32) -> Self {
+        match id {
+            0x10de => GpuVendor::Nvidia,
+            0x1002 => GpuVendor::Amd,
+            0x8086 => GpuVendor::Intel,
+            _ => GpuVendor::Unknown,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            GpuVendor::Nvidia => "NVIDIA",
+            GpuVendor::Amd => "AMD",
+            GpuVendor::Intel => "Intel",
+            GpuVendor::Apple => "Apple",
+            GpuVendor::Unknown => "Unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GpuInfo {
+    pub vendor: GpuVendor,
+    pub name: String,
+    /// 独立显存；统一内存架构（Apple Silicon）或无法读取时为 None
+    pub vram_bytes: Option<u64>,
+    pub driver_version: Option<String>,
+}
+
+/// 本机硬件画像
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HardwareProfile {
+    /// std::env::consts::OS（macos / linux / windows）
+    pub os: String,
+    /// std::env::consts::ARCH（aarch64 / x86_64）
+    pub arch: String,
+    pub os_version: Option<String>,
+    pub cpu_brand: String,
+    pub cpu_vendor: CpuVendor,
+    pub physical_cores: Option<usize>,
+    pub logical_cores: usize,
+    pub simd: SimdFeatures,
+    pub total_memory_bytes: u64,
+    pub available_memory_bytes: u64,
+    pub gpus: Vec<GpuInfo>,
+}
+
+impl HardwareProfile {
+    /// 探测本机硬件（会调用 nvidia-smi，可能耗时数百毫秒，应在阻塞线程中执行）
+    pub fn detect() -> Self {
+        let mut sys = System::new();
+        sys.refresh_cpu();
+        sys.refresh_memory();
+
+        let cpu = sys.cpus().first();
+        let cpu_brand = cpu.map(|c| c.brand().trim().to_string()).unwrap_or_default();
+        let vendor_id = cpu.map(|c| c.vendor_id().to_string()).unwrap_or_default();
+        let os = std::env::consts::OS.to_string();
+        let arch = std::env::consts::ARCH.to_string();
+        let cpu_vendor = cpu_vendor(&vendor_id, &cpu_brand, &os, &arch);
+
+        let mut gpus = Vec::new();
+        if os == "linux" {
+            gpus = scan_drm(Path::new("/sys/class/drm"));
+        }
+        let nvidia = query_nvidia_smi();
+        if !nvidia.is_empty() {
+            // nvidia-smi 的信息更完整，替换 sysfs 中的 NVIDIA 条目
+            gpus.retain(|g| g.vendor != GpuVendor::Nvidia);
+            gpus.extend(nvidia);
+        }
+        if cpu_vendor == CpuVendor::Apple {
+            gpus.push(GpuInfo {
+                vendor: GpuVendor::Apple,
+                name: cpu_brand.clone(),
+                vram_bytes: None,
+                driver_version: None,
+            });
+        }
+
+        let profile = Self {
+            os,
+            arch,
+            os_version: System::long_os_version(),
+            cpu_brand,
+            cpu_vendor,
+            physical_cores: sys.physical_core_count(),
+            logical_cores: sys.cpus().len(),
+            simd: detect_simd(),
+            total_memory_bytes: sys.total_memory(),
+            available_memory_bytes: sys.available_memory(),
+            gpus,
+        };
+        tracing::info!(
+            "Hardware: {} ({:?}), {}/{} cores, {:.1} GiB RAM ({:.1} GiB available), {:?}, GPUs: [{}]",
+            profile.cpu_brand,
+            profile.cpu_vendor,
+            profile.physical_cores.unwrap_or(0),
+            profile.logical_cores,
+            gib(profile.total_memory_bytes),
+            gib(profile.available_memory_bytes),
+            profile.simd,
+            profile.gpus.iter().map(|g| g.name.as_str()).collect::<Vec<_>>().join(", ")
+        );
+        profile
+    }
+
+    pub fn is_apple_silicon(&self) -> bool {
+        self.os == "macos" && self.arch == "aarch64"
+    }
+
+    /// 显存最大的 NVIDIA GPU
+    pub fn best_nvidia_gpu(&self) -> Option<&GpuInfo> {
+        self.gpus
+            .iter()
+            .filter(|g| g.vendor == GpuVendor::Nvidia)
+            .max_by_key(|g| g.vram_bytes.unwrap_or(0))
+    }
+}
+
+/// 后端选择结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackendSelection {
+    pub backend: BackendType,
+    /// 选择依据（写入日志，便于排查）
+    pub reason: String,
+}
+
+/// 按硬件画像选择本地推理后端（不含需要探测的本地服务）
+pub fn select_backend(profile: &HardwareProfile) -> BackendSelection {
+    // 规则 1: Apple Silicon 使用 MLX（统一内存，Metal 加速）
+    if profile.is_apple_silicon() {
+        return BackendSelection {
+            backend: BackendType::MlxSidecar,
+            reason: format!("Apple Silicon ({})", profile.cpu_brand),
+        };
+    }
+
+    // 规则 2: 显存足够的 NVIDIA GPU 使用 Inferflow
+    if let Some(gpu) = profile.best_nvidia_gpu() {
+        match gpu.vram_bytes {
+            Some(vram) if vram >= MIN_GPU_VRAM_BYTES => {
+                return BackendSelection {
+                    backend: BackendType::InferflowCpp,
+                    reason: format!("NVIDIA GPU {} with {:.1} GiB VRAM", gpu.name, gib(vram)),
+                };
+            }
+            vram => tracing::info!(
+                "NVIDIA GPU {} has insufficient or unknown VRAM ({:?} bytes), using CPU",
+                gpu.name,
+                vram
+            ),
+        }
+    }
+
+    // 规则 3: CPU 后端
+    let simd = if profile.simd.avx512f {
+        "AVX-512"
+    } else if profile.simd.avx2 {
+        "AVX2"
+    } else if profile.simd.neon {
+        "NEON"
+    } else {
+        "no SIMD"
+    };
+    BackendSelection {
+        backend: BackendType::LlamaCppCpu,
+        reason: format!("CPU {} ({}, {} threads)", profile.cpu_brand, simd, profile.logical_cores),
+    }
+}
+
+fn gib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0 * 1024.0)
+}
+
+fn cpu_vendor(vendor_id: &str, brand: &str, os: &str, arch: &str) -> CpuVendor {
+    match vendor_id {
+        "GenuineIntel" => CpuVendor::Intel,
+        "AuthenticAMD" => CpuVendor::Amd,
+        _ if brand.starts_with("Apple") || (os == "macos" && arch == "aarch64") => CpuVendor::Apple,
+        _ if arch == "aarch64" || arch == "arm" => CpuVendor::Arm,
+        _ => CpuVendor::Unknown,
+    }
+}
+
+fn detect_simd() -> SimdFeatures {
+    #[cfg(target_arch = "x86_64")]
+    {
+        SimdFeatures {
+            avx2: std::arch::is_x86_feature_detected!("avx2"),
+            avx512f: std::arch::is_x86_feature_detected!("avx512f"),
+            fma: std::arch::is_x86_feature_detected!("fma"),
+            neon: false,
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        SimdFeatures {
+            neon: std::arch::is_aarch64_feature_detected!("neon"),
+            ..SimdFeatures::default()
+        }
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        SimdFeatures::default()
+    }
+}
+
+/// 扫描 /sys/class/drm/cardN/device 下的 PCI 厂商与显存信息
+pub fn scan_drm(root: &Path) -> Vec<GpuInfo> {
+    let Ok(entries) = std::fs::read_dir(root) else {
+        return vec![];
+    };
+    let mut cards: Vec<_> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        // 只取 card0、card1 等设备节点，跳过 card0-HDMI-A-1 等连接器
+        .filter(|name| name.strip_prefix("card").is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())))
+        .collect();
+    cards.sort();
+
+    cards
+        .into_iter()
+        .filter_map(|card| {
+            let device = root.join(&card).join("device");
+            let read = |file: &str| std::fs::read_to_string(device.join(file)).ok().map(|s| s.trim().to_string());
+            let parse_hex = |s: String| u32::from_str_radix(s.trim_start_matches("0x"), 16).ok();
+            let vendor_id = read("vendor").and_then(parse_hex)?;
+            let device_id = read("device").and_then(parse_hex).unwrap_or(0);
+            let vendor = GpuVendor::from_pci_id(vendor_id);
+            Some(GpuInfo {
+                vendor,
+                name: format!("{} GPU [{:04x}:{:04x}]", vendor.label(), vendor_id, device_id),
+                // amdgpu 驱动提供显存大小；i915 等集成显卡没有
+                vram_bytes: read("mem_info_vram_total").and_then(|s| s.parse().ok()),
+                driver_version: None,
+            })
+        })
+        .collect()
+}
+
+fn query_nvidia_smi() -> Vec<GpuInfo> {
+    let output = std::process::Command::new("nvidia-smi")
+        .args([
+            "--query-gpu=name,memory.total,driver_version",
+            "--format=csv,noheader,nounits",
+        ])
+        .output();
+    match output {
+        Ok(output) if output.status.success() => parse_nvidia_smi(&String::from_utf8_lossy(&output.stdout)),
+        Ok(output) => {
+            tracing::debug!("nvidia-smi exited with {}", output.status);
+            vec![]
+        }
+        // 未安装 NVIDIA 驱动
+        Err(_) => vec![],
+    }
+}
+
+/// 解析 `nvidia-smi --query-gpu=name,memory.total,driver_version --format=csv,noheader,nounits`
+/// 每行一块 GPU，显存单位为 MiB
+pub fn parse_nvidia_smi(output: &str) -> Vec<GpuInfo> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [name, memory, driver] = fields.as_slice() else {
+                return None;
+            };
+            if name.is_empty() {
+                return None;
+            }
+            Some(GpuInfo {
+                vendor: GpuVendor::Nvidia,
+                name: name.to_string(),
+                vram_bytes: memory.parse::<u64>().ok().map(|mib| mib * 1024 * 1024),
+                driver_version: Some(driver.to_string()).filter(|d| !d.is_empty()),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 录制的硬件画像（fixtures/hardware）
+    fn fixture(json: &str) -> HardwareProfile {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn apple_silicon_selects_mlx() {
+        let selection = select_backend(&fixture(include_str!("../../fixtures/hardware/apple_m2.json")));
+        assert_eq!(selection.backend, BackendType::MlxSidecar);
+        assert!(selection.reason.contains("Apple M2 Pro"));
+    }
+
+    #[test]
+    fn nvidia_with_enough_vram_selects_inferflow() {
+        let selection = select_backend(&fixture(include_str!("../../fixtures/hardware/nvidia_rtx4090.json")));
+        assert_eq!(selection.backend, BackendType::InferflowCpp);
+        assert!(selection.reason.contains("RTX 4090"));
+    }
+
+    #[test]
+    fn nvidia_with_little_vram_falls_back_to_cpu() {
+        let profile = fixture(include_str!("../../fixtures/hardware/nvidia_gt1030.json"));
+        assert!(profile.best_nvidia_gpu().is_some());
+        assert_eq!(select_backend(&profile).backend, BackendType::LlamaCppCpu);
+    }
+
+    #[test]
+    fn amd_gpu_uses_cpu() {
+        let profile = fixture(include_str!("../../fixtures/hardware/amd_radeon.json"));
+        assert!(profile.best_nvidia_gpu().is_none());
+        let selection = select_backend(&profile);
+        assert_eq!(selection.backend, BackendType::LlamaCppCpu);
+        assert!(selection.reason.contains("AVX2"));
+    }
+
+    #[test]
+    fn cpu_only() {
+        let selection = select_backend(&fixture(include_str!("../../fixtures/hardware/cpu_only.json")));
+        assert_eq!(selection.backend, BackendType::LlamaCppCpu);
+        assert!(selection.reason.contains("AVX-512"));
+    }
+
+    #[test]
+    fn parses_nvidia_smi_output() {
+        let output = "NVIDIA GeForce RTX 4090, 24564, 550.54.14\nNVIDIA GeForce GT 1030, 2048, 470.239.06\n\n";
+        let gpus = parse_nvidia_smi(output);
+        assert_eq!(gpus.len(), 2);
+        assert_eq!(gpus[0].name, "NVIDIA GeForce RTX 4090");
+        assert_eq!(gpus[0].vram_bytes, Some(24564 * 1024 * 1024));
+        assert_eq!(gpus[0].driver_version.as_deref(), Some("550.54.14"));
+        assert_eq!(gpus[1].vram_bytes, Some(2048 * 1024 * 1024));
+
+        // 显存为 [N/A] 时保留 GPU，显存未知
+        let gpus = parse_nvidia_smi("NVIDIA A100-SXM4-40GB, [N/A], 535.104.05\nmalformed line\n");
+        assert_eq!(gpus.len(), 1);
+        assert_eq!(gpus[0].vram_bytes, None);
+    }
+
+    #[test]
+    fn scans_drm_sysfs() {
+        let root = std::env::temp_dir().join(format!("silo-drm-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for (card, vendor, vram) in [("card0", "0x8086", None), ("card1", "0x1002", Some("17163091968"))] {
+            let device = root.join(card).join("device");
+            std::fs::create_dir_all(&device).unwrap();
+            std::fs::write(device.join("vendor"), format!("{}\n", vendor)).unwrap();
+            std::fs::write(device.join("device"), "0x73bf\n").unwrap();
+            if let Some(vram) = vram {
+                std::fs::write(device.join("mem_info_vram_total"), vram).unwrap();
+            }
+        }
+        // 连接器节点与没有 vendor 文件的设备被跳过
+        std::fs::create_dir_all(root.join("card0-HDMI-A-1")).unwrap();
+        std::fs::create_dir_all(root.join("card2").join("device")).unwrap();
+
+        let gpus = scan_drm(&root);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(gpus.len(), 2);
+        assert_eq!(gpus[0].vendor, GpuVendor::Intel);
+        assert_eq!(gpus[0].vram_bytes, None);
+        assert_eq!(gpus[1].vendor, GpuVendor::Amd);
+        assert_eq!(gpus[1].name, "AMD GPU [1002:73bf]");
+        assert_eq!(gpus[1].vram_bytes, Some(17163091968));
+        assert!(scan_drm(&root).is_empty());
+    }
+}
@@ -0,0 +1,567 @@
+// JSON Schema - 转换为 GBNF 语法用于约束解码，并校验生成结果
+// 支持常用子集：object / array / string / number / integer / boolean / null、enum、const、
+// anyOf / oneOf / allOf、类型数组与指向 #/$defs、#/definitions 的 $ref
+
+use anyhow::Result;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// 通用 JSON 值的语法规则（每个值后允许少量空白）
+const JSON_RULES: &[(&str, &str)] = &[
+    ("ws", r#"[ \t\n]{0,20}"#),
+    ("value", r#"object | array | string | number | boolean | null"#),
+    ("object", r#""{" ws ( string ":" ws value ( "," ws string ":" ws value )* )? "}" ws"#),
+    ("array", r#""[" ws ( value ( "," ws value )* )? "]" ws"#),
+    ("string", r#""\"" char* "\"" ws"#),
+    ("char", r#"[^"\\\x7F\x00-\x1F] | "\\" ( ["\\/bfnrt] | "u" [0-9a-fA-F]{4} )"#),
+    ("number", r#""-"? integral ( "." [0-9]+ )? ( [eE] [-+]? [0-9]+ )? ws"#),
+    ("integer", r#""-"? integral ws"#),
+    ("integral", r#""0" | [1-9] [0-9]{0,15}"#),
+    ("boolean", r#"( "true" | "false" ) ws"#),
+    ("null", r#""null" ws"#),
+];
+
+/// 任意 JSON 值的 GBNF 语法
+pub fn json_gbnf() -> String {
+    let mut converter = Converter::new(&Value::Null);
+    converter.use_rule("value");
+    converter.finish("value")
+}
+
+/// 把 JSON Schema 转换为 GBNF 语法
+pub fn schema_to_gbnf(schema: &Value) -> Result<String> {
+    let mut converter = Converter::new(schema);
+    let root = converter.visit(schema, "root-value")?;
+    Ok(converter.finish(&root))
+}
+
+struct Converter<'a> {
+    root_schema: &'a Value,
+    rules: Vec<(String, String)>,
+    /// $ref 到规则名的映射（同时用于支持递归结构）
+    refs: HashMap<String, String>,
+}
+
+impl<'a> Converter<'a> {
+    fn new(root_schema: &'a Value) -> Self {
+        Self {
+            root_schema,
+            rules: Vec::new(),
+            refs: HashMap::new(),
+        }
+    }
+
+    fn finish(mut self, root: &str) -> String {
+        self.use_rule("ws");
+        let mut out = format!("root ::= {}\n", root);
+        for (name, body) in &self.rules {
+            out.push_str(&format!("{} ::= {}\n", name, body));
+        }
+        out
+    }
+
+    fn has_rule(&self, name: &str) -> bool {
+        self.rules.iter().any(|(n, _)| n == name)
+    }
+
+    /// 引入通用 JSON 规则及其依赖
+    fn use_rule(&mut self, name: &str) {
+        if self.has_rule(name) {
+            return;
+        }
+        let Some(&(_, body)) = JSON_RULES.iter().find(|(n, _)| *n == name) else { return };
+        self.rules.push((name.to_string(), body.to_string()));
+        for (dep, _) in JSON_RULES {
+            if body.split(|c: char| !c.is_ascii_alphanumeric()).any(|word| word == *dep) {
+                self.use_rule(dep);
+            }
+        }
+    }
+
+    /// 添加规则，名称冲突时追加序号
+    fn add_rule(&mut self, hint: &str, body: String) -> String {
+        let base: String = hint
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+            .collect();
+        let base = if base.is_empty() { "rule".to_string() } else { base };
+        let mut name = base.clone();
+        let mut n = 1;
+        while self.has_rule(&name) || JSON_RULES.iter().any(|(r, _)| *r == name) || name == "root" {
+            name = format!("{}-{}", base, n);
+            n += 1;
+        }
+        self.rules.push((name.clone(), body));
+        name
+    }
+
+    /// 为 schema 生成规则，返回可在其他规则中引用的表达式
+    fn visit(&mut self, schema: &Value, hint: &str) -> Result<String> {
+        let Some(obj) = schema.as_object() else {
+            // true 或缺省表示任意值
+            self.use_rule("value");
+            return Ok("value".to_string());
+        };
+
+        if let Some(reference) = obj.get("$ref").and_then(Value::as_str) {
+            return self.visit_ref(reference);
+        }
+        if let Some(value) = obj.get("const") {
+            return Ok(self.add_rule(hint, format!("{} ws", literal(&value.to_string()))));
+        }
+        if let Some(values) = obj.get("enum").and_then(Value::as_array) {
+            let alternatives: Vec<String> = values.iter().map(|v| literal(&v.to_string())).collect();
+            self.use_rule("ws");
+            return Ok(self.add_rule(hint, format!("( {} ) ws", alternatives.join(" | "))));
+        }
+        for key in ["anyOf", "oneOf"] {
+            if let Some(variants) = obj.get(key).and_then(Value::as_array) {
+                let alternatives = variants
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| self.visit(v, &format!("{}-{}", hint, i)))
+                    .collect::<Result<Vec<_>>>()?;
+                return Ok(self.add_rule(hint, alternatives.join(" | ")));
+            }
+        }
+        if let Some(parts) = obj.get("allOf").and_then(Value::as_array) {
+            return self.visit(&merge_all_of(obj, parts, self.root_schema)?, hint);
+        }
+
+        match obj.get("type") {
+            Some(Value::Array(types)) => {
+                let alternatives = types
+                    .iter()
+                    .map(|t| {
+                        let mut single = obj.clone();
+                        single.insert("type".to_string(), t.clone());
+                        self.visit(&Value::Object(single), &format!("{}-{}", hint, t.as_str().unwrap_or("value")))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(self.add_rule(hint, alternatives.join(" | ")))
+            }
+            Some(Value::String(t)) => self.visit_type(t, obj, hint),
+            Some(other) => anyhow::bail!("Unsupported JSON Schema type: {}", other),
+            None if obj.contains_key("properties") => self.visit_type("object", obj, hint),
+            None if obj.contains_key("items") => self.visit_type("array", obj, hint),
+            None => {
+                self.use_rule("value");
+                Ok("value".to_string())
+            }
+        }
+    }
+
+    fn visit_ref(&mut self, reference: &str) -> Result<String> {
+        if let Some(name) = self.refs.get(reference) {
+            return Ok(name.clone());
+        }
+        let target = resolve_ref(self.root_schema, reference)?;
+        // 先占位再展开，递归引用直接指向该规则
+        let hint = reference.rsplit('/').next().unwrap_or("ref").to_string();
+        let name = self.add_rule(&hint, String::new());
+        self.refs.insert(reference.to_string(), name.clone());
+        let body = self.visit(target, &format!("{}-inner", hint))?;
+        if let Some(rule) = self.rules.iter_mut().find(|(n, _)| *n == name) {
+            rule.1 = body;
+        }
+        Ok(name)
+    }
+
+    fn visit_type(&mut self, ty: &str, obj: &Map<String, Value>, hint: &str) -> Result<String> {
+        match ty {
+            "object" => self.visit_object(obj, hint),
+            "array" => self.visit_array(obj, hint),
+            "string" => {
+                let min = obj.get("minLength").and_then(Value::as_u64);
+                let max = obj.get("maxLength").and_then(Value::as_u64);
+                if min.is_none() && max.is_none() {
+                    self.use_rule("string");
+                    return Ok("string".to_string());
+                }
+                self.use_rule("char");
+                self.use_rule("ws");
+                let repeat = match max {
+                    Some(max) => format!("{{{},{}}}", min.unwrap_or(0), max),
+                    None => format!("{{{},}}", min.unwrap_or(0)),
+                };
+                Ok(self.add_rule(hint, format!(r#""\"" char{} "\"" ws"#, repeat)))
+            }
+            "number" | "integer" | "boolean" | "null" => {
+                self.use_rule(ty);
+                Ok(ty.to_string())
+            }
+            other => anyhow::bail!("Unsupported JSON Schema type: {}", other),
+        }
+    }
+
+    fn visit_object(&mut self, obj: &Map<String, Value>, hint: &str) -> Result<String> {
+        let Some(properties) = obj.get("properties").and_then(Value::as_object).filter(|p| !p.is_empty()) else {
+            self.use_rule("object");
+            return Ok("object".to_string());
+        };
+        let required: Vec<&str> = obj
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|r| r.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+
+        // 属性顺序固定：必填属性按 required 中的顺序在前（如先 thought 后 actions），可选属性按名称在后
+        let mut members = Vec::new();
+        let ordered = required
+            .iter()
+            .filter_map(|&k| properties.get_key_value(k))
+            .chain(properties.iter().filter(|(k, _)| !required.contains(&k.as_str())));
+        for (key, prop) in ordered {
+            let value = self.visit(prop, &format!("{}-{}", hint, key))?;
+            let member = self.add_rule(
+                &format!("{}-{}-kv", hint, key),
+                format!(r#"{} ws ":" ws {}"#, literal(&Value::String(key.clone()).to_string()), value),
+            );
+            members.push((member, required.contains(&key.as_str())));
+        }
+        self.use_rule("ws");
+        let body = format!(r#""{{" ws {} "}}" ws"#, members_expr(&members, true));
+        Ok(self.add_rule(hint, body))
+    }
+
+    fn visit_array(&mut self, obj: &Map<String, Value>, hint: &str) -> Result<String> {
+        let item = match obj.get("items") {
+            Some(items) => self.visit(items, &format!("{}-item", hint))?,
+            None => {
+                self.use_rule("value");
+                "value".to_string()
+            }
+        };
+        let min = obj.get("minItems").and_then(Value::as_u64).unwrap_or(0);
+        let max = obj.get("maxItems").and_then(Value::as_u64);
+        self.use_rule("ws");
+        let list = match max {
+            Some(0) => String::new(),
+            Some(max) => format!(r#"{} ( "," ws {} ){{{},{}}}"#, item, item, min.saturating_sub(1), max - 1),
+            None => format!(r#"{} ( "," ws {} ){{{},}}"#, item, item, min.saturating_sub(1)),
+        };
+        let list = if min == 0 && !list.is_empty() { format!("( {} )?", list) } else { list };
+        Ok(self.add_rule(hint, format!(r#""[" ws {} "]" ws"#, list)))
+    }
+}
+
+/// 对象成员序列：必填成员必须出现，可选成员可省略，逗号只出现在成员之间
+fn members_expr(members: &[(String, bool)], first: bool) -> String {
+    let Some(((member, required), rest)) = members.split_first() else {
+        return String::new();
+    };
+    let sep = if first { "" } else { r#""," ws "# };
+    if *required {
+        format!("{}{} {}", sep, member, members_expr(rest, false))
+    } else if !first {
+        format!("( {}{} )? {}", sep, member, members_expr(rest, false))
+    } else {
+        // 首个可选成员：出现则后续成员需要逗号，省略则下一个成员成为首个
+        format!("( {} {} | {} )", member, members_expr(rest, false), members_expr(rest, true))
+    }
+}
+
+/// 把文本写成 GBNF 字符串字面量
+fn literal(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn resolve_ref<'v>(root: &'v Value, reference: &str) -> Result<&'v Value> {
+    let pointer = reference
+        .strip_prefix('#')
+        .ok_or_else(|| anyhow::anyhow!("Only local $ref is supported: {}", reference))?;
+    root.pointer(pointer)
+        .ok_or_else(|| anyhow::anyhow!("Unresolved $ref: {}", reference))
+}
+
+/// 合并 allOf 中的对象约束（properties 与 required 取并集）
+fn merge_all_of(obj: &Map<String, Value>, parts: &[Value], root: &Value) -> Result<Value> {
+    let mut merged = obj.clone();
+    merged.remove("allOf");
+    let mut properties = merged
+        .remove("properties")
+        .and_then(|p| p.as_object().cloned())
+        .unwrap_or_default();
+    let mut required: Vec<Value> = merged
+        .remove("required")
+        .and_then(|r| r.as_array().cloned())
+        .unwrap_or_default();
+    for part in parts {
+        let part = match part.get("$ref").and_then(Value::as_str) {
+            Some(reference) => resolve_ref(root, reference)?,
+            None => part,
+        };
+        let Some(part) = part.as_object() else { continue };
+        for (key, value) in part {
+            match key.as_str() {
+                "properties" => properties.extend(value.as_object().cloned().unwrap_or_default()),
+                "required" => required.extend(value.as_array().cloned().unwrap_or_default()),
+                _ => {
+                    merged.entry(key.clone()).or_insert_with(|| value.clone());
+                }
+            }
+        }
+    }
+    if !properties.is_empty() {
+        merged.insert("properties".to_string(), Value::Object(properties));
+    }
+    if !required.is_empty() {
+        merged.insert("required".to_string(), Value::Array(required));
+    }
+    Ok(Value::Object(merged))
+}
+
+/// 校验 JSON 值是否符合 schema，不符合时返回第一处错误（带 JSON 路径）
+pub fn validate(schema: &Value, value: &Value) -> std::result::Result<(), String> {
+    validate_at(schema, schema, value, "$")
+}
+
+fn validate_at(root: &Value, schema: &Value, value: &Value, path: &str) -> std::result::Result<(), String> {
+    let Some(obj) = schema.as_object() else {
+        return match schema {
+            Value::Bool(false) => Err(format!("{}: no value is allowed here", path)),
+            _ => Ok(()),
+        };
+    };
+
+    if let Some(reference) = obj.get("$ref").and_then(Value::as_str) {
+        let target = resolve_ref(root, reference).map_err(|e| e.to_string())?;
+        return validate_at(root, target, value, path);
+    }
+    if let Some(expected) = obj.get("const")
+        && expected != value
+    {
+        return Err(format!("{}: expected {}", path, expected));
+    }
+    if let Some(values) = obj.get("enum").and_then(Value::as_array)
+        && !values.contains(value)
+    {
+        let allowed: Vec<String> = values.iter().map(Value::to_string).collect();
+        return Err(format!("{}: expected one of {}", path, allowed.join(", ")));
+    }
+    for key in ["anyOf", "oneOf"] {
+        if let Some(variants) = obj.get(key).and_then(Value::as_array) {
+            let errors: Vec<String> = variants
+                .iter()
+                .filter_map(|v| validate_at(root, v, value, path).err())
+                .collect();
+            if errors.len() == variants.len() {
+                return Err(format!("{}: does not match any allowed variant ({})", path, errors.join("; ")));
+            }
+        }
+    }
+    if let Some(parts) = obj.get("allOf").and_then(Value::as_array) {
+        for part in parts {
+            validate_at(root, part, value, path)?;
+        }
+    }
+
+    if let Some(ty) = obj.get("type") {
+        let types: Vec<&str> = match ty {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        if !types.iter().any(|t| type_matches(t, value)) {
+            return Err(format!("{}: expected {}, got {}", path, types.join(" or "), type_name(value)));
+        }
+    }
+
+    match value {
+        Value::Object(map) => {
+            if let Some(required) = obj.get("required").and_then(Value::as_array) {
+                for key in required.iter().filter_map(Value::as_str) {
+                    if !map.contains_key(key) {
+                        return Err(format!("{}: missing required property \"{}\"", path, key));
+                    }
+                }
+            }
+            let properties = obj.get("properties").and_then(Value::as_object);
+            for (key, item) in map {
+                match properties.and_then(|p| p.get(key)) {
+                    Some(prop) => validate_at(root, prop, item, &format!("{}.{}", path, key))?,
+                    None if obj.get("additionalProperties") == Some(&Value::Bool(false)) => {
+                        return Err(format!("{}: unexpected property \"{}\"", path, key));
+                    }
+                    None => {}
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(min) = obj.get("minItems").and_then(Value::as_u64)
+                && (items.len() as u64) < min
+            {
+                return Err(format!("{}: expected at least {} items", path, min));
+            }
+            if let Some(max) = obj.get("maxItems").and_then(Value::as_u64)
+                && items.len() as u64 > max
+            {
+                return Err(format!("{}: expected at most {} items", path, max));
+            }
+            if let Some(item_schema) = obj.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    validate_at(root, item_schema, item, &format!("{}[{}]", path, i))?;
+                }
+            }
+        }
+        Value::String(s) => {
+            let len = s.chars().count() as u64;
+            if let Some(min) = obj.get("minLength").and_then(Value::as_u64)
+                && len < min
+            {
+                return Err(format!("{}: expected at least {} characters", path, min));
+            }
+            if let Some(max) = obj.get("maxLength").and_then(Value::as_u64)
+                && len > max
+            {
+                return Err(format!("{}: expected at most {} characters", path, max));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn type_matches(ty: &str, value: &Value) -> bool {
+    match ty {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|f| f.fract() == 0.0),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Object(_) => "object",
+        Value::Array(_) => "array",
+        Value::String(_) => "string",
+        Value::Number(_) => "number",
+        Value::Bool(_) => "boolean",
+        Value::Null => "null",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::grammar::Grammar;
+    use serde_json::json;
+
+    fn grammar(schema: &Value) -> std::sync::Arc<Grammar> {
+        let gbnf = schema_to_gbnf(schema).unwrap();
+        Grammar::parse(&gbnf).unwrap_or_else(|e| panic!("{}\n{}", e, gbnf))
+    }
+
+    #[test]
+    fn object_grammar_follows_required_order_and_item_limits() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "tags": { "type": "array", "items": { "enum": ["x", "y"] }, "minItems": 1, "maxItems": 2 },
+                "age": { "type": "integer" },
+                "name": { "type": "string", "maxLength": 4 },
+            },
+            "required": ["name", "age"],
+        });
+        let grammar = grammar(&schema);
+        for text in [
+            r#"{"name":"bob","age":3}"#,
+            r#"{ "name": "bob", "age": -12, "tags": ["x", "y"] }"#,
+            r#"{"name":"","age":0,"tags":["y"]}"#,
+        ] {
+            assert!(grammar.matches(text), "{}", text);
+            validate(&schema, &serde_json::from_str(text).unwrap()).unwrap();
+        }
+        for text in [
+            r#"{"name":"bob"}"#,
+            r#"{"age":3,"name":"bob"}"#,
+            r#"{"name":"bobby","age":3}"#,
+            r#"{"name":"bob","age":3.5}"#,
+            r#"{"name":"bob","age":3,"tags":[]}"#,
+            r#"{"name":"bob","age":3,"tags":["x","y","x"]}"#,
+            r#"{"name":"bob","age":3,"tags":["z"]}"#,
+            r#"{"name":"bob","age":3,}"#,
+        ] {
+            assert!(!grammar.matches(text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn optional_members_and_enums() {
+        let grammar = grammar(&json!({
+            "properties": { "a": { "type": "boolean" }, "b": { "enum": ["red", 1, null] } }
+        }));
+        for text in [r#"{}"#, r#"{"b":"red"}"#, r#"{"a":true,"b":null}"#, r#"{"a":false}"#, r#"{"b":1}"#] {
+            assert!(grammar.matches(text), "{}", text);
+        }
+        for text in [r#"{,"b":1}"#, r#"{"a":true"b":1}"#, r#"{"b":"blue"}"#, r#"{"b":2}"#, r#"{"b":1,"a":true}"#] {
+            assert!(!grammar.matches(text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn refs_any_of_and_generic_json() {
+        let schema = json!({
+            "$defs": {
+                "node": {
+                    "type": "object",
+                    "properties": { "children": { "type": "array", "items": { "$ref": "#/$defs/node" } } },
+                    "required": ["children"],
+                }
+            },
+            "anyOf": [{ "$ref": "#/$defs/node" }, { "type": ["integer", "null"] }],
+        });
+        let grammar = grammar(&schema);
+        for text in [r#"{"children":[{"children":[]}]}"#, "7", "null"] {
+            assert!(grammar.matches(text), "{}", text);
+        }
+        assert!(!grammar.matches(r#"{"children":[{}]}"#));
+        assert!(!grammar.matches(r#""7""#));
+
+        let any = Grammar::parse(&json_gbnf()).unwrap();
+        assert!(any.matches(r#"{"a": [1, 2.5e3, "é\n", true, null, {}]}"#));
+        assert!(!any.matches("{'a': 1}"));
+
+        assert!(schema_to_gbnf(&json!({ "type": "date" })).is_err());
+        assert!(schema_to_gbnf(&json!({ "$ref": "#/$defs/missing" })).is_err());
+        assert!(schema_to_gbnf(&json!({ "$ref": "other.json#/a" })).is_err());
+    }
+
+    #[test]
+    fn validate_reports_json_path() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "items": { "type": "array", "items": { "type": "integer" }, "maxItems": 3 },
+                "mode": { "enum": ["fast", "slow"] },
+            },
+            "required": ["items"],
+            "additionalProperties": false,
+        });
+        let check = |value: Value| validate(&schema, &value).unwrap_err();
+        assert_eq!(check(json!({})), "$: missing required property \"items\"");
+        assert_eq!(check(json!({ "items": [1, "2"] })), "$.items[1]: expected integer, got string");
+        assert_eq!(check(json!({ "items": [1, 2, 3, 4] })), "$.items: expected at most 3 items");
+        assert_eq!(check(json!({ "items": [], "mode": "x" })), "$.mode: expected one of \"fast\", \"slow\"");
+        assert_eq!(check(json!({ "items": [], "extra": 1 })), "$: unexpected property \"extra\"");
+        assert_eq!(check(json!([])), "$: expected object, got array");
+        validate(&schema, &json!({ "items": [1.0, 2], "mode": "fast" })).unwrap();
+    }
+}
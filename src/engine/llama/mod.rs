@@ -0,0 +1,830 @@
+// 纯 Rust GGUF 推理运行时 (CPU)
+// 支持 llama 系列（Llama 2/3、Mistral）与 qwen2 架构，权重通过 mmap 按需反量化
+
+pub mod quant;
+pub mod sampler;
+pub mod tokenizer;
+
+use crate::engine::gguf::{GgmlType, GgufFile, GgufModelInfo, GgufTensorInfo};
+use crate::engine::grammar::GrammarMatcher;
+use crate::engine::prefix_cache::{CacheState, PrefixCache, PrefixCacheConfig, PrefixCacheStats};
+use crate::engine::{FinishReason, GenerationParams, StopMatcher, TokenUsage};
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use sampler::Sampler;
+use std::path::Path;
+use tokenizer::GgufTokenizer;
+
+/// 预填充阶段每批处理的 token 数，限制中间激活的内存占用
+const PREFILL_BATCH: usize = 64;
+
+/// CPU 运行时支持的模型架构
+pub const SUPPORTED_ARCHITECTURES: &[&str] = &["llama", "mistral", "qwen2"];
+
+/// CPU 运行时支持的内嵌分词器类型
+pub const SUPPORTED_TOKENIZERS: &[&str] = &["llama", "gpt2"];
+
+/// 加载前检查模型能否由 CPU 运行时执行，给出可读的拒绝原因
+pub fn check_compatible(info: &GgufModelInfo) -> Result<()> {
+    if !SUPPORTED_ARCHITECTURES.contains(&info.architecture.as_str()) {
+        anyhow::bail!(
+            "Model architecture '{}' is not supported by the CPU runtime (supported: {})",
+            info.architecture,
+            SUPPORTED_ARCHITECTURES.join(", ")
+        );
+    }
+    match info.tokenizer_model.as_deref() {
+        Some(model) if SUPPORTED_TOKENIZERS.contains(&model) => {}
+        Some(model) => anyhow::bail!("Tokenizer type '{}' is not supported (supported: {})", model, SUPPORTED_TOKENIZERS.join(", ")),
+        None => anyhow::bail!("Model file has no embedded tokenizer"),
+    }
+    let unsupported: Vec<&str> = info
+        .tensor_types
+        .iter()
+        .map(String::as_str)
+        .filter(|t| t.starts_with("ggml_type("))
+        .collect();
+    if !unsupported.is_empty() {
+        anyhow::bail!(
+            "Model quantization {} uses tensor types the CPU runtime cannot decode: {}",
+            info.quantization,
+            unsupported.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// 模型超参数（来自 GGUF 元数据）
+#[derive(Debug, Clone)]
+pub struct HParams {
+    pub architecture: String,
+    pub n_vocab: usize,
+    pub n_embd: usize,
+    pub n_layer: usize,
+    pub n_head: usize,
+    pub n_head_kv: usize,
+    pub n_ff: usize,
+    pub n_ctx_train: usize,
+    pub head_dim: usize,
+    pub rope_dim: usize,
+    pub rope_base: f32,
+    /// NEOX 风格 RoPE（前后半维配对），否则相邻两维配对
+    pub rope_neox: bool,
+    pub rms_eps: f32,
+    /// 计算文本向量时的汇聚方式
+    pub pooling: Pooling,
+}
+
+/// 文本向量的汇聚方式（对应 GGUF 的 {arch}.pooling_type）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pooling {
+    /// 所有 token 的平均
+    Mean,
+    /// 第一个 token
+    Cls,
+    /// 最后一个 token（gte-Qwen2 等基于解码器的嵌入模型）
+    Last,
+}
+
+/// mmap 中的一个二维权重张量
+struct Tensor {
+    ggml_type: GgmlType,
+    offset: usize,
+    rows: usize,
+    cols: usize,
+    row_bytes: usize,
+}
+
+struct Layer {
+    attn_norm: Vec<f32>,
+    wq: Tensor,
+    wk: Tensor,
+    wv: Tensor,
+    wo: Tensor,
+    bq: Option<Vec<f32>>,
+    bk: Option<Vec<f32>>,
+    bv: Option<Vec<f32>>,
+    ffn_norm: Vec<f32>,
+    w_gate: Tensor,
+    w_up: Tensor,
+    w_down: Tensor,
+}
+
+pub struct LlamaModel {
+    mmap: memmap2::Mmap,
+    hparams: HParams,
+    tok_embd: Tensor,
+    layers: Vec<Layer>,
+    output_norm: Vec<f32>,
+    output: Tensor,
+    rope_freq_factors: Option<Vec<f32>>,
+    tokenizer: GgufTokenizer,
+    /// 提示词前缀的 KV 缓存，未启用时为 None
+    prefix_cache: Option<PrefixCache<KvCache>>,
+}
+
+/// 单次生成的结果
+#[derive(Debug, Clone)]
+pub struct GenerateOutput {
+    pub pieces: Vec<String>,
+    pub finish_reason: FinishReason,
+    pub usage: TokenUsage,
+}
+
+/// 单个会话的 KV 缓存
+struct KvCache {
+    keys: Vec<Vec<f32>>,
+    values: Vec<Vec<f32>>,
+    len: usize,
+}
+
+impl KvCache {
+    fn new(n_layer: usize) -> Self {
+        Self {
+            keys: vec![Vec::new(); n_layer],
+            values: vec![Vec::new(); n_layer],
+            len: 0,
+        }
+    }
+
+    /// 复制前 len 个位置
+    fn prefix(&self, len: usize) -> Self {
+        let kv_dim = self.keys.first().map_or(0, |k| k.len() / self.len.max(1));
+        Self {
+            keys: self.keys.iter().map(|k| k[..len * kv_dim].to_vec()).collect(),
+            values: self.values.iter().map(|v| v[..len * kv_dim].to_vec()).collect(),
+            len,
+        }
+    }
+
+    fn truncate(&mut self, len: usize) {
+        let kv_dim = self.keys.first().map_or(0, |k| k.len() / self.len.max(1));
+        for layer in self.keys.iter_mut().chain(self.values.iter_mut()) {
+            layer.truncate(len * kv_dim);
+        }
+        self.len = len;
+    }
+}
+
+impl CacheState for KvCache {
+    fn size_bytes(&self) -> u64 {
+        self.keys.iter().chain(&self.values).map(|l| l.len() as u64 * 4).sum()
+    }
+
+    /// 层数、位置数、每层元素数，之后依次为各层的 K 与 V
+    fn to_bytes(&self) -> Vec<u8> {
+        let per_layer = self.keys.first().map_or(0, Vec::len);
+        let mut bytes = Vec::with_capacity(24 + self.size_bytes() as usize);
+        bytes.extend_from_slice(&(self.keys.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&(self.len as u64).to_le_bytes());
+        bytes.extend_from_slice(&(per_layer as u64).to_le_bytes());
+        for layer in self.keys.iter().chain(&self.values) {
+            for v in layer {
+                bytes.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let header = |i: usize| -> Result<usize> {
+            let b = bytes.get(i * 8..i * 8 + 8).context("Truncated KV cache state")?;
+            Ok(u64::from_le_bytes(b.try_into().unwrap()) as usize)
+        };
+        let (n_layer, len, per_layer) = (header(0)?, header(1)?, header(2)?);
+        let data = &bytes[24..];
+        // 每层元素数为 0 的文件无法按层切分，视为损坏
+        if per_layer == 0 {
+            anyhow::bail!("KV cache state has empty layers");
+        }
+        let expected = n_layer.checked_mul(2 * 4).and_then(|n| n.checked_mul(per_layer));
+        if expected != Some(data.len()) {
+            anyhow::bail!("KV cache state size mismatch");
+        }
+        let mut layers = data
+            .chunks_exact(per_layer * 4)
+            .map(|layer| layer.chunks_exact(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect::<Vec<f32>>());
+        let keys = layers.by_ref().take(n_layer).collect();
+        let values = layers.collect();
+        Ok(Self { keys, values, len })
+    }
+}
+
+impl LlamaModel {
+    /// 加载 GGUF 模型（权重以 mmap 方式映射，不整体读入内存）
+    pub fn load(path: &Path) -> Result<Self> {
+        let gguf = GgufFile::open(path)?;
+        let arch = gguf
+            .architecture()
+            .ok_or_else(|| anyhow::anyhow!("GGUF file has no general.architecture"))?
+            .to_string();
+        let rope_neox = match arch.as_str() {
+            "llama" | "mistral" => false,
+            "qwen2" => true,
+            other => anyhow::bail!(
+                "Model architecture '{}' is not supported by the CPU runtime (supported: {})",
+                other,
+                SUPPORTED_ARCHITECTURES.join(", ")
+            ),
+        };
+
+        let file = std::fs::File::open(path)?;
+        // SAFETY: 模型文件以只读方式映射，运行期间不会被本进程修改
+        let mmap = unsafe { memmap2::Mmap::map(&file) }
+            .with_context(|| format!("Failed to mmap model file: {:?}", path))?;
+
+        let required = |key: &str| -> Result<usize> {
+            gguf.get_arch_u64(key)
+                .map(|v| v as usize)
+                .ok_or_else(|| anyhow::anyhow!("GGUF metadata missing {}.{}", arch, key))
+        };
+        let n_embd = required("embedding_length")?;
+        let n_head = required("attention.head_count")?;
+        let head_dim = n_embd / n_head;
+        let tokenizer = GgufTokenizer::from_gguf(&gguf)?;
+
+        let hparams = HParams {
+            architecture: arch.clone(),
+            n_vocab: tokenizer.vocab_size(),
+            n_embd,
+            n_layer: required("block_count")?,
+            n_head,
+            n_head_kv: gguf.get_arch_u64("attention.head_count_kv").map(|v| v as usize).unwrap_or(n_head),
+            n_ff: required("feed_forward_length")?,
+            n_ctx_train: gguf.get_arch_u64("context_length").map(|v| v as usize).unwrap_or(2048),
+            head_dim,
+            rope_dim: gguf.get_arch_u64("rope.dimension_count").map(|v| v as usize).unwrap_or(head_dim),
+            rope_base: gguf.get_arch_f32("rope.freq_base").unwrap_or(10000.0),
+            rope_neox,
+            rms_eps: gguf.get_arch_f32("attention.layer_norm_rms_epsilon").unwrap_or(1e-5),
+            pooling: match gguf.get_arch_u64("pooling_type") {
+                Some(2) => Pooling::Cls,
+                Some(3) => Pooling::Last,
+                _ => Pooling::Mean,
+            },
+        };
+
+        let loader = TensorLoader { gguf: &gguf, mmap: &mmap };
+        let tok_embd = loader.matrix("token_embd.weight")?;
+        let output = if gguf.tensor("output.weight").is_some() {
+            loader.matrix("output.weight")?
+        } else {
+            // 词嵌入与输出层共享权重
+            loader.matrix("token_embd.weight")?
+        };
+        let output_norm = loader.vector("output_norm.weight")?;
+        let rope_freq_factors = loader.optional_vector("rope_freqs.weight")?;
+
+        let mut layers = Vec::with_capacity(hparams.n_layer);
+        for i in 0..hparams.n_layer {
+            let name = |suffix: &str| format!("blk.{}.{}", i, suffix);
+            layers.push(Layer {
+                attn_norm: loader.vector(&name("attn_norm.weight"))?,
+                wq: loader.matrix(&name("attn_q.weight"))?,
+                wk: loader.matrix(&name("attn_k.weight"))?,
+                wv: loader.matrix(&name("attn_v.weight"))?,
+                wo: loader.matrix(&name("attn_output.weight"))?,
+                bq: loader.optional_vector(&name("attn_q.bias"))?,
+                bk: loader.optional_vector(&name("attn_k.bias"))?,
+                bv: loader.optional_vector(&name("attn_v.bias"))?,
+                ffn_norm: loader.vector(&name("ffn_norm.weight"))?,
+                w_gate: loader.matrix(&name("ffn_gate.weight"))?,
+                w_up: loader.matrix(&name("ffn_up.weight"))?,
+                w_down: loader.matrix(&name("ffn_down.weight"))?,
+            });
+        }
+
+        tracing::info!(
+            "Loaded {} model: {} layers, {} embd, {} heads ({} kv), vocab {}",
+            hparams.architecture, hparams.n_layer, hparams.n_embd, hparams.n_head, hparams.n_head_kv, hparams.n_vocab
+        );
+
+        Ok(Self {
+            mmap,
+            hparams,
+            tok_embd,
+            layers,
+            output_norm,
+            output,
+            rope_freq_factors,
+            tokenizer,
+            prefix_cache: None,
+        })
+    }
+
+    /// 启用提示词前缀的 KV 缓存；namespace 标识模型文件（见 prefix_cache::model_namespace）
+    pub fn enable_prefix_cache(&mut self, namespace: u64, config: PrefixCacheConfig) -> Result<()> {
+        self.prefix_cache = Some(PrefixCache::open(namespace, config)?);
+        Ok(())
+    }
+
+    pub fn prefix_cache_stats(&self) -> Option<PrefixCacheStats> {
+        self.prefix_cache.as_ref().map(PrefixCache::stats)
+    }
+
+    pub fn hparams(&self) -> &HParams {
+        &self.hparams
+    }
+
+    pub fn tokenizer(&self) -> &GgufTokenizer {
+        &self.tokenizer
+    }
+
+    /// 生成文本；on_piece 每产生一段完整 UTF-8 文本回调一次，返回 false 时提前停止
+    pub fn generate(
+        &self,
+        prompt: &str,
+        context_size: usize,
+        params: &GenerationParams,
+        mut on_piece: impl FnMut(&str) -> bool,
+    ) -> Result<GenerateOutput> {
+        let n_ctx = context_size.min(self.hparams.n_ctx_train).max(1);
+        let prompt_tokens = self.tokenizer.encode(prompt, true);
+        if prompt_tokens.len() >= n_ctx {
+            anyhow::bail!(
+                "Prompt is {} tokens but the context window is {} tokens",
+                prompt_tokens.len(),
+                n_ctx
+            );
+        }
+
+        // 命中前缀缓存时从缓存的状态继续预填充
+        let mut cache = match self.prefix_cache.as_ref().and_then(|c| c.lookup(&prompt_tokens)) {
+            Some((len, state)) => {
+                tracing::debug!("Prefix cache hit: reusing {} of {} prompt tokens", len, prompt_tokens.len());
+                state.prefix(len)
+            }
+            None => KvCache::new(self.hparams.n_layer),
+        };
+        let reused = cache.len;
+        let mut sampler = Sampler::new(params);
+        let mut stop_matcher = StopMatcher::new(&params.stop);
+        let mut history = prompt_tokens.clone();
+        let mut grammar = match &params.constraint {
+            Some(constraint) => Some(GrammarMatcher::new(constraint.grammar()?)),
+            None => None,
+        };
+        // 词表各 token 的字节，首次需要屏蔽 token 时计算
+        let mut vocab_bytes: Vec<Vec<u8>> = Vec::new();
+
+        let mut logits = Vec::new();
+        for batch in prompt_tokens[reused..].chunks(PREFILL_BATCH) {
+            logits = self.forward(batch, &mut cache);
+        }
+
+        let mut pieces = Vec::new();
+        let mut pending_bytes: Vec<u8> = Vec::new();
+        let mut finish_reason = FinishReason::Length;
+        let room = n_ctx - prompt_tokens.len();
+        let budget = params.max_tokens.map_or(room, |n| n.min(room));
+        for _ in 0..budget {
+            let next = match &mut grammar {
+                Some(grammar) => self.sample_constrained(&mut sampler, &logits, &history, grammar, &mut vocab_bytes)?,
+                None => sampler.sample(&logits, &history),
+            };
+            if self.tokenizer.is_stop_token(next) {
+                finish_reason = FinishReason::Stop;
+                break;
+            }
+            history.push(next);
+
+            // token 可能只包含多字节字符的一部分，凑齐完整 UTF-8 再输出
+            let bytes = self.tokenizer.token_bytes(next);
+            if let Some(grammar) = &mut grammar {
+                grammar.accept_bytes(&bytes);
+            }
+            pending_bytes.extend(bytes);
+            // 确定无效的字节（而非未凑齐的多字节字符）直接按替换字符输出，避免阻塞后续文本
+            let valid = match std::str::from_utf8(&pending_bytes) {
+                Ok(s) => s.len(),
+                Err(e) => e.valid_up_to() + e.error_len().unwrap_or(0),
+            };
+            if valid > 0 {
+                let text = String::from_utf8_lossy(&pending_bytes[..valid]).into_owned();
+                pending_bytes.drain(..valid);
+                let (piece, stopped) = stop_matcher.push(&text);
+                let keep_going = piece.is_empty() || on_piece(&piece);
+                if !piece.is_empty() {
+                    pieces.push(piece);
+                }
+                if stopped {
+                    finish_reason = FinishReason::StopSequence;
+                    break;
+                }
+                if !keep_going {
+                    finish_reason = FinishReason::Cancelled;
+                    break;
+                }
+            }
+            // 语法已完整匹配且不允许继续输出，不必再等结束符
+            if grammar.as_ref().is_some_and(|g| g.is_complete() && !g.can_continue()) {
+                finish_reason = FinishReason::Stop;
+                break;
+            }
+
+            logits = self.forward(&[next], &mut cache);
+        }
+
+        // 命中停止序列时剩余内容不再输出；其余情况输出暂存的尾部文本
+        if finish_reason != FinishReason::StopSequence {
+            let mut rest = stop_matcher.finish();
+            rest.push_str(&String::from_utf8_lossy(&pending_bytes));
+            if !rest.is_empty() {
+                on_piece(&rest);
+                pieces.push(rest);
+            }
+        }
+
+        let usage = TokenUsage {
+            prompt_tokens: prompt_tokens.len(),
+            completion_tokens: history.len() - prompt_tokens.len(),
+        };
+
+        // 缓存块对齐的提示词前缀，已从缓存恢复的部分不必再存
+        if let Some(prefix_cache) = &self.prefix_cache {
+            let boundary = prefix_cache.block_boundary(prompt_tokens.len());
+            if boundary > reused {
+                cache.truncate(boundary);
+                prefix_cache.insert(&prompt_tokens[..boundary], cache);
+            }
+        }
+        Ok(GenerateOutput { pieces, finish_reason, usage })
+    }
+
+    /// 按语法约束采样：先正常采样，结果不符合语法时屏蔽所有不合法的 token 后重新采样
+    fn sample_constrained(
+        &self,
+        sampler: &mut Sampler,
+        logits: &[f32],
+        history: &[u32],
+        grammar: &GrammarMatcher,
+        vocab_bytes: &mut Vec<Vec<u8>>,
+    ) -> Result<u32> {
+        let allowed = |id: u32, bytes: &[u8]| {
+            if self.tokenizer.is_stop_token(id) {
+                // 只有语法完整匹配时才允许结束
+                grammar.is_complete()
+            } else {
+                // 控制 token 没有文本，不能推进语法
+                !bytes.is_empty() && grammar.allows(bytes)
+            }
+        };
+
+        let next = sampler.sample(logits, history);
+        if allowed(next, &self.tokenizer.token_bytes(next)) {
+            return Ok(next);
+        }
+
+        if vocab_bytes.is_empty() {
+            *vocab_bytes = (0..logits.len() as u32).map(|id| self.tokenizer.token_bytes(id)).collect();
+        }
+        let mut masked = logits.to_vec();
+        for (id, logit) in masked.iter_mut().enumerate() {
+            if !allowed(id as u32, &vocab_bytes[id]) {
+                *logit = f32::NEG_INFINITY;
+            }
+        }
+        if masked.iter().all(|&l| l == f32::NEG_INFINITY) {
+            anyhow::bail!("No token in the vocabulary satisfies the output constraint");
+        }
+        Ok(sampler.sample(&masked, history))
+    }
+
+    /// 计算文本向量：最后一层隐状态经 output_norm 后按 pooling 汇聚，并做 L2 归一化
+    pub fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let hp = &self.hparams;
+        let mut tokens = self.tokenizer.encode(text, true);
+        if tokens.is_empty() {
+            anyhow::bail!("Cannot embed empty text");
+        }
+        // 超出训练上下文的部分截断
+        tokens.truncate(hp.n_ctx_train);
+
+        let d = hp.n_embd;
+        let mut cache = KvCache::new(hp.n_layer);
+        let mut pooled = vec![0.0f32; d];
+        let mut normed = vec![0.0f32; d];
+        for (b, batch) in tokens.chunks(PREFILL_BATCH).enumerate() {
+            let hidden = self.forward_hidden(batch, &mut cache);
+            for (i, row) in hidden.chunks_exact(d).enumerate() {
+                let is_first = b == 0 && i == 0;
+                let is_last = b * PREFILL_BATCH + i + 1 == tokens.len();
+                let wanted = match hp.pooling {
+                    Pooling::Mean => true,
+                    Pooling::Cls => is_first,
+                    Pooling::Last => is_last,
+                };
+                if wanted {
+                    rms_norm(row, &self.output_norm, hp.rms_eps, &mut normed);
+                    for (p, v) in pooled.iter_mut().zip(&normed) {
+                        *p += v;
+                    }
+                }
+            }
+        }
+
+        let norm = pooled.iter().map(|v| v * v).sum::<f32>().sqrt().max(1e-12);
+        Ok(pooled.into_iter().map(|v| v / norm).collect())
+    }
+
+    /// 处理一批连续 token，更新 KV 缓存并返回最后一个 token 的 logits
+    fn forward(&self, tokens: &[u32], cache: &mut KvCache) -> Vec<f32> {
+        let hp = &self.hparams;
+        let d = hp.n_embd;
+        let x = self.forward_hidden(tokens, cache);
+
+        // 只需要最后一个位置的 logits
+        let last = &x[(tokens.len() - 1) * d..];
+        let mut normed = vec![0.0f32; d];
+        rms_norm(last, &self.output_norm, hp.rms_eps, &mut normed);
+        let mut logits = vec![0.0f32; self.output.rows];
+        self.matmul(&self.output, &normed, 1, &mut logits);
+        logits
+    }
+
+    /// 处理一批连续 token，更新 KV 缓存并返回最后一层的隐状态（n × n_embd，未经 output_norm）
+    fn forward_hidden(&self, tokens: &[u32], cache: &mut KvCache) -> Vec<f32> {
+        let hp = &self.hparams;
+        let n = tokens.len();
+        let d = hp.n_embd;
+        let kv_dim = hp.n_head_kv * hp.head_dim;
+        let pos0 = cache.len;
+
+        let mut x = vec![0.0f32; n * d];
+        for (i, &token) in tokens.iter().enumerate() {
+            self.read_row(&self.tok_embd, token as usize, &mut x[i * d..(i + 1) * d]);
+        }
+
+        let mut xn = vec![0.0f32; n * d];
+        let mut q = vec![0.0f32; n * d];
+        let mut k = vec![0.0f32; n * kv_dim];
+        let mut v = vec![0.0f32; n * kv_dim];
+        let mut att = vec![0.0f32; n * d];
+        let mut proj = vec![0.0f32; n * d];
+        let mut gate = vec![0.0f32; n * hp.n_ff];
+        let mut up = vec![0.0f32; n * hp.n_ff];
+
+        for (l, layer) in self.layers.iter().enumerate() {
+            rms_norm(&x, &layer.attn_norm, hp.rms_eps, &mut xn);
+            self.matmul(&layer.wq, &xn, n, &mut q);
+            self.matmul(&layer.wk, &xn, n, &mut k);
+            self.matmul(&layer.wv, &xn, n, &mut v);
+            add_bias(&mut q, layer.bq.as_deref());
+            add_bias(&mut k, layer.bk.as_deref());
+            add_bias(&mut v, layer.bv.as_deref());
+
+            for i in 0..n {
+                let pos = pos0 + i;
+                for h in q[i * d..(i + 1) * d].chunks_exact_mut(hp.head_dim) {
+                    self.apply_rope(h, pos);
+                }
+                for h in k[i * kv_dim..(i + 1) * kv_dim].chunks_exact_mut(hp.head_dim) {
+                    self.apply_rope(h, pos);
+                }
+            }
+            cache.keys[l].extend_from_slice(&k);
+            cache.values[l].extend_from_slice(&v);
+
+            self.attention(&q, &cache.keys[l], &cache.values[l], pos0, n, &mut att);
+            self.matmul(&layer.wo, &att, n, &mut proj);
+            for (xi, pi) in x.iter_mut().zip(&proj) {
+                *xi += pi;
+            }
+
+            rms_norm(&x, &layer.ffn_norm, hp.rms_eps, &mut xn);
+            self.matmul(&layer.w_gate, &xn, n, &mut gate);
+            self.matmul(&layer.w_up, &xn, n, &mut up);
+            for (g, u) in gate.iter_mut().zip(&up) {
+                *g = silu(*g) * u;
+            }
+            self.matmul(&layer.w_down, &gate, n, &mut proj);
+            for (xi, pi) in x.iter_mut().zip(&proj) {
+                *xi += pi;
+            }
+        }
+        cache.len += n;
+        x
+    }
+
+    /// 因果自注意力（支持 GQA），并行计算每个 (token, head)
+    fn attention(&self, q: &[f32], keys: &[f32], values: &[f32], pos0: usize, n: usize, out: &mut [f32]) {
+        let hp = &self.hparams;
+        let hd = hp.head_dim;
+        let kv_dim = hp.n_head_kv * hd;
+        let group = hp.n_head / hp.n_head_kv;
+        let scale = 1.0 / (hd as f32).sqrt();
+
+        out.par_chunks_mut(hd).enumerate().for_each(|(idx, o)| {
+            let i = idx / hp.n_head;
+            let h = idx % hp.n_head;
+            let kv_h = h / group;
+            let qh = &q[idx * hd..(idx + 1) * hd];
+            let visible = pos0 + i + 1;
+            debug_assert!(i < n);
+
+            let mut scores: Vec<f32> = (0..visible)
+                .map(|t| dot(qh, &keys[t * kv_dim + kv_h * hd..t * kv_dim + (kv_h + 1) * hd]) * scale)
+                .collect();
+            softmax(&mut scores);
+
+            o.fill(0.0);
+            for (t, s) in scores.iter().enumerate() {
+                let vt = &values[t * kv_dim + kv_h * hd..t * kv_dim + (kv_h + 1) * hd];
+                for (oi, vi) in o.iter_mut().zip(vt) {
+                    *oi += s * vi;
+                }
+            }
+        });
+    }
+
+    fn apply_rope(&self, x: &mut [f32], pos: usize) {
+        let hp = &self.hparams;
+        let dim = hp.rope_dim.min(x.len());
+        let half = dim / 2;
+        for i in 0..half {
+            let mut theta = pos as f32 * hp.rope_base.powf(-((2 * i) as f32) / dim as f32);
+            if let Some(factors) = &self.rope_freq_factors {
+                theta /= factors.get(i).copied().unwrap_or(1.0);
+            }
+            let (sin, cos) = theta.sin_cos();
+            let (a, b) = if hp.rope_neox { (i, i + half) } else { (2 * i, 2 * i + 1) };
+            let (x0, x1) = (x[a], x[b]);
+            x[a] = x0 * cos - x1 * sin;
+            x[b] = x0 * sin + x1 * cos;
+        }
+    }
+
+    fn row_data(&self, tensor: &Tensor, row: usize) -> &[u8] {
+        let start = tensor.offset + row * tensor.row_bytes;
+        &self.mmap[start..start + tensor.row_bytes]
+    }
+
+    fn read_row(&self, tensor: &Tensor, row: usize, out: &mut [f32]) {
+        quant::dequantize_row(tensor.ggml_type, self.row_data(tensor, row), out);
+    }
+
+    /// out[n × rows] = x[n × cols] · Wᵀ，按权重行并行，每行只反量化一次
+    fn matmul(&self, w: &Tensor, x: &[f32], n: usize, out: &mut [f32]) {
+        let mut out_t = vec![0.0f32; w.rows * n];
+        out_t
+            .par_chunks_mut(n)
+            .enumerate()
+            .for_each_init(
+                || vec![0.0f32; w.cols],
+                |row_buf, (r, o)| {
+                    self.read_row(w, r, row_buf);
+                    for (j, oj) in o.iter_mut().enumerate() {
+                        *oj = dot(row_buf, &x[j * w.cols..(j + 1) * w.cols]);
+                    }
+                },
+            );
+        for r in 0..w.rows {
+            for j in 0..n {
+                out[j * w.rows + r] = out_t[r * n + j];
+            }
+        }
+    }
+}
+
+struct TensorLoader<'a> {
+    gguf: &'a GgufFile,
+    mmap: &'a memmap2::Mmap,
+}
+
+impl TensorLoader<'_> {
+    fn info(&self, name: &str) -> Result<&GgufTensorInfo> {
+        self.gguf
+            .tensor(name)
+            .ok_or_else(|| anyhow::anyhow!("Model is missing tensor {}", name))
+    }
+
+    fn matrix(&self, name: &str) -> Result<Tensor> {
+        let info = self.info(name)?;
+        let ggml_type = info.ggml_type;
+        let type_size = ggml_type
+            .type_size()
+            .ok_or_else(|| anyhow::anyhow!("Tensor {} uses unsupported quantization {:?}", name, ggml_type))?;
+        let cols = *info.dims.first().unwrap_or(&0) as usize;
+        let rows = info.dims.iter().skip(1).product::<u64>() as usize;
+        if cols == 0 || !cols.is_multiple_of(ggml_type.block_size()) {
+            anyhow::bail!("Tensor {} has row length {} incompatible with {:?}", name, cols, ggml_type);
+        }
+        let row_bytes = cols / ggml_type.block_size() * type_size;
+        let offset = (self.gguf.data_offset + info.offset) as usize;
+        if offset + rows * row_bytes > self.mmap.len() {
+            anyhow::bail!("Tensor {} extends past end of file (truncated download?)", name);
+        }
+        Ok(Tensor {
+            ggml_type,
+            offset,
+            rows,
+            cols,
+            row_bytes,
+        })
+    }
+
+    fn vector(&self, name: &str) -> Result<Vec<f32>> {
+        let tensor = self.matrix(name)?;
+        let mut out = vec![0.0f32; tensor.rows * tensor.cols];
+        let start = tensor.offset;
+        let data = &self.mmap[start..start + tensor.rows * tensor.row_bytes];
+        quant::dequantize_row(tensor.ggml_type, data, &mut out);
+        Ok(out)
+    }
+
+    fn optional_vector(&self, name: &str) -> Result<Option<Vec<f32>>> {
+        if self.gguf.tensor(name).is_some() {
+            self.vector(name).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    // 8 路累加便于编译器自动向量化
+    let mut acc = [0.0f32; 8];
+    let chunks_a = a.chunks_exact(8);
+    let chunks_b = b.chunks_exact(8);
+    let tail: f32 = chunks_a
+        .remainder()
+        .iter()
+        .zip(chunks_b.remainder())
+        .map(|(x, y)| x * y)
+        .sum();
+    for (ca, cb) in chunks_a.zip(chunks_b) {
+        for k in 0..8 {
+            acc[k] += ca[k] * cb[k];
+        }
+    }
+    acc.iter().sum::<f32>() + tail
+}
+
+fn rms_norm(x: &[f32], weight: &[f32], eps: f32, out: &mut [f32]) {
+    let d = weight.len();
+    for (xi, oi) in x.chunks_exact(d).zip(out.chunks_exact_mut(d)) {
+        let mean_sq = xi.iter().map(|v| v * v).sum::<f32>() / d as f32;
+        let scale = 1.0 / (mean_sq + eps).sqrt();
+        for ((o, v), w) in oi.iter_mut().zip(xi).zip(weight) {
+            *o = v * scale * w;
+        }
+    }
+}
+
+fn add_bias(x: &mut [f32], bias: Option<&[f32]>) {
+    if let Some(bias) = bias {
+        for row in x.chunks_exact_mut(bias.len()) {
+            for (v, b) in row.iter_mut().zip(bias) {
+                *v += b;
+            }
+        }
+    }
+}
+
+fn softmax(x: &mut [f32]) {
+    let max = x.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let mut sum = 0.0;
+    for v in x.iter_mut() {
+        *v = (*v - max).exp();
+        sum += *v;
+    }
+    for v in x.iter_mut() {
+        *v /= sum;
+    }
+}
+
+fn silu(x: f32) -> f32 {
+    x / (1.0 + (-x).exp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kv_cache_bytes_round_trip_and_reject_corruption() {
+        let cache = KvCache {
+            keys: vec![vec![1.0, 2.0], vec![3.0, 4.0]],
+            values: vec![vec![5.0, 6.0], vec![7.0, 8.0]],
+            len: 1,
+        };
+        let restored = KvCache::from_bytes(&cache.to_bytes()).unwrap();
+        assert_eq!(restored.keys, cache.keys);
+        assert_eq!(restored.values, cache.values);
+        assert_eq!(restored.len, 1);
+
+        // 层数非零但每层元素数为 0
+        let mut empty_layers = Vec::new();
+        for n in [2u64, 1, 0] {
+            empty_layers.extend_from_slice(&n.to_le_bytes());
+        }
+        assert!(KvCache::from_bytes(&empty_layers).is_err());
+
+        // 头部声明的大小溢出
+        let mut huge = Vec::new();
+        for n in [u64::MAX, 1, u64::MAX] {
+            huge.extend_from_slice(&n.to_le_bytes());
+        }
+        assert!(KvCache::from_bytes(&huge).is_err());
+    }
+}
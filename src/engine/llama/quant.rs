@@ -0,0 +1,460 @@
+// ggml 量化格式反量化内核
+// 布局与 ggml-quants.c 保持一致，按块把量化数据还原为 f32
+
+use crate::engine::gguf::GgmlType;
+
+/// IEEE 754 半精度转单精度
+pub fn f16_to_f32(bits: u16) -> f32 {
+    let sign = ((bits >> 15) & 1) as u32;
+    let exp = ((bits >> 10) & 0x1f) as u32;
+    let mant = (bits & 0x3ff) as u32;
+
+    let bits32 = if exp == 0 {
+        if mant == 0 {
+            sign << 31
+        } else {
+            // 非规格化数：归一化尾数
+            let mut e = 0i32;
+            let mut m = mant;
+            while m & 0x400 == 0 {
+                m <<= 1;
+                e -= 1;
+            }
+            let m = m & 0x3ff;
+            (sign << 31) | (((127 - 15 + 1 + e) as u32) << 23) | (m << 13)
+        }
+    } else if exp == 0x1f {
+        (sign << 31) | (0xff << 23) | (mant << 13)
+    } else {
+        (sign << 31) | ((exp + 127 - 15) << 23) | (mant << 13)
+    };
+    f32::from_bits(bits32)
+}
+
+fn read_f16(data: &[u8], offset: usize) -> f32 {
+    f16_to_f32(u16::from_le_bytes([data[offset], data[offset + 1]]))
+}
+
+/// 把一行量化数据反量化到 out（out.len() 必须是块大小的整数倍）
+pub fn dequantize_row(ggml_type: GgmlType, data: &[u8], out: &mut [f32]) {
+    match ggml_type {
+        GgmlType::F32 => {
+            for (i, v) in out.iter_mut().enumerate() {
+                *v = f32::from_le_bytes([data[4 * i], data[4 * i + 1], data[4 * i + 2], data[4 * i + 3]]);
+            }
+        }
+        GgmlType::F16 => {
+            for (i, v) in out.iter_mut().enumerate() {
+                *v = read_f16(data, 2 * i);
+            }
+        }
+        GgmlType::BF16 => {
+            for (i, v) in out.iter_mut().enumerate() {
+                let bits = u16::from_le_bytes([data[2 * i], data[2 * i + 1]]) as u32;
+                *v = f32::from_bits(bits << 16);
+            }
+        }
+        GgmlType::Q4_0 => for_blocks(data, out, 18, 32, dequantize_q4_0),
+        GgmlType::Q4_1 => for_blocks(data, out, 20, 32, dequantize_q4_1),
+        GgmlType::Q5_0 => for_blocks(data, out, 22, 32, dequantize_q5_0),
+        GgmlType::Q5_1 => for_blocks(data, out, 24, 32, dequantize_q5_1),
+        GgmlType::Q8_0 => for_blocks(data, out, 34, 32, dequantize_q8_0),
+        GgmlType::Q2K => for_blocks(data, out, 84, 256, dequantize_q2_k),
+        GgmlType::Q3K => for_blocks(data, out, 110, 256, dequantize_q3_k),
+        GgmlType::Q4K => for_blocks(data, out, 144, 256, dequantize_q4_k),
+        GgmlType::Q5K => for_blocks(data, out, 176, 256, dequantize_q5_k),
+        GgmlType::Q6K => for_blocks(data, out, 210, 256, dequantize_q6_k),
+        GgmlType::Unknown(t) => unreachable!("unsupported ggml type {} should be rejected at load time", t),
+    }
+}
+
+fn for_blocks(data: &[u8], out: &mut [f32], type_size: usize, block_size: usize, f: fn(&[u8], &mut [f32])) {
+    for (block, y) in data.chunks_exact(type_size).zip(out.chunks_exact_mut(block_size)) {
+        f(block, y);
+    }
+}
+
+fn dequantize_q4_0(b: &[u8], y: &mut [f32]) {
+    let d = read_f16(b, 0);
+    let qs = &b[2..18];
+    for j in 0..16 {
+        y[j] = ((qs[j] & 0x0f) as i32 - 8) as f32 * d;
+        y[j + 16] = ((qs[j] >> 4) as i32 - 8) as f32 * d;
+    }
+}
+
+fn dequantize_q4_1(b: &[u8], y: &mut [f32]) {
+    let d = read_f16(b, 0);
+    let m = read_f16(b, 2);
+    let qs = &b[4..20];
+    for j in 0..16 {
+        y[j] = (qs[j] & 0x0f) as f32 * d + m;
+        y[j + 16] = (qs[j] >> 4) as f32 * d + m;
+    }
+}
+
+fn dequantize_q5_0(b: &[u8], y: &mut [f32]) {
+    let d = read_f16(b, 0);
+    let qh = u32::from_le_bytes([b[2], b[3], b[4], b[5]]);
+    let qs = &b[6..22];
+    for j in 0..16 {
+        let xh_0 = ((qh >> j) << 4) & 0x10;
+        let xh_1 = (qh >> (j + 12)) & 0x10;
+        let x0 = ((qs[j] as u32 & 0x0f) | xh_0) as i32 - 16;
+        let x1 = ((qs[j] as u32 >> 4) | xh_1) as i32 - 16;
+        y[j] = x0 as f32 * d;
+        y[j + 16] = x1 as f32 * d;
+    }
+}
+
+fn dequantize_q5_1(b: &[u8], y: &mut [f32]) {
+    let d = read_f16(b, 0);
+    let m = read_f16(b, 2);
+    let qh = u32::from_le_bytes([b[4], b[5], b[6], b[7]]);
+    let qs = &b[8..24];
+    for j in 0..16 {
+        let xh_0 = ((qh >> j) << 4) & 0x10;
+        let xh_1 = (qh >> (j + 12)) & 0x10;
+        let x0 = (qs[j] as u32 & 0x0f) | xh_0;
+        let x1 = (qs[j] as u32 >> 4) | xh_1;
+        y[j] = x0 as f32 * d + m;
+        y[j + 16] = x1 as f32 * d + m;
+    }
+}
+
+fn dequantize_q8_0(b: &[u8], y: &mut [f32]) {
+    let d = read_f16(b, 0);
+    for (j, v) in y.iter_mut().enumerate().take(32) {
+        *v = (b[2 + j] as i8) as f32 * d;
+    }
+}
+
+fn dequantize_q2_k(b: &[u8], y: &mut [f32]) {
+    let scales = &b[0..16];
+    let qs = &b[16..80];
+    let d = read_f16(b, 80);
+    let dmin = read_f16(b, 82);
+
+    let mut out = 0;
+    let mut is = 0;
+    for n in (0..256).step_by(128) {
+        let q = &qs[n / 4..n / 4 + 32];
+        let mut shift = 0;
+        for _ in 0..4 {
+            for half in 0..2 {
+                let sc = scales[is];
+                is += 1;
+                let dl = d * (sc & 0x0f) as f32;
+                let ml = dmin * (sc >> 4) as f32;
+                for l in 0..16 {
+                    y[out] = dl * ((q[l + 16 * half] >> shift) & 3) as f32 - ml;
+                    out += 1;
+                }
+            }
+            shift += 2;
+        }
+    }
+}
+
+fn dequantize_q3_k(b: &[u8], y: &mut [f32]) {
+    const KMASK1: u32 = 0x0303_0303;
+    const KMASK2: u32 = 0x0f0f_0f0f;
+
+    let hmask = &b[0..32];
+    let qs = &b[32..96];
+    let raw_scales = &b[96..108];
+    let d_all = read_f16(b, 108);
+
+    let mut aux = [0u32; 4];
+    for (i, a) in aux.iter_mut().enumerate().take(3) {
+        *a = u32::from_le_bytes([raw_scales[4 * i], raw_scales[4 * i + 1], raw_scales[4 * i + 2], raw_scales[4 * i + 3]]);
+    }
+    let tmp = aux[2];
+    aux[2] = ((aux[0] >> 4) & KMASK2) | (((tmp >> 4) & KMASK1) << 4);
+    aux[3] = ((aux[1] >> 4) & KMASK2) | (((tmp >> 6) & KMASK1) << 4);
+    aux[0] = (aux[0] & KMASK2) | ((tmp & KMASK1) << 4);
+    aux[1] = (aux[1] & KMASK2) | (((tmp >> 2) & KMASK1) << 4);
+    let mut scales = [0i8; 16];
+    for (i, a) in aux.iter().enumerate() {
+        for (k, byte) in a.to_le_bytes().iter().enumerate() {
+            scales[4 * i + k] = *byte as i8;
+        }
+    }
+
+    let mut out = 0;
+    let mut is = 0;
+    let mut m = 1u8;
+    for n in (0..256).step_by(128) {
+        let q = &qs[n / 4..n / 4 + 32];
+        let mut shift = 0;
+        for _ in 0..4 {
+            for half in 0..2 {
+                let dl = d_all * (scales[is] as i32 - 32) as f32;
+                is += 1;
+                for l in 0..16 {
+                    let idx = l + 16 * half;
+                    let low = ((q[idx] >> shift) & 3) as i32;
+                    let high = if hmask[idx] & m != 0 { 0 } else { 4 };
+                    y[out] = dl * (low - high) as f32;
+                    out += 1;
+                }
+            }
+            shift += 2;
+            m <<= 1;
+        }
+    }
+}
+
+/// Q4_K / Q5_K 的 6 bit 缩放系数解包
+fn scale_min_k4(j: usize, q: &[u8]) -> (u8, u8) {
+    if j < 4 {
+        (q[j] & 63, q[j + 4] & 63)
+    } else {
+        let d = (q[j + 4] & 0x0f) | ((q[j - 4] >> 6) << 4);
+        let m = (q[j + 4] >> 4) | ((q[j] >> 6) << 4);
+        (d, m)
+    }
+}
+
+fn dequantize_q4_k(b: &[u8], y: &mut [f32]) {
+    let d = read_f16(b, 0);
+    let dmin = read_f16(b, 2);
+    let scales = &b[4..16];
+    let qs = &b[16..144];
+
+    let mut out = 0;
+    let mut is = 0;
+    for j in (0..256).step_by(64) {
+        let q = &qs[j / 2..j / 2 + 32];
+        let (sc1, m1) = scale_min_k4(is, scales);
+        let (sc2, m2) = scale_min_k4(is + 1, scales);
+        let (d1, min1) = (d * sc1 as f32, dmin * m1 as f32);
+        let (d2, min2) = (d * sc2 as f32, dmin * m2 as f32);
+        for l in 0..32 {
+            y[out + l] = d1 * (q[l] & 0x0f) as f32 - min1;
+            y[out + 32 + l] = d2 * (q[l] >> 4) as f32 - min2;
+        }
+        out += 64;
+        is += 2;
+    }
+}
+
+fn dequantize_q5_k(b: &[u8], y: &mut [f32]) {
+    let d = read_f16(b, 0);
+    let dmin = read_f16(b, 2);
+    let scales = &b[4..16];
+    let qh = &b[16..48];
+    let qs = &b[48..176];
+
+    let mut out = 0;
+    let mut is = 0;
+    let (mut u1, mut u2) = (1u8, 2u8);
+    for j in (0..256).step_by(64) {
+        let ql = &qs[j / 2..j / 2 + 32];
+        let (sc1, m1) = scale_min_k4(is, scales);
+        let (sc2, m2) = scale_min_k4(is + 1, scales);
+        let (d1, min1) = (d * sc1 as f32, dmin * m1 as f32);
+        let (d2, min2) = (d * sc2 as f32, dmin * m2 as f32);
+        for l in 0..32 {
+            let h1 = if qh[l] & u1 != 0 { 16 } else { 0 };
+            let h2 = if qh[l] & u2 != 0 { 16 } else { 0 };
+            y[out + l] = d1 * ((ql[l] & 0x0f) + h1) as f32 - min1;
+            y[out + 32 + l] = d2 * ((ql[l] >> 4) + h2) as f32 - min2;
+        }
+        out += 64;
+        is += 2;
+        u1 <<= 2;
+        u2 <<= 2;
+    }
+}
+
+fn dequantize_q6_k(b: &[u8], y: &mut [f32]) {
+    let ql_all = &b[0..128];
+    let qh_all = &b[128..192];
+    let sc_all = &b[192..208];
+    let d = read_f16(b, 208);
+
+    for n in 0..2 {
+        let ql = &ql_all[64 * n..64 * n + 64];
+        let qh = &qh_all[32 * n..32 * n + 32];
+        let sc = &sc_all[8 * n..8 * n + 8];
+        let y = &mut y[128 * n..128 * n + 128];
+        for l in 0..32 {
+            let is = l / 16;
+            let q1 = ((ql[l] & 0x0f) | ((qh[l] & 3) << 4)) as i32 - 32;
+            let q2 = ((ql[l + 32] & 0x0f) | (((qh[l] >> 2) & 3) << 4)) as i32 - 32;
+            let q3 = ((ql[l] >> 4) | (((qh[l] >> 4) & 3) << 4)) as i32 - 32;
+            let q4 = ((ql[l + 32] >> 4) | (((qh[l] >> 6) & 3) << 4)) as i32 - 32;
+            y[l] = d * (sc[is] as i8) as f32 * q1 as f32;
+            y[l + 32] = d * (sc[is + 2] as i8) as f32 * q2 as f32;
+            y[l + 64] = d * (sc[is + 4] as i8) as f32 * q3 as f32;
+            y[l + 96] = d * (sc[is + 6] as i8) as f32 * q4 as f32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const F16_ONE: [u8; 2] = 0x3c00u16.to_le_bytes();
+    const F16_HALF: [u8; 2] = 0x3800u16.to_le_bytes();
+    const F16_TWO: [u8; 2] = 0x4000u16.to_le_bytes();
+    const F16_MINUS_ONE: [u8; 2] = 0xbc00u16.to_le_bytes();
+
+    fn dequantize(ggml_type: GgmlType, block: &[u8]) -> Vec<f32> {
+        let mut out = vec![0.0; ggml_type.block_size()];
+        dequantize_row(ggml_type, block, &mut out);
+        out
+    }
+
+    #[test]
+    fn f16_conversion() {
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        assert_eq!(f16_to_f32(0x3555), 0.333_251_95);
+        assert_eq!(f16_to_f32(0x7bff), 65504.0);
+        // 非规格化数
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(f16_to_f32(0x0200), 2f32.powi(-15));
+        assert!(f16_to_f32(0x8000).is_sign_negative());
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+        assert!(f16_to_f32(0x7e00).is_nan());
+    }
+
+    #[test]
+    fn dequantize_float_rows() {
+        let mut out = [0.0; 2];
+        dequantize_row(GgmlType::F32, &[1.5f32.to_le_bytes(), (-2.0f32).to_le_bytes()].concat(), &mut out);
+        assert_eq!(out, [1.5, -2.0]);
+        dequantize_row(GgmlType::F16, &[F16_HALF, F16_MINUS_ONE].concat(), &mut out);
+        assert_eq!(out, [0.5, -1.0]);
+        // BF16 为 f32 的高 16 位
+        dequantize_row(GgmlType::BF16, &[0x3fc0u16.to_le_bytes(), 0xc040u16.to_le_bytes()].concat(), &mut out);
+        assert_eq!(out, [1.5, -3.0]);
+    }
+
+    #[test]
+    fn dequantize_legacy_blocks() {
+        // Q8_0：d = 0.5，两个块
+        let mut row = Vec::new();
+        for base in [0i8, -32] {
+            row.extend_from_slice(&F16_HALF);
+            row.extend((0..32).map(|i| (base + i as i8) as u8));
+        }
+        let mut out = vec![0.0; 64];
+        dequantize_row(GgmlType::Q8_0, &row, &mut out);
+        assert_eq!(out[0], 0.0);
+        assert_eq!(out[31], 15.5);
+        assert_eq!(out[32], -16.0);
+        assert_eq!(out[63], -0.5);
+
+        // Q4_0：低 4 位是前 16 个元素，高 4 位是后 16 个，减 8 后乘 d
+        let mut block = F16_ONE.to_vec();
+        block.extend((0..16u8).map(|j| j | ((15 - j) << 4)));
+        let y = dequantize(GgmlType::Q4_0, &block);
+        assert_eq!(&y[..3], [-8.0, -7.0, -6.0]);
+        assert_eq!(y[15], 7.0);
+        assert_eq!(y[16], 7.0);
+        assert_eq!(y[31], -8.0);
+
+        // Q4_1：q * d + m
+        let mut block = [F16_TWO, F16_MINUS_ONE].concat();
+        block.extend((0..16u8).map(|j| j | ((15 - j) << 4)));
+        let y = dequantize(GgmlType::Q4_1, &block);
+        assert_eq!(&y[..2], [-1.0, 1.0]);
+        assert_eq!(y[16], 29.0);
+
+        // Q5_0：qh 的第 j 位是元素 j 的第 5 位，第 j + 16 位是元素 j + 16 的第 5 位
+        let mut block = F16_ONE.to_vec();
+        block.extend_from_slice(&0x0001_0001u32.to_le_bytes());
+        block.extend([0u8; 16]);
+        let y = dequantize(GgmlType::Q5_0, &block);
+        assert_eq!(y[0], 0.0);
+        assert_eq!(y[1], -16.0);
+        assert_eq!(y[16], 0.0);
+        assert_eq!(y[17], -16.0);
+
+        // Q5_1：(q | 第 5 位) * d + m
+        let mut block = [F16_HALF, F16_ONE].concat();
+        block.extend_from_slice(&0x8000_0000u32.to_le_bytes());
+        block.extend([0xffu8; 16]);
+        let y = dequantize(GgmlType::Q5_1, &block);
+        assert_eq!(y[0], 8.5);
+        assert_eq!(y[31], 16.5);
+    }
+
+    #[test]
+    fn dequantize_k_quant_blocks() {
+        // Q2_K：scales 低 4 位为 scale、高 4 位为 min；每字节 4 个 2 bit 值分属 4 组
+        let mut block = vec![0u8; 84];
+        block[0] = 0x12;
+        block[1] = 0x03;
+        block[2] = 0x21;
+        block[16] = 0b1110_0111;
+        block[80..82].copy_from_slice(&F16_ONE);
+        block[82..84].copy_from_slice(&F16_ONE);
+        let y = dequantize(GgmlType::Q2K, &block);
+        assert_eq!(y[0], 2.0 * 3.0 - 1.0);
+        assert_eq!(y[1], -1.0);
+        assert_eq!(y[16], 0.0);
+        // 第二组取 bit 2-3，使用 scales[2]
+        assert_eq!(y[32], 1.0 - 2.0);
+        assert_eq!(y[33], -2.0);
+
+        // Q3_K：hmask 置位时不减 4；6 bit scale 减 32
+        let mut block = vec![0u8; 110];
+        block[0] = 0x01;
+        block[32] = 0x03;
+        block[96] = 0x01 | (0x01 << 4);
+        block[104] = 0x02;
+        block[108..110].copy_from_slice(&F16_ONE);
+        let y = dequantize(GgmlType::Q3K, &block);
+        // scales[0] = 1 | (2 << 4) = 33，dl = 1
+        assert_eq!(y[0], 3.0);
+        assert_eq!(y[1], -4.0);
+        // scales[1] = 0 → dl = -32
+        assert_eq!(y[16], 128.0);
+
+        // Q4_K：每 64 个元素一对 6 bit scale / min
+        let mut block = vec![0u8; 144];
+        block[0..2].copy_from_slice(&F16_ONE);
+        block[2..4].copy_from_slice(&F16_HALF);
+        block[4] = 2;
+        block[5] = 3;
+        block[8] = 2;
+        block[16] = 0x21;
+        let y = dequantize(GgmlType::Q4K, &block);
+        assert_eq!(y[0], 2.0 * 1.0 - 1.0);
+        assert_eq!(y[1], -1.0);
+        assert_eq!(y[32], 3.0 * 2.0);
+        assert_eq!(y[64], 0.0);
+
+        // Q5_K：qh 每对 bit 为两组 32 个元素的第 5 位
+        let mut block = vec![0u8; 176];
+        block[0..2].copy_from_slice(&F16_ONE);
+        block[4] = 1;
+        block[5] = 1;
+        block[16] = 0b0000_0011;
+        block[48] = 0x0f;
+        let y = dequantize(GgmlType::Q5K, &block);
+        assert_eq!(y[0], 31.0);
+        assert_eq!(y[32], 16.0);
+        assert_eq!(y[1], 0.0);
+
+        // Q6_K：低 4 位 + 高 2 位，减 32 后乘 d 与 8 bit scale
+        let mut block = vec![0u8; 210];
+        block[0] = 0x0f;
+        block[128] = 0x03;
+        for (i, sc) in block[192..208].iter_mut().enumerate() {
+            *sc = (i as i8 - 4) as u8;
+        }
+        block[208..210].copy_from_slice(&F16_HALF);
+        let y = dequantize(GgmlType::Q6K, &block);
+        assert_eq!(y[0], 0.5 * -4.0 * 31.0);
+        assert_eq!(y[16], 0.5 * -3.0 * -32.0);
+        assert_eq!(y[32], 0.5 * -2.0 * -32.0);
+        assert_eq!(y[128], 0.5 * 4.0 * -32.0);
+        assert_eq!(y[255], 0.5 * 11.0 * -32.0);
+    }
+}
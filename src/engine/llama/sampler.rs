@@ -0,0 +1,187 @@
+// 采样器 - logit 偏置、重复惩罚、temperature / top-k / top-p 采样
+
+use crate::engine::GenerationParams;
+use std::collections::HashMap;
+
+/// 重复惩罚只考虑最近的 token 数（与 llama.cpp 的 penalty_last_n 默认值一致）
+const PENALTY_LAST_N: usize = 64;
+
+pub struct Sampler {
+    temperature: f32,
+    top_p: f32,
+    top_k: Option<usize>,
+    repetition_penalty: f32,
+    logit_bias: HashMap<u32, f32>,
+    rng_state: u64,
+}
+
+impl Sampler {
+    pub fn new(params: &GenerationParams) -> Self {
+        Self {
+            temperature: params.temperature,
+            top_p: params.top_p,
+            top_k: params.top_k.filter(|&k| k > 0),
+            repetition_penalty: params.repetition_penalty,
+            logit_bias: params.logit_bias.clone(),
+            // xorshift 状态不能为 0
+            rng_state: params.seed_or_random().max(1),
+        }
+    }
+
+    fn next_f32(&mut self) -> f32 {
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng_state = x;
+        (x >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// 根据 logits 采样下一个 token；history 为此前的 token 序列，用于重复惩罚
+    pub fn sample(&mut self, logits: &[f32], history: &[u32]) -> u32 {
+        let mut logits = logits.to_vec();
+        for (&id, &bias) in &self.logit_bias {
+            if let Some(l) = logits.get_mut(id as usize) {
+                *l += bias;
+            }
+        }
+        if self.repetition_penalty != 1.0 {
+            let recent = &history[history.len().saturating_sub(PENALTY_LAST_N)..];
+            let mut seen = recent.to_vec();
+            seen.sort_unstable();
+            seen.dedup();
+            for id in seen {
+                if let Some(l) = logits.get_mut(id as usize) {
+                    *l = if *l > 0.0 { *l / self.repetition_penalty } else { *l * self.repetition_penalty };
+                }
+            }
+        }
+
+        // temperature 为 0 时退化为贪心解码
+        if self.temperature <= 0.0 {
+            return argmax(&logits);
+        }
+
+        // 被约束解码屏蔽（-inf）的 token 不参与采样
+        let mut candidates: Vec<(u32, f32)> = logits
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| **l > f32::NEG_INFINITY)
+            .map(|(i, &l)| (i as u32, l))
+            .collect();
+        candidates.sort_unstable_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        if let Some(k) = self.top_k {
+            candidates.truncate(k);
+        }
+
+        let max = candidates.first().map(|(_, l)| *l).unwrap_or(0.0);
+        let mut probs: Vec<(u32, f32)> = candidates
+            .into_iter()
+            .map(|(id, l)| (id, ((l - max) / self.temperature).exp()))
+            .collect();
+        let sum: f32 = probs.iter().map(|(_, p)| p).sum();
+        for (_, p) in probs.iter_mut() {
+            *p /= sum;
+        }
+
+        // top-p：保留累计概率达到 top_p 的最小候选集
+        let mut cumulative = 0.0;
+        let mut cutoff = probs.len();
+        for (i, (_, p)) in probs.iter().enumerate() {
+            cumulative += p;
+            if cumulative >= self.top_p {
+                cutoff = i + 1;
+                break;
+            }
+        }
+        probs.truncate(cutoff.max(1));
+
+        let total: f32 = probs.iter().map(|(_, p)| p).sum();
+        let mut r = self.next_f32() * total;
+        for (id, p) in &probs {
+            r -= p;
+            if r <= 0.0 {
+                return *id;
+            }
+        }
+        probs.last().map(|(id, _)| *id).unwrap_or(0)
+    }
+}
+
+pub fn argmax(logits: &[f32]) -> u32 {
+    logits
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.partial_cmp(b.1).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(i, _)| i as u32)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(temperature: f32) -> GenerationParams {
+        GenerationParams {
+            temperature,
+            top_p: 1.0,
+            seed: Some(42),
+            ..GenerationParams::default()
+        }
+    }
+
+    #[test]
+    fn greedy_picks_argmax_after_bias_and_penalty() {
+        let mut sampler = Sampler::new(&params(0.0));
+        assert_eq!(sampler.sample(&[0.1, 2.0, 1.9], &[]), 1);
+
+        // 偏置改变最大值
+        let mut biased = params(0.0);
+        biased.logit_bias.insert(0, 5.0);
+        assert_eq!(Sampler::new(&biased).sample(&[0.1, 2.0, 1.9], &[]), 0);
+
+        // 正 logit 除以惩罚系数，负 logit 乘以惩罚系数
+        let mut penalized = params(0.0);
+        penalized.repetition_penalty = 1.5;
+        let mut sampler = Sampler::new(&penalized);
+        assert_eq!(sampler.sample(&[0.1, 2.0, 1.9], &[1]), 2);
+        assert_eq!(sampler.sample(&[-1.0, -1.2, -3.0], &[0]), 1);
+        // 只考虑最近 PENALTY_LAST_N 个 token
+        let mut history = vec![1];
+        history.extend(std::iter::repeat_n(0, PENALTY_LAST_N));
+        assert_eq!(sampler.sample(&[0.1, 2.0, 1.9], &history), 1);
+    }
+
+    #[test]
+    fn top_k_top_p_and_masked_tokens_limit_candidates() {
+        let logits = [1.0, 3.0, 2.9, 0.5];
+        let mut top_k = params(1.0);
+        top_k.top_k = Some(1);
+        let mut sampler = Sampler::new(&top_k);
+        assert!((0..50).all(|_| sampler.sample(&logits, &[]) == 1));
+
+        let mut top_p = params(1.0);
+        top_p.top_p = 0.01;
+        let mut sampler = Sampler::new(&top_p);
+        assert!((0..50).all(|_| sampler.sample(&logits, &[]) == 1));
+
+        // 约束解码屏蔽的 token 永远不会被采到
+        let masked = [f32::NEG_INFINITY, 0.0, f32::NEG_INFINITY, 0.0];
+        let mut sampler = Sampler::new(&params(1.0));
+        assert!((0..50).map(|_| sampler.sample(&masked, &[])).all(|id| id == 1 || id == 3));
+    }
+
+    #[test]
+    fn fixed_seed_is_reproducible() {
+        let logits = [1.0, 1.1, 0.9, 1.05, 0.95];
+        let run = || {
+            let mut sampler = Sampler::new(&params(1.0));
+            (0..32).map(|_| sampler.sample(&logits, &[])).collect::<Vec<_>>()
+        };
+        let first = run();
+        assert_eq!(first, run());
+        // 温度为 1 时不会总选同一个 token
+        assert!(first.iter().any(|id| *id != first[0]));
+        assert_eq!(argmax(&[]), 0);
+    }
+}
@@ -0,0 +1,615 @@
+// GGUF 内嵌词表分词器（也可从 HF tokenizer.json 构建）
+// 支持 SentencePiece（tokenizer.ggml.model = "llama"）与字节级 BPE（"gpt2"）两类词表
+
+use crate::engine::gguf::{GgufFile, GgufValue};
+use anyhow::Result;
+use std::collections::HashMap;
+
+// llama.cpp 的 token 类型编号
+const TOKEN_TYPE_NORMAL: i32 = 1;
+const TOKEN_TYPE_CONTROL: i32 = 3;
+const TOKEN_TYPE_USER_DEFINED: i32 = 4;
+const TOKEN_TYPE_BYTE: i32 = 6;
+
+// 不同模型族的 BPE 预分词正则（与 llama.cpp 的 tokenizer.ggml.pre 对应）
+const PRE_GPT2: &str = r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+";
+const PRE_LLAMA3: &str = r"(?:'[sS]|'[tT]|'[rR][eE]|'[vV][eE]|'[mM]|'[lL][lL]|'[dD])|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+const PRE_QWEN2: &str = r"(?:'[sS]|'[tT]|'[rR][eE]|'[vV][eE]|'[mM]|'[lL][lL]|'[dD])|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+/// 对话结束类控制符，遇到即停止生成
+const END_OF_TURN_MARKERS: &[&str] = &["<|eot_id|>", "<|im_end|>", "<|end|>", "<end_of_turn>", "<|endoftext|>", "</s>"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VocabKind {
+    SentencePiece,
+    BytePairEncoding,
+}
+
+pub struct GgufTokenizer {
+    kind: VocabKind,
+    tokens: Vec<String>,
+    token_types: Vec<i32>,
+    scores: Vec<f32>,
+    token_to_id: HashMap<String, u32>,
+    merge_ranks: HashMap<(String, String), usize>,
+    /// 需要在原文中按字面匹配的特殊 token，按长度降序
+    special_tokens: Vec<(String, u32)>,
+    pre_tokenizer: Option<fancy_regex::Regex>,
+    byte_encoder: [char; 256],
+    byte_decoder: HashMap<char, u8>,
+    bos_id: Option<u32>,
+    eos_id: Option<u32>,
+    add_bos: bool,
+    add_space_prefix: bool,
+    stop_ids: Vec<u32>,
+}
+
+/// 构建分词器所需的词表数据（来自 GGUF 元数据或 HF tokenizer.json）
+struct Vocab {
+    kind: VocabKind,
+    tokens: Vec<String>,
+    token_types: Vec<i32>,
+    scores: Vec<f32>,
+    merges: Vec<(String, String)>,
+    /// BPE 预分词正则
+    pre_pattern: &'static str,
+    /// HF tokenizer.json 中 Split 预分词器给出的正则，优先于 pre_pattern
+    custom_pre_pattern: Option<String>,
+    bos_id: Option<u32>,
+    eos_id: Option<u32>,
+    eot_id: Option<u32>,
+    add_bos: bool,
+    add_space_prefix: bool,
+}
+
+impl GgufTokenizer {
+    pub fn from_gguf(gguf: &GgufFile) -> Result<Self> {
+        let kind = match gguf.get_str("tokenizer.ggml.model") {
+            Some("llama") => VocabKind::SentencePiece,
+            Some("gpt2") => VocabKind::BytePairEncoding,
+            Some(other) => anyhow::bail!("Unsupported tokenizer model: {}", other),
+            None => anyhow::bail!("GGUF file has no embedded tokenizer (tokenizer.ggml.model)"),
+        };
+
+        let tokens: Vec<String> = gguf
+            .get("tokenizer.ggml.tokens")
+            .and_then(GgufValue::as_array)
+            .ok_or_else(|| anyhow::anyhow!("GGUF file has no tokenizer.ggml.tokens"))?
+            .iter()
+            .map(|v| v.as_str().unwrap_or_default().to_string())
+            .collect();
+        let token_types: Vec<i32> = gguf
+            .get("tokenizer.ggml.token_type")
+            .and_then(GgufValue::as_array)
+            .map(|arr| arr.iter().map(|v| v.as_u64().unwrap_or(1) as i32).collect())
+            .unwrap_or_else(|| vec![TOKEN_TYPE_NORMAL; tokens.len()]);
+        let scores: Vec<f32> = gguf
+            .get("tokenizer.ggml.scores")
+            .and_then(GgufValue::as_array)
+            .map(|arr| arr.iter().map(|v| v.as_f32().unwrap_or(0.0)).collect())
+            .unwrap_or_else(|| vec![0.0; tokens.len()]);
+        let merges: Vec<(String, String)> = gguf
+            .get("tokenizer.ggml.merges")
+            .and_then(GgufValue::as_array)
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|v| v.as_str())
+                    .filter_map(|m| m.split_once(' '))
+                    .map(|(a, b)| (a.to_string(), b.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let pre_pattern = match gguf.get_str("tokenizer.ggml.pre").unwrap_or("default") {
+            "llama3" | "llama-bpe" | "smaug-bpe" | "falcon3" => PRE_LLAMA3,
+            "qwen2" | "deepseek-r1-qwen" => PRE_QWEN2,
+            _ => PRE_GPT2,
+        };
+
+        Self::from_vocab(Vocab {
+            kind,
+            tokens,
+            token_types,
+            scores,
+            merges,
+            pre_pattern,
+            custom_pre_pattern: None,
+            bos_id: gguf.get_u64("tokenizer.ggml.bos_token_id").map(|v| v as u32),
+            eos_id: gguf.get_u64("tokenizer.ggml.eos_token_id").map(|v| v as u32),
+            eot_id: gguf.get_u64("tokenizer.ggml.eot_token_id").map(|v| v as u32),
+            add_bos: gguf
+                .get("tokenizer.ggml.add_bos_token")
+                .and_then(GgufValue::as_bool)
+                .unwrap_or(kind == VocabKind::SentencePiece),
+            add_space_prefix: gguf
+                .get("tokenizer.ggml.add_space_prefix")
+                .and_then(GgufValue::as_bool)
+                .unwrap_or(true),
+        })
+    }
+
+    /// 从 Hugging Face tokenizer.json 加载（仅支持 BPE 模型：字节级 BPE 与带字节回退的 SentencePiece 式 BPE）
+    pub fn from_hf_json(json: &serde_json::Value) -> Result<Self> {
+        let model = &json["model"];
+        match model["type"].as_str() {
+            Some("BPE") => {}
+            Some(other) => anyhow::bail!("Unsupported tokenizer.json model type: {}", other),
+            None => anyhow::bail!("tokenizer.json has no model.type"),
+        }
+        let vocab = model["vocab"]
+            .as_object()
+            .ok_or_else(|| anyhow::anyhow!("tokenizer.json has no model.vocab"))?;
+        let added = json["added_tokens"].as_array().cloned().unwrap_or_default();
+        let size = vocab
+            .values()
+            .chain(added.iter().map(|t| &t["id"]))
+            .filter_map(|id| id.as_u64())
+            .max()
+            .map_or(0, |max| max as usize + 1);
+        let mut tokens = vec![String::new(); size];
+        let mut token_types = vec![TOKEN_TYPE_NORMAL; size];
+        for (token, id) in vocab {
+            if let Some(id) = id.as_u64() {
+                tokens[id as usize] = token.clone();
+                if token.len() == 6 && token.starts_with("<0x") && token.ends_with('>') {
+                    token_types[id as usize] = TOKEN_TYPE_BYTE;
+                }
+            }
+        }
+        for token in &added {
+            let (Some(id), Some(content)) = (token["id"].as_u64(), token["content"].as_str()) else { continue };
+            tokens[id as usize] = content.to_string();
+            token_types[id as usize] = if token["special"].as_bool().unwrap_or(false) {
+                TOKEN_TYPE_CONTROL
+            } else {
+                TOKEN_TYPE_USER_DEFINED
+            };
+        }
+
+        // 新版 tokenizer.json 的 merges 为 [a, b] 数组，旧版为 "a b" 字符串
+        let merges: Vec<(String, String)> = model["merges"]
+            .as_array()
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|m| match m {
+                        serde_json::Value::String(m) => m.split_once(' ').map(|(a, b)| (a.to_string(), b.to_string())),
+                        serde_json::Value::Array(pair) => {
+                            Some((pair.first()?.as_str()?.to_string(), pair.get(1)?.as_str()?.to_string()))
+                        }
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let byte_level = has_component(&json["pre_tokenizer"], "ByteLevel") || has_component(&json["decoder"], "ByteLevel");
+        let kind = if byte_level { VocabKind::BytePairEncoding } else { VocabKind::SentencePiece };
+        // SentencePiece 式 BPE 按合并顺序打分，越早合并得分越高
+        let mut scores = vec![f32::MIN; size];
+        if kind == VocabKind::SentencePiece {
+            let token_to_id: HashMap<&str, usize> = tokens.iter().enumerate().map(|(id, t)| (t.as_str(), id)).collect();
+            for (rank, (a, b)) in merges.iter().enumerate() {
+                if let Some(&id) = token_to_id.get(format!("{}{}", a, b).as_str())
+                    && scores[id] == f32::MIN
+                {
+                    scores[id] = -(rank as f32);
+                }
+            }
+        }
+
+        let id_of = |name: &str| tokens.iter().position(|t| t == name).map(|id| id as u32);
+        // 后处理模板中的第一个特殊 token 即 BOS
+        let bos_id = json["post_processor"]["single"]
+            .as_array()
+            .and_then(|items| items.iter().find_map(|item| item["SpecialToken"]["id"].as_str()))
+            .and_then(id_of);
+        let eos_id = ["</s>", "<|endoftext|>", "<|end_of_text|>", "<|im_end|>", "<|eot_id|>"]
+            .iter()
+            .find_map(|name| id_of(name));
+        let add_space_prefix = has_component(&json["normalizer"], "Prepend")
+            || find_component(&json["pre_tokenizer"], "Metaspace").is_some_and(|m| {
+                m["prepend_scheme"].as_str().map_or(m["add_prefix_space"].as_bool().unwrap_or(true), |s| s != "never")
+            });
+        let custom_pre_pattern = find_component(&json["pre_tokenizer"], "Split")
+            .and_then(|split| split["pattern"]["Regex"].as_str())
+            .map(str::to_string);
+
+        Self::from_vocab(Vocab {
+            kind,
+            tokens,
+            token_types,
+            scores,
+            merges,
+            pre_pattern: PRE_GPT2,
+            custom_pre_pattern,
+            add_bos: bos_id.is_some(),
+            bos_id,
+            eos_id,
+            eot_id: None,
+            add_space_prefix,
+        })
+    }
+
+    fn from_vocab(vocab: Vocab) -> Result<Self> {
+        let Vocab { kind, tokens, token_types, scores, .. } = vocab;
+        let token_to_id: HashMap<String, u32> = tokens
+            .iter()
+            .enumerate()
+            .map(|(id, t)| (t.clone(), id as u32))
+            .collect();
+
+        let merge_ranks: HashMap<(String, String), usize> = vocab
+            .merges
+            .into_iter()
+            .enumerate()
+            .map(|(rank, pair)| (pair, rank))
+            .collect();
+
+        let mut special_tokens: Vec<(String, u32)> = tokens
+            .iter()
+            .enumerate()
+            .filter(|(id, t)| {
+                let ty = token_types.get(*id).copied().unwrap_or(TOKEN_TYPE_NORMAL);
+                (ty == TOKEN_TYPE_CONTROL || ty == TOKEN_TYPE_USER_DEFINED) && !t.is_empty()
+            })
+            .map(|(id, t)| (t.clone(), id as u32))
+            .collect();
+        special_tokens.sort_by_key(|(t, _)| std::cmp::Reverse(t.len()));
+
+        let pre_tokenizer = match kind {
+            VocabKind::SentencePiece => None,
+            VocabKind::BytePairEncoding => Some(fancy_regex::Regex::new(
+                vocab.custom_pre_pattern.as_deref().unwrap_or(vocab.pre_pattern),
+            )?),
+        };
+
+        let byte_encoder = bytes_to_unicode();
+        let byte_decoder = byte_encoder.iter().enumerate().map(|(b, c)| (*c, b as u8)).collect();
+
+        let mut stop_ids: Vec<u32> = [vocab.eos_id, vocab.eot_id].into_iter().flatten().collect();
+        for marker in END_OF_TURN_MARKERS {
+            if let Some(id) = token_to_id.get(*marker)
+                && !stop_ids.contains(id)
+            {
+                stop_ids.push(*id);
+            }
+        }
+
+        Ok(Self {
+            kind,
+            tokens,
+            token_types,
+            scores,
+            token_to_id,
+            merge_ranks,
+            special_tokens,
+            pre_tokenizer,
+            byte_encoder,
+            byte_decoder,
+            bos_id: vocab.bos_id,
+            eos_id: vocab.eos_id,
+            add_bos: vocab.add_bos,
+            add_space_prefix: vocab.add_space_prefix,
+            stop_ids,
+        })
+    }
+
+    pub fn vocab_size(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// BOS token 的文本（如 <s>、<|begin_of_text|>），供对话模板使用
+    pub fn bos_token(&self) -> Option<&str> {
+        self.bos_id.and_then(|id| self.tokens.get(id as usize)).map(String::as_str)
+    }
+
+    /// EOS token 的文本
+    pub fn eos_token(&self) -> Option<&str> {
+        self.eos_id.and_then(|id| self.tokens.get(id as usize)).map(String::as_str)
+    }
+
+    /// 是否为结束生成的 token（EOS / EOT）
+    pub fn is_stop_token(&self, id: u32) -> bool {
+        self.stop_ids.contains(&id)
+    }
+
+    /// 编码文本；特殊 token（如 <|im_start|>）按字面识别
+    /// 对话模板已渲染出 BOS 文本时不再重复添加
+    pub fn encode(&self, text: &str, add_bos: bool) -> Vec<u32> {
+        let mut ids = Vec::new();
+        if add_bos
+            && self.add_bos
+            && let Some(bos) = self.bos_id
+            && !self.bos_token().is_some_and(|t| text.starts_with(t))
+        {
+            ids.push(bos);
+        }
+
+        let mut rest = text;
+        let mut first_segment = true;
+        while !rest.is_empty() {
+            // 找到最早出现的特殊 token（相同位置取最长）
+            let next_special = self
+                .special_tokens
+                .iter()
+                .filter_map(|(t, id)| rest.find(t.as_str()).map(|pos| (pos, t, *id)))
+                .min_by(|a, b| a.0.cmp(&b.0).then(b.1.len().cmp(&a.1.len())));
+
+            let (segment, special) = match next_special {
+                Some((pos, t, id)) => (&rest[..pos], Some((t.len(), id, pos))),
+                None => (rest, None),
+            };
+            if !segment.is_empty() {
+                self.encode_segment(segment, first_segment, &mut ids);
+            }
+            first_segment = false;
+            match special {
+                Some((len, id, pos)) => {
+                    ids.push(id);
+                    rest = &rest[pos + len..];
+                }
+                None => break,
+            }
+        }
+        ids
+    }
+
+    fn encode_segment(&self, text: &str, is_first: bool, ids: &mut Vec<u32>) {
+        match self.kind {
+            VocabKind::SentencePiece => self.encode_spm(text, is_first, ids),
+            VocabKind::BytePairEncoding => self.encode_bpe(text, ids),
+        }
+    }
+
+    fn encode_spm(&self, text: &str, is_first: bool, ids: &mut Vec<u32>) {
+        let mut normalized = String::with_capacity(text.len() + 3);
+        if self.add_space_prefix && is_first {
+            normalized.push('\u{2581}');
+        }
+        normalized.push_str(&text.replace(' ', "\u{2581}"));
+
+        // 合并是 O(n²) 的，先在“非空白 → 空白”边界切成词再分别合并
+        let mut start = 0;
+        let mut prev_is_space = true;
+        for (pos, c) in normalized.char_indices() {
+            let is_space = c == '\u{2581}';
+            if is_space && !prev_is_space {
+                self.encode_spm_word(&normalized[start..pos], ids);
+                start = pos;
+            }
+            prev_is_space = is_space;
+        }
+        self.encode_spm_word(&normalized[start..], ids);
+    }
+
+    fn encode_spm_word(&self, word: &str, ids: &mut Vec<u32>) {
+        // 以字符为初始符号，反复合并得分最高的相邻符号对
+        let mut symbols: Vec<String> = word.chars().map(|c| c.to_string()).collect();
+        loop {
+            let mut best: Option<(usize, f32)> = None;
+            for i in 0..symbols.len().saturating_sub(1) {
+                let merged = format!("{}{}", symbols[i], symbols[i + 1]);
+                if let Some(&id) = self.token_to_id.get(&merged) {
+                    let score = self.scores.get(id as usize).copied().unwrap_or(0.0);
+                    if best.is_none_or(|(_, s)| score > s) {
+                        best = Some((i, score));
+                    }
+                }
+            }
+            let Some((i, _)) = best else { break };
+            let right = symbols.remove(i + 1);
+            symbols[i].push_str(&right);
+        }
+
+        for symbol in symbols {
+            match self.token_to_id.get(&symbol) {
+                Some(&id) => ids.push(id),
+                None => {
+                    // 词表外字符回退为 <0xXX> 字节 token
+                    for byte in symbol.as_bytes() {
+                        if let Some(&id) = self.token_to_id.get(&format!("<0x{:02X}>", byte)) {
+                            ids.push(id);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn encode_bpe(&self, text: &str, ids: &mut Vec<u32>) {
+        let Some(pre) = &self.pre_tokenizer else { return };
+        for piece in pre.find_iter(text).filter_map(|m| m.ok()) {
+            let encoded: String = piece.as_str().bytes().map(|b| self.byte_encoder[b as usize]).collect();
+            if let Some(&id) = self.token_to_id.get(&encoded) {
+                ids.push(id);
+                continue;
+            }
+
+            let mut symbols: Vec<String> = encoded.chars().map(|c| c.to_string()).collect();
+            loop {
+                let best = (0..symbols.len().saturating_sub(1))
+                    .filter_map(|i| {
+                        self.merge_ranks
+                            .get(&(symbols[i].clone(), symbols[i + 1].clone()))
+                            .map(|rank| (i, *rank))
+                    })
+                    .min_by_key(|(_, rank)| *rank);
+                let Some((i, _)) = best else { break };
+                let right = symbols.remove(i + 1);
+                symbols[i].push_str(&right);
+            }
+            ids.extend(symbols.iter().filter_map(|s| self.token_to_id.get(s).copied()));
+        }
+    }
+
+    /// 把单个 token 还原为原始字节（流式输出时由调用方拼接完整 UTF-8）
+    pub fn token_bytes(&self, id: u32) -> Vec<u8> {
+        let Some(token) = self.tokens.get(id as usize) else {
+            return vec![];
+        };
+        let ty = self.token_types.get(id as usize).copied().unwrap_or(TOKEN_TYPE_NORMAL);
+        if ty == TOKEN_TYPE_CONTROL {
+            return vec![];
+        }
+        match self.kind {
+            VocabKind::SentencePiece => {
+                if ty == TOKEN_TYPE_BYTE {
+                    let hex = token.trim_start_matches("<0x").trim_end_matches('>');
+                    return u8::from_str_radix(hex, 16).map(|b| vec![b]).unwrap_or_default();
+                }
+                token.replace('\u{2581}', " ").into_bytes()
+            }
+            VocabKind::BytePairEncoding => {
+                if ty == TOKEN_TYPE_USER_DEFINED {
+                    return token.as_bytes().to_vec();
+                }
+                token
+                    .chars()
+                    .map(|c| self.byte_decoder.get(&c).copied().unwrap_or(b'?'))
+                    .collect()
+            }
+        }
+    }
+
+}
+
+/// 在 tokenizer.json 的组件（可能嵌套在 Sequence 中）里查找指定类型
+fn find_component<'a>(value: &'a serde_json::Value, ty: &str) -> Option<&'a serde_json::Value> {
+    if value["type"] == ty {
+        return Some(value);
+    }
+    ["pretokenizers", "normalizers", "decoders"]
+        .iter()
+        .filter_map(|key| value[*key].as_array())
+        .flatten()
+        .find_map(|child| find_component(child, ty))
+}
+
+fn has_component(value: &serde_json::Value, ty: &str) -> bool {
+    find_component(value, ty).is_some()
+}
+
+/// GPT-2 字节到可见 Unicode 字符的映射
+fn bytes_to_unicode() -> [char; 256] {
+    let mut table = ['\0'; 256];
+    let mut n = 0u32;
+    for b in 0..256u32 {
+        let printable = (b'!' as u32..=b'~' as u32).contains(&b)
+            || (0xA1..=0xAC).contains(&b)
+            || (0xAE..=0xFF).contains(&b);
+        table[b as usize] = if printable {
+            char::from_u32(b).unwrap()
+        } else {
+            n += 1;
+            char::from_u32(255 + n).unwrap()
+        };
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::gguf::GgufFile;
+    use std::collections::BTreeMap;
+
+    fn strings(items: &[&str]) -> GgufValue {
+        GgufValue::Array(items.iter().map(|s| GgufValue::String(s.to_string())).collect())
+    }
+
+    fn gguf(metadata: Vec<(&str, GgufValue)>) -> GgufFile {
+        GgufFile {
+            version: 3,
+            metadata: metadata.into_iter().map(|(k, v)| (k.to_string(), v)).collect::<BTreeMap<_, _>>(),
+            tensors: vec![],
+            data_offset: 0,
+        }
+    }
+
+    fn text_of(tokenizer: &GgufTokenizer, ids: &[u32]) -> String {
+        String::from_utf8(ids.iter().flat_map(|id| tokenizer.token_bytes(*id)).collect()).unwrap()
+    }
+
+    /// SentencePiece 词表：得分越高越先合并，词表外字符回退为字节 token
+    fn spm_tokenizer() -> GgufTokenizer {
+        let tokens = [
+            "<unk>", "<s>", "</s>", "\u{2581}", "h", "e", "l", "o", "\u{2581}h", "ll", "\u{2581}he", "llo", "\u{2581}hello",
+            "<0xE4>", "<0xBD>", "<0xA0>",
+        ];
+        let mut types = vec![2, TOKEN_TYPE_CONTROL, TOKEN_TYPE_CONTROL];
+        types.extend([TOKEN_TYPE_NORMAL; 10]);
+        types.extend([TOKEN_TYPE_BYTE; 3]);
+        let mut scores = vec![0.0f32; tokens.len()];
+        for (token, score) in [("\u{2581}h", -1.0), ("ll", -2.0), ("\u{2581}he", -3.0), ("llo", -4.0), ("\u{2581}hello", -5.0)] {
+            scores[tokens.iter().position(|t| *t == token).unwrap()] = score;
+        }
+        GgufTokenizer::from_gguf(&gguf(vec![
+            ("tokenizer.ggml.model", GgufValue::String("llama".into())),
+            ("tokenizer.ggml.tokens", strings(&tokens)),
+            ("tokenizer.ggml.token_type", GgufValue::Array(types.into_iter().map(GgufValue::I32).collect())),
+            ("tokenizer.ggml.scores", GgufValue::Array(scores.into_iter().map(GgufValue::F32).collect())),
+            ("tokenizer.ggml.bos_token_id", GgufValue::U32(1)),
+            ("tokenizer.ggml.eos_token_id", GgufValue::U32(2)),
+        ]))
+        .unwrap()
+    }
+
+    /// 字节级 BPE 词表：按合并规则的先后顺序合并
+    fn bpe_tokenizer() -> GgufTokenizer {
+        let tokens = [
+            "h", "e", "l", "o", "\u{120}", "w", "r", "d", "he", "ll", "hell", "hello", "\u{120}w", "or", "\u{120}wor", "ld", "\u{c3}",
+            "\u{a9}", "<|endoftext|>",
+        ];
+        let mut types = vec![TOKEN_TYPE_NORMAL; tokens.len() - 1];
+        types.push(TOKEN_TYPE_CONTROL);
+        GgufTokenizer::from_gguf(&gguf(vec![
+            ("tokenizer.ggml.model", GgufValue::String("gpt2".into())),
+            ("tokenizer.ggml.tokens", strings(&tokens)),
+            ("tokenizer.ggml.token_type", GgufValue::Array(types.into_iter().map(GgufValue::I32).collect())),
+            (
+                "tokenizer.ggml.merges",
+                strings(&["h e", "l l", "he ll", "hell o", "\u{120} w", "o r", "\u{120}w or", "l d"]),
+            ),
+            ("tokenizer.ggml.eos_token_id", GgufValue::U32(18)),
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn spm_merges_by_score_and_falls_back_to_bytes() {
+        let tokenizer = spm_tokenizer();
+        // ▁h → ll → ▁he → llo → ▁hello
+        assert_eq!(tokenizer.encode("hello", true), vec![1, 12]);
+        assert_eq!(tokenizer.encode("hello", false), vec![12]);
+        assert_eq!(text_of(&tokenizer, &[12]), " hello");
+
+        // 词表外的字符拆成 UTF-8 字节 token，拼接后还原原文
+        let ids = tokenizer.encode("你", false);
+        assert_eq!(ids, vec![3, 13, 14, 15]);
+        assert_eq!(text_of(&tokenizer, &ids), " 你");
+
+        // 特殊 token 按字面识别，控制符不输出文本
+        assert_eq!(tokenizer.encode("hello</s>", true), vec![1, 12, 2]);
+        assert_eq!(text_of(&tokenizer, &[1, 12, 2]), " hello");
+        assert!(tokenizer.is_stop_token(2));
+        assert_eq!(tokenizer.bos_token(), Some("<s>"));
+    }
+
+    #[test]
+    fn bpe_merges_by_rank_over_byte_level_pieces() {
+        let tokenizer = bpe_tokenizer();
+        // "hello" 整词命中；" world" 经 Ġw → or → Ġwor → ld 合并，没有 Ġwor + ld 的规则
+        let ids = tokenizer.encode("hello world", true);
+        assert_eq!(ids, vec![11, 14, 15]);
+        assert_eq!(text_of(&tokenizer, &ids), "hello world");
+
+        let ids = tokenizer.encode("hell", false);
+        assert_eq!(ids, vec![10]);
+
+        // 多字节字符按字节映射后编码，解码还原
+        let ids = tokenizer.encode("é<|endoftext|>", false);
+        assert_eq!(ids, vec![16, 17, 18]);
+        assert_eq!(text_of(&tokenizer, &ids), "é");
+        assert!(tokenizer.is_stop_token(18));
+        assert_eq!(tokenizer.vocab_size(), 19);
+    }
+}
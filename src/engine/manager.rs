@@ -1,72 +1,656 @@
 // 推理引擎管理器 - 根据硬件自动选择最优后端
+// 后端按优先级组成降级链：首选后端初始化失败或请求出错时依次尝试下一个，反复失败的后端被熔断
 
-use crate::engine::backend::{InferenceBackend, LlamaCppBackend, MlxBackend, InferflowBackend};
-use crate::engine::{BackendType, InferenceConfig, InferenceResponse};
+use crate::engine::backend::{InferenceBackend, LlamaCppBackend, MlxBackend, InferflowBackend, OllamaBackend, OpenAiCompatBackend};
+use crate::engine::benchmark::{self, BenchmarkConfig, BenchmarkReport, BenchmarkResult, BenchmarkTarget};
+use crate::engine::constraint;
+use crate::engine::context::{self, ContextConfig, ContextFit, ContextPolicy};
+use crate::engine::fallback::{BackendEvent, BackendHealth, BreakerState, CircuitBreaker};
+use crate::engine::gguf::{self, GgufModelInfo};
+use crate::engine::hardware::{self, HardwareProfile};
+use crate::engine::llama;
+use crate::engine::memory::{self, MemoryEstimate, MemoryReport};
+use crate::engine::plugin::{self, PluginBackend, PluginInfo};
+use crate::engine::prefix_cache::{PrefixCacheConfig, PrefixCacheStats};
+use crate::engine::residency::{ModelPool, ResidentModelInfo, SharedBackend, DEFAULT_MEMORY_BUDGET_BYTES};
+use crate::engine::scheduler::{QueueStats, Scheduler, SchedulerConfig};
+use crate::engine::scripted::{Recorder, RecordingBackend, ScriptedBackend};
+use crate::engine::tokenizer::{self, EstimateTokenizer, Tokenizer};
+use crate::engine::{
+    BackendStats, BackendStatsSummary, BackendType, ChatMessage, FinishReason, GenerationParams, InferenceConfig,
+    InferenceMetrics, InferenceResponse, InferenceStream, ModelDescriptor, Priority,
+};
 use anyhow::Result;
-use sysinfo::System;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, RwLock};
+use tokio_util::sync::CancellationToken;
+
+/// 降级链中的一个后端
+struct BackendSlot {
+    backend_type: BackendType,
+    backend: RwLock<Box<dyn InferenceBackend>>,
+    /// 是否可以直接处理请求；为 false 时需先用当前推理配置初始化
+    ready: AtomicBool,
+    /// 最近一次初始化时的推理配置；None 表示未用模型配置初始化过
+    initialized_with: Mutex<Option<InferenceConfig>>,
+    breaker: Mutex<CircuitBreaker>,
+}
+
+impl BackendSlot {
+    fn new(backend: Box<dyn InferenceBackend>, ready: bool) -> Arc<Self> {
+        Arc::new(Self {
+            backend_type: backend.backend_type(),
+            backend: RwLock::new(backend),
+            ready: AtomicBool::new(ready),
+            initialized_with: Mutex::new(None),
+            breaker: Mutex::new(CircuitBreaker::default()),
+        })
+    }
+    
+    fn breaker(&self) -> std::sync::MutexGuard<'_, CircuitBreaker> {
+        self.breaker.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    
+    fn initialized_with(&self) -> std::sync::MutexGuard<'_, Option<InferenceConfig>> {
+        self.initialized_with.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// 统计与事件，健康检查任务和流结束回调也会写入
+#[derive(Clone)]
+struct Monitor {
+    stats: Arc<Mutex<HashMap<BackendType, BackendStats>>>,
+    events: broadcast::Sender<BackendEvent>,
+}
+
+impl Monitor {
+    fn emit(&self, event: BackendEvent) {
+        tracing::debug!("Backend event: {:?}", event);
+        // 没有订阅者时发送失败，忽略
+        let _ = self.events.send(event);
+    }
+    
+    fn record_metrics(&self, backend: BackendType, finish_reason: FinishReason, metrics: &InferenceMetrics) {
+        tracing::debug!(
+            "{:?} inference finished ({}): {:?} prompt + {} completion tokens, ttft {:?} ms, {:.1} tok/s",
+            backend,
+            finish_reason,
+            metrics.prompt_tokens,
+            metrics.completion_tokens,
+            metrics.time_to_first_token_ms,
+            metrics.tokens_per_second
+        );
+        let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+        stats.entry(backend).or_default().record(finish_reason, metrics);
+    }
+    
+    fn record_error(&self, backend: BackendType) {
+        let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+        stats.entry(backend).or_default().record_error();
+    }
+    
+    fn succeeded(&self, slot: &BackendSlot) {
+        let recovered = slot.breaker().record_success();
+        if recovered {
+            tracing::info!("{:?} backend recovered", slot.backend_type);
+            self.emit(BackendEvent::Recovered {
+                backend: slot.backend_type.clone(),
+            });
+        }
+    }
+    
+    fn failed(&self, slot: &BackendSlot, error: &str) {
+        tracing::warn!("{:?} backend failed: {}", slot.backend_type, error);
+        self.emit(BackendEvent::Failed {
+            backend: slot.backend_type.clone(),
+            error: error.to_string(),
+        });
+        let opened = slot.breaker().record_failure(error);
+        if let Some(cooldown) = opened {
+            tracing::warn!("{:?} backend disabled for {}s after repeated failures", slot.backend_type, cooldown.as_secs());
+            self.emit(BackendEvent::CircuitOpened {
+                backend: slot.backend_type.clone(),
+                cooldown_secs: cooldown.as_secs(),
+            });
+        }
+    }
+    
+    fn served(&self, slot: &BackendSlot, primary: &BackendType) {
+        self.succeeded(slot);
+        let fallback_from = (slot.backend_type != *primary).then(|| primary.clone());
+        if let Some(primary) = &fallback_from {
+            tracing::info!("Request served by fallback {:?} backend (primary {:?} unavailable)", slot.backend_type, primary);
+        }
+        self.emit(BackendEvent::Served {
+            backend: slot.backend_type.clone(),
+            fallback_from,
+        });
+    }
+    
+    /// 健康检查：失败计入熔断器；成功只用于恢复已熔断的后端，不抵消请求失败
+    async fn check(&self, slot: &BackendSlot) {
+        let healthy = slot.backend.read().await.health_check().await;
+        if !healthy {
+            self.failed(slot, "health check failed");
+        } else if slot.breaker().state() != BreakerState::Closed {
+            self.succeeded(slot);
+        }
+    }
+}
 
 pub struct EngineManager {
-    backend: Arc<RwLock<Box<dyn InferenceBackend>>>,
+    /// 按优先级排列的后端降级链，第一个可用的为首选
+    chain: Arc<RwLock<Vec<Arc<BackendSlot>>>>,
     current_backend_type: BackendType,
     initialized: bool,
+    /// 已配置的本地 OpenAI 兼容服务地址（如 http://127.0.0.1:8080）
+    openai_endpoint: Option<String>,
+    /// 访问 OpenAI 兼容服务的 API Key
+    openai_api_key: Option<String>,
+    /// OpenAI 兼容服务上使用的模型名，未配置时取 /v1/models 中的第一个
+    openai_model: Option<String>,
+    /// 已配置的 Ollama 服务地址（如 http://127.0.0.1:11434）
+    ollama_endpoint: Option<String>,
+    /// Ollama 上使用的模型名，未配置时取第一个已安装模型
+    ollama_model: Option<String>,
+    /// 当前加载的本地模型信息
+    model_info: Option<GgufModelInfo>,
+    /// 检测后端时记录的硬件画像
+    hardware: Option<HardwareProfile>,
+    /// 后端插件目录，检测后端时扫描
+    plugin_dir: Option<PathBuf>,
+    /// 已加载的后端插件
+    plugins: Vec<PluginInfo>,
+    /// 初始化时的推理配置，降级到尚未初始化的后端时复用
+    config: Option<InferenceConfig>,
+    /// 各后端的滚动性能统计与路由事件
+    monitor: Monitor,
+    /// 按模型 ID 驻留的本地模型，请求通过 GenerationParams::model 指定
+    models: Mutex<ModelPool>,
+    /// 按 GenerationParams::priority 排队，限制同时执行的请求数
+    scheduler: Scheduler,
+    /// 专用的嵌入模型；未设置时使用降级链中第一个能生成文本向量的后端
+    embedding: Mutex<Option<SharedBackend>>,
+    /// 当前模型的分词器；未加载模型（如远程后端）时按字符估算
+    tokenizer: Option<Arc<dyn Tokenizer>>,
+    /// 超出上下文长度时的裁剪策略
+    context: Mutex<ContextConfig>,
+    /// 本地 CPU 后端的提示词前缀 KV 缓存配置
+    prefix_cache: PrefixCacheConfig,
+    /// 基准测试报告目录；有本机的报告时按实测速度选择后端
+    benchmark_dir: Option<PathBuf>,
 }
 
+/// 未指定时的默认上下文长度（不超过模型训练长度）
+const DEFAULT_CONTEXT_SIZE: usize = 4096;
+
 impl EngineManager {
     pub fn new() -> Self {
+        let (events, _) = broadcast::channel(64);
+        let prefix_cache = PrefixCacheConfig::default();
+        let cpu_backend = LlamaCppBackend::new().with_prefix_cache(prefix_cache.clone());
         Self {
-            backend: Arc::new(RwLock::new(Box::new(LlamaCppBackend::new()))),
+            chain: Arc::new(RwLock::new(vec![BackendSlot::new(Box::new(cpu_backend), true)])),
             current_backend_type: BackendType::LlamaCppCpu,
             initialized: false,
+            openai_endpoint: None,
+            openai_api_key: None,
+            openai_model: None,
+            ollama_endpoint: None,
+            ollama_model: None,
+            model_info: None,
+            hardware: None,
+            plugin_dir: None,
+            plugins: vec![],
+            config: None,
+            monitor: Monitor {
+                stats: Arc::new(Mutex::new(HashMap::new())),
+                events,
+            },
+            models: Mutex::new(ModelPool::new(DEFAULT_MEMORY_BUDGET_BYTES)),
+            scheduler: Scheduler::default(),
+            embedding: Mutex::new(None),
+            tokenizer: None,
+            context: Mutex::new(ContextConfig::default()),
+            prefix_cache,
+            benchmark_dir: None,
         }
     }
     
-    /// 检测硬件并选择最优后端
+    /// 配置提示词前缀 KV 缓存；对之后创建的 CPU 后端生效（应在检测后端之前调用）
+    pub fn set_prefix_cache_config(&mut self, config: PrefixCacheConfig) {
+        self.prefix_cache = config;
+    }
+    
+    /// 各后端的提示词前缀缓存命中情况（含驻留模型，键为模型 ID）
+    pub async fn prefix_cache_stats(&self) -> Vec<(String, PrefixCacheStats)> {
+        let mut stats = vec![];
+        for slot in self.chain.read().await.iter() {
+            if let Some(s) = slot.backend.read().await.prefix_cache_stats() {
+                stats.push((format!("{:?}", slot.backend_type), s));
+            }
+        }
+        let resident = self.model_pool().backends();
+        for (id, backend) in resident {
+            if let Some(s) = backend.read().await.prefix_cache_stats() {
+                stats.push((id, s));
+            }
+        }
+        stats
+    }
+    
+    fn cpu_backend(&self) -> LlamaCppBackend {
+        LlamaCppBackend::new().with_prefix_cache(self.prefix_cache.clone())
+    }
+    
+    /// 配置本地 OpenAI 兼容服务地址、API Key 与模型名，检测后端时优先探测
+    pub fn set_openai_endpoint(&mut self, endpoint: impl Into<String>, api_key: Option<String>, model: Option<String>) {
+        self.openai_endpoint = Some(endpoint.into());
+        self.openai_api_key = api_key;
+        self.openai_model = model;
+    }
+    
+    /// 配置 Ollama 服务地址与模型名，检测后端时探测其是否在运行
+    pub fn set_ollama_endpoint(&mut self, endpoint: impl Into<String>, model: Option<String>) {
+        self.ollama_endpoint = Some(endpoint.into());
+        self.ollama_model = model;
+    }
+    
+    /// 配置后端插件目录（C ABI 动态库），检测后端时加载
+    pub fn set_plugin_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.plugin_dir = Some(dir.into());
+    }
+    
+    /// 配置基准测试报告目录，检测后端时读取本机的报告
+    pub fn set_benchmark_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.benchmark_dir = Some(dir.into());
+    }
+    
+    /// 检测硬件并建立降级链：本地服务 → 硬件匹配的加速后端 → CPU 后端
     pub async fn detect_and_select_backend(&mut self) -> Result<BackendType> {
-        let mut sys = System::new();
-        sys.refresh_all();
-        
-        // 策略 1: Apple Silicon (Mac)
-        if cfg!(target_os = "macos") {
-            // 检查是否为 Apple Silicon
-            let cpu_name = sys.cpus().first().map(|c| c.name().to_lowercase()).unwrap_or_default();
-            if cpu_name.contains("apple") || cpu_name.contains("m1") || cpu_name.contains("m2") || cpu_name.contains("m3") {
-                let mlx_backend = MlxBackend {};
-                if mlx_backend.is_available() {
-                    *self.backend.write().await = Box::new(mlx_backend);
-                    self.current_backend_type = BackendType::MlxSidecar;
-                    tracing::info!("Selected MLX backend for Apple Silicon");
-                    return Ok(BackendType::MlxSidecar);
+        let mut chain = Vec::new();
+        
+        // 策略 0: 已配置的本地 OpenAI 兼容服务；暂时无响应时仍保留在链中，恢复后自动启用
+        if let Some(endpoint) = &self.openai_endpoint {
+            let mut openai_backend = OpenAiCompatBackend::new(endpoint.clone());
+            if let Some(api_key) = &self.openai_api_key {
+                openai_backend = openai_backend.with_api_key(api_key.clone());
+            }
+            if let Some(model) = &self.openai_model {
+                openai_backend = openai_backend.with_model(model.clone());
+            }
+            let healthy = openai_backend.probe().await;
+            if !healthy {
+                tracing::warn!("OpenAI-compatible endpoint {} did not respond, falling back", endpoint);
+            }
+            chain.push(BackendSlot::new(Box::new(openai_backend), healthy));
+        }
+        
+        // 策略 0.5: 已配置的 Ollama 服务
+        if let Some(endpoint) = &self.ollama_endpoint {
+            let mut ollama_backend = OllamaBackend::new(endpoint.clone());
+            if let Some(model) = &self.ollama_model {
+                ollama_backend = ollama_backend.with_model(model.clone());
+            }
+            let healthy = ollama_backend.probe().await;
+            if !healthy {
+                tracing::warn!("Ollama endpoint {} did not respond, falling back", endpoint);
+            }
+            chain.push(BackendSlot::new(Box::new(ollama_backend), healthy));
+        }
+        
+        // 策略 1-2: 按硬件画像选择加速后端（Apple Silicon → MLX，NVIDIA GPU → Inferflow）
+        let profile = tokio::task::spawn_blocking(HardwareProfile::detect).await?;
+        let mut selection = hardware::select_backend(&profile);
+        // 本机跑过基准测试时以实测结果为准
+        if let Some(dir) = &self.benchmark_dir {
+            match BenchmarkReport::load(dir, &profile) {
+                Ok(Some(report)) => {
+                    if let Some(measured) = benchmark::select_backend(&report, &profile) {
+                        selection = measured;
+                    }
                 }
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Failed to read benchmark report in {:?}: {}", dir, e),
             }
         }
+        let plugin_dir = self.plugin_dir.clone();
+        let mut plugins = match plugin_dir {
+            Some(dir) => tokio::task::spawn_blocking(move || plugin::discover(&dir)).await?,
+            None => vec![],
+        };
+        self.plugins = plugins.iter().map(|p| p.info().clone()).collect();
+        // Inferflow 由名为 inferflow 的插件提供，只在选中 NVIDIA GPU 时使用；没有插件时内置的占位后端不可用，回退到 CPU
+        let inferflow = plugins.iter().position(|p| p.name() == "inferflow").map(|i| plugins.remove(i));
+        let accelerated: Option<Box<dyn InferenceBackend>> = match selection.backend {
+            BackendType::MlxSidecar => Some(Box::new(MlxBackend::new())),
+            BackendType::InferflowCpp => match inferflow {
+                Some(plugin) => Some(Box::new(PluginBackend::new(plugin))),
+                None => Some(Box::new(InferflowBackend::new())),
+            },
+            _ => None,
+        };
+        match accelerated {
+            Some(backend) if backend.is_available() => {
+                tracing::info!("Hardware backend {:?}: {}", selection.backend, selection.reason);
+                chain.push(BackendSlot::new(backend, true));
+            }
+            Some(_) => tracing::warn!("{:?} backend unavailable, falling back to CPU", selection.backend),
+            None => tracing::info!("Hardware backend: {}", selection.reason),
+        }
         
-        // 策略 2: NVIDIA GPU (PC/Server)
-        // TODO: 使用 sysinfo 或 nvidia-smi 检测 GPU
-        let inferflow_backend = InferflowBackend {};
-        if inferflow_backend.is_available() {
-            *self.backend.write().await = Box::new(inferflow_backend);
-            self.current_backend_type = BackendType::InferflowCpp;
-            tracing::info!("Selected Inferflow backend for NVIDIA GPU");
-            return Ok(BackendType::InferflowCpp);
+        // 其他插件（如 OpenVINO 适配器）位于加速后端之后、CPU 后端之前
+        for plugin in plugins {
+            chain.push(BackendSlot::new(Box::new(PluginBackend::new(plugin)), true));
         }
         
-        // 策略 3: 默认 CPU 后端
-        *self.backend.write().await = Box::new(LlamaCppBackend::new());
-        self.current_backend_type = BackendType::LlamaCppCpu;
-        tracing::info!("Selected Llama.cpp CPU backend");
-        Ok(BackendType::LlamaCppCpu)
+        // 策略 3: CPU 后端总是位于链尾兜底
+        chain.push(BackendSlot::new(Box::new(self.cpu_backend()), true));
+        
+        // 模型驻留预算默认取物理内存的一半
+        if profile.total_memory_bytes > 0 {
+            self.set_model_budget(profile.total_memory_bytes / 2);
+        }
+        self.hardware = Some(profile);
+        Ok(self.install_chain(chain).await)
+    }
+    
+    /// 手动指定降级链（按优先级排列）
+    pub async fn set_backends(&mut self, backends: Vec<Box<dyn InferenceBackend>>) -> Result<BackendType> {
+        if backends.is_empty() {
+            anyhow::bail!("Backend chain must contain at least one backend");
+        }
+        let chain = backends.into_iter().map(|b| BackendSlot::new(b, true)).collect();
+        Ok(self.install_chain(chain).await)
+    }
+    
+    /// 录制降级链中各后端的对话到脚本文件（供 ScriptedBackend 回放）；
+    /// 之后调用 detect_and_select_backend / set_backends 安装的新后端不录制
+    pub async fn record_sessions(&self, path: impl Into<PathBuf>) -> Result<()> {
+        let recorder = Recorder::open(path)?;
+        for slot in self.chain.read().await.iter() {
+            let mut backend = slot.backend.write().await;
+            let inner = std::mem::replace(&mut *backend, Box::new(ScriptedBackend::new(vec![])));
+            *backend = Box::new(RecordingBackend::new(inner, recorder.clone()));
+        }
+        tracing::info!("Recording inference sessions to {:?}", recorder.path());
+        Ok(())
+    }
+    
+    async fn install_chain(&mut self, chain: Vec<Arc<BackendSlot>>) -> BackendType {
+        // 探测失败的服务先熔断，由健康检查恢复
+        for slot in chain.iter().filter(|s| !s.ready.load(Ordering::Acquire)) {
+            slot.breaker().trip("did not respond to probe");
+        }
+        let primary = chain
+            .iter()
+            .find(|s| s.ready.load(Ordering::Acquire))
+            .unwrap_or(&chain[0])
+            .backend_type
+            .clone();
+        tracing::info!(
+            "Selected {:?} backend (chain: {:?})",
+            primary,
+            chain.iter().map(|s| &s.backend_type).collect::<Vec<_>>()
+        );
+        *self.chain.write().await = chain;
+        self.current_backend_type = primary.clone();
+        primary
+    }
+    
+    /// 已加载的后端插件
+    pub fn plugins(&self) -> &[PluginInfo] {
+        &self.plugins
+    }
+    
+    /// 最近一次检测到的硬件画像
+    pub fn hardware_profile(&self) -> Option<&HardwareProfile> {
+        self.hardware.as_ref()
+    }
+    
+    /// 读取模型文件元数据（不加载权重）
+    pub fn inspect_model(path: &Path) -> Result<GgufModelInfo> {
+        if !path.exists() {
+            anyhow::bail!("Model file not found: {:?}", path);
+        }
+        gguf::inspect(path)
+    }
+    
+    /// 根据模型元数据生成默认推理配置
+    pub fn default_config_for(&self, model_path: &Path) -> Result<InferenceConfig> {
+        // MLX 侧车加载的是 MLX 格式的模型目录（或 Hugging Face 仓库名），不是 GGUF 文件
+        if self.current_backend_type == BackendType::MlxSidecar && model_path.extension().is_none_or(|ext| ext != "gguf") {
+            return Ok(InferenceConfig {
+                model_path: model_path.to_path_buf(),
+                backend: BackendType::MlxSidecar,
+                context_size: DEFAULT_CONTEXT_SIZE,
+            });
+        }
+        let info = Self::inspect_model(model_path)?;
+        let context_size = info
+            .context_length
+            .map(|n| (n as usize).min(DEFAULT_CONTEXT_SIZE))
+            .unwrap_or(DEFAULT_CONTEXT_SIZE);
+        Ok(InferenceConfig {
+            model_path: model_path.to_path_buf(),
+            backend: self.current_backend_type.clone(),
+            context_size,
+        })
+    }
+    
+    /// 估算模型在给定上下文长度（默认同 default_config_for）下的内存占用，并与当前可用内存比较
+    pub fn estimate_memory(model_path: &Path, context_size: Option<usize>) -> Result<MemoryReport> {
+        let info = Self::inspect_model(model_path)?;
+        let n_ctx_train = info.context_length.map(|n| n as usize);
+        let context_size = context_size
+            .unwrap_or_else(|| n_ctx_train.unwrap_or(DEFAULT_CONTEXT_SIZE).min(DEFAULT_CONTEXT_SIZE))
+            .min(n_ctx_train.unwrap_or(usize::MAX));
+        Ok(MemoryReport::new(&info, context_size, memory::available_memory_bytes()))
     }
     
     /// 初始化推理引擎
-    pub async fn initialize(&mut self, config: InferenceConfig) -> Result<()> {
-        let mut backend = self.backend.write().await;
+    /// 按降级链顺序初始化，直到有一个后端成功；其余后端在需要降级时再初始化
+    pub async fn initialize(&mut self, mut config: InferenceConfig) -> Result<()> {
+        // 本地 CPU 后端为首选时，上下文长度以准入结果为准（也用于裁剪提示词）
+        if matches!(self.current_backend_type, BackendType::LlamaCppCpu) {
+            let info = Self::admit_local_model(&mut config)?;
+            tracing::info!(
+                "Model {}: {} {} ({} params, n_ctx_train: {:?})",
+                info.name.as_deref().unwrap_or("unnamed"),
+                info.architecture,
+                info.quantization,
+                info.parameter_count,
+                info.context_length
+            );
+            self.model_info = Some(info);
+        }
+        
+        // GGUF 文件读取内嵌词表，模型目录读取其中的 tokenizer.json
+        self.tokenizer = match tokenizer::load(&config.model_path) {
+            Ok(tokenizer) => Some(tokenizer),
+            Err(e) => {
+                tracing::warn!("No tokenizer for {:?}, estimating token counts: {}", config.model_path, e);
+                None
+            }
+        };
+        self.config = Some(config);
+        let chain = self.chain.read().await.clone();
+        // 已用相同配置初始化的后端无需重新加载模型
+        for slot in chain.iter().filter(|s| s.initialized_with().as_ref() != self.config.as_ref()) {
+            slot.ready.store(false, Ordering::Release);
+        }
+        let mut first_error = None;
+        for slot in &chain {
+            match self.ensure_ready(slot).await {
+                Ok(()) => {
+                    if first_error.is_some() {
+                        tracing::warn!("Primary backend failed to initialize, using {:?}", slot.backend_type);
+                    }
+                    self.current_backend_type = slot.backend_type.clone();
+                    self.initialized = true;
+                    return Ok(());
+                }
+                Err(e) => {
+                    self.monitor.failed(slot, &e.to_string());
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        Err(first_error.unwrap_or_else(|| anyhow::anyhow!("No inference backend configured")))
+    }
+    
+    /// 本地 CPU 后端加载模型前的检查：校验模型文件，不兼容时给出明确错误；
+    /// 上下文长度不超过训练长度；准入控制：内存不足时缩小上下文，仍不足则拒绝加载
+    fn admit_local_model(config: &mut InferenceConfig) -> Result<GgufModelInfo> {
+        let info = Self::inspect_model(&config.model_path)?;
+        llama::check_compatible(&info)?;
+        if let Some(n_ctx_train) = info.context_length.map(|n| n as usize) {
+            if config.context_size == 0 {
+                config.context_size = n_ctx_train.min(DEFAULT_CONTEXT_SIZE);
+            } else if config.context_size > n_ctx_train {
+                tracing::warn!("context_size {} exceeds model training context {}, clamping", config.context_size, n_ctx_train);
+                config.context_size = n_ctx_train;
+            }
+        }
+        let report = MemoryReport::new(&info, config.context_size, memory::available_memory_bytes());
+        config.context_size = report.admit(info.name.as_deref().unwrap_or(&info.architecture))?;
+        Ok(info)
+    }
+    
+    /// 使用指定的分词器（如远程后端所用模型的 tokenizer.json）
+    pub fn set_tokenizer(&mut self, tokenizer: Arc<dyn Tokenizer>) {
+        self.tokenizer = Some(tokenizer);
+    }
+    
+    /// 当前模型的分词器，没有时返回估算分词器
+    pub fn tokenizer(&self) -> Arc<dyn Tokenizer> {
+        self.tokenizer.clone().unwrap_or_else(|| Arc::new(EstimateTokenizer))
+    }
+    
+    /// 文本在当前模型下的 token 数
+    pub fn count_tokens(&self, text: &str) -> usize {
+        self.tokenizer().count_tokens(text)
+    }
+    
+    /// 截取不超过 max_tokens 的最长前缀
+    pub fn truncate_to_tokens(&self, text: &str, max_tokens: usize) -> String {
+        self.tokenizer().truncate_to_tokens(text, max_tokens)
+    }
+    
+    pub fn set_context_config(&self, config: ContextConfig) {
+        *self.context.lock().unwrap_or_else(|e| e.into_inner()) = config;
+    }
+    
+    pub fn context_config(&self) -> ContextConfig {
+        self.context.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+    
+    /// 按上下文策略将消息裁剪到模型上下文长度以内（预留生成所需的 token）
+    /// 上下文长度未知（尚未初始化）时原样返回；Summarize 策略会调用模型生成摘要
+    pub async fn fit_context(&self, messages: &[ChatMessage], params: &GenerationParams) -> Result<ContextFit> {
+        let config = self.context_config();
+        let context_size = match &params.model {
+            Some(model) => self.model_pool().context_size(model),
+            None => self.config.as_ref().map(|c| c.context_size),
+        };
+        let Some(context_size) = context_size.filter(|_| config.policy != ContextPolicy::Disabled) else {
+            return Ok(context::fit(messages, usize::MAX, ContextPolicy::Disabled, 0, &EstimateTokenizer));
+        };
+        
+        // 驻留模型与当前模型的词表可能不同，此时只是近似
+        let tokenizer = self.tokenizer();
+        let reserve = params.max_tokens.unwrap_or(config.reserve_tokens).min(context_size / 2);
+        let budget = context_size - reserve;
+        let mut fit = context::fit(messages, budget, config.policy, config.summary_tokens, &*tokenizer);
+        
+        if !fit.summarized.is_empty() {
+            let request = context::summary_request(&fit.summarized, budget.saturating_sub(config.summary_tokens), &*tokenizer);
+            let summary_params = GenerationParams {
+                model: params.model.clone(),
+                ..GenerationParams::deterministic()
+            }
+            .with_max_tokens(config.summary_tokens)
+            .with_priority(params.priority);
+            let summary = match self.dispatch(&request, &summary_params).await {
+                Ok(response) => Some(response.tokens.concat()),
+                Err(e) => {
+                    tracing::warn!("Failed to summarize earlier turns, dropping them instead: {}", e);
+                    None
+                }
+            };
+            fit.apply_summary(summary, &*tokenizer);
+        }
+        
+        if fit.report.is_trimmed() {
+            tracing::info!(
+                "Prompt exceeds context window ({} > {} tokens), {:?} removed {} message(s), now {} tokens",
+                fit.report.tokens_before,
+                budget,
+                config.policy,
+                fit.report.dropped.len(),
+                fit.report.tokens_after
+            );
+        }
+        Ok(fit)
+    }
+    
+    /// 未初始化的后端先用当前配置初始化
+    async fn ensure_ready(&self, slot: &BackendSlot) -> Result<()> {
+        if slot.ready.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let mut backend = slot.backend.write().await;
+        if slot.ready.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let config = match &self.config {
+            // 本地 CPU 后端作为降级目标延迟初始化时同样经过兼容性检查与内存准入
+            Some(config) if slot.backend_type == BackendType::LlamaCppCpu => {
+                let mut config = InferenceConfig {
+                    backend: BackendType::LlamaCppCpu,
+                    ..config.clone()
+                };
+                Self::admit_local_model(&mut config)?;
+                config
+            }
+            Some(config) => InferenceConfig {
+                backend: slot.backend_type.clone(),
+                ..config.clone()
+            },
+            // 未加载本地模型时只有探测失败的本地服务需要初始化（重新探测）
+            None => InferenceConfig {
+                model_path: PathBuf::new(),
+                backend: slot.backend_type.clone(),
+                context_size: DEFAULT_CONTEXT_SIZE,
+            },
+        };
+        tracing::info!("Initializing {:?} backend", slot.backend_type);
         backend.initialize(config).await?;
-        self.initialized = true;
+        *slot.initialized_with() = self.config.clone();
+        slot.ready.store(true, Ordering::Release);
         Ok(())
     }
     
+    /// 本次请求依次尝试的后端：跳过熔断中的后端；全部熔断时仍按顺序尝试，尽量不直接报错
+    async fn candidates(&self) -> Vec<Arc<BackendSlot>> {
+        let chain = self.chain.read().await.clone();
+        let allowed: Vec<_> = chain.iter().filter(|s| s.breaker().allow()).cloned().collect();
+        if allowed.is_empty() {
+            tracing::warn!("All backends are disabled, trying the full chain");
+            chain
+        } else {
+            allowed
+        }
+    }
+    
+    /// 当前加载的本地模型信息
+    pub fn model_info(&self) -> Option<&GgufModelInfo> {
+        self.model_info.as_ref()
+    }
+    
     /// 检查是否已初始化
     pub fn is_initialized(&self) -> bool {
         self.initialized
@@ -74,14 +658,459 @@ impl EngineManager {
     
     /// 执行推理
     pub async fn infer(&self, prompt: &str) -> Result<InferenceResponse> {
-        let backend = self.backend.read().await;
-        backend.infer(prompt).await
+        self.chat(&[ChatMessage::user(prompt)], &GenerationParams::default()).await
+    }
+    
+    /// 流式推理；cancel 被触发或返回的流被丢弃时停止生成
+    pub async fn infer_stream(&self, prompt: &str, cancel: CancellationToken) -> Result<InferenceStream> {
+        self.chat_stream(&[ChatMessage::user(prompt)], &GenerationParams::default(), cancel).await
+    }
+    
+    /// 多轮对话推理；失败时沿降级链重试
+    /// 指定了输出约束时，不支持约束解码的后端会校验输出并重试（见 constraint::chat）
+    /// 超出上下文长度时按上下文策略裁剪，裁剪报告见 metrics.context
+    pub async fn chat(&self, messages: &[ChatMessage], params: &GenerationParams) -> Result<InferenceResponse> {
+        let fit = self.fit_context(messages, params).await?;
+        let mut response = self.dispatch(&fit.messages, params).await?;
+        if fit.report.is_trimmed() {
+            response.metrics.context = Some(fit.report);
+        }
+        Ok(response)
+    }
+    
+    async fn dispatch(&self, messages: &[ChatMessage], params: &GenerationParams) -> Result<InferenceResponse> {
+        let _permit = self.scheduler.acquire(params.priority).await;
+        if let Some(model) = &params.model {
+            let (backend_type, backend) = self.resident(model)?;
+            let response = constraint::chat(&**backend.read().await, messages, params).await;
+            match &response {
+                Ok(response) => self.monitor.record_metrics(backend_type, response.finish_reason, &response.metrics),
+                Err(_) => self.monitor.record_error(backend_type),
+            }
+            return response;
+        }
+        
+        let mut last_error = None;
+        for slot in self.candidates().await {
+            let result = match self.ensure_ready(&slot).await {
+                Ok(()) => constraint::chat(&**slot.backend.read().await, messages, params).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(response) => {
+                    self.monitor.record_metrics(slot.backend_type.clone(), response.finish_reason, &response.metrics);
+                    self.monitor.served(&slot, &self.current_backend_type);
+                    return Ok(response);
+                }
+                Err(e) => {
+                    self.monitor.record_error(slot.backend_type.clone());
+                    self.monitor.failed(&slot, &e.to_string());
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No inference backend available")))
+    }
+    
+    /// 多轮对话流式推理；建立流失败时沿降级链重试，流中途出错计入该后端的失败次数
+    /// 超出上下文长度时按上下文策略裁剪，裁剪报告见 InferenceStream::context_report
+    pub async fn chat_stream(
+        &self,
+        messages: &[ChatMessage],
+        params: &GenerationParams,
+        cancel: CancellationToken,
+    ) -> Result<InferenceStream> {
+        let fit = self.fit_context(messages, params).await?;
+        let stream = self.dispatch_stream(&fit.messages, params, cancel).await?;
+        Ok(if fit.report.is_trimmed() { stream.with_context_report(fit.report) } else { stream })
+    }
+    
+    async fn dispatch_stream(
+        &self,
+        messages: &[ChatMessage],
+        params: &GenerationParams,
+        cancel: CancellationToken,
+    ) -> Result<InferenceStream> {
+        // 名额随流一起持有，流结束或被丢弃时归还
+        let Some(permit) = self.scheduler.acquire_or_cancel(params.priority, &cancel).await else {
+            let (sender, stream) = InferenceStream::channel(cancel);
+            sender.finish(FinishReason::Cancelled).await;
+            return Ok(stream);
+        };
+        
+        if let Some(model) = &params.model {
+            let (backend_type, backend) = self.resident(model)?;
+            let stream = constraint::chat_stream(&**backend.read().await, messages, params, cancel).await;
+            let monitor = self.monitor.clone();
+            return match stream {
+                Ok(stream) => Ok(stream.on_finish(move |reason, metrics| {
+                    drop(permit);
+                    monitor.record_metrics(backend_type, reason, metrics);
+                })),
+                Err(e) => {
+                    monitor.record_error(backend_type);
+                    Err(e)
+                }
+            };
+        }
+        
+        let mut last_error = None;
+        for slot in self.candidates().await {
+            let result = match self.ensure_ready(&slot).await {
+                Ok(()) => constraint::chat_stream(&**slot.backend.read().await, messages, params, cancel.clone()).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(stream) => {
+                    self.monitor.served(&slot, &self.current_backend_type);
+                    let monitor = self.monitor.clone();
+                    return Ok(stream.on_finish(move |reason, metrics| {
+                        drop(permit);
+                        monitor.record_metrics(slot.backend_type.clone(), reason, metrics);
+                        if reason == FinishReason::Error {
+                            monitor.failed(&slot, "stream ended with an error");
+                        }
+                    }));
+                }
+                Err(e) => {
+                    self.monitor.record_error(slot.backend_type.clone());
+                    self.monitor.failed(&slot, &e.to_string());
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No inference backend available")))
+    }
+    
+    /// 用标准提示词集对降级链中的各后端与各驻留模型运行基准测试
+    /// 按后台优先级排队，不挤占交互请求；未加载本地模型时只测试本地服务
+    pub async fn run_benchmark(&self, config: &BenchmarkConfig, cancel: CancellationToken) -> Result<BenchmarkReport> {
+        let hardware = match &self.hardware {
+            Some(profile) => profile.clone(),
+            None => tokio::task::spawn_blocking(HardwareProfile::detect).await?,
+        };
+        let tokenizer = self.tokenizer();
+        let model = self
+            .config
+            .as_ref()
+            .and_then(|c| c.model_path.file_stem())
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "default".to_string());
+        let context_size = self.config.as_ref().map_or(DEFAULT_CONTEXT_SIZE, |c| c.context_size);
+        let mut results = vec![];
+        
+        let chain = self.chain.read().await.clone();
+        for slot in chain {
+            let remote = matches!(slot.backend_type, BackendType::OpenAiCompat | BackendType::Ollama);
+            if self.config.is_none() && !remote {
+                continue;
+            }
+            let target = BenchmarkTarget {
+                backend: slot.backend_type.clone(),
+                model: model.clone(),
+                context_size,
+            };
+            if let Err(e) = self.ensure_ready(&slot).await {
+                tracing::warn!("Skipping {:?} benchmark: {}", slot.backend_type, e);
+                results.extend(config.prompts().iter().map(|p| BenchmarkResult::failed(&target, p, 0, &e)));
+                continue;
+            }
+            self.benchmark_backend(&slot.backend, &target, config, &*tokenizer, &cancel, &mut results).await?;
+        }
+        
+        let resident = self.model_pool().backends();
+        for (id, backend) in resident {
+            let context_size = self.model_pool().context_size(&id).unwrap_or(DEFAULT_CONTEXT_SIZE);
+            let target = BenchmarkTarget {
+                backend: backend.read().await.backend_type(),
+                model: id,
+                context_size,
+            };
+            self.benchmark_backend(&backend, &target, config, &*tokenizer, &cancel, &mut results).await?;
+        }
+        Ok(BenchmarkReport::new(hardware, config.clone(), results))
+    }
+    
+    async fn benchmark_backend(
+        &self,
+        backend: &RwLock<Box<dyn InferenceBackend>>,
+        target: &BenchmarkTarget,
+        config: &BenchmarkConfig,
+        tokenizer: &dyn Tokenizer,
+        cancel: &CancellationToken,
+        results: &mut Vec<BenchmarkResult>,
+    ) -> Result<()> {
+        for &context_length in &config.context_lengths {
+            if context_length > target.context_size || context_length <= config.max_tokens {
+                tracing::info!(
+                    "Skipping {}-token benchmark for {:?} / {} (context size {})",
+                    context_length,
+                    target.backend,
+                    target.model,
+                    target.context_size
+                );
+                continue;
+            }
+            for prompt in config.prompts() {
+                let Some(permit) = self.scheduler.acquire_or_cancel(Priority::Background, cancel).await else {
+                    anyhow::bail!("Benchmark cancelled");
+                };
+                let backend = backend.read().await;
+                let result = benchmark::run(&**backend, target, &prompt, context_length, config.max_tokens, tokenizer, cancel.clone()).await;
+                drop(permit);
+                if cancel.is_cancelled() {
+                    anyhow::bail!("Benchmark cancelled");
+                }
+                tracing::info!(
+                    "Benchmark {:?} / {} / {} @ {} tokens: ttft {:?} ms, prefill {:?} tok/s, decode {:.1} tok/s{}",
+                    target.backend,
+                    target.model,
+                    prompt.name,
+                    context_length,
+                    result.ttft_ms,
+                    result.prefill_tokens_per_second,
+                    result.decode_tokens_per_second,
+                    result.error.as_deref().map(|e| format!(" (failed: {})", e)).unwrap_or_default()
+                );
+                results.push(result);
+            }
+        }
+        Ok(())
+    }
+    
+    /// 各优先级的排队深度、执行数与等待时间
+    pub fn scheduler_stats(&self) -> Vec<QueueStats> {
+        self.scheduler.stats()
+    }
+    
+    pub fn set_scheduler_config(&self, config: SchedulerConfig) {
+        self.scheduler.set_config(config);
+    }
+    
+    /// 设置专用的嵌入后端（需已初始化），之后 embed 只使用它
+    pub fn set_embedding_backend(&self, backend: Box<dyn InferenceBackend>) {
+        *self.embedding.lock().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(RwLock::new(backend)));
+    }
+    
+    /// 加载本地 GGUF 嵌入模型（如 bge、gte-Qwen2）作为专用的嵌入后端
+    pub async fn load_embedding_model(&self, model_path: &Path) -> Result<()> {
+        let info = Self::inspect_model(model_path)?;
+        llama::check_compatible(&info)?;
+        let context_size = info
+            .context_length
+            .map(|n| (n as usize).min(DEFAULT_CONTEXT_SIZE))
+            .unwrap_or(DEFAULT_CONTEXT_SIZE);
+        let report = MemoryReport::new(&info, context_size, memory::available_memory_bytes());
+        let context_size = report.admit("embedding")?;
+        let mut backend: Box<dyn InferenceBackend> = Box::new(LlamaCppBackend::new().for_embeddings());
+        backend
+            .initialize(InferenceConfig {
+                model_path: model_path.to_path_buf(),
+                backend: BackendType::LlamaCppCpu,
+                context_size,
+            })
+            .await?;
+        self.set_embedding_backend(backend);
+        tracing::info!("Embedding model loaded: {:?}", model_path);
+        Ok(())
+    }
+    
+    /// 移除专用的嵌入后端，返回此前是否已设置
+    pub fn clear_embedding_backend(&self) -> bool {
+        self.embedding.lock().unwrap_or_else(|e| e.into_inner()).take().is_some()
+    }
+    
+    fn dedicated_embedding(&self) -> Option<SharedBackend> {
+        self.embedding.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+    
+    /// 当前 embed 使用的向量空间标识；没有能生成文本向量的后端时返回 None
+    pub async fn embedding_model(&self) -> Option<String> {
+        if let Some(backend) = self.dedicated_embedding() {
+            return backend.read().await.embedding_model();
+        }
+        let chain = self.chain.read().await.clone();
+        for slot in chain.iter().filter(|s| s.ready.load(Ordering::Acquire)) {
+            if let Some(model) = slot.backend.read().await.embedding_model() {
+                return Some(model);
+            }
+        }
+        None
+    }
+    
+    /// 批量计算文本向量（交互优先级）
+    pub async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        self.embed_with_priority(texts, Priority::Interactive).await
     }
     
-    /// 流式推理
-    pub async fn infer_stream(&self, prompt: &str) -> Result<tokio::sync::mpsc::Receiver<String>> {
-        let backend = self.backend.read().await;
-        backend.infer_stream(prompt).await
+    /// 批量计算文本向量，与对话请求一起排队。
+    /// 不同后端的向量不可比较，因此不沿降级链重试，只用 embedding_model 对应的后端
+    pub async fn embed_with_priority(&self, texts: &[String], priority: Priority) -> Result<Vec<Vec<f32>>> {
+        if texts.is_empty() {
+            return Ok(vec![]);
+        }
+        let _permit = self.scheduler.acquire(priority).await;
+        if let Some(backend) = self.dedicated_embedding() {
+            return backend.read().await.embed(texts).await;
+        }
+        let chain = self.chain.read().await.clone();
+        for slot in chain.iter().filter(|s| s.ready.load(Ordering::Acquire)) {
+            let backend = slot.backend.read().await;
+            if backend.embedding_model().is_some() {
+                return backend.embed(texts).await;
+            }
+        }
+        anyhow::bail!("No backend supports embeddings; load an embedding model first")
+    }
+    
+    /// 各后端的性能统计（首 token 延迟、生成速度、token 用量）
+    pub fn backend_stats(&self) -> Vec<BackendStatsSummary> {
+        let stats = self.monitor.stats.lock().unwrap_or_else(|e| e.into_inner());
+        stats
+            .iter()
+            .map(|(backend, stats)| stats.summary(backend.clone()))
+            .collect()
+    }
+    
+    /// 订阅后端路由事件（由哪个后端完成请求、失败、熔断与恢复）
+    pub fn subscribe_events(&self) -> broadcast::Receiver<BackendEvent> {
+        self.monitor.events.subscribe()
+    }
+    
+    /// 降级链中各后端的熔断状态
+    pub async fn backend_health(&self) -> Vec<BackendHealth> {
+        let chain = self.chain.read().await;
+        chain
+            .iter()
+            .enumerate()
+            .map(|(position, slot)| slot.breaker().health(slot.backend_type.clone(), position))
+            .collect()
+    }
+    
+    /// 立即对所有后端执行一次健康检查
+    pub async fn check_health(&self) -> Vec<BackendHealth> {
+        let chain = self.chain.read().await.clone();
+        for slot in &chain {
+            self.monitor.check(slot).await;
+        }
+        self.backend_health().await
+    }
+    
+    /// 启动后台定期健康检查，返回的任务句柄可用于停止检查
+    pub fn spawn_health_checks(&self, interval: std::time::Duration) -> tokio::task::JoinHandle<()> {
+        let chain = self.chain.clone();
+        let monitor = self.monitor.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            // 第一次 tick 立即返回，跳过（刚检测过后端）
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let slots = chain.read().await.clone();
+                for slot in &slots {
+                    monitor.check(slot).await;
+                }
+            }
+        })
+    }
+    
+    fn model_pool(&self) -> std::sync::MutexGuard<'_, ModelPool> {
+        self.models.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    
+    fn resident(&self, model: &str) -> Result<(BackendType, SharedBackend)> {
+        self.model_pool()
+            .get(model)
+            .ok_or_else(|| anyhow::anyhow!("Model '{}' is not loaded", model))
+    }
+    
+    /// 设置模型驻留的内存预算（字节），超出部分立即按 LRU 卸载
+    pub fn set_model_budget(&self, budget_bytes: u64) {
+        let evicted = self.model_pool().set_budget(budget_bytes);
+        tracing::info!("Model memory budget: {} MiB", budget_bytes / (1024 * 1024));
+        for id in evicted {
+            tracing::info!("Evicted model '{}' to fit the new memory budget", id);
+        }
+    }
+    
+    /// 加载本地模型并以 model_id 驻留；预算不足时先卸载最久未使用的模型
+    pub async fn load_model(&self, model_id: &str, model_path: &Path) -> Result<()> {
+        let info = Self::inspect_model(model_path)?;
+        llama::check_compatible(&info)?;
+        let context_size = info
+            .context_length
+            .map(|n| (n as usize).min(DEFAULT_CONTEXT_SIZE))
+            .unwrap_or(DEFAULT_CONTEXT_SIZE);
+        let size_bytes = MemoryEstimate::for_model(&info, context_size).total_bytes;
+        let evicted = self.model_pool().reserve(model_id, size_bytes)?;
+        for id in evicted {
+            tracing::info!("Evicted model '{}' to load '{}'", id, model_id);
+        }
+        
+        // 腾出空间后再按实际可用内存做准入控制
+        let report = MemoryReport::new(&info, context_size, memory::available_memory_bytes());
+        let context_size = report.admit(model_id)?;
+        let size_bytes = MemoryEstimate::for_model(&info, context_size).total_bytes;
+        let config = InferenceConfig {
+            model_path: model_path.to_path_buf(),
+            backend: BackendType::LlamaCppCpu,
+            context_size,
+        };
+        let mut backend: Box<dyn InferenceBackend> = Box::new(self.cpu_backend());
+        backend.initialize(config).await?;
+        
+        let mut pool = self.model_pool();
+        // 加载期间其他模型可能已被使用或加载，插入前再检查一次预算
+        for id in pool.reserve(model_id, size_bytes)? {
+            tracing::info!("Evicted model '{}' to load '{}'", id, model_id);
+        }
+        pool.insert(model_id, model_path.to_path_buf(), size_bytes, context_size, backend);
+        tracing::info!(
+            "Model '{}' resident ({} MiB, {} / {} MiB used)",
+            model_id,
+            size_bytes / (1024 * 1024),
+            pool.used_bytes() / (1024 * 1024),
+            pool.budget_bytes() / (1024 * 1024)
+        );
+        Ok(())
+    }
+    
+    /// 卸载驻留模型，返回模型此前是否已加载
+    pub fn unload_model(&self, model_id: &str) -> bool {
+        self.model_pool().remove(model_id)
+    }
+    
+    pub fn is_model_loaded(&self, model_id: &str) -> bool {
+        self.model_pool().contains(model_id)
+    }
+    
+    /// 当前驻留的模型（最近使用的在前）
+    pub fn resident_models(&self) -> Vec<ResidentModelInfo> {
+        self.model_pool().list()
+    }
+    
+    async fn primary_slot(&self) -> Arc<BackendSlot> {
+        let chain = self.chain.read().await;
+        chain
+            .iter()
+            .find(|s| s.backend_type == self.current_backend_type)
+            .unwrap_or(&chain[0])
+            .clone()
+    }
+    
+    /// 列出当前后端可用的模型
+    pub async fn list_models(&self) -> Result<Vec<ModelDescriptor>> {
+        let slot = self.primary_slot().await;
+        let backend = slot.backend.read().await;
+        backend.list_models().await
+    }
+    
+    /// 切换当前后端使用的模型
+    pub async fn select_model(&self, model: &str) -> Result<()> {
+        let slot = self.primary_slot().await;
+        let mut backend = slot.backend.write().await;
+        backend.select_model(model)
     }
     
     /// 获取当前后端类型
@@ -95,3 +1124,76 @@ impl Default for EngineManager {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::sync::atomic::AtomicUsize;
+
+    /// 只记录初始化次数的后端
+    struct CountingBackend {
+        initializations: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl InferenceBackend for CountingBackend {
+        async fn initialize(&mut self, _config: InferenceConfig) -> Result<()> {
+            self.initializations.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        async fn chat(&self, _messages: &[ChatMessage], _params: &GenerationParams) -> Result<InferenceResponse> {
+            anyhow::bail!("not used")
+        }
+
+        async fn chat_stream(
+            &self,
+            _messages: &[ChatMessage],
+            _params: &GenerationParams,
+            _cancel: CancellationToken,
+        ) -> Result<InferenceStream> {
+            anyhow::bail!("not used")
+        }
+
+        fn backend_type(&self) -> BackendType {
+            BackendType::Scripted
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn initialize_reloads_only_when_config_changes() {
+        let initializations = Arc::new(AtomicUsize::new(0));
+        let mut engine = EngineManager::new();
+        engine
+            .set_backends(vec![Box::new(CountingBackend {
+                initializations: initializations.clone(),
+            })])
+            .await
+            .unwrap();
+        let config = InferenceConfig {
+            model_path: PathBuf::from("/nonexistent/model.gguf"),
+            backend: BackendType::Scripted,
+            context_size: 2048,
+        };
+
+        engine.initialize(config.clone()).await.unwrap();
+        assert_eq!(initializations.load(Ordering::SeqCst), 1);
+        // 相同配置不重新加载
+        engine.initialize(config.clone()).await.unwrap();
+        assert_eq!(initializations.load(Ordering::SeqCst), 1);
+
+        engine
+            .initialize(InferenceConfig {
+                context_size: 4096,
+                ..config
+            })
+            .await
+            .unwrap();
+        assert_eq!(initializations.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,230 @@
+// 模型内存估算 - 预测加载模型后的常驻内存（权重 + KV 缓存 + 运行时开销）
+// 加载前据此做准入控制：内存不足时缩小上下文，仍不足则拒绝加载，避免系统因 OOM 杀进程
+
+use crate::engine::gguf::GgufModelInfo;
+use serde::{Deserialize, Serialize};
+use sysinfo::System;
+
+/// 运行时固定开销（分词器、计算缓冲区、线程栈等）
+const FIXED_OVERHEAD_BYTES: u64 = 128 * 1024 * 1024;
+/// 为系统和其他程序保留的内存
+const RESERVED_BYTES: u64 = 512 * 1024 * 1024;
+/// 自动缩小上下文时的下限，再小基本无法完成对话
+pub const MIN_CONTEXT_SIZE: usize = 512;
+/// 自动缩小后的上下文按此粒度向下取整
+const CONTEXT_STEP: usize = 256;
+/// CPU 运行时的 KV 缓存以 f32 存储
+const KV_BYTES_PER_ELEMENT: u64 = 4;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryEstimate {
+    pub weights_bytes: u64,
+    pub kv_cache_bytes: u64,
+    pub overhead_bytes: u64,
+    pub total_bytes: u64,
+    pub context_size: usize,
+}
+
+impl MemoryEstimate {
+    /// 按模型元数据估算给定上下文长度下的内存占用
+    pub fn for_model(info: &GgufModelInfo, context_size: usize) -> Self {
+        let weights_bytes = if info.file_size > 0 {
+            // 权重以 mmap 映射，常驻部分约等于文件大小
+            info.file_size
+        } else {
+            weights_bytes(info.parameter_count, &info.quantization)
+        };
+        // 输出层 logits 与各层激活的缓冲区
+        let vocab_bytes = info.vocab_size.unwrap_or(32000) as u64 * 4;
+        let overhead_bytes = FIXED_OVERHEAD_BYTES + weights_bytes / 50 + vocab_bytes * 4;
+        let kv_cache_bytes = kv_bytes_per_token(info) * context_size as u64;
+        Self {
+            weights_bytes,
+            kv_cache_bytes,
+            overhead_bytes,
+            total_bytes: weights_bytes + kv_cache_bytes + overhead_bytes,
+            context_size,
+        }
+    }
+}
+
+/// 按量化类型的平均每权重比特数估算权重大小（用于尚未下载的模型或其他量化版本）
+pub fn weights_bytes(parameter_count: u64, quantization: &str) -> u64 {
+    (parameter_count as f64 * bits_per_weight(quantization) / 8.0) as u64
+}
+
+/// 常见量化类型的平均每权重比特数（含分块缩放因子）
+pub fn bits_per_weight(quantization: &str) -> f64 {
+    match quantization.to_ascii_uppercase().as_str() {
+        "F32" => 32.0,
+        "F16" | "BF16" => 16.0,
+        "Q8_0" => 8.5,
+        "Q6_K" => 6.56,
+        "Q5_K_M" => 5.69,
+        "Q5_K_S" => 5.54,
+        "Q5_0" | "Q5_1" => 5.5,
+        "Q4_K_M" => 4.85,
+        "Q4_K_S" => 4.58,
+        "Q4_0" | "Q4_1" => 4.5,
+        "Q3_K_L" => 4.27,
+        "Q3_K_M" => 3.91,
+        "Q3_K_S" => 3.5,
+        "Q2_K" => 2.96,
+        // 未知类型按 Q4_K_M 估算
+        _ => 4.85,
+    }
+}
+
+/// 每个 token 的 KV 缓存大小：层数 × (K + V) × KV 头数 × 头维度
+fn kv_bytes_per_token(info: &GgufModelInfo) -> u64 {
+    let n_embd = info.embedding_length.unwrap_or(4096);
+    let n_layer = info.block_count.unwrap_or(32);
+    let n_head = info.head_count.unwrap_or(32).max(1);
+    let n_head_kv = info.head_count_kv.unwrap_or(n_head);
+    let head_dim = n_embd / n_head;
+    n_layer * 2 * n_head_kv * head_dim * KV_BYTES_PER_ELEMENT
+}
+
+/// 当前可用的物理内存
+pub fn available_memory_bytes() -> u64 {
+    let mut sys = System::new();
+    sys.refresh_memory();
+    sys.available_memory()
+}
+
+/// 加载前的内存评估结果，供界面提示（如"需要约 9.8 GB，当前可用 7.1 GB"）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryReport {
+    /// 请求的上下文长度下的估算
+    pub estimate: MemoryEstimate,
+    pub available_bytes: u64,
+    pub fits: bool,
+    /// 可用内存下能容纳的最大上下文；连最小上下文都放不下时为 None
+    pub max_context_size: Option<usize>,
+}
+
+impl MemoryReport {
+    pub fn new(info: &GgufModelInfo, context_size: usize, available_bytes: u64) -> Self {
+        let estimate = MemoryEstimate::for_model(info, context_size);
+        let usable = available_bytes.saturating_sub(RESERVED_BYTES);
+        let fixed = estimate.weights_bytes + estimate.overhead_bytes;
+        let max_context_size = usable
+            .checked_sub(fixed)
+            .map(|room| (room / kv_bytes_per_token(info).max(1)) as usize)
+            .map(|n| n.min(context_size) / CONTEXT_STEP * CONTEXT_STEP)
+            .filter(|&n| n >= MIN_CONTEXT_SIZE.min(context_size));
+        Self {
+            fits: estimate.total_bytes <= usable,
+            estimate,
+            available_bytes,
+            max_context_size,
+        }
+    }
+
+    /// 准入控制：放得下则沿用请求的上下文，否则缩小到能容纳的最大值；仍放不下时报错
+    pub fn admit(&self, model: &str) -> anyhow::Result<usize> {
+        if self.fits {
+            return Ok(self.estimate.context_size);
+        }
+        match self.max_context_size {
+            Some(context_size) => {
+                tracing::warn!(
+                    "Model '{}' needs ~{:.1} GB at context {}, only {:.1} GB available; shrinking context to {}",
+                    model,
+                    gb(self.estimate.total_bytes),
+                    self.estimate.context_size,
+                    gb(self.available_bytes),
+                    context_size
+                );
+                Ok(context_size)
+            }
+            None => anyhow::bail!(
+                "Model '{}' needs ~{:.1} GB of memory but only {:.1} GB is available",
+                model,
+                gb(self.estimate.total_bytes),
+                gb(self.available_bytes)
+            ),
+        }
+    }
+}
+
+fn gb(bytes: u64) -> f64 {
+    bytes as f64 / 1e9
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 32 层、8 个 KV 头、头维度 128：每个 token 的 KV 缓存 256 KiB
+    fn info() -> GgufModelInfo {
+        GgufModelInfo {
+            path: "model.gguf".into(),
+            file_size: 4_000_000_000,
+            gguf_version: 3,
+            name: None,
+            architecture: "llama".to_string(),
+            parameter_count: 7_000_000_000,
+            quantization: "Q4_K_M".to_string(),
+            context_length: Some(8192),
+            embedding_length: Some(4096),
+            block_count: Some(32),
+            head_count: Some(32),
+            head_count_kv: Some(8),
+            chat_template: None,
+            tokenizer_model: None,
+            vocab_size: Some(32000),
+            tensor_types: vec![],
+            pooling_type: None,
+        }
+    }
+
+    const KV_PER_TOKEN: u64 = 256 * 1024;
+
+    #[test]
+    fn estimates_weights_kv_cache_and_overhead() {
+        let estimate = MemoryEstimate::for_model(&info(), 4096);
+        assert_eq!(estimate.weights_bytes, 4_000_000_000);
+        assert_eq!(estimate.kv_cache_bytes, 4096 * KV_PER_TOKEN);
+        assert_eq!(estimate.overhead_bytes, FIXED_OVERHEAD_BYTES + 80_000_000 + 512_000);
+        assert_eq!(estimate.total_bytes, estimate.weights_bytes + estimate.kv_cache_bytes + estimate.overhead_bytes);
+
+        // 没有 GQA 时 KV 头数等于注意力头数
+        let mha = GgufModelInfo { head_count_kv: None, ..info() };
+        assert_eq!(MemoryEstimate::for_model(&mha, 1).kv_cache_bytes, 4 * KV_PER_TOKEN);
+
+        // 文件大小未知时按量化类型估算权重
+        let remote = GgufModelInfo { file_size: 0, quantization: "q8_0".to_string(), ..info() };
+        assert_eq!(MemoryEstimate::for_model(&remote, 0).weights_bytes, 7_437_500_000);
+        assert_eq!(bits_per_weight("IQ9_XL"), bits_per_weight("Q4_K_M"));
+    }
+
+    #[test]
+    fn admission_shrinks_context_or_refuses() {
+        let info = info();
+        let estimate = MemoryEstimate::for_model(&info, 4096);
+        let fixed = estimate.weights_bytes + estimate.overhead_bytes;
+
+        // 放得下时沿用请求的上下文
+        let report = MemoryReport::new(&info, 4096, RESERVED_BYTES + estimate.total_bytes);
+        assert!(report.fits);
+        assert_eq!(report.max_context_size, Some(4096));
+        assert_eq!(report.admit("m").unwrap(), 4096);
+
+        // 只放得下 1000 个 token 时按粒度向下取整
+        let report = MemoryReport::new(&info, 4096, RESERVED_BYTES + fixed + 1000 * KV_PER_TOKEN);
+        assert!(!report.fits);
+        assert_eq!(report.max_context_size, Some(768));
+        assert_eq!(report.admit("m").unwrap(), 768);
+
+        // 连最小上下文都放不下时拒绝加载
+        let report = MemoryReport::new(&info, 4096, RESERVED_BYTES + fixed + 300 * KV_PER_TOKEN);
+        assert_eq!(report.max_context_size, None);
+        let err = report.admit("m").unwrap_err().to_string();
+        assert!(err.contains("Model 'm' needs ~"), "{}", err);
+
+        // 可用内存不足以放下权重或保留内存时同样拒绝
+        assert_eq!(MemoryReport::new(&info, 4096, fixed).max_context_size, None);
+        assert_eq!(MemoryReport::new(&info, 4096, 0).max_context_size, None);
+    }
+}
@@ -0,0 +1,276 @@
+// 推理性能指标 - 每次推理的 token 用量、首 token 延迟与吞吐，以及按后端累计的滚动统计
+// 用于在本机实测比较各后端（MLX / llama.cpp / 本地服务）的 TTFT 与生成速度
+
+use crate::engine::context::ContextReport;
+use crate::engine::{BackendType, FinishReason};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// 滚动统计保留的最近请求数
+const STATS_WINDOW: usize = 100;
+
+/// 后端上报的 token 用量
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+}
+
+/// 单次推理的性能指标
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InferenceMetrics {
+    /// 提示词 token 数，后端未上报时为 None
+    pub prompt_tokens: Option<usize>,
+    /// 生成 token 数，后端未上报时按输出片段数估计
+    pub completion_tokens: usize,
+    /// 首 token 延迟；非流式 HTTP 请求无法观测时为 None
+    pub time_to_first_token_ms: Option<f64>,
+    pub total_duration_ms: f64,
+    /// 解码速度：首 token 之后的生成速度，不含预填充时间
+    pub tokens_per_second: f64,
+    /// 提示词超出上下文长度时的裁剪报告
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<ContextReport>,
+}
+
+impl InferenceMetrics {
+    pub fn from_durations(
+        prompt_tokens: Option<usize>,
+        completion_tokens: usize,
+        time_to_first_token: Option<Duration>,
+        total: Duration,
+    ) -> Self {
+        // 首 token 计入延迟而非吞吐
+        let (decode_tokens, decode_time) = match time_to_first_token {
+            Some(ttft) if completion_tokens > 1 && total > ttft => (completion_tokens - 1, total - ttft),
+            _ => (completion_tokens, total),
+        };
+        let tokens_per_second = if decode_time.is_zero() {
+            0.0
+        } else {
+            decode_tokens as f64 / decode_time.as_secs_f64()
+        };
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            time_to_first_token_ms: time_to_first_token.map(as_millis),
+            total_duration_ms: as_millis(total),
+            tokens_per_second,
+            context: None,
+        }
+    }
+}
+
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// 计时器：请求开始时创建，收到第一个 token 时标记
+#[derive(Debug, Clone, Copy)]
+pub struct MetricsTimer {
+    started: Instant,
+    first_token: Option<Instant>,
+}
+
+impl MetricsTimer {
+    pub fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            first_token: None,
+        }
+    }
+
+    /// 只记录第一次调用的时间
+    pub fn mark_token(&mut self) {
+        self.first_token.get_or_insert_with(Instant::now);
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn time_to_first_token(&self) -> Option<Duration> {
+        self.first_token.map(|t| t - self.started)
+    }
+
+    pub fn finish(&self, prompt_tokens: Option<usize>, completion_tokens: usize) -> InferenceMetrics {
+        InferenceMetrics::from_durations(
+            prompt_tokens,
+            completion_tokens,
+            self.time_to_first_token(),
+            self.elapsed(),
+        )
+    }
+}
+
+/// 单个后端的累计统计
+#[derive(Debug, Clone, Default)]
+pub struct BackendStats {
+    requests: u64,
+    errors: u64,
+    cancelled: u64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    recent: VecDeque<InferenceMetrics>,
+}
+
+impl BackendStats {
+    pub fn record(&mut self, finish_reason: FinishReason, metrics: &InferenceMetrics) {
+        self.requests += 1;
+        self.prompt_tokens += metrics.prompt_tokens.unwrap_or(0) as u64;
+        self.completion_tokens += metrics.completion_tokens as u64;
+        match finish_reason {
+            FinishReason::Error => self.errors += 1,
+            FinishReason::Cancelled => self.cancelled += 1,
+            _ => {
+                if self.recent.len() == STATS_WINDOW {
+                    self.recent.pop_front();
+                }
+                self.recent.push_back(metrics.clone());
+            }
+        }
+    }
+
+    /// 请求未能开始（连接失败、模型未加载等）
+    pub fn record_error(&mut self) {
+        self.requests += 1;
+        self.errors += 1;
+    }
+
+    pub fn summary(&self, backend: BackendType) -> BackendStatsSummary {
+        let mut ttft: Vec<f64> = self.recent.iter().filter_map(|m| m.time_to_first_token_ms).collect();
+        ttft.sort_by(|a, b| a.total_cmp(b));
+        let throughput: Vec<f64> = self
+            .recent
+            .iter()
+            .filter(|m| m.completion_tokens > 0)
+            .map(|m| m.tokens_per_second)
+            .collect();
+        BackendStatsSummary {
+            backend,
+            requests: self.requests,
+            errors: self.errors,
+            cancelled: self.cancelled,
+            prompt_tokens: self.prompt_tokens,
+            completion_tokens: self.completion_tokens,
+            window: self.recent.len(),
+            avg_time_to_first_token_ms: mean(&ttft),
+            p95_time_to_first_token_ms: percentile(&ttft, 0.95),
+            avg_tokens_per_second: mean(&throughput),
+        }
+    }
+}
+
+/// 后端统计摘要；平均值与分位数基于最近 STATS_WINDOW 次成功请求
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackendStatsSummary {
+    pub backend: BackendType,
+    pub requests: u64,
+    pub errors: u64,
+    pub cancelled: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// 参与平均值计算的请求数
+    pub window: usize,
+    pub avg_time_to_first_token_ms: Option<f64>,
+    pub p95_time_to_first_token_ms: Option<f64>,
+    pub avg_tokens_per_second: Option<f64>,
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+/// values 需已排序（最近邻取整）
+fn percentile(values: &[f64], p: f64) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let rank = (p * values.len() as f64).ceil() as usize;
+    Some(values[rank.clamp(1, values.len()) - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics(ttft_ms: Option<u64>, total_ms: u64, completion_tokens: usize) -> InferenceMetrics {
+        InferenceMetrics::from_durations(
+            Some(10),
+            completion_tokens,
+            ttft_ms.map(Duration::from_millis),
+            Duration::from_millis(total_ms),
+        )
+    }
+
+    #[test]
+    fn percentile_and_mean_handle_small_inputs() {
+        assert_eq!(percentile(&[], 0.95), None);
+        assert_eq!(mean(&[]), None);
+        assert_eq!(percentile(&[5.0], 0.95), Some(5.0));
+        assert_eq!(percentile(&[5.0], 0.0), Some(5.0));
+        assert_eq!(mean(&[5.0]), Some(5.0));
+
+        let values: Vec<f64> = (1..=100).map(f64::from).collect();
+        assert_eq!(percentile(&values, 0.95), Some(95.0));
+        assert_eq!(percentile(&values, 1.0), Some(100.0));
+        assert_eq!(percentile(&values[..20], 0.95), Some(19.0));
+        assert_eq!(percentile(&[1.0, 2.0], 0.5), Some(1.0));
+    }
+
+    #[test]
+    fn throughput_excludes_first_token_latency() {
+        let m = metrics(Some(100), 1100, 11);
+        assert_eq!(m.time_to_first_token_ms, Some(100.0));
+        assert_eq!(m.total_duration_ms, 1100.0);
+        assert!((m.tokens_per_second - 10.0).abs() < 1e-9);
+
+        // 无法观测首 token 或只有一个 token 时按总时长计算
+        assert!((metrics(None, 1100, 11).tokens_per_second - 10.0).abs() < 1e-9);
+        assert!((metrics(Some(100), 500, 1).tokens_per_second - 2.0).abs() < 1e-9);
+        assert_eq!(metrics(None, 0, 5).tokens_per_second, 0.0);
+
+        let mut timer = MetricsTimer::start();
+        assert_eq!(timer.time_to_first_token(), None);
+        timer.mark_token();
+        let first = timer.time_to_first_token();
+        timer.mark_token();
+        assert!(first.is_some());
+        assert_eq!(timer.time_to_first_token(), first);
+    }
+
+    #[test]
+    fn backend_stats_summarize_recent_successes() {
+        let mut stats = BackendStats::default();
+        let empty = stats.summary(BackendType::Ollama);
+        assert_eq!((empty.requests, empty.window), (0, 0));
+        assert_eq!(empty.avg_time_to_first_token_ms, None);
+        assert_eq!(empty.p95_time_to_first_token_ms, None);
+
+        stats.record(FinishReason::Stop, &metrics(Some(100), 1100, 11));
+        stats.record(FinishReason::Length, &metrics(Some(300), 1300, 11));
+        // 没有输出的请求不计入吞吐
+        stats.record(FinishReason::Stop, &metrics(Some(200), 200, 0));
+        // 失败与取消只计数，不进入滚动窗口
+        stats.record(FinishReason::Error, &metrics(Some(5000), 5000, 1));
+        stats.record(FinishReason::Cancelled, &metrics(Some(9000), 9000, 1));
+        stats.record_error();
+
+        let summary = stats.summary(BackendType::Ollama);
+        assert_eq!((summary.requests, summary.errors, summary.cancelled), (6, 2, 1));
+        assert_eq!((summary.prompt_tokens, summary.completion_tokens), (50, 24));
+        assert_eq!(summary.window, 3);
+        assert_eq!(summary.avg_time_to_first_token_ms, Some(200.0));
+        assert_eq!(summary.p95_time_to_first_token_ms, Some(300.0));
+        assert!((summary.avg_tokens_per_second.unwrap() - 10.0).abs() < 1e-9);
+
+        // 窗口只保留最近 STATS_WINDOW 次
+        for _ in 0..STATS_WINDOW {
+            stats.record(FinishReason::Stop, &metrics(Some(50), 1050, 11));
+        }
+        let summary = stats.summary(BackendType::Ollama);
+        assert_eq!(summary.window, STATS_WINDOW);
+        assert_eq!(summary.p95_time_to_first_token_ms, Some(50.0));
+    }
+}
@@ -5,11 +5,37 @@ use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
 pub mod backend;
+pub mod benchmark;
+pub mod constraint;
+pub mod context;
+pub mod fallback;
+pub mod gguf;
+pub mod grammar;
+pub mod hardware;
+pub mod json_schema;
+pub mod llama;
 pub mod manager;
+pub mod memory;
+pub mod metrics;
+pub mod params;
+pub mod plugin;
+pub mod prefix_cache;
+pub mod residency;
+pub mod scheduler;
+pub mod scripted;
+pub mod sidecar;
+pub mod store;
+pub mod stream;
+pub mod template;
+pub mod tokenizer;
 
 pub use manager::*;
+pub use metrics::*;
+pub use params::*;
+pub use scheduler::Priority;
+pub use stream::*;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum BackendType {
     /// Apple Silicon 优化后端 (MLX)
     MlxSidecar,
@@ -17,21 +43,122 @@ pub enum BackendType {
     InferflowCpp,
     /// 通用 CPU 后端 (llama.cpp)
     LlamaCppCpu,
+    /// 本地 OpenAI 兼容服务 (llama-server / vLLM / LM Studio)
+    OpenAiCompat,
+    /// 本地 Ollama 服务
+    Ollama,
     /// 蜂群模式 (分布式推理)
     Swarm,
+    /// 通过 C ABI 动态加载的后端插件（如 OpenVINO 适配器），值为插件名
+    Plugin(String),
+    /// 按脚本回放的确定性输出（测试与演示）
+    Scripted,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct InferenceConfig {
     pub model_path: PathBuf,
     pub backend: BackendType,
     pub context_size: usize,
-    pub temperature: f32,
-    pub top_p: f32,
+}
+
+/// 对话消息角色
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    System,
+    User,
+    Assistant,
+    /// 工具调用结果（如沙箱执行输出、知识库检索结果）
+    Tool,
+}
+
+impl ChatRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChatRole::System => "system",
+            ChatRole::User => "user",
+            ChatRole::Assistant => "assistant",
+            ChatRole::Tool => "tool",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: ChatRole,
+    pub content: String,
+    /// 工具消息对应的工具名
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// 检索片段的相关度；上下文超长时按 EvictRetrieved 策略优先丢弃相关度低的片段
+    #[serde(skip)]
+    pub relevance: Option<f32>,
+}
+
+impl ChatMessage {
+    pub fn new(role: ChatRole, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            name: None,
+            relevance: None,
+        }
+    }
+
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new(ChatRole::System, content)
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new(ChatRole::User, content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new(ChatRole::Assistant, content)
+    }
+
+    pub fn tool(name: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            role: ChatRole::Tool,
+            content: content.into(),
+            name: Some(name.into()),
+            relevance: None,
+        }
+    }
+
+    /// 知识库检索片段（作为 vault 工具的输出），score 为相关度
+    pub fn retrieved(content: impl Into<String>, score: f32) -> Self {
+        Self {
+            relevance: Some(score),
+            ..Self::tool("vault", content)
+        }
+    }
+}
+
+/// 取最后一条用户消息（模拟后端据此生成响应）
+pub fn last_user_message(messages: &[ChatMessage]) -> &str {
+    messages
+        .iter()
+        .rev()
+        .find(|m| m.role == ChatRole::User)
+        .map(|m| m.content.as_str())
+        .unwrap_or("")
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InferenceResponse {
     pub tokens: Vec<String>,
-    pub finish_reason: String,
+    pub finish_reason: FinishReason,
+    pub metrics: InferenceMetrics,
+}
+
+/// 后端可用的模型（由推理服务上报，供 UI 选择）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelDescriptor {
+    pub name: String,
+    pub size_bytes: Option<u64>,
+    pub family: Option<String>,
+    pub parameter_size: Option<String>,
+    pub quantization: Option<String>,
 }
@@ -0,0 +1,265 @@
+// 单次请求的生成参数与结束原因
+
+use crate::engine::constraint::MAX_CONSTRAINED_ATTEMPTS;
+use crate::engine::scheduler::Priority;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// 单次推理请求的生成参数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerationParams {
+    /// 目标模型 ID（已驻留的本地模型）；None 表示由当前后端降级链处理
+    pub model: Option<String>,
+    /// 最多生成的 token 数，None 表示直到上下文用尽
+    pub max_tokens: Option<usize>,
+    /// 为 0 时使用贪心解码
+    pub temperature: f32,
+    pub top_p: f32,
+    /// 仅在概率最高的 k 个候选中采样，None 表示不限制
+    pub top_k: Option<usize>,
+    /// 重复惩罚系数，1.0 表示不惩罚
+    pub repetition_penalty: f32,
+    /// 随机种子，固定后相同输入得到相同输出
+    pub seed: Option<u64>,
+    /// 生成内容出现任一停止序列时结束（停止序列本身不输出）
+    pub stop: Vec<String>,
+    /// token id 到 logit 偏置的映射
+    pub logit_bias: HashMap<u32, f32>,
+    /// 输出约束（JSON / JSON Schema / GBNF 语法）；不支持约束解码的后端改为校验后重试
+    pub constraint: Option<OutputConstraint>,
+    /// 不支持约束解码的后端最多生成几次（含首次），默认为 MAX_CONSTRAINED_ATTEMPTS
+    pub constraint_attempts: usize,
+    /// 调度优先级，决定排队顺序与并发名额
+    pub priority: Priority,
+}
+
+impl Default for GenerationParams {
+    fn default() -> Self {
+        Self {
+            model: None,
+            max_tokens: None,
+            temperature: 0.7,
+            top_p: 0.9,
+            top_k: None,
+            repetition_penalty: 1.0,
+            seed: None,
+            stop: vec![],
+            logit_bias: HashMap::new(),
+            constraint: None,
+            constraint_attempts: MAX_CONSTRAINED_ATTEMPTS,
+            priority: Priority::Interactive,
+        }
+    }
+}
+
+impl GenerationParams {
+    /// 确定性输出：贪心解码 + 固定种子，用于工具选择、结构化输出等
+    pub fn deterministic() -> Self {
+        Self {
+            temperature: 0.0,
+            top_p: 1.0,
+            seed: Some(0),
+            ..Self::default()
+        }
+    }
+
+    /// 发散性输出：较高温度，用于起草、改写等
+    pub fn creative() -> Self {
+        Self {
+            temperature: 0.9,
+            top_p: 0.95,
+            repetition_penalty: 1.1,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn with_stop(mut self, stop: impl Into<String>) -> Self {
+        self.stop.push(stop.into());
+        self
+    }
+
+    pub fn with_constraint(mut self, constraint: OutputConstraint) -> Self {
+        self.constraint = Some(constraint);
+        self
+    }
+
+    /// 不支持约束解码时的最多生成次数；调用方自己有退路时可设为 1，避免反复生成不合格的输出
+    pub fn with_constraint_attempts(mut self, attempts: usize) -> Self {
+        self.constraint_attempts = attempts.max(1);
+        self
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// 未指定种子时按时间生成
+    pub fn seed_or_random(&self) -> u64 {
+        self.seed
+            .unwrap_or_else(|| chrono::Utc::now().timestamp_nanos_opt().unwrap_or(0) as u64)
+    }
+}
+
+/// 生成内容必须满足的格式约束
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutputConstraint {
+    /// 任意合法 JSON
+    Json,
+    /// 符合给定 JSON Schema 的 JSON
+    JsonSchema { schema: serde_json::Value },
+    /// llama.cpp 格式的 GBNF 语法，入口规则为 root
+    Grammar { gbnf: String },
+}
+
+/// 生成结束的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    /// 模型输出了结束符
+    Stop,
+    /// 达到 max_tokens 或上下文上限
+    Length,
+    /// 命中调用方指定的停止序列
+    StopSequence,
+    /// 调用方取消
+    Cancelled,
+    /// 生成过程中出错
+    Error,
+}
+
+impl FinishReason {
+    /// 解析推理服务返回的结束原因（OpenAI finish_reason / Ollama done_reason）
+    pub fn from_server(reason: &str) -> Self {
+        match reason {
+            "length" => FinishReason::Length,
+            "stop_sequence" => FinishReason::StopSequence,
+            "cancelled" | "abort" => FinishReason::Cancelled,
+            "error" => FinishReason::Error,
+            _ => FinishReason::Stop,
+        }
+    }
+}
+
+impl std::fmt::Display for FinishReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            FinishReason::Stop => "stop",
+            FinishReason::Length => "length",
+            FinishReason::StopSequence => "stop_sequence",
+            FinishReason::Cancelled => "cancelled",
+            FinishReason::Error => "error",
+        };
+        f.write_str(name)
+    }
+}
+
+/// 在流式输出中检测停止序列
+/// 可能是停止序列前缀的尾部文本先暂存，确认不匹配后再输出，避免把停止序列的一部分发给调用方
+pub struct StopMatcher {
+    stops: Vec<String>,
+    held: String,
+}
+
+impl StopMatcher {
+    pub fn new(stops: &[String]) -> Self {
+        Self {
+            stops: stops.iter().filter(|s| !s.is_empty()).cloned().collect(),
+            held: String::new(),
+        }
+    }
+
+    /// 输入一段新文本，返回可以安全输出的文本，以及是否已命中停止序列
+    pub fn push(&mut self, piece: &str) -> (String, bool) {
+        self.held.push_str(piece);
+        if let Some(pos) = self.stops.iter().filter_map(|s| self.held.find(s.as_str())).min() {
+            let emit = self.held[..pos].to_string();
+            self.held.clear();
+            return (emit, true);
+        }
+
+        // 保留最长的、可能是某个停止序列前缀的后缀
+        let keep = self
+            .stops
+            .iter()
+            .flat_map(|stop| {
+                let held = &self.held;
+                (1..stop.len().min(held.len() + 1))
+                    .rev()
+                    .filter(move |&n| stop.is_char_boundary(n) && held.ends_with(&stop[..n]))
+                    .take(1)
+            })
+            .max()
+            .unwrap_or(0);
+        let split = self.held.len() - keep;
+        let emit = self.held[..split].to_string();
+        self.held.drain(..split);
+        (emit, false)
+    }
+
+    /// 生成结束时取出暂存的文本
+    pub fn finish(&mut self) -> String {
+        std::mem::take(&mut self.held)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(stops: &[&str]) -> StopMatcher {
+        StopMatcher::new(&stops.iter().map(|s| s.to_string()).collect::<Vec<_>>())
+    }
+
+    fn owned(emit: &str, stopped: bool) -> (String, bool) {
+        (emit.to_string(), stopped)
+    }
+
+    #[test]
+    fn detects_stop_sequence_split_across_pieces() {
+        let mut m = matcher(&["</s>"]);
+        assert_eq!(m.push("Hello <"), owned("Hello ", false));
+        assert_eq!(m.push("/"), owned("", false));
+        assert_eq!(m.push("s>ignored"), owned("", true));
+
+        // 暂存的前缀确认不匹配后原样输出
+        let mut m = matcher(&["</s>"]);
+        assert_eq!(m.push("a <"), owned("a ", false));
+        assert_eq!(m.push("b>"), owned("<b>", false));
+        assert_eq!(m.push("<"), owned("", false));
+        assert_eq!(m.finish(), "<");
+
+        // 多字节字符组成的停止序列
+        let mut m = matcher(&["结束"]);
+        assert_eq!(m.push("好的结"), owned("好的", false));
+        assert_eq!(m.push("束了"), owned("", true));
+    }
+
+    #[test]
+    fn earliest_stop_wins_and_empty_stops_are_ignored() {
+        let mut m = matcher(&["END", "ST"]);
+        assert_eq!(m.push("aSTbEND"), owned("a", true));
+
+        // 停止序列自身有重复前缀时保留最长的可能前缀
+        let mut m = matcher(&["aab"]);
+        assert_eq!(m.push("xaa"), owned("x", false));
+        assert_eq!(m.push("a"), owned("a", false));
+        assert_eq!(m.push("b"), owned("", true));
+
+        let mut m = matcher(&[""]);
+        assert_eq!(m.push("text"), owned("text", false));
+        assert_eq!(m.finish(), "");
+    }
+}
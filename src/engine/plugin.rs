@@ -0,0 +1,665 @@
+// 后端插件 - 通过稳定的 C ABI（include/silo_plugin.h）在运行时加载第三方推理后端
+// 厂商或团队可以单独发布 Inferflow、OpenVINO 等适配器，放入插件目录即可使用，无需重新编译 Silo
+
+use crate::engine::backend::InferenceBackend;
+use crate::engine::{
+    BackendType, ChatMessage, FinishReason, GenerationParams, InferenceConfig, InferenceResponse, InferenceStream,
+    MetricsTimer, TokenUsage,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use libloading::Library;
+use serde::{Deserialize, Serialize};
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+/// 与 silo_plugin.h 中的 SILO_PLUGIN_ABI_VERSION 一致
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+const PLUGIN_ENTRY: &[u8] = b"silo_plugin_entry\0";
+const SILO_OK: i32 = 0;
+const SILO_ERR_BUFFER_TOO_SMALL: i32 = 2;
+const ERR_BUFFER_LEN: usize = 1024;
+/// embed 首次调用的输出缓冲区大小，不足时按插件返回的维度重试
+const DEFAULT_EMBED_DIM: usize = 4096;
+
+type TokenCallback = unsafe extern "C" fn(*const c_char, usize, *mut c_void) -> i32;
+
+#[repr(C)]
+#[derive(Default)]
+struct GenerateResult {
+    finish_reason: u32,
+    prompt_tokens: u32,
+    completion_tokens: u32,
+}
+
+/// SiloPluginV1 函数表
+#[repr(C)]
+struct PluginVTable {
+    abi_version: u32,
+    name: *const c_char,
+    init: Option<unsafe extern "C" fn(*const c_char, *mut c_char, usize) -> *mut c_void>,
+    generate: Option<
+        unsafe extern "C" fn(*mut c_void, *const c_char, TokenCallback, *mut c_void, *mut GenerateResult, *mut c_char, usize) -> i32,
+    >,
+    embed: Option<unsafe extern "C" fn(*mut c_void, *const c_char, *mut f32, usize, *mut usize, *mut c_char, usize) -> i32>,
+    free: Option<unsafe extern "C" fn(*mut c_void)>,
+}
+
+/// 已加载的插件信息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PluginInfo {
+    pub name: String,
+    pub path: PathBuf,
+    pub supports_embed: bool,
+}
+
+/// 已加载的插件库；函数表指向库中的静态数据，库在所有克隆释放后才卸载
+#[derive(Clone)]
+pub struct Plugin {
+    info: PluginInfo,
+    vtable: *const PluginVTable,
+    _library: Arc<Library>,
+}
+
+// SAFETY: 函数表是插件库中的只读静态数据，ABI 约定插件函数可以在任意线程调用
+unsafe impl Send for Plugin {}
+unsafe impl Sync for Plugin {}
+
+impl Plugin {
+    /// 加载插件并校验 ABI 版本与必需的函数
+    pub fn load(path: &Path) -> Result<Self> {
+        // SAFETY: 加载动态库会执行其初始化代码，插件目录中的库视为可信
+        let library = unsafe { Library::new(path) }.with_context(|| format!("Failed to load plugin {:?}", path))?;
+        // SAFETY: silo_plugin_entry 的签名由 silo_plugin.h 约定
+        let vtable = unsafe {
+            let entry = library
+                .get::<unsafe extern "C" fn() -> *const PluginVTable>(PLUGIN_ENTRY)
+                .with_context(|| format!("Plugin {:?} does not export silo_plugin_entry", path))?;
+            entry()
+        };
+        Self::from_vtable(path, vtable, library)
+    }
+
+    /// 校验插件返回的函数表（ABI 版本与必需的函数）
+    fn from_vtable(path: &Path, vtable: *const PluginVTable, library: Library) -> Result<Self> {
+        // SAFETY: 非空时指向插件的静态函数表
+        let Some(table) = (unsafe { vtable.as_ref() }) else {
+            anyhow::bail!("Plugin {:?} returned a null function table", path);
+        };
+        if table.abi_version != PLUGIN_ABI_VERSION {
+            anyhow::bail!(
+                "Plugin {:?} uses ABI version {}, expected {}",
+                path,
+                table.abi_version,
+                PLUGIN_ABI_VERSION
+            );
+        }
+        if table.init.is_none() || table.generate.is_none() || table.free.is_none() {
+            anyhow::bail!("Plugin {:?} is missing init, generate or free", path);
+        }
+        let name = if table.name.is_null() {
+            path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default()
+        } else {
+            // SAFETY: name 为插件提供的 NUL 结尾字符串
+            unsafe { CStr::from_ptr(table.name) }.to_string_lossy().into_owned()
+        };
+        let info = PluginInfo {
+            name,
+            path: path.to_path_buf(),
+            supports_embed: table.embed.is_some(),
+        };
+        Ok(Self {
+            info,
+            vtable,
+            _library: Arc::new(library),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.info.name
+    }
+
+    pub fn info(&self) -> &PluginInfo {
+        &self.info
+    }
+
+    fn vtable(&self) -> &PluginVTable {
+        // SAFETY: load 已校验非空，库在 self 存活期间保持加载
+        unsafe { &*self.vtable }
+    }
+}
+
+/// 扫描插件目录，加载所有动态库；无法加载的插件记录日志后跳过
+pub fn discover(dir: &Path) -> Vec<Plugin> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == std::env::consts::DLL_EXTENSION))
+        .collect();
+    paths.sort();
+
+    let mut plugins: Vec<Plugin> = Vec::new();
+    for path in paths {
+        match Plugin::load(&path) {
+            Ok(plugin) if plugins.iter().any(|p| p.name() == plugin.name()) => {
+                tracing::warn!("Skipping plugin {:?}: duplicate name '{}'", path, plugin.name());
+            }
+            Ok(plugin) => {
+                tracing::info!("Loaded backend plugin '{}' from {:?}", plugin.name(), path);
+                plugins.push(plugin);
+            }
+            Err(e) => tracing::warn!("Skipping plugin: {:#}", e),
+        }
+    }
+    plugins
+}
+
+/// 插件实例，释放时调用插件的 free
+struct PluginInstance {
+    handle: *mut c_void,
+    plugin: Plugin,
+}
+
+// SAFETY: ABI 约定实例可以在不同线程上使用；并发访问由外层 Mutex 串行化
+unsafe impl Send for PluginInstance {}
+
+impl Drop for PluginInstance {
+    fn drop(&mut self) {
+        if let Some(free) = self.plugin.vtable().free {
+            // SAFETY: handle 由 init 创建，只释放一次
+            unsafe { free(self.handle) };
+        }
+    }
+}
+
+impl PluginInstance {
+    fn init(plugin: Plugin, config: &InferenceConfig) -> Result<Self> {
+        let config = CString::new(serde_json::to_string(&serde_json::json!({
+            "model_path": config.model_path,
+            "context_size": config.context_size,
+        }))?)?;
+        let mut err = [0 as c_char; ERR_BUFFER_LEN];
+        let init = plugin.vtable().init.expect("validated in Plugin::load");
+        // SAFETY: 参数均为有效的 NUL 结尾字符串与缓冲区
+        let handle = unsafe { init(config.as_ptr(), err.as_mut_ptr(), err.len()) };
+        if handle.is_null() {
+            anyhow::bail!("Plugin '{}' failed to initialize: {}", plugin.name(), error_message(&err));
+        }
+        Ok(Self { handle, plugin })
+    }
+
+    /// 阻塞执行生成；on_piece 返回 false 时通知插件停止
+    fn generate(&mut self, request: &str, mut on_piece: impl FnMut(String) -> bool) -> Result<(FinishReason, TokenUsage)> {
+        let request = CString::new(request)?;
+        let mut result = GenerateResult::default();
+        let mut err = [0 as c_char; ERR_BUFFER_LEN];
+        // 插件可能在 UTF-8 字符中间切分，不完整的尾部暂存到下一段
+        let mut pending: Vec<u8> = Vec::new();
+        let mut callback = |bytes: &[u8]| -> bool {
+            pending.extend_from_slice(bytes);
+            let valid = match std::str::from_utf8(&pending) {
+                Ok(s) => s.len(),
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                Err(_) => pending.len(),
+            };
+            if valid == 0 {
+                return true;
+            }
+            let text = String::from_utf8_lossy(&pending[..valid]).into_owned();
+            pending.drain(..valid);
+            on_piece(text)
+        };
+        let mut callback_ref: &mut dyn FnMut(&[u8]) -> bool = &mut callback;
+        let generate = self.plugin.vtable().generate.expect("validated in Plugin::load");
+        // SAFETY: user_data 指向栈上的回调，生成返回前一直有效
+        let code = unsafe {
+            generate(
+                self.handle,
+                request.as_ptr(),
+                on_token,
+                &mut callback_ref as *mut &mut dyn FnMut(&[u8]) -> bool as *mut c_void,
+                &mut result,
+                err.as_mut_ptr(),
+                err.len(),
+            )
+        };
+        if code != SILO_OK {
+            anyhow::bail!("Plugin '{}' generation failed: {}", self.plugin.name(), error_message(&err));
+        }
+        if !pending.is_empty() {
+            on_piece(String::from_utf8_lossy(&pending).into_owned());
+        }
+        let reason = match result.finish_reason {
+            0 => FinishReason::Stop,
+            1 => FinishReason::Length,
+            2 => FinishReason::StopSequence,
+            3 => FinishReason::Cancelled,
+            _ => FinishReason::Error,
+        };
+        let usage = TokenUsage {
+            prompt_tokens: result.prompt_tokens as usize,
+            completion_tokens: result.completion_tokens as usize,
+        };
+        Ok((reason, usage))
+    }
+
+    fn embed(&mut self, text: &str) -> Result<Vec<f32>> {
+        let Some(embed) = self.plugin.vtable().embed else {
+            anyhow::bail!("Plugin '{}' does not support embeddings", self.plugin.name());
+        };
+        let text = CString::new(text)?;
+        let mut out = vec![0f32; DEFAULT_EMBED_DIM];
+        loop {
+            let mut written = 0usize;
+            let mut err = [0 as c_char; ERR_BUFFER_LEN];
+            // SAFETY: out 的长度如实传给插件
+            let code = unsafe {
+                embed(
+                    self.handle,
+                    text.as_ptr(),
+                    out.as_mut_ptr(),
+                    out.len(),
+                    &mut written,
+                    err.as_mut_ptr(),
+                    err.len(),
+                )
+            };
+            match code {
+                SILO_OK => {
+                    out.truncate(written.min(out.len()));
+                    return Ok(out);
+                }
+                SILO_ERR_BUFFER_TOO_SMALL if written > out.len() => out.resize(written, 0.0),
+                _ => anyhow::bail!("Plugin '{}' embedding failed: {}", self.plugin.name(), error_message(&err)),
+            }
+        }
+    }
+}
+
+/// 插件的 token 回调；回调 panic 时通知插件停止，不让 panic 穿过 FFI 边界
+unsafe extern "C" fn on_token(piece: *const c_char, len: usize, user_data: *mut c_void) -> i32 {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        // SAFETY: user_data 为 generate 传入的回调指针，piece 指向插件提供的 len 字节
+        let callback = unsafe { &mut *(user_data as *mut &mut dyn FnMut(&[u8]) -> bool) };
+        let bytes = if piece.is_null() || len == 0 {
+            &[][..]
+        } else {
+            unsafe { std::slice::from_raw_parts(piece as *const u8, len) }
+        };
+        if callback(bytes) { 0 } else { 1 }
+    }))
+    .unwrap_or(1)
+}
+
+fn error_message(err: &[c_char]) -> String {
+    // SAFETY: 缓冲区初始为全 0，强制最后一个字节为 NUL，避免插件写满后越界读取
+    let mut buf = err.to_vec();
+    if let Some(last) = buf.last_mut() {
+        *last = 0;
+    }
+    let message = unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy().into_owned();
+    if message.is_empty() {
+        "unknown error".to_string()
+    } else {
+        message
+    }
+}
+
+/// 由插件提供的推理后端
+pub struct PluginBackend {
+    plugin: Plugin,
+    instance: Option<Arc<Mutex<PluginInstance>>>,
+    model_path: Option<PathBuf>,
+}
+
+impl PluginBackend {
+    pub fn new(plugin: Plugin) -> Self {
+        Self {
+            plugin,
+            instance: None,
+            model_path: None,
+        }
+    }
+
+    fn instance(&self) -> Result<Arc<Mutex<PluginInstance>>> {
+        self.instance
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Plugin '{}' is not initialized", self.plugin.name()))
+    }
+}
+
+fn request_json(messages: &[ChatMessage], params: &GenerationParams) -> Result<String> {
+    Ok(serde_json::to_string(&serde_json::json!({
+        "messages": messages,
+        "params": params,
+    }))?)
+}
+
+#[async_trait]
+impl InferenceBackend for PluginBackend {
+    async fn initialize(&mut self, config: InferenceConfig) -> Result<()> {
+        // 先释放旧实例，避免同时持有两份模型
+        self.instance = None;
+        self.model_path = Some(config.model_path.clone());
+        let plugin = self.plugin.clone();
+        let instance = tokio::task::spawn_blocking(move || PluginInstance::init(plugin, &config)).await??;
+        self.instance = Some(Arc::new(Mutex::new(instance)));
+        tracing::info!("Plugin backend '{}' initialized", self.plugin.name());
+        Ok(())
+    }
+
+    async fn chat(&self, messages: &[ChatMessage], params: &GenerationParams) -> Result<InferenceResponse> {
+        let instance = self.instance()?;
+        let request = request_json(messages, params)?;
+        tokio::task::spawn_blocking(move || {
+            let mut timer = MetricsTimer::start();
+            let mut tokens = Vec::new();
+            let mut instance = instance.lock().unwrap_or_else(|e| e.into_inner());
+            let (finish_reason, usage) = instance.generate(&request, |piece| {
+                timer.mark_token();
+                tokens.push(piece);
+                true
+            })?;
+            Ok(InferenceResponse {
+                tokens,
+                finish_reason,
+                metrics: timer.finish(Some(usage.prompt_tokens), usage.completion_tokens),
+            })
+        })
+        .await?
+    }
+
+    async fn chat_stream(
+        &self,
+        messages: &[ChatMessage],
+        params: &GenerationParams,
+        cancel: CancellationToken,
+    ) -> Result<InferenceStream> {
+        let instance = self.instance()?;
+        let request = request_json(messages, params)?;
+        let (mut sender, stream) = InferenceStream::channel(cancel);
+        let name = self.plugin.name().to_string();
+        // 插件的生成是阻塞调用，取消时回调返回非 0 通知插件停止
+        tokio::task::spawn_blocking(move || {
+            let mut instance = instance.lock().unwrap_or_else(|e| e.into_inner());
+            match instance.generate(&request, |piece| sender.blocking_send(piece)) {
+                Ok((reason, usage)) => {
+                    sender.set_usage(usage);
+                    let reason = if sender.is_cancelled() { FinishReason::Cancelled } else { reason };
+                    sender.blocking_finish(reason);
+                }
+                Err(e) => {
+                    tracing::error!("Plugin '{}' generation failed: {}", name, e);
+                    sender.blocking_finish(FinishReason::Error);
+                }
+            }
+        });
+        Ok(stream)
+    }
+
+    fn backend_type(&self) -> BackendType {
+        // 名为 inferflow 的插件即 Inferflow 后端
+        match self.plugin.name() {
+            "inferflow" => BackendType::InferflowCpp,
+            name => BackendType::Plugin(name.to_string()),
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn embedding_model(&self) -> Option<String> {
+        if !self.plugin.info().supports_embed || self.instance.is_none() {
+            return None;
+        }
+        let model = self.model_path.as_ref().map(|p| p.display().to_string()).unwrap_or_default();
+        Some(format!("plugin:{}:{}", self.plugin.name(), model))
+    }
+
+    /// 插件的 embed 一次处理一条文本
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let instance = self.instance()?;
+        let texts = texts.to_vec();
+        tokio::task::spawn_blocking(move || {
+            let mut instance = instance.lock().unwrap_or_else(|e| e.into_inner());
+            texts.iter().map(|text| instance.embed(text)).collect()
+        })
+        .await?
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const FAKE_EMBED_DIM: usize = DEFAULT_EMBED_DIM + 904;
+
+    /// 已释放的实例（按模型路径记录）
+    static FREED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    /// 被回调要求停止时已输出的片段数（按模型路径记录）
+    static STOPPED: Mutex<Vec<(String, u32)>> = Mutex::new(Vec::new());
+    static EMBED_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    struct FakeInstance {
+        model_path: String,
+    }
+
+    unsafe fn write_err(err: *mut c_char, err_len: usize, message: &str) {
+        let len = message.len().min(err_len - 1);
+        unsafe {
+            std::ptr::copy_nonoverlapping(message.as_ptr() as *const c_char, err, len);
+            *err.add(len) = 0;
+        }
+    }
+
+    unsafe extern "C" fn fake_init(config: *const c_char, err: *mut c_char, err_len: usize) -> *mut c_void {
+        let config: serde_json::Value = serde_json::from_slice(unsafe { CStr::from_ptr(config) }.to_bytes()).unwrap();
+        let model_path = config["model_path"].as_str().unwrap().to_string();
+        if model_path.ends_with("missing.bin") {
+            unsafe { write_err(err, err_len, "model not found") };
+            return std::ptr::null_mut();
+        }
+        Box::into_raw(Box::new(FakeInstance { model_path })) as *mut c_void
+    }
+
+    /// "hello" 把一个汉字拆在两次回调中输出；"forever" 持续输出直到回调要求停止；"fail" 返回错误
+    unsafe extern "C" fn fake_generate(
+        handle: *mut c_void,
+        request: *const c_char,
+        callback: TokenCallback,
+        user_data: *mut c_void,
+        result: *mut GenerateResult,
+        err: *mut c_char,
+        err_len: usize,
+    ) -> i32 {
+        let instance = unsafe { &*(handle as *const FakeInstance) };
+        let request: serde_json::Value = serde_json::from_slice(unsafe { CStr::from_ptr(request) }.to_bytes()).unwrap();
+        let result = unsafe { &mut *result };
+        match request["messages"][0]["content"].as_str().unwrap() {
+            "hello" => {
+                for piece in [&b"Hi "[..], &[0xe4, 0xbd], &[0xa0], b"!"] {
+                    unsafe { callback(piece.as_ptr() as *const c_char, piece.len(), user_data) };
+                }
+                *result = GenerateResult { finish_reason: 1, prompt_tokens: 3, completion_tokens: 4 };
+                SILO_OK
+            }
+            "forever" => {
+                let mut count = 0;
+                while count < 100_000 {
+                    count += 1;
+                    if unsafe { callback(b"x".as_ptr() as *const c_char, 1, user_data) } != 0 {
+                        STOPPED.lock().unwrap().push((instance.model_path.clone(), count));
+                        *result = GenerateResult { finish_reason: 3, prompt_tokens: 1, completion_tokens: count };
+                        return SILO_OK;
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                }
+                *result = GenerateResult { finish_reason: 1, prompt_tokens: 1, completion_tokens: count };
+                SILO_OK
+            }
+            _ => {
+                unsafe { write_err(err, err_len, "out of memory") };
+                1
+            }
+        }
+    }
+
+    /// 向量维度大于默认缓冲区，第一次调用总是返回 SILO_ERR_BUFFER_TOO_SMALL
+    unsafe extern "C" fn fake_embed(
+        _handle: *mut c_void,
+        _text: *const c_char,
+        out: *mut f32,
+        out_len: usize,
+        written: *mut usize,
+        _err: *mut c_char,
+        _err_len: usize,
+    ) -> i32 {
+        EMBED_CALLS.fetch_add(1, Ordering::SeqCst);
+        unsafe { *written = FAKE_EMBED_DIM };
+        if out_len < FAKE_EMBED_DIM {
+            return SILO_ERR_BUFFER_TOO_SMALL;
+        }
+        let out = unsafe { std::slice::from_raw_parts_mut(out, out_len) };
+        for (i, v) in out.iter_mut().enumerate().take(FAKE_EMBED_DIM) {
+            *v = i as f32;
+        }
+        SILO_OK
+    }
+
+    unsafe extern "C" fn fake_free(handle: *mut c_void) {
+        let instance = unsafe { Box::from_raw(handle as *mut FakeInstance) };
+        FREED.lock().unwrap().push(instance.model_path);
+    }
+
+    fn vtable() -> PluginVTable {
+        PluginVTable {
+            abi_version: PLUGIN_ABI_VERSION,
+            name: c"fake".as_ptr(),
+            init: Some(fake_init),
+            generate: Some(fake_generate),
+            embed: Some(fake_embed),
+            free: Some(fake_free),
+        }
+    }
+
+    fn load(table: PluginVTable) -> Result<Plugin> {
+        let table: &'static PluginVTable = Box::leak(Box::new(table));
+        Plugin::from_vtable(Path::new("/plugins/libfake.so"), table, libloading::os::unix::Library::this().into())
+    }
+
+    async fn backend(model_path: &str) -> PluginBackend {
+        let mut backend = PluginBackend::new(load(vtable()).unwrap());
+        backend
+            .initialize(InferenceConfig {
+                model_path: PathBuf::from(model_path),
+                backend: BackendType::Plugin("fake".into()),
+                context_size: 2048,
+            })
+            .await
+            .unwrap();
+        backend
+    }
+
+    fn error_of(result: Result<Plugin>) -> String {
+        format!("{:#}", result.err().unwrap())
+    }
+
+    #[test]
+    fn validates_function_table() {
+        let plugin = load(vtable()).unwrap();
+        assert_eq!(plugin.name(), "fake");
+        assert!(plugin.info().supports_embed);
+
+        let err = error_of(load(PluginVTable { abi_version: 2, ..vtable() }));
+        assert!(err.contains("uses ABI version 2, expected 1"), "{}", err);
+        let err = error_of(load(PluginVTable { generate: None, ..vtable() }));
+        assert!(err.contains("missing init, generate or free"), "{}", err);
+        let err = error_of(Plugin::from_vtable(
+            Path::new("libnull.so"),
+            std::ptr::null(),
+            libloading::os::unix::Library::this().into(),
+        ));
+        assert!(err.contains("null function table"), "{}", err);
+
+        // 没有名字时取文件名，没有 embed 时不提供向量
+        let plugin = load(PluginVTable { name: std::ptr::null(), embed: None, ..vtable() }).unwrap();
+        assert_eq!(plugin.name(), "libfake");
+        assert!(!plugin.info().supports_embed);
+    }
+
+    #[tokio::test]
+    async fn generates_through_callback_and_cancels() {
+        let backend = backend("/models/generate.bin").await;
+        let params = GenerationParams::default();
+
+        // 拆开的 UTF-8 字符在凑齐后才输出
+        let resp = backend.chat(&[ChatMessage::user("hello")], &params).await.unwrap();
+        assert_eq!(resp.tokens, ["Hi ", "你", "!"]);
+        assert_eq!(resp.finish_reason, FinishReason::Length);
+        assert_eq!(resp.metrics.prompt_tokens, Some(3));
+        assert_eq!(resp.metrics.completion_tokens, 4);
+
+        let err = backend.chat(&[ChatMessage::user("fail")], &params).await.unwrap_err();
+        assert!(err.to_string().contains("out of memory"), "{}", err);
+
+        let cancel = CancellationToken::new();
+        let mut stream = backend
+            .chat_stream(&[ChatMessage::user("forever")], &params, cancel.clone())
+            .await
+            .unwrap();
+        for _ in 0..3 {
+            assert_eq!(stream.next_token().await.as_deref(), Some("x"));
+        }
+        cancel.cancel();
+        while stream.next_token().await.is_some() {}
+        assert_eq!(stream.finish_reason(), Some(FinishReason::Cancelled));
+
+        // 下一次请求要等插件返回后才能拿到实例
+        backend.chat(&[ChatMessage::user("hello")], &params).await.unwrap();
+        let stopped = STOPPED.lock().unwrap().clone();
+        let (_, count) = stopped.iter().find(|(path, _)| path == "/models/generate.bin").unwrap();
+        assert!((3..100_000).contains(count), "plugin stopped after {} pieces", count);
+    }
+
+    #[tokio::test]
+    async fn embed_grows_buffer_and_drop_frees_instance() {
+        let backend = backend("/models/embed.bin").await;
+        assert_eq!(backend.embedding_model().as_deref(), Some("plugin:fake:/models/embed.bin"));
+        let vectors = backend.embed(&["a".to_string(), "b".to_string()]).await.unwrap();
+        assert_eq!(vectors.len(), 2);
+        assert_eq!(vectors[0].len(), FAKE_EMBED_DIM);
+        assert_eq!(vectors[1][FAKE_EMBED_DIM - 1], (FAKE_EMBED_DIM - 1) as f32);
+        // 每条文本先以默认大小调用一次，再按返回的维度重试
+        assert_eq!(EMBED_CALLS.load(Ordering::SeqCst), 4);
+
+        assert!(!FREED.lock().unwrap().iter().any(|p| p == "/models/embed.bin"));
+        drop(backend);
+        assert!(FREED.lock().unwrap().iter().any(|p| p == "/models/embed.bin"));
+
+    }
+
+    #[tokio::test]
+    async fn failed_init_reports_plugin_error_after_freeing_old_instance() {
+        let mut backend = backend("/models/first.bin").await;
+        let err = backend
+            .initialize(InferenceConfig {
+                model_path: PathBuf::from("/models/missing.bin"),
+                backend: BackendType::Plugin("fake".into()),
+                context_size: 2048,
+            })
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("model not found"), "{}", err);
+        // 重新初始化前先释放旧实例，失败后不再持有任何实例
+        assert!(FREED.lock().unwrap().iter().any(|p| p == "/models/first.bin"));
+        assert!(backend.embedding_model().is_none());
+        assert!(backend.embed(&["a".to_string()]).await.is_err());
+    }
+}
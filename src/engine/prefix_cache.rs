@@ -0,0 +1,627 @@
+// 提示词前缀 KV 缓存 - 按提示词前缀的哈希缓存推理状态（如 KV cache），
+// 相同前缀（系统提示词、检索上下文）的后续请求直接恢复状态，跳过这部分的预填充
+//
+// 前缀按固定长度的块切分，每个块边界有一个链式哈希；缓存的状态可以服务于任意更短的公共前缀。
+// 内存与磁盘（数据目录下）两级存储，各自按最近最少使用（LRU）顺序淘汰到容量上限以内
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const FILE_MAGIC: &[u8; 4] = b"SKVC";
+const FILE_VERSION: u32 = 1;
+const FILE_EXT: &str = "kv";
+
+/// 可缓存的推理状态；恢复时由后端截取到命中的前缀长度
+pub trait CacheState: Send + Sync + Sized {
+    /// 占用的内存字节数
+    fn size_bytes(&self) -> u64;
+    fn to_bytes(&self) -> Vec<u8>;
+    fn from_bytes(bytes: &[u8]) -> Result<Self>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrefixCacheConfig {
+    /// 内存缓存上限，0 表示不缓存在内存中
+    pub memory_bytes: u64,
+    /// 磁盘缓存目录，None 表示不写入磁盘
+    pub disk_dir: Option<PathBuf>,
+    /// 磁盘缓存上限，默认 0：KV 状态体积大，写入磁盘需要显式开启
+    pub disk_bytes: u64,
+    /// 块大小（token），前缀按块对齐缓存
+    pub block_tokens: usize,
+}
+
+impl PrefixCacheConfig {
+    /// 默认位置：dirs::data_dir()/silo/kv_cache
+    pub fn default_dir() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("silo")
+            .join("kv_cache")
+    }
+
+    /// 只缓存在内存中
+    pub fn memory_only(memory_bytes: u64) -> Self {
+        Self {
+            memory_bytes,
+            disk_dir: None,
+            ..Self::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.memory_bytes > 0 || (self.disk_dir.is_some() && self.disk_bytes > 0)
+    }
+}
+
+impl Default for PrefixCacheConfig {
+    fn default() -> Self {
+        Self {
+            memory_bytes: 1024 * 1024 * 1024,
+            disk_dir: Some(Self::default_dir()),
+            disk_bytes: 0,
+            block_tokens: 64,
+        }
+    }
+}
+
+/// 缓存命中情况
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PrefixCacheStats {
+    pub entries: usize,
+    pub memory_bytes: u64,
+    pub disk_bytes: u64,
+    pub memory_hits: u64,
+    pub disk_hits: u64,
+    pub misses: u64,
+    /// 命中后跳过预填充的 token 总数
+    pub reused_tokens: u64,
+}
+
+struct Entry<S> {
+    /// 每个块边界的链式哈希，最后一个即条目的键
+    hashes: Vec<u64>,
+    size_bytes: u64,
+    last_used: u64,
+    memory: Option<Arc<S>>,
+    /// 磁盘文件大小，不在磁盘上时为 None
+    disk_bytes: Option<u64>,
+}
+
+struct Inner<S> {
+    entries: HashMap<u64, Entry<S>>,
+    clock: u64,
+    stats: PrefixCacheStats,
+}
+
+impl<S> Inner<S> {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+pub struct PrefixCache<S> {
+    config: PrefixCacheConfig,
+    /// 模型标识，参与哈希：不同模型的状态互不可用
+    namespace: u64,
+    /// 本模型的磁盘目录
+    disk_dir: Option<PathBuf>,
+    inner: Mutex<Inner<S>>,
+}
+
+impl<S: CacheState> PrefixCache<S> {
+    /// 打开缓存；namespace 区分模型（见 model_namespace），磁盘上已有的条目重新载入索引
+    pub fn open(namespace: u64, config: PrefixCacheConfig) -> Result<Self> {
+        let block_tokens = config.block_tokens.max(1);
+        let config = PrefixCacheConfig { block_tokens, ..config };
+        let disk_dir = config
+            .disk_dir
+            .as_ref()
+            .filter(|_| config.disk_bytes > 0)
+            .map(|dir| dir.join(format!("{:016x}", namespace)));
+        let mut inner = Inner {
+            entries: HashMap::new(),
+            clock: 0,
+            stats: PrefixCacheStats::default(),
+        };
+
+        if let Some(dir) = &disk_dir {
+            std::fs::create_dir_all(dir)?;
+            let mut found = vec![];
+            for entry in std::fs::read_dir(dir)?.flatten() {
+                let path = entry.path();
+                if path.extension().is_none_or(|ext| ext != FILE_EXT) {
+                    continue;
+                }
+                match read_header(&path, block_tokens) {
+                    Ok(hashes) if !hashes.is_empty() => {
+                        let metadata = entry.metadata()?;
+                        let modified = metadata.modified().unwrap_or(std::time::UNIX_EPOCH);
+                        found.push((modified, hashes, metadata.len()));
+                    }
+                    _ => {
+                        // 格式或块大小不符的旧文件无法使用
+                        let _ = std::fs::remove_file(&path);
+                    }
+                }
+            }
+            // 按修改时间恢复使用顺序
+            found.sort_by_key(|(modified, _, _)| *modified);
+            for (_, hashes, size) in found {
+                let last_used = inner.tick();
+                inner.entries.insert(
+                    *hashes.last().unwrap(),
+                    Entry {
+                        hashes,
+                        size_bytes: size,
+                        last_used,
+                        memory: None,
+                        disk_bytes: Some(size),
+                    },
+                );
+            }
+            if !inner.entries.is_empty() {
+                tracing::info!("Prefix cache: {} entries on disk in {:?}", inner.entries.len(), dir);
+            }
+        }
+
+        let cache = Self {
+            config,
+            namespace,
+            disk_dir,
+            inner: Mutex::new(inner),
+        };
+        cache.evict(&mut cache.inner.lock().unwrap());
+        Ok(cache)
+    }
+
+    /// 长度为 len 的提示词可缓存的前缀长度：块对齐，且至少留一个 token 用于计算 logits
+    pub fn block_boundary(&self, len: usize) -> usize {
+        len.saturating_sub(1) / self.config.block_tokens * self.config.block_tokens
+    }
+
+    /// 查找与 tokens 公共前缀最长的缓存状态，返回命中的前缀长度与状态
+    /// 命中长度总小于 tokens.len()；状态可能覆盖更长的前缀，使用时需截取
+    pub fn lookup(&self, tokens: &[u32]) -> Option<(usize, Arc<S>)> {
+        let hashes = self.hashes(&tokens[..self.block_boundary(tokens.len())]);
+        let (key, blocks) = {
+            let mut inner = self.inner.lock().unwrap();
+            let best = inner
+                .entries
+                .iter()
+                .map(|(key, entry)| (*key, common_prefix(&entry.hashes, &hashes)))
+                .max_by_key(|(_, blocks)| *blocks)
+                .filter(|(_, blocks)| *blocks > 0);
+            let Some((key, blocks)) = best else {
+                inner.stats.misses += 1;
+                return None;
+            };
+            let tick = inner.tick();
+            let entry = inner.entries.get_mut(&key).unwrap();
+            entry.last_used = tick;
+            if let Some(state) = entry.memory.clone() {
+                inner.stats.memory_hits += 1;
+                inner.stats.reused_tokens += (blocks * self.config.block_tokens) as u64;
+                return Some((blocks * self.config.block_tokens, state));
+            }
+            (key, blocks)
+        };
+
+        // 只在磁盘上：读取文件时不持有锁
+        let path = self.entry_path(key)?;
+        let state = match read_state::<S>(&path) {
+            Ok(state) => Arc::new(state),
+            Err(e) => {
+                tracing::warn!("Failed to read prefix cache entry {:?}: {}", path, e);
+                let mut inner = self.inner.lock().unwrap();
+                inner.entries.remove(&key);
+                inner.stats.misses += 1;
+                let _ = std::fs::remove_file(&path);
+                return None;
+            }
+        };
+        touch(&path);
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.stats.disk_hits += 1;
+        inner.stats.reused_tokens += (blocks * self.config.block_tokens) as u64;
+        if self.config.memory_bytes >= state.size_bytes()
+            && let Some(entry) = inner.entries.get_mut(&key)
+        {
+            // 从磁盘载入索引时只知道文件大小，载入内存后按状态的实际大小计算
+            entry.size_bytes = state.size_bytes();
+            entry.memory = Some(state.clone());
+            self.evict(&mut inner);
+        }
+        Some((blocks * self.config.block_tokens, state))
+    }
+
+    /// 缓存 tokens 对应的状态；tokens 长度应为块大小的整数倍（见 block_boundary）
+    /// 新条目覆盖的较短条目会被移除
+    pub fn insert(&self, tokens: &[u32], state: S) {
+        let hashes = self.hashes(tokens);
+        let Some(&key) = hashes.last() else {
+            return;
+        };
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.entries.contains_key(&key) {
+                let tick = inner.tick();
+                inner.entries.get_mut(&key).unwrap().last_used = tick;
+                return;
+            }
+        }
+
+        let size_bytes = state.size_bytes();
+        let keep_in_memory = size_bytes <= self.config.memory_bytes;
+        // 写入磁盘时不持有锁
+        let disk_bytes = match self.entry_path(key) {
+            Some(path) if size_bytes <= self.config.disk_bytes => match write_state(&path, self.config.block_tokens, &hashes, &state) {
+                Ok(written) => Some(written),
+                Err(e) => {
+                    tracing::warn!("Failed to write prefix cache entry {:?}: {}", path, e);
+                    None
+                }
+            },
+            _ => None,
+        };
+        if !keep_in_memory && disk_bytes.is_none() {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let superseded: Vec<u64> = inner
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.hashes.len() < hashes.len() && hashes.starts_with(&entry.hashes))
+            .map(|(key, _)| *key)
+            .collect();
+        for old in superseded {
+            self.remove(&mut inner, old);
+        }
+        let last_used = inner.tick();
+        inner.entries.insert(
+            key,
+            Entry {
+                hashes,
+                size_bytes,
+                last_used,
+                memory: keep_in_memory.then(|| Arc::new(state)),
+                disk_bytes,
+            },
+        );
+        self.evict(&mut inner);
+    }
+
+    pub fn stats(&self) -> PrefixCacheStats {
+        let inner = self.inner.lock().unwrap();
+        PrefixCacheStats {
+            entries: inner.entries.len(),
+            memory_bytes: memory_bytes(&inner),
+            disk_bytes: disk_bytes(&inner),
+            ..inner.stats.clone()
+        }
+    }
+
+    /// 每个块边界的链式哈希
+    fn hashes(&self, tokens: &[u32]) -> Vec<u64> {
+        let mut hash = fnv1a(FNV_OFFSET, &self.namespace.to_le_bytes());
+        tokens
+            .chunks_exact(self.config.block_tokens)
+            .map(|block| {
+                for token in block {
+                    hash = fnv1a(hash, &token.to_le_bytes());
+                }
+                hash
+            })
+            .collect()
+    }
+
+    fn entry_path(&self, key: u64) -> Option<PathBuf> {
+        self.disk_dir.as_ref().map(|dir| dir.join(format!("{:016x}.{}", key, FILE_EXT)))
+    }
+
+    fn remove(&self, inner: &mut Inner<S>, key: u64) {
+        if let Some(entry) = inner.entries.remove(&key)
+            && entry.disk_bytes.is_some()
+            && let Some(path) = self.entry_path(key)
+        {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    /// 两级存储分别按 LRU 淘汰到上限以内
+    fn evict(&self, inner: &mut Inner<S>) {
+        while memory_bytes(inner) > self.config.memory_bytes {
+            let Some(key) = lru(inner, |entry| entry.memory.is_some()) else {
+                break;
+            };
+            let entry = inner.entries.get_mut(&key).unwrap();
+            entry.memory = None;
+            if entry.disk_bytes.is_none() {
+                inner.entries.remove(&key);
+            }
+        }
+        while disk_bytes(inner) > self.config.disk_bytes {
+            let Some(key) = lru(inner, |entry| entry.disk_bytes.is_some()) else {
+                break;
+            };
+            if let Some(path) = self.entry_path(key) {
+                let _ = std::fs::remove_file(path);
+            }
+            let entry = inner.entries.get_mut(&key).unwrap();
+            entry.disk_bytes = None;
+            if entry.memory.is_none() {
+                inner.entries.remove(&key);
+            }
+        }
+    }
+}
+
+/// 模型文件的缓存命名空间：路径、大小与修改时间任一变化都使旧缓存失效
+pub fn model_namespace(model_path: &Path) -> u64 {
+    let mut hash = fnv1a(FNV_OFFSET, model_path.to_string_lossy().as_bytes());
+    if let Ok(metadata) = std::fs::metadata(model_path) {
+        hash = fnv1a(hash, &metadata.len().to_le_bytes());
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        hash = fnv1a(hash, &modified.to_le_bytes());
+    }
+    hash
+}
+
+fn memory_bytes<S>(inner: &Inner<S>) -> u64 {
+    inner.entries.values().filter(|e| e.memory.is_some()).map(|e| e.size_bytes).sum()
+}
+
+fn disk_bytes<S>(inner: &Inner<S>) -> u64 {
+    inner.entries.values().filter_map(|e| e.disk_bytes).sum()
+}
+
+fn lru<S>(inner: &Inner<S>, filter: impl Fn(&Entry<S>) -> bool) -> Option<u64> {
+    inner
+        .entries
+        .iter()
+        .filter(|(_, entry)| filter(entry))
+        .min_by_key(|(_, entry)| entry.last_used)
+        .map(|(key, _)| *key)
+}
+
+fn common_prefix(a: &[u64], b: &[u64]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+pub(crate) const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// FNV-1a：哈希值写入磁盘，不能随 Rust 版本变化
+pub(crate) fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// 文件格式：魔数、版本、块大小、哈希个数、各块边界哈希，之后为状态数据
+fn write_state<S: CacheState>(path: &Path, block_tokens: usize, hashes: &[u64], state: &S) -> Result<u64> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(FILE_MAGIC);
+    bytes.extend_from_slice(&FILE_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(block_tokens as u32).to_le_bytes());
+    bytes.extend_from_slice(&(hashes.len() as u32).to_le_bytes());
+    for hash in hashes {
+        bytes.extend_from_slice(&hash.to_le_bytes());
+    }
+    bytes.extend_from_slice(&state.to_bytes());
+    // 先写临时文件再改名，中途退出不会留下不完整的条目
+    let tmp = path.with_extension("tmp");
+    std::fs::File::create(&tmp)?.write_all(&bytes)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(bytes.len() as u64)
+}
+
+fn read_header(path: &Path, block_tokens: usize) -> Result<Vec<u64>> {
+    let mut file = std::fs::File::open(path)?;
+    let mut header = [0u8; 16];
+    file.read_exact(&mut header)?;
+    if &header[..4] != FILE_MAGIC || u32_at(&header, 4) != FILE_VERSION {
+        anyhow::bail!("Not a prefix cache file");
+    }
+    if u32_at(&header, 8) as usize != block_tokens {
+        anyhow::bail!("Prefix cache block size mismatch");
+    }
+    // 哈希个数来自文件内容，按文件长度校验后再分配
+    let hash_bytes = u32_at(&header, 12) as u64 * 8;
+    if hash_bytes > file.metadata()?.len().saturating_sub(16) {
+        anyhow::bail!("Truncated prefix cache file");
+    }
+    let mut hashes = vec![0u8; hash_bytes as usize];
+    file.read_exact(&mut hashes)?;
+    Ok(hashes.chunks_exact(8).map(|b| u64::from_le_bytes(b.try_into().unwrap())).collect())
+}
+
+fn read_state<S: CacheState>(path: &Path) -> Result<S> {
+    let bytes = std::fs::read(path)?;
+    if bytes.len() < 16 || &bytes[..4] != FILE_MAGIC {
+        anyhow::bail!("Not a prefix cache file");
+    }
+    let offset = 16 + u32_at(&bytes, 12) as usize * 8;
+    S::from_bytes(bytes.get(offset..).ok_or_else(|| anyhow::anyhow!("Truncated prefix cache file"))?)
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// 更新修改时间，重启后按此恢复 LRU 顺序
+fn touch(path: &Path) {
+    if let Ok(file) = std::fs::File::options().write(true).open(path) {
+        let _ = file.set_modified(std::time::SystemTime::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 大小即字节数的测试状态
+    #[derive(Debug, PartialEq)]
+    struct Blob(Vec<u8>);
+
+    impl CacheState for Blob {
+        fn size_bytes(&self) -> u64 {
+            self.0.len() as u64
+        }
+
+        fn to_bytes(&self) -> Vec<u8> {
+            self.0.clone()
+        }
+
+        fn from_bytes(bytes: &[u8]) -> Result<Self> {
+            Ok(Blob(bytes.to_vec()))
+        }
+    }
+
+    fn blob(tag: u8, len: usize) -> Blob {
+        Blob(vec![tag; len])
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("silo-prefix-cache-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn config(memory_bytes: u64, disk: Option<(&Path, u64)>) -> PrefixCacheConfig {
+        PrefixCacheConfig {
+            memory_bytes,
+            disk_dir: disk.map(|(dir, _)| dir.to_path_buf()),
+            disk_bytes: disk.map_or(0, |(_, bytes)| bytes),
+            block_tokens: 4,
+        }
+    }
+
+    #[test]
+    fn disk_tier_is_opt_in() {
+        let config = PrefixCacheConfig::default();
+        assert_eq!(config.disk_bytes, 0);
+        assert!(config.is_enabled());
+        let cache = PrefixCache::<Blob>::open(1, config).unwrap();
+        assert!(cache.disk_dir.is_none());
+        assert!(!PrefixCacheConfig::memory_only(0).is_enabled());
+    }
+
+    #[test]
+    fn lookup_reuses_shared_chained_blocks() {
+        let cache = PrefixCache::<Blob>::open(7, config(1024, None)).unwrap();
+        let tokens: Vec<u32> = (0..8).collect();
+        assert_eq!(cache.block_boundary(9), 8);
+        assert_eq!(cache.block_boundary(8), 4);
+        cache.insert(&tokens, blob(1, 16));
+
+        // 完整命中两个块；提示词刚好 8 个 token 时只命中一个块，留一个 token 计算 logits
+        let (len, state) = cache.lookup(&[0, 1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        assert_eq!((len, &*state), (8, &blob(1, 16)));
+        assert_eq!(cache.lookup(&tokens).unwrap().0, 4);
+        // 第二个块不同时共享第一个块
+        assert_eq!(cache.lookup(&[0, 1, 2, 3, 9, 9, 9, 9, 9]).unwrap().0, 4);
+        // 哈希是链式的：相同内容的块出现在不同位置不会命中
+        assert!(cache.lookup(&[9, 9, 9, 9, 4, 5, 6, 7, 8]).is_none());
+        assert!(cache.lookup(&[0, 1, 2]).is_none());
+
+        // 更长的前缀取代被它覆盖的条目
+        cache.insert(&(0..12).collect::<Vec<u32>>(), blob(2, 16));
+        assert_eq!(*cache.lookup(&[0, 1, 2, 3, 4, 5, 6, 7, 8]).unwrap().1, blob(2, 16));
+
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 1);
+        assert_eq!(stats.memory_bytes, 16);
+        assert_eq!(stats.memory_hits, 4);
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.reused_tokens, 8 + 4 + 4 + 8);
+
+        // 不同模型（命名空间）的状态互不可用
+        let other = PrefixCache::<Blob>::open(8, config(1024, None)).unwrap();
+        other.insert(&[0, 1, 2, 3], blob(3, 4));
+        assert!(cache.lookup(&[0, 1, 2, 3, 4]).is_some());
+        assert_eq!(*other.lookup(&[0, 1, 2, 3, 4]).unwrap().1, blob(3, 4));
+    }
+
+    #[test]
+    fn memory_tier_evicts_least_recently_used() {
+        let cache = PrefixCache::<Blob>::open(1, config(20, None)).unwrap();
+        cache.insert(&[1, 1, 1, 1], blob(1, 8));
+        cache.insert(&[2, 2, 2, 2], blob(2, 8));
+        assert!(cache.lookup(&[1, 1, 1, 1, 0]).is_some());
+        cache.insert(&[3, 3, 3, 3], blob(3, 8));
+
+        assert!(cache.lookup(&[2, 2, 2, 2, 0]).is_none());
+        assert!(cache.lookup(&[1, 1, 1, 1, 0]).is_some());
+        assert!(cache.lookup(&[3, 3, 3, 3, 0]).is_some());
+        assert_eq!(cache.stats().memory_bytes, 16);
+
+        // 超过内存上限且没有磁盘层的状态不缓存
+        cache.insert(&[4, 4, 4, 4], blob(4, 21));
+        assert!(cache.lookup(&[4, 4, 4, 4, 0]).is_none());
+        assert_eq!(cache.stats().entries, 2);
+    }
+
+    #[test]
+    fn disk_tier_evicts_within_budget_and_survives_reopen() {
+        let dir = temp_dir("disk");
+        // 每个文件 16 字节文件头 + 8 字节哈希 + 100 字节状态，磁盘上限容纳两个
+        let disk = Some((dir.as_path(), 300));
+        let cache = PrefixCache::<Blob>::open(5, config(0, disk)).unwrap();
+        cache.insert(&[1, 1, 1, 1], blob(1, 100));
+        cache.insert(&[2, 2, 2, 2], blob(2, 100));
+        cache.insert(&[3, 3, 3, 3], blob(3, 100));
+        assert_eq!(cache.stats().disk_bytes, 248);
+        let namespace_dir = dir.join(format!("{:016x}", 5));
+        assert_eq!(std::fs::read_dir(&namespace_dir).unwrap().count(), 2);
+
+        assert!(cache.lookup(&[1, 1, 1, 1, 0]).is_none());
+        let (_, state) = cache.lookup(&[2, 2, 2, 2, 0]).unwrap();
+        assert_eq!(*state, blob(2, 100));
+        let stats = cache.stats();
+        assert_eq!((stats.disk_hits, stats.memory_hits, stats.memory_bytes), (1, 0, 0));
+        drop(cache);
+
+        // 重新打开后从磁盘恢复索引；内存层有空间时磁盘命中的状态载入内存
+        let cache = PrefixCache::<Blob>::open(5, config(1024, disk)).unwrap();
+        assert_eq!(cache.stats().entries, 2);
+        assert_eq!(*cache.lookup(&[3, 3, 3, 3, 0]).unwrap().1, blob(3, 100));
+        assert_eq!(*cache.lookup(&[3, 3, 3, 3, 0]).unwrap().1, blob(3, 100));
+        let stats = cache.stats();
+        assert_eq!((stats.disk_hits, stats.memory_hits, stats.memory_bytes), (1, 1, 100));
+        drop(cache);
+
+        // 块大小改变后旧文件无法使用，打开时删除
+        let cache = PrefixCache::<Blob>::open(5, PrefixCacheConfig { block_tokens: 8, ..config(0, disk) }).unwrap();
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(std::fs::read_dir(&namespace_dir).unwrap().count(), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rejects_hash_count_beyond_file_length() {
+        let dir = std::env::temp_dir().join(format!("silo-prefix-cache-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("corrupt.kv");
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(FILE_MAGIC);
+        bytes.extend_from_slice(&FILE_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&64u32.to_le_bytes());
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&1u64.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+
+        let err = read_header(&path, 64).unwrap_err();
+        assert!(err.to_string().contains("Truncated"), "{}", err);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,233 @@
+// 多模型驻留 - 按模型 ID 同时保留多个已加载的本地模型，受内存预算约束
+// 超出预算时按最近最少使用（LRU）顺序卸载模型
+
+use crate::engine::backend::InferenceBackend;
+use crate::engine::BackendType;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::RwLock;
+
+/// 未配置时的默认驻留预算
+pub const DEFAULT_MEMORY_BUDGET_BYTES: u64 = 8 * 1024 * 1024 * 1024;
+
+/// 已加载模型共享的后端实例；被卸载时正在进行的请求仍持有引用，结束后才释放内存
+pub type SharedBackend = Arc<RwLock<Box<dyn InferenceBackend>>>;
+
+struct ResidentModel {
+    path: PathBuf,
+    backend_type: BackendType,
+    backend: SharedBackend,
+    size_bytes: u64,
+    context_size: usize,
+    loaded_at: Instant,
+    last_used: Instant,
+}
+
+/// 驻留模型的状态
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResidentModelInfo {
+    pub id: String,
+    pub path: PathBuf,
+    pub backend: BackendType,
+    pub size_bytes: u64,
+    pub loaded_secs_ago: u64,
+    pub idle_secs: u64,
+}
+
+pub struct ModelPool {
+    models: HashMap<String, ResidentModel>,
+    budget_bytes: u64,
+}
+
+impl ModelPool {
+    pub fn new(budget_bytes: u64) -> Self {
+        Self {
+            models: HashMap::new(),
+            budget_bytes,
+        }
+    }
+
+    pub fn budget_bytes(&self) -> u64 {
+        self.budget_bytes
+    }
+
+    /// 调整预算；返回因此被卸载的模型 ID
+    pub fn set_budget(&mut self, budget_bytes: u64) -> Vec<String> {
+        self.budget_bytes = budget_bytes;
+        self.evict_to_fit(0)
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        self.models.values().map(|m| m.size_bytes).sum()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.models.contains_key(id)
+    }
+
+    /// 取出模型并更新最近使用时间
+    pub fn get(&mut self, id: &str) -> Option<(BackendType, SharedBackend)> {
+        let model = self.models.get_mut(id)?;
+        model.last_used = Instant::now();
+        Some((model.backend_type.clone(), model.backend.clone()))
+    }
+
+    /// 所有驻留模型的后端（不更新使用时间）
+    pub fn backends(&self) -> Vec<(String, SharedBackend)> {
+        self.models.iter().map(|(id, m)| (id.clone(), m.backend.clone())).collect()
+    }
+
+    /// 为即将加载的模型腾出空间，返回被卸载的模型 ID；模型本身超出预算时报错
+    pub fn reserve(&mut self, id: &str, size_bytes: u64) -> anyhow::Result<Vec<String>> {
+        if size_bytes > self.budget_bytes {
+            anyhow::bail!(
+                "Model '{}' needs {} MiB, which exceeds the model memory budget of {} MiB",
+                id,
+                size_bytes / (1024 * 1024),
+                self.budget_bytes / (1024 * 1024)
+            );
+        }
+        // 重新加载同一模型时先释放旧实例
+        let mut evicted: Vec<String> = self.models.remove(id).map(|_| id.to_string()).into_iter().collect();
+        evicted.extend(self.evict_to_fit(size_bytes));
+        Ok(evicted)
+    }
+
+    /// 模型的上下文长度
+    pub fn context_size(&self, id: &str) -> Option<usize> {
+        self.models.get(id).map(|m| m.context_size)
+    }
+
+    pub fn insert(
+        &mut self,
+        id: impl Into<String>,
+        path: PathBuf,
+        size_bytes: u64,
+        context_size: usize,
+        backend: Box<dyn InferenceBackend>,
+    ) {
+        let now = Instant::now();
+        self.models.insert(
+            id.into(),
+            ResidentModel {
+                path,
+                backend_type: backend.backend_type(),
+                backend: Arc::new(RwLock::new(backend)),
+                size_bytes,
+                context_size,
+                loaded_at: now,
+                last_used: now,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, id: &str) -> bool {
+        self.models.remove(id).is_some()
+    }
+
+    /// 按最近使用时间排序（最近的在前）
+    pub fn list(&self) -> Vec<ResidentModelInfo> {
+        let mut models: Vec<_> = self.models.iter().collect();
+        models.sort_by_key(|(id, m)| (std::cmp::Reverse(m.last_used), id.as_str()));
+        models
+            .into_iter()
+            .map(|(id, m)| ResidentModelInfo {
+                id: id.clone(),
+                path: m.path.clone(),
+                backend: m.backend_type.clone(),
+                size_bytes: m.size_bytes,
+                loaded_secs_ago: m.loaded_at.elapsed().as_secs(),
+                idle_secs: m.last_used.elapsed().as_secs(),
+            })
+            .collect()
+    }
+
+    /// 卸载最久未使用的模型，直到再放入 incoming 字节仍不超出预算
+    fn evict_to_fit(&mut self, incoming: u64) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while self.used_bytes() + incoming > self.budget_bytes {
+            let Some(lru) = self
+                .models
+                .iter()
+                .min_by_key(|(_, m)| m.last_used)
+                .map(|(id, _)| id.clone())
+            else {
+                break;
+            };
+            self.models.remove(&lru);
+            evicted.push(lru);
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::scripted::ScriptedBackend;
+    use std::time::Duration;
+
+    fn insert(pool: &mut ModelPool, id: &str, size_bytes: u64) {
+        pool.insert(id, PathBuf::from(format!("{}.gguf", id)), size_bytes, 2048, Box::new(ScriptedBackend::new(vec![])));
+    }
+
+    /// 把最近使用时间设为 secs_ago 秒之前，不依赖两次 Instant::now 之间的间隔
+    fn used_ago(pool: &mut ModelPool, id: &str, secs_ago: u64) {
+        pool.models.get_mut(id).unwrap().last_used = Instant::now() - Duration::from_secs(secs_ago);
+    }
+
+    fn ids(pool: &ModelPool) -> Vec<String> {
+        pool.list().into_iter().map(|m| m.id).collect()
+    }
+
+    #[test]
+    fn evicts_least_recently_used_to_fit_budget() {
+        let mut pool = ModelPool::new(100);
+        insert(&mut pool, "a", 40);
+        insert(&mut pool, "b", 40);
+        used_ago(&mut pool, "a", 20);
+        used_ago(&mut pool, "b", 10);
+        assert_eq!(ids(&pool), ["b", "a"]);
+
+        // 使用过的模型变为最近使用
+        assert!(pool.get("a").is_some());
+        assert_eq!(ids(&pool), ["a", "b"]);
+        assert_eq!(pool.reserve("c", 40).unwrap(), ["b"]);
+        insert(&mut pool, "c", 40);
+        used_ago(&mut pool, "a", 5);
+        assert_eq!(pool.used_bytes(), 80);
+
+        // 只卸载到刚好放得下为止
+        assert_eq!(pool.reserve("d", 60).unwrap(), ["a"]);
+        assert!(pool.contains("c"));
+        assert_eq!(pool.context_size("c"), Some(2048));
+        assert!(pool.reserve("d", 20).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reserve_rejects_oversized_model_and_replaces_same_id() {
+        let mut pool = ModelPool::new(100);
+        insert(&mut pool, "a", 50);
+        insert(&mut pool, "b", 50);
+        used_ago(&mut pool, "a", 10);
+
+        // 超出预算的模型不卸载任何模型
+        let err = pool.reserve("huge", 101).unwrap_err();
+        assert!(err.to_string().contains("exceeds the model memory budget"), "{}", err);
+        assert_eq!(pool.used_bytes(), 100);
+
+        // 重新加载同一模型时先释放旧实例，不必卸载其他模型
+        assert_eq!(pool.reserve("b", 50).unwrap(), ["b"]);
+        assert_eq!(ids(&pool), ["a"]);
+
+        // 缩小预算时按 LRU 卸载
+        insert(&mut pool, "b", 50);
+        assert_eq!(pool.set_budget(60), ["a"]);
+        assert_eq!(pool.set_budget(10), ["b"]);
+        assert_eq!(pool.used_bytes(), 0);
+        assert!(!pool.remove("b"));
+    }
+}
@@ -0,0 +1,445 @@
+// 推理请求调度 - 按优先级排队，限制并发
+// 交互式对话 > Agent 工具步骤 > 后台任务（索引、摘要）；同一优先级内先到先得，
+// 低优先级请求等待过久时提前调度，避免被持续的高优先级请求饿死
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+/// 请求的优先级
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    /// 用户正在等待的对话
+    #[default]
+    Interactive,
+    /// Agent 规划与工具调用
+    Agent,
+    /// 后台索引、摘要等批量任务
+    Background,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::Interactive, Priority::Agent, Priority::Background];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// 调度配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchedulerConfig {
+    /// 同时执行的请求总数上限
+    pub max_concurrent: usize,
+    /// 各优先级同时执行的请求数上限，按 Priority::ALL 的顺序
+    pub class_limits: [usize; 3],
+    /// 低优先级请求等待超过该时长后，优先于高优先级请求调度
+    pub starvation_timeout: Duration,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        // Agent 与后台请求合计最多占 3 个名额，总是给交互式对话留出一个
+        Self {
+            max_concurrent: 4,
+            class_limits: [4, 2, 1],
+            starvation_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// 某个优先级的队列统计
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueStats {
+    pub priority: Priority,
+    /// 当前排队数
+    pub queued: usize,
+    /// 当前执行数
+    pub running: usize,
+    pub limit: usize,
+    /// 历史最大排队数
+    pub max_queue_depth: usize,
+    /// 已调度的请求数
+    pub dispatched: u64,
+    pub avg_wait_ms: f64,
+    pub max_wait_ms: f64,
+}
+
+struct Waiter {
+    id: u64,
+    enqueued: Instant,
+    tx: oneshot::Sender<()>,
+}
+
+#[derive(Default)]
+struct ClassState {
+    queue: VecDeque<Waiter>,
+    running: usize,
+    max_queue_depth: usize,
+    dispatched: u64,
+    total_wait: Duration,
+    max_wait: Duration,
+}
+
+struct State {
+    config: SchedulerConfig,
+    classes: [ClassState; 3],
+    next_id: u64,
+}
+
+impl State {
+    fn running(&self) -> usize {
+        self.classes.iter().map(|c| c.running).sum()
+    }
+
+    fn has_capacity(&self, priority: Priority) -> bool {
+        self.running() < self.config.max_concurrent
+            && self.classes[priority.index()].running < self.config.class_limits[priority.index()]
+    }
+
+    fn start(&mut self, priority: Priority, waited: Duration) {
+        let class = &mut self.classes[priority.index()];
+        class.running += 1;
+        class.dispatched += 1;
+        class.total_wait += waited;
+        class.max_wait = class.max_wait.max(waited);
+    }
+
+    /// 选出下一个可以执行的排队请求：先看等待超时的低优先级请求，再按优先级
+    fn next_class(&self) -> Option<Priority> {
+        let now = Instant::now();
+        let starved = Priority::ALL.into_iter().rev().find(|&p| {
+            self.classes[p.index()]
+                .queue
+                .front()
+                .is_some_and(|w| now.duration_since(w.enqueued) >= self.config.starvation_timeout)
+                && self.has_capacity(p)
+        });
+        starved.or_else(|| {
+            Priority::ALL
+                .into_iter()
+                .find(|&p| !self.classes[p.index()].queue.is_empty() && self.has_capacity(p))
+        })
+    }
+
+    fn dispatch(&mut self) {
+        while let Some(priority) = self.next_class() {
+            let Some(waiter) = self.classes[priority.index()].queue.pop_front() else { break };
+            self.start(priority, waiter.enqueued.elapsed());
+            // 接收端已丢弃时由其 QueuedGuard 归还名额
+            let _ = waiter.tx.send(());
+        }
+    }
+
+    fn release(&mut self, priority: Priority) {
+        let class = &mut self.classes[priority.index()];
+        class.running = class.running.saturating_sub(1);
+        self.dispatch();
+    }
+}
+
+/// 推理请求调度器；克隆后共享同一组队列
+#[derive(Clone)]
+pub struct Scheduler {
+    state: Arc<Mutex<State>>,
+}
+
+impl Scheduler {
+    pub fn new(config: SchedulerConfig) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                config,
+                classes: Default::default(),
+                next_id: 0,
+            })),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 修改配置；放宽限制后立即调度排队中的请求
+    pub fn set_config(&self, config: SchedulerConfig) {
+        let mut state = self.lock();
+        state.config = config;
+        state.dispatch();
+    }
+
+    /// 等待执行名额；返回的 SchedulerPermit 被丢弃时归还名额
+    pub async fn acquire(&self, priority: Priority) -> SchedulerPermit {
+        let (id, rx) = {
+            let mut state = self.lock();
+            // 同级或更高优先级有人排队时不插队
+            let queued_ahead = Priority::ALL[..=priority.index()]
+                .iter()
+                .any(|p| !state.classes[p.index()].queue.is_empty());
+            if !queued_ahead && state.has_capacity(priority) {
+                state.start(priority, Duration::ZERO);
+                return SchedulerPermit {
+                    state: self.state.clone(),
+                    priority,
+                };
+            }
+
+            let id = state.next_id;
+            state.next_id += 1;
+            let (tx, rx) = oneshot::channel();
+            let class = &mut state.classes[priority.index()];
+            class.queue.push_back(Waiter {
+                id,
+                enqueued: Instant::now(),
+                tx,
+            });
+            class.max_queue_depth = class.max_queue_depth.max(class.queue.len());
+            (id, rx)
+        };
+
+        let mut guard = QueuedGuard {
+            state: self.state.clone(),
+            priority,
+            id,
+            granted: false,
+        };
+        let _ = rx.await;
+        guard.granted = true;
+        SchedulerPermit {
+            state: self.state.clone(),
+            priority,
+        }
+    }
+
+    /// 等待执行名额，期间 cancel 被触发时返回 None
+    pub async fn acquire_or_cancel(&self, priority: Priority, cancel: &CancellationToken) -> Option<SchedulerPermit> {
+        tokio::select! {
+            permit = self.acquire(priority) => Some(permit),
+            _ = cancel.cancelled() => None,
+        }
+    }
+
+    /// 各优先级的排队与执行情况
+    pub fn stats(&self) -> Vec<QueueStats> {
+        let state = self.lock();
+        Priority::ALL
+            .into_iter()
+            .map(|p| {
+                let class = &state.classes[p.index()];
+                QueueStats {
+                    priority: p,
+                    queued: class.queue.len(),
+                    running: class.running,
+                    limit: state.config.class_limits[p.index()],
+                    max_queue_depth: class.max_queue_depth,
+                    dispatched: class.dispatched,
+                    avg_wait_ms: if class.dispatched > 0 {
+                        class.total_wait.as_secs_f64() * 1000.0 / class.dispatched as f64
+                    } else {
+                        0.0
+                    },
+                    max_wait_ms: class.max_wait.as_secs_f64() * 1000.0,
+                }
+            })
+            .collect()
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(SchedulerConfig::default())
+    }
+}
+
+/// 排队中的请求被取消（future 被丢弃）时移出队列；已获得名额但未来得及返回时归还名额
+struct QueuedGuard {
+    state: Arc<Mutex<State>>,
+    priority: Priority,
+    id: u64,
+    granted: bool,
+}
+
+impl Drop for QueuedGuard {
+    fn drop(&mut self) {
+        if self.granted {
+            return;
+        }
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let queue = &mut state.classes[self.priority.index()].queue;
+        match queue.iter().position(|w| w.id == self.id) {
+            Some(pos) => {
+                queue.remove(pos);
+            }
+            None => state.release(self.priority),
+        }
+    }
+}
+
+/// 执行名额，丢弃时归还并调度下一个排队的请求
+pub struct SchedulerPermit {
+    state: Arc<Mutex<State>>,
+    priority: Priority,
+}
+
+impl Drop for SchedulerPermit {
+    fn drop(&mut self) {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).release(self.priority);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 在后台等待名额，拿到后把标签记入 order 并持有名额直到 release 被触发
+    fn spawn_request(
+        scheduler: &Scheduler,
+        priority: Priority,
+        label: &'static str,
+        order: &Arc<Mutex<Vec<&'static str>>>,
+        release: &CancellationToken,
+    ) -> tokio::task::JoinHandle<()> {
+        let (scheduler, order, release) = (scheduler.clone(), order.clone(), release.clone());
+        tokio::spawn(async move {
+            let _permit = scheduler.acquire(priority).await;
+            order.lock().unwrap().push(label);
+            release.cancelled().await;
+        })
+    }
+
+    fn counts(scheduler: &Scheduler) -> Vec<(usize, usize)> {
+        scheduler.stats().iter().map(|s| (s.running, s.queued)).collect()
+    }
+
+    #[tokio::test]
+    async fn higher_priority_runs_first() {
+        let scheduler = Scheduler::new(SchedulerConfig {
+            max_concurrent: 1,
+            ..SchedulerConfig::default()
+        });
+        let busy = scheduler.acquire(Priority::Interactive).await;
+        let order = Arc::new(Mutex::new(Vec::new()));
+        // 拿到名额后立即归还
+        let done = CancellationToken::new();
+        done.cancel();
+        let mut tasks = Vec::new();
+        for (priority, label) in [
+            (Priority::Background, "background"),
+            (Priority::Agent, "agent"),
+            (Priority::Interactive, "interactive"),
+        ] {
+            tasks.push(spawn_request(&scheduler, priority, label, &order, &done));
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(counts(&scheduler), [(1, 1), (0, 1), (0, 1)]);
+
+        drop(busy);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), ["interactive", "agent", "background"]);
+        assert!(scheduler.stats().iter().all(|s| s.running == 0 && s.dispatched >= 1));
+    }
+
+    #[tokio::test]
+    async fn class_limits_cap_concurrency() {
+        let scheduler = Scheduler::default();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let release = CancellationToken::new();
+        let mut tasks = Vec::new();
+        for _ in 0..3 {
+            tasks.push(spawn_request(&scheduler, Priority::Agent, "agent", &order, &release));
+            tasks.push(spawn_request(&scheduler, Priority::Background, "background", &order, &release));
+        }
+        tokio::task::yield_now().await;
+        // Agent 最多 2 个，后台最多 1 个
+        assert_eq!(counts(&scheduler), [(0, 0), (2, 1), (1, 2)]);
+
+        // 剩下的 1 个名额留给交互式对话
+        tasks.push(spawn_request(&scheduler, Priority::Interactive, "interactive", &order, &release));
+        tasks.push(spawn_request(&scheduler, Priority::Interactive, "interactive", &order, &release));
+        tokio::task::yield_now().await;
+        assert_eq!(counts(&scheduler), [(1, 1), (2, 1), (1, 2)]);
+
+        release.cancel();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let stats = scheduler.stats();
+        assert_eq!(stats.iter().map(|s| s.dispatched).collect::<Vec<_>>(), [2, 3, 3]);
+        assert_eq!(stats.iter().map(|s| s.max_queue_depth).collect::<Vec<_>>(), [1, 1, 2]);
+        assert_eq!(stats.iter().map(|s| s.limit).collect::<Vec<_>>(), [4, 2, 1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn starved_request_is_promoted() {
+        let scheduler = Scheduler::new(SchedulerConfig {
+            max_concurrent: 1,
+            ..SchedulerConfig::default()
+        });
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let done = CancellationToken::new();
+        done.cancel();
+        let busy = scheduler.acquire(Priority::Interactive).await;
+        let background = spawn_request(&scheduler, Priority::Background, "background", &order, &done);
+        tokio::task::yield_now().await;
+
+        // 等待未超时：交互式请求优先
+        tokio::time::advance(Duration::from_secs(9)).await;
+        let interactive = spawn_request(&scheduler, Priority::Interactive, "interactive", &order, &done);
+        tokio::task::yield_now().await;
+        drop(busy);
+        interactive.await.unwrap();
+        background.await.unwrap();
+        assert_eq!(*order.lock().unwrap(), ["interactive", "background"]);
+
+        // 等待超过 starvation_timeout 后，后台请求先于新到的交互式请求
+        order.lock().unwrap().clear();
+        let busy = scheduler.acquire(Priority::Interactive).await;
+        let background = spawn_request(&scheduler, Priority::Background, "background", &order, &done);
+        tokio::task::yield_now().await;
+        tokio::time::advance(Duration::from_secs(10)).await;
+        let interactive = spawn_request(&scheduler, Priority::Interactive, "interactive", &order, &done);
+        tokio::task::yield_now().await;
+        drop(busy);
+        background.await.unwrap();
+        interactive.await.unwrap();
+        assert_eq!(*order.lock().unwrap(), ["background", "interactive"]);
+        assert!(scheduler.stats()[2].max_wait_ms >= 10_000.0);
+    }
+
+    #[tokio::test]
+    async fn cancelled_waiter_leaves_queue_and_frees_permit() {
+        let scheduler = Scheduler::new(SchedulerConfig {
+            max_concurrent: 1,
+            ..SchedulerConfig::default()
+        });
+        let busy = scheduler.acquire(Priority::Interactive).await;
+
+        // 排队中取消：移出队列
+        let cancel = CancellationToken::new();
+        let waiting = {
+            let (scheduler, cancel) = (scheduler.clone(), cancel.clone());
+            tokio::spawn(async move { scheduler.acquire_or_cancel(Priority::Agent, &cancel).await.is_some() })
+        };
+        tokio::task::yield_now().await;
+        assert_eq!(scheduler.stats()[1].queued, 1);
+        cancel.cancel();
+        assert!(!waiting.await.unwrap());
+        assert_eq!(scheduler.stats()[1].queued, 0);
+
+        // 已被调度但 future 在返回前被丢弃：名额归还给调度器
+        let mut acquire = Box::pin(scheduler.acquire(Priority::Agent));
+        assert!(futures::poll!(acquire.as_mut()).is_pending());
+        drop(busy);
+        assert_eq!(counts(&scheduler)[1], (1, 0));
+        drop(acquire);
+        assert_eq!(counts(&scheduler), [(0, 0), (0, 0), (0, 0)]);
+
+        let _permit = scheduler.acquire(Priority::Background).await;
+        assert_eq!(scheduler.stats()[2].running, 1);
+    }
+}
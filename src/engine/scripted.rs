@@ -0,0 +1,421 @@
+// 脚本化推理后端 - 按提示词匹配预先录制的回复并逐 token 回放，输出完全可复现
+// 用于 Agent 与 UI 流程的集成测试，以及未加载模型时的演示模式；
+// RecordingBackend 包装真实后端，把对话录制为同样格式的脚本文件
+
+use crate::engine::backend::InferenceBackend;
+use crate::engine::{
+    last_user_message, BackendType, ChatMessage, FinishReason, GenerationParams, InferenceConfig, InferenceResponse,
+    InferenceStream, MetricsTimer, TokenUsage,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// 未加载模型时使用的演示脚本
+const DEMO_SCRIPT: &str = include_str!("../../fixtures/demo.json");
+
+/// 回复文本中的占位符，回放时替换为最后一条用户消息
+const PROMPT_PLACEHOLDER: &str = "{prompt}";
+
+/// 提示词匹配条件，作用于最后一条用户消息
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptMatch {
+    /// 任意提示词（放在脚本末尾作为兜底）
+    Any,
+    /// 去掉首尾空白后完全相同（录制时使用）
+    Exact(String),
+    /// 包含子串
+    Contains(String),
+    /// 正则表达式
+    Regex(String),
+    /// 全部条件都满足
+    AllOf(Vec<PromptMatch>),
+    /// 任一条件满足
+    AnyOf(Vec<PromptMatch>),
+}
+
+impl PromptMatch {
+    pub fn matches(&self, prompt: &str) -> bool {
+        match self {
+            PromptMatch::Any => true,
+            PromptMatch::Exact(text) => prompt.trim() == text.trim(),
+            PromptMatch::Contains(text) => prompt.contains(text.as_str()),
+            // 加载脚本时已校验过正则
+            PromptMatch::Regex(pattern) => fancy_regex::Regex::new(pattern)
+                .ok()
+                .is_some_and(|re| re.is_match(prompt).unwrap_or(false)),
+            PromptMatch::AllOf(all) => all.iter().all(|m| m.matches(prompt)),
+            PromptMatch::AnyOf(any) => any.iter().any(|m| m.matches(prompt)),
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self {
+            PromptMatch::Regex(pattern) => {
+                fancy_regex::Regex::new(pattern).map_err(|e| anyhow::anyhow!("Invalid regex '{}': {}", pattern, e))?;
+            }
+            PromptMatch::AllOf(list) | PromptMatch::AnyOf(list) => list.iter().try_for_each(PromptMatch::validate)?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// 脚本中的回复：整段文本（回放时切分为 token），或录制得到的 token 序列
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ScriptResponse {
+    Text(String),
+    Tokens(Vec<String>),
+}
+
+impl ScriptResponse {
+    fn tokens(&self, prompt: &str) -> Vec<String> {
+        match self {
+            ScriptResponse::Text(text) => split_tokens(&text.replace(PROMPT_PLACEHOLDER, prompt)),
+            ScriptResponse::Tokens(tokens) => tokens.iter().map(|t| t.replace(PROMPT_PLACEHOLDER, prompt)).collect(),
+        }
+    }
+}
+
+/// 一条脚本：匹配条件与回复
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScriptEntry {
+    #[serde(rename = "match")]
+    pub matcher: PromptMatch,
+    pub response: ScriptResponse,
+    #[serde(default = "default_finish_reason")]
+    pub finish_reason: FinishReason,
+    /// 录制时后端上报的 token 用量
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+}
+
+fn default_finish_reason() -> FinishReason {
+    FinishReason::Stop
+}
+
+/// 把整段文本切分为 token：英文按单词（带后随空白），中日韩文字等非 ASCII 字符逐字
+fn split_tokens(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_space = false;
+    for c in text.chars() {
+        if in_space && !c.is_whitespace() {
+            tokens.push(std::mem::take(&mut current));
+            in_space = false;
+        }
+        if !c.is_ascii() && !c.is_whitespace() {
+            if !current.is_empty() {
+                tokens.push(std::mem::take(&mut current));
+            }
+            tokens.push(c.to_string());
+            continue;
+        }
+        current.push(c);
+        in_space = c.is_whitespace();
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+/// 回放速度
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplayLatency {
+    /// 首个 token 前的延迟
+    pub first_token: Duration,
+    /// 之后每个 token 之间的延迟
+    pub per_token: Duration,
+}
+
+/// 读取脚本：单个 JSON 文件，或目录下按文件名排序的所有 .json 文件
+pub fn load_script(path: &Path) -> Result<Vec<ScriptEntry>> {
+    let files = if path.is_dir() {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(path)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        files.sort();
+        files
+    } else {
+        vec![path.to_path_buf()]
+    };
+    let mut entries = Vec::new();
+    for file in files {
+        let text = std::fs::read_to_string(&file)
+            .map_err(|e| anyhow::anyhow!("Failed to read script {:?}: {}", file, e))?;
+        entries.extend(parse_script(&text).map_err(|e| anyhow::anyhow!("Invalid script {:?}: {}", file, e))?);
+    }
+    Ok(entries)
+}
+
+fn parse_script(text: &str) -> Result<Vec<ScriptEntry>> {
+    let entries: Vec<ScriptEntry> = serde_json::from_str(text)?;
+    for entry in &entries {
+        entry.matcher.validate()?;
+    }
+    Ok(entries)
+}
+
+/// 按脚本回放的推理后端；按顺序取第一条匹配的脚本
+pub struct ScriptedBackend {
+    entries: Vec<ScriptEntry>,
+    latency: ReplayLatency,
+}
+
+impl ScriptedBackend {
+    pub fn new(entries: Vec<ScriptEntry>) -> Self {
+        Self {
+            entries,
+            latency: ReplayLatency::default(),
+        }
+    }
+
+    /// 从脚本文件或目录加载
+    pub fn load(path: &Path) -> Result<Self> {
+        let entries = load_script(path)?;
+        tracing::info!("Loaded {} scripted responses from {:?}", entries.len(), path);
+        Ok(Self::new(entries))
+    }
+
+    /// 内置演示脚本，流式输出时模拟生成速度
+    pub fn demo() -> Self {
+        let entries = parse_script(DEMO_SCRIPT).expect("built-in demo script is valid");
+        Self::new(entries).with_latency(ReplayLatency {
+            first_token: Duration::ZERO,
+            per_token: Duration::from_millis(50),
+        })
+    }
+
+    /// 流式回放的速度（非流式请求立即返回）
+    pub fn with_latency(mut self, latency: ReplayLatency) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// 匹配最后一条用户消息，返回回复 token、结束原因与用量
+    fn reply(&self, messages: &[ChatMessage]) -> Result<(Vec<String>, FinishReason, Option<TokenUsage>)> {
+        let prompt = last_user_message(messages);
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.matcher.matches(prompt))
+            .ok_or_else(|| anyhow::anyhow!("No scripted response matches prompt: {:?}", prompt))?;
+        Ok((entry.response.tokens(prompt.trim()), entry.finish_reason, entry.usage))
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for ScriptedBackend {
+    async fn initialize(&mut self, _config: InferenceConfig) -> Result<()> {
+        Ok(())
+    }
+
+    async fn chat(&self, messages: &[ChatMessage], _params: &GenerationParams) -> Result<InferenceResponse> {
+        let timer = MetricsTimer::start();
+        let (tokens, finish_reason, usage) = self.reply(messages)?;
+        let completion_tokens = usage.map_or(tokens.len(), |u| u.completion_tokens);
+        Ok(InferenceResponse {
+            metrics: timer.finish(usage.map(|u| u.prompt_tokens), completion_tokens),
+            tokens,
+            finish_reason,
+        })
+    }
+
+    async fn chat_stream(
+        &self,
+        messages: &[ChatMessage],
+        _params: &GenerationParams,
+        cancel: CancellationToken,
+    ) -> Result<InferenceStream> {
+        let (tokens, finish_reason, usage) = self.reply(messages)?;
+        let (mut sender, stream) = InferenceStream::channel(cancel);
+        if let Some(usage) = usage {
+            sender.set_usage(usage);
+        }
+        let latency = self.latency;
+        tokio::spawn(async move {
+            for (i, token) in tokens.into_iter().enumerate() {
+                let delay = if i == 0 { latency.first_token } else { latency.per_token };
+                if !delay.is_zero() {
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        _ = sender.cancelled() => break,
+                    }
+                }
+                if !sender.send(token).await {
+                    break;
+                }
+            }
+            sender.finish(finish_reason).await;
+        });
+        Ok(stream)
+    }
+
+    fn backend_type(&self) -> BackendType {
+        BackendType::Scripted
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+}
+
+/// 把对话追加到脚本文件；同一提示词只保留最近一次的回复
+pub struct Recorder {
+    path: PathBuf,
+    entries: Mutex<Vec<ScriptEntry>>,
+}
+
+impl Recorder {
+    /// 打开脚本文件，已存在时在其基础上追加
+    pub fn open(path: impl Into<PathBuf>) -> Result<Arc<Self>> {
+        let path = path.into();
+        let entries = if path.exists() { load_script(&path)? } else { vec![] };
+        Ok(Arc::new(Self {
+            path,
+            entries: Mutex::new(entries),
+        }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn record(&self, messages: &[ChatMessage], response: &InferenceResponse) {
+        let prompt = last_user_message(messages).trim().to_string();
+        let entry = ScriptEntry {
+            matcher: PromptMatch::Exact(prompt.clone()),
+            response: ScriptResponse::Tokens(response.tokens.clone()),
+            finish_reason: response.finish_reason,
+            usage: response.metrics.prompt_tokens.map(|prompt_tokens| TokenUsage {
+                prompt_tokens,
+                completion_tokens: response.metrics.completion_tokens,
+            }),
+        };
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.retain(|e| e.matcher != entry.matcher);
+        entries.push(entry);
+        let result = serde_json::to_string_pretty(&*entries)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| {
+                if let Some(dir) = self.path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                Ok(std::fs::write(&self.path, json)?)
+            });
+        if let Err(e) = result {
+            tracing::warn!("Failed to write recorded session to {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// 录制被包装后端的对话；取消或出错的请求不录制
+pub struct RecordingBackend {
+    inner: Box<dyn InferenceBackend>,
+    recorder: Arc<Recorder>,
+}
+
+impl RecordingBackend {
+    pub fn new(inner: Box<dyn InferenceBackend>, recorder: Arc<Recorder>) -> Self {
+        Self { inner, recorder }
+    }
+}
+
+fn recordable(reason: FinishReason) -> bool {
+    !matches!(reason, FinishReason::Cancelled | FinishReason::Error)
+}
+
+#[async_trait]
+impl InferenceBackend for RecordingBackend {
+    async fn initialize(&mut self, config: InferenceConfig) -> Result<()> {
+        self.inner.initialize(config).await
+    }
+
+    async fn chat(&self, messages: &[ChatMessage], params: &GenerationParams) -> Result<InferenceResponse> {
+        let response = self.inner.chat(messages, params).await?;
+        if recordable(response.finish_reason) {
+            self.recorder.record(messages, &response);
+        }
+        Ok(response)
+    }
+
+    /// 转发被包装后端的流，结束后录制完整输出
+    async fn chat_stream(
+        &self,
+        messages: &[ChatMessage],
+        params: &GenerationParams,
+        cancel: CancellationToken,
+    ) -> Result<InferenceStream> {
+        let mut inner = self.inner.chat_stream(messages, params, cancel.clone()).await?;
+        let (mut sender, stream) = InferenceStream::channel(cancel);
+        let (recorder, messages) = (self.recorder.clone(), messages.to_vec());
+        tokio::spawn(async move {
+            let mut tokens = Vec::new();
+            while let Some(token) = inner.next_token().await {
+                if !sender.send(token.clone()).await {
+                    // 丢弃 inner 会停止被包装后端的生成
+                    return;
+                }
+                tokens.push(token);
+            }
+            let reason = inner.finish_reason().unwrap_or(FinishReason::Error);
+            let metrics = inner.metrics().cloned().unwrap_or_default();
+            if let Some(prompt_tokens) = metrics.prompt_tokens {
+                sender.set_usage(TokenUsage {
+                    prompt_tokens,
+                    completion_tokens: metrics.completion_tokens,
+                });
+            }
+            if recordable(reason) {
+                let response = InferenceResponse {
+                    tokens,
+                    finish_reason: reason,
+                    metrics,
+                };
+                recorder.record(&messages, &response);
+            }
+            sender.finish(reason).await;
+        });
+        Ok(stream)
+    }
+
+    fn backend_type(&self) -> BackendType {
+        self.inner.backend_type()
+    }
+
+    fn is_available(&self) -> bool {
+        self.inner.is_available()
+    }
+
+    fn supports_constraint(&self, constraint: &crate::engine::OutputConstraint) -> bool {
+        self.inner.supports_constraint(constraint)
+    }
+
+    fn embedding_model(&self) -> Option<String> {
+        self.inner.embedding_model()
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        self.inner.embed(texts).await
+    }
+
+    async fn health_check(&self) -> bool {
+        self.inner.health_check().await
+    }
+
+    async fn list_models(&self) -> Result<Vec<crate::engine::ModelDescriptor>> {
+        self.inner.list_models().await
+    }
+
+    fn select_model(&mut self, model: &str) -> Result<()> {
+        self.inner.select_model(model)
+    }
+}
@@ -0,0 +1,520 @@
+// 侧车进程监管 - 启动外部推理进程（如 Python MLX 服务），通过 Unix Domain Socket 通信
+// 协议：每帧为 4 字节大端长度 + JSON；连接后先握手（hello → ready），请求与响应以 id 关联
+// 侧车崩溃或断开时，进行中的请求收到错误帧，按指数退避重启并重放初始化帧；最后一个 Sidecar 句柄被丢弃时关闭进程
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, watch};
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+/// 协议版本，握手时发送给侧车
+pub const PROTOCOL_VERSION: u32 = 1;
+/// 单帧上限，防止错误数据导致分配过大内存
+const MAX_FRAME_BYTES: usize = 16 * 1024 * 1024;
+/// 稳定运行超过该时长后重置重启计数
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct SidecarConfig {
+    /// 日志中使用的名称
+    pub name: String,
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    /// 等待侧车监听 socket 并完成握手的时长（含加载 Python 依赖）
+    pub ready_timeout: Duration,
+    /// 连续重启上限，超过后停止重启
+    pub max_restarts: u32,
+    /// 首次重启前的等待时长，之后每次翻倍
+    pub restart_backoff: Duration,
+}
+
+impl SidecarConfig {
+    pub fn new(name: impl Into<String>, program: impl Into<PathBuf>) -> Self {
+        Self {
+            name: name.into(),
+            program: program.into(),
+            args: vec![],
+            env: vec![],
+            ready_timeout: Duration::from_secs(30),
+            max_restarts: 5,
+            restart_backoff: Duration::from_millis(500),
+        }
+    }
+
+    pub fn with_args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SidecarState {
+    Starting,
+    Ready,
+    /// 崩溃后等待重启
+    Restarting,
+    /// 无法启动或超过重启上限
+    Failed,
+    Stopped,
+}
+
+type Pending = Arc<Mutex<HashMap<u64, mpsc::UnboundedSender<Value>>>>;
+type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+struct Shared {
+    config: SidecarConfig,
+    socket_path: PathBuf,
+    writer: tokio::sync::Mutex<Option<Writer>>,
+    pending: Pending,
+    next_id: AtomicU64,
+    state: watch::Sender<SidecarState>,
+    /// 每次（重新）启动后都要发送的初始化帧，如加载模型
+    init: Mutex<Option<Value>>,
+    /// 握手时侧车返回的 ready 帧
+    info: Mutex<Value>,
+    last_error: Mutex<Option<String>>,
+}
+
+/// 受监管的侧车进程句柄，可克隆；所有句柄丢弃后关闭进程
+#[derive(Clone)]
+pub struct Sidecar {
+    shared: Arc<Shared>,
+    _shutdown: Arc<DropGuard>,
+}
+
+impl Sidecar {
+    /// 启动侧车并等待首次握手完成
+    pub async fn spawn(config: SidecarConfig) -> Result<Self> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        // macOS 的 socket 路径上限为 104 字节，文件名保持简短
+        let socket_path = std::env::temp_dir().join(format!(
+            "silo-{}-{}.sock",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let (state, mut state_rx) = watch::channel(SidecarState::Starting);
+        let shared = Arc::new(Shared {
+            config,
+            socket_path,
+            writer: tokio::sync::Mutex::new(None),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            next_id: AtomicU64::new(1),
+            state,
+            init: Mutex::new(None),
+            info: Mutex::new(Value::Null),
+            last_error: Mutex::new(None),
+        });
+        let shutdown = CancellationToken::new();
+        tokio::spawn(supervise(shared.clone(), shutdown.clone()));
+
+        let sidecar = Self {
+            shared,
+            _shutdown: Arc::new(shutdown.drop_guard()),
+        };
+        let state = *state_rx
+            .wait_for(|s| matches!(s, SidecarState::Ready | SidecarState::Failed))
+            .await?;
+        if state == SidecarState::Failed {
+            anyhow::bail!(
+                "Sidecar '{}' failed to start: {}",
+                sidecar.shared.config.name,
+                sidecar.last_error().unwrap_or_default()
+            );
+        }
+        Ok(sidecar)
+    }
+
+    pub fn state(&self) -> SidecarState {
+        *self.shared.state.borrow()
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.state() == SidecarState::Ready
+    }
+
+    /// 握手时侧车返回的信息（ready 帧）
+    pub fn info(&self) -> Value {
+        lock(&self.shared.info).clone()
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        lock(&self.shared.last_error).clone()
+    }
+
+    /// 设置重启后自动重放的初始化帧
+    pub fn set_init(&self, frame: Option<Value>) {
+        *lock(&self.shared.init) = frame;
+    }
+
+    /// 发送请求，返回该请求的响应帧接收端；帧中的 id 字段由这里分配
+    pub async fn request(&self, mut frame: Value) -> Result<(u64, mpsc::UnboundedReceiver<Value>)> {
+        if !self.is_ready() {
+            anyhow::bail!("Sidecar '{}' is not ready ({:?})", self.shared.config.name, self.state());
+        }
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        frame["id"] = json!(id);
+        let (tx, rx) = mpsc::unbounded_channel();
+        lock(&self.shared.pending).insert(id, tx);
+        if let Err(e) = self.shared.send(&frame).await {
+            lock(&self.shared.pending).remove(&id);
+            return Err(e);
+        }
+        Ok((id, rx))
+    }
+
+    /// 发送请求并等待第一个响应帧；error 帧转换为错误
+    pub async fn call(&self, frame: Value) -> Result<Value> {
+        let (id, mut rx) = self.request(frame).await?;
+        let reply = rx.recv().await;
+        self.finish(id);
+        let reply = reply.ok_or_else(|| anyhow::anyhow!("Sidecar '{}' closed the request", self.shared.config.name))?;
+        check_error(reply)
+    }
+
+    /// 请求结束后注销，之后该 id 的帧被丢弃
+    pub fn finish(&self, id: u64) {
+        lock(&self.shared.pending).remove(&id);
+    }
+
+    /// 通知侧车取消请求（尽力而为，侧车可能已经结束）
+    pub async fn cancel(&self, id: u64) {
+        self.finish(id);
+        if let Err(e) = self.shared.send(&json!({ "type": "cancel", "id": id })).await {
+            tracing::debug!("Failed to send cancel to sidecar '{}': {}", self.shared.config.name, e);
+        }
+    }
+}
+
+/// error 帧转换为错误
+pub fn check_error(frame: Value) -> Result<Value> {
+    if frame["type"] == "error" {
+        anyhow::bail!("{}", frame["message"].as_str().unwrap_or("sidecar error"));
+    }
+    Ok(frame)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Shared {
+    async fn send(&self, frame: &Value) -> Result<()> {
+        let mut writer = self.writer.lock().await;
+        let writer = writer
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("Sidecar '{}' is not connected", self.config.name))?;
+        write_frame(writer, frame).await
+    }
+
+    fn set_state(&self, state: SidecarState) {
+        self.state.send_replace(state);
+    }
+
+    /// 通知所有进行中的请求连接已断开
+    fn fail_pending(&self, message: &str) {
+        let pending: Vec<_> = lock(&self.pending).drain().collect();
+        for (id, tx) in pending {
+            let _ = tx.send(json!({ "type": "error", "id": id, "message": message }));
+        }
+    }
+}
+
+/// 监管循环：启动 → 等待退出或断开 → 退避后重启
+async fn supervise(shared: Arc<Shared>, shutdown: CancellationToken) {
+    let name = shared.config.name.clone();
+    let mut restarts = 0u32;
+    loop {
+        let started_at = Instant::now();
+        match start(&shared).await {
+            Ok((mut child, disconnected)) => {
+                shared.set_state(SidecarState::Ready);
+                tracing::info!("Sidecar '{}' ready (pid {:?})", name, child.id());
+                tokio::select! {
+                    status = child.wait() => {
+                        let status = status.map(|s| s.to_string()).unwrap_or_else(|e| e.to_string());
+                        tracing::warn!("Sidecar '{}' exited: {}", name, status);
+                        *lock(&shared.last_error) = Some(format!("sidecar exited: {}", status));
+                    }
+                    _ = disconnected.cancelled() => {
+                        tracing::warn!("Sidecar '{}' closed the connection, restarting", name);
+                        *lock(&shared.last_error) = Some("sidecar closed the connection".to_string());
+                        let _ = child.kill().await;
+                    }
+                    _ = shutdown.cancelled() => {
+                        let _ = child.kill().await;
+                    }
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Sidecar '{}' failed to start: {:#}", name, e);
+                *lock(&shared.last_error) = Some(format!("{:#}", e));
+                // 程序不存在等无法执行的情况不重试
+                if e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound) {
+                    restarts = shared.config.max_restarts;
+                }
+            }
+        }
+        shared.writer.lock().await.take();
+        shared.fail_pending("sidecar exited");
+        let _ = std::fs::remove_file(&shared.socket_path);
+        if shutdown.is_cancelled() {
+            shared.set_state(SidecarState::Stopped);
+            tracing::info!("Sidecar '{}' stopped", name);
+            return;
+        }
+
+        if started_at.elapsed() > STABLE_AFTER {
+            restarts = 0;
+        }
+        if restarts >= shared.config.max_restarts {
+            tracing::error!("Sidecar '{}' failed too many times, giving up", name);
+            shared.set_state(SidecarState::Failed);
+            return;
+        }
+        let backoff = shared
+            .config
+            .restart_backoff
+            .saturating_mul(1 << restarts.min(16))
+            .min(MAX_BACKOFF);
+        restarts += 1;
+        shared.set_state(SidecarState::Restarting);
+        tracing::info!("Restarting sidecar '{}' in {:?} (attempt {})", name, backoff, restarts);
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            _ = shutdown.cancelled() => {
+                shared.set_state(SidecarState::Stopped);
+                return;
+            }
+        }
+    }
+}
+
+/// 启动进程、连接 socket、握手并重放初始化帧；返回子进程与连接断开信号
+async fn start(shared: &Arc<Shared>) -> Result<(tokio::process::Child, CancellationToken)> {
+    let config = &shared.config;
+    let _ = std::fs::remove_file(&shared.socket_path);
+    let mut child = tokio::process::Command::new(&config.program)
+        .args(&config.args)
+        .envs(config.env.iter().cloned())
+        .env("SILO_SIDECAR_SOCKET", &shared.socket_path)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to spawn {:?}", config.program))?;
+
+    let handshake = async {
+        let (mut reader, writer) = connect(shared, &mut child).await?;
+        let mut writer: Writer = writer;
+        write_frame(&mut writer, &json!({ "type": "hello", "protocol": PROTOCOL_VERSION })).await?;
+        let ready = read_frame(&mut reader).await?;
+        if ready["type"] != "ready" {
+            anyhow::bail!("Unexpected handshake reply: {}", ready);
+        }
+        *lock(&shared.info) = ready;
+        anyhow::Ok((reader, writer))
+    };
+    let (mut reader, mut writer) = match tokio::time::timeout(config.ready_timeout, handshake).await {
+        Ok(Ok(conn)) => conn,
+        Ok(Err(e)) => {
+            let _ = child.kill().await;
+            return Err(e);
+        }
+        Err(_) => {
+            let _ = child.kill().await;
+            anyhow::bail!("Sidecar did not become ready within {:?}", config.ready_timeout);
+        }
+    };
+
+    // 重放初始化帧（如加载模型），失败视为启动失败
+    let init = lock(&shared.init).clone();
+    if let Some(mut frame) = init {
+        frame["id"] = json!(0);
+        write_frame(&mut writer, &frame).await?;
+        if let Err(e) = check_error(read_frame(&mut reader).await?) {
+            let _ = child.kill().await;
+            return Err(e.context("Sidecar initialization failed"));
+        }
+    }
+
+    *shared.writer.lock().await = Some(writer);
+    let disconnected = CancellationToken::new();
+    let pending = shared.pending.clone();
+    let guard = disconnected.clone();
+    tokio::spawn(async move {
+        // 按 id 分发响应帧；连接断开时通知监管循环
+        while let Ok(frame) = read_frame(&mut reader).await {
+            let Some(id) = frame["id"].as_u64() else {
+                tracing::debug!("Ignoring sidecar frame without id: {}", frame);
+                continue;
+            };
+            if let Some(tx) = lock(&pending).get(&id) {
+                let _ = tx.send(frame);
+            }
+        }
+        guard.cancel();
+    });
+    Ok((child, disconnected))
+}
+
+/// 侧车启动后才会创建 socket，轮询连接直到成功或进程退出
+#[cfg(unix)]
+async fn connect(
+    shared: &Shared,
+    child: &mut tokio::process::Child,
+) -> Result<(Box<dyn AsyncRead + Send + Unpin>, Box<dyn AsyncWrite + Send + Unpin>)> {
+    loop {
+        if let Ok(stream) = tokio::net::UnixStream::connect(&shared.socket_path).await {
+            let (reader, writer) = stream.into_split();
+            return Ok((Box::new(reader), Box::new(writer)));
+        }
+        if let Some(status) = child.try_wait()? {
+            anyhow::bail!("Sidecar exited before listening on its socket: {}", status);
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[cfg(not(unix))]
+async fn connect(
+    _shared: &Shared,
+    _child: &mut tokio::process::Child,
+) -> Result<(Box<dyn AsyncRead + Send + Unpin>, Box<dyn AsyncWrite + Send + Unpin>)> {
+    anyhow::bail!("Unix domain sockets are not supported on this platform")
+}
+
+pub async fn write_frame<W: AsyncWrite + Unpin + ?Sized>(writer: &mut W, frame: &Value) -> Result<()> {
+    let body = serde_json::to_vec(frame)?;
+    writer.write_all(&(body.len() as u32).to_be_bytes()).await?;
+    writer.write_all(&body).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+pub async fn read_frame<R: AsyncRead + Unpin + ?Sized>(reader: &mut R) -> Result<Value> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_BYTES {
+        anyhow::bail!("Sidecar frame too large: {} bytes", len);
+    }
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    const FAKE_SIDECAR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/sidecar/fake_sidecar.py");
+
+    fn fake_config() -> SidecarConfig {
+        let mut config = SidecarConfig::new("fake", "python3").with_args([FAKE_SIDECAR]);
+        config.ready_timeout = Duration::from_secs(5);
+        config.restart_backoff = Duration::from_millis(50);
+        config
+    }
+
+    fn is_running(pid: u64) -> bool {
+        // 已退出但未被回收的僵尸进程不算运行中
+        std::fs::read_to_string(format!("/proc/{}/stat", pid)).is_ok_and(|stat| !stat.contains(") Z "))
+    }
+
+    async fn wait_for_state(sidecar: &Sidecar, state: SidecarState) {
+        let mut rx = sidecar.shared.state.subscribe();
+        tokio::time::timeout(Duration::from_secs(5), rx.wait_for(|s| *s == state))
+            .await
+            .expect("sidecar did not reach the expected state")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn handshake_and_kill_on_drop() {
+        let sidecar = Sidecar::spawn(fake_config()).await.unwrap();
+        assert!(sidecar.is_ready());
+        assert_eq!(sidecar.info()["protocol"], PROTOCOL_VERSION);
+        let pid = sidecar.info()["pid"].as_u64().unwrap();
+        assert!(is_running(pid));
+
+        let reply = sidecar.call(json!({ "type": "load", "model_path": "/models/a" })).await.unwrap();
+        assert_eq!(reply["type"], "loaded");
+        let err = sidecar.call(json!({ "type": "load", "model_path": "/models/bad" })).await.unwrap_err();
+        assert!(err.to_string().contains("cannot load model"));
+
+        let socket_path = sidecar.shared.socket_path.clone();
+        drop(sidecar);
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while is_running(pid) {
+            assert!(Instant::now() < deadline, "sidecar still running after the last handle was dropped");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(!socket_path.exists());
+    }
+
+    #[tokio::test]
+    async fn crash_fails_pending_requests_and_restarts_with_init() {
+        let sidecar = Sidecar::spawn(fake_config()).await.unwrap();
+        let old_pid = sidecar.info()["pid"].as_u64().unwrap();
+        let load = json!({ "type": "load", "model_path": "/models/a" });
+        sidecar.call(load.clone()).await.unwrap();
+        sidecar.set_init(Some(load));
+
+        let crash = json!({ "type": "generate", "messages": [{ "role": "user", "content": "crash" }] });
+        let err = sidecar.call(crash).await.unwrap_err();
+        assert!(err.to_string().contains("sidecar exited"), "{}", err);
+        let crashed_at = Instant::now();
+
+        wait_for_state(&sidecar, SidecarState::Ready).await;
+        assert!(crashed_at.elapsed() >= Duration::from_millis(50), "restarted without backoff");
+        let new_pid = sidecar.info()["pid"].as_u64().unwrap();
+        assert_ne!(old_pid, new_pid);
+        assert!(!is_running(old_pid));
+
+        // 重启后重放了 load，新进程可以直接生成
+        let generate = json!({ "type": "generate", "messages": [{ "role": "user", "content": "back again" }] });
+        let (id, mut frames) = sidecar.request(generate).await.unwrap();
+        let mut text = String::new();
+        while let Some(frame) = frames.recv().await {
+            match frame["type"].as_str() {
+                Some("token") => text.push_str(frame["text"].as_str().unwrap()),
+                Some("done") => break,
+                _ => panic!("unexpected frame: {}", frame),
+            }
+        }
+        sidecar.finish(id);
+        assert_eq!(text, "back again ");
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_exponential_backoff() {
+        let mut config = fake_config();
+        config.env.push(("FAKE_SIDECAR_EXIT".to_string(), "1".to_string()));
+        config.max_restarts = 3;
+        config.restart_backoff = Duration::from_millis(20);
+        let started_at = Instant::now();
+        let err = Sidecar::spawn(config).await.err().unwrap();
+        assert!(err.to_string().contains("exited before listening"), "{}", err);
+        // 20ms + 40ms + 80ms
+        assert!(started_at.elapsed() >= Duration::from_millis(140));
+    }
+
+    #[tokio::test]
+    async fn missing_program_is_not_retried() {
+        let mut config = SidecarConfig::new("missing", "/nonexistent/silo-sidecar");
+        config.restart_backoff = Duration::from_secs(10);
+        let started_at = Instant::now();
+        let err = Sidecar::spawn(config).await.err().unwrap();
+        assert!(err.to_string().contains("Failed to spawn"), "{}", err);
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+    }
+}
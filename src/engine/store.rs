@@ -0,0 +1,448 @@
+// 本地模型仓库 - 管理 data_dir/silo/models 下已安装的模型及其清单
+
+use crate::engine::gguf;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// 已安装模型的清单条目
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstalledModel {
+    pub name: String,
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    pub sha256: String,
+    pub quantization: Option<String>,
+    /// 模型家族（GGUF general.architecture，如 llama、qwen2）
+    pub family: Option<String>,
+    pub added_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Hugging Face 本地缓存中找到的 GGUF 文件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HfCachedFile {
+    pub repo_id: String,
+    pub file_name: String,
+    pub path: PathBuf,
+    pub size_bytes: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    models: Vec<InstalledModel>,
+}
+
+pub struct ModelStore {
+    root: PathBuf,
+    manifest: Manifest,
+}
+
+impl ModelStore {
+    /// 默认位置：dirs::data_dir()/silo/models
+    pub fn default_root() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("silo")
+            .join("models")
+    }
+
+    pub fn open(root: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&root)?;
+        let manifest_path = root.join(MANIFEST_FILE);
+        let manifest = if manifest_path.exists() {
+            let content = std::fs::read_to_string(&manifest_path)?;
+            serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse model manifest {:?}", manifest_path))?
+        } else {
+            Manifest::default()
+        };
+        tracing::info!("ModelStore opened at: {:?} ({} models)", root, manifest.models.len());
+        Ok(Self { root, manifest })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn list(&self) -> &[InstalledModel] {
+        &self.manifest.models
+    }
+
+    pub fn get(&self, name: &str) -> Option<&InstalledModel> {
+        self.manifest.models.iter().find(|m| m.name == name)
+    }
+
+    /// 导入本地 GGUF 文件：复制到仓库目录并写入清单
+    pub fn import_from_path(&mut self, source: &Path, name: Option<&str>) -> Result<InstalledModel> {
+        let file_name = source
+            .file_name()
+            .ok_or_else(|| anyhow::anyhow!("Invalid model path: {:?}", source))?;
+        self.import_file(source, Path::new(file_name), name)
+    }
+
+    fn import_file(&mut self, source: &Path, file_name: &Path, name: Option<&str>) -> Result<InstalledModel> {
+        if !source.is_file() {
+            anyhow::bail!("Model file not found: {:?}", source);
+        }
+        // 先解析元数据，拒绝非 GGUF 文件
+        let info = gguf::inspect(source)?;
+
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => file_name
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| file_name.to_string_lossy().into_owned()),
+        };
+        if self.get(&name).is_some() {
+            anyhow::bail!("A model named '{}' is already installed", name);
+        }
+
+        let sha256 = sha256_file(source)?;
+        if let Some(existing) = self.manifest.models.iter().find(|m| m.sha256 == sha256) {
+            anyhow::bail!("This file is already installed as '{}'", existing.name);
+        }
+
+        let dest = self.root.join(file_name);
+        if dest.exists() {
+            anyhow::bail!("File {:?} already exists in the model store", dest);
+        }
+        // 不使用硬链接：硬链接与源文件共享数据，源文件被就地修改后仓库中的模型也会随之改变。
+        // 先复制到临时文件，完整写入后再重命名，中途失败不会留下不完整的模型文件
+        let partial = self.root.join(format!("{}.partial", file_name.to_string_lossy()));
+        if let Err(e) = std::fs::copy(source, &partial).and_then(|_| std::fs::rename(&partial, &dest)) {
+            let _ = std::fs::remove_file(&partial);
+            return Err(e).with_context(|| format!("Failed to copy {:?} into model store", source));
+        }
+
+        let model = InstalledModel {
+            name,
+            size_bytes: std::fs::metadata(&dest)?.len(),
+            path: dest,
+            sha256,
+            quantization: Some(info.quantization),
+            family: Some(info.architecture),
+            added_at: chrono::Utc::now(),
+        };
+        self.manifest.models.push(model.clone());
+        self.save()?;
+        tracing::info!("Imported model '{}' from {:?}", model.name, source);
+        Ok(model)
+    }
+
+    /// 从本地 Hugging Face 缓存导入，repo 内有多个 GGUF 时必须指定文件名
+    pub fn import_from_hf_cache(&mut self, repo_id: &str, file_name: Option<&str>, name: Option<&str>) -> Result<InstalledModel> {
+        self.import_cached(scan_hf_cache()?, repo_id, file_name, name)
+    }
+
+    fn import_cached(
+        &mut self,
+        cached: Vec<HfCachedFile>,
+        repo_id: &str,
+        file_name: Option<&str>,
+        name: Option<&str>,
+    ) -> Result<InstalledModel> {
+        let candidates: Vec<HfCachedFile> = cached
+            .into_iter()
+            .filter(|f| f.repo_id == repo_id)
+            .filter(|f| file_name.is_none_or(|n| f.file_name == n))
+            .collect();
+
+        match candidates.as_slice() {
+            [] => anyhow::bail!("No cached GGUF file found for {} in the Hugging Face cache", repo_id),
+            [file] => {
+                // 缓存中的实际文件以哈希命名，沿用快照里的文件名
+                let snapshot_name = Path::new(&file.file_name);
+                let file_name = snapshot_name.file_name().map(Path::new).unwrap_or(snapshot_name);
+                self.import_file(&file.path, file_name, name)
+            }
+            files => anyhow::bail!(
+                "Multiple GGUF files cached for {}, specify one of: {}",
+                repo_id,
+                files.iter().map(|f| f.file_name.as_str()).collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+
+    /// 重新计算 SHA-256 并与清单比对，文件缺失或被修改时返回 false
+    pub fn verify(&self, name: &str) -> Result<bool> {
+        let model = self
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("Model '{}' is not installed", name))?;
+        if !model.path.is_file() {
+            tracing::warn!("Model file missing: {:?}", model.path);
+            return Ok(false);
+        }
+        let ok = sha256_file(&model.path)? == model.sha256;
+        if !ok {
+            tracing::warn!("Checksum mismatch for model '{}'", name);
+        }
+        Ok(ok)
+    }
+
+    /// 从清单中移除模型并删除仓库目录内的文件
+    pub fn delete(&mut self, name: &str) -> Result<()> {
+        let index = self
+            .manifest
+            .models
+            .iter()
+            .position(|m| m.name == name)
+            .ok_or_else(|| anyhow::anyhow!("Model '{}' is not installed", name))?;
+        let model = self.manifest.models.remove(index);
+        // 只删除仓库自己管理的文件
+        if model.path.starts_with(&self.root) && model.path.exists() {
+            std::fs::remove_file(&model.path)?;
+        }
+        self.save()?;
+        tracing::info!("Deleted model '{}'", name);
+        Ok(())
+    }
+
+    /// 先写临时文件再重命名，避免中途崩溃损坏清单
+    fn save(&self) -> Result<()> {
+        let manifest_path = self.root.join(MANIFEST_FILE);
+        let tmp_path = self.root.join(format!("{}.tmp", MANIFEST_FILE));
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(&self.manifest)?)?;
+        std::fs::rename(&tmp_path, &manifest_path)?;
+        Ok(())
+    }
+}
+
+/// Hugging Face hub 缓存目录：HF_HUB_CACHE > HF_HOME/hub > ~/.cache/huggingface/hub
+pub fn hf_cache_dir() -> Option<PathBuf> {
+    if let Ok(dir) = std::env::var("HF_HUB_CACHE") {
+        return Some(PathBuf::from(dir));
+    }
+    if let Ok(home) = std::env::var("HF_HOME") {
+        return Some(PathBuf::from(home).join("hub"));
+    }
+    dirs::home_dir().map(|home| home.join(".cache").join("huggingface").join("hub"))
+}
+
+/// 列出 Hugging Face 缓存中所有 GGUF 文件（每个 repo 取 refs/main 指向的快照）
+pub fn scan_hf_cache() -> Result<Vec<HfCachedFile>> {
+    match hf_cache_dir().filter(|d| d.is_dir()) {
+        Some(cache_dir) => scan_cache_dir(&cache_dir),
+        None => Ok(vec![]),
+    }
+}
+
+fn scan_cache_dir(cache_dir: &Path) -> Result<Vec<HfCachedFile>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(cache_dir)? {
+        let repo_dir = entry?.path();
+        let Some(dir_name) = repo_dir.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        // 目录名形如 models--Qwen--Qwen2-7B-Instruct-GGUF
+        let Some(repo_id) = dir_name.strip_prefix("models--").map(|r| r.replace("--", "/")) else {
+            continue;
+        };
+        let Some(snapshot) = resolve_snapshot(&repo_dir) else {
+            continue;
+        };
+        collect_gguf(&snapshot, &snapshot, &repo_id, &mut files)?;
+    }
+    files.sort_by(|a, b| (&a.repo_id, &a.file_name).cmp(&(&b.repo_id, &b.file_name)));
+    Ok(files)
+}
+
+/// 优先使用 refs/main，否则取最近修改的快照
+fn resolve_snapshot(repo_dir: &Path) -> Option<PathBuf> {
+    let snapshots = repo_dir.join("snapshots");
+    if let Ok(revision) = std::fs::read_to_string(repo_dir.join("refs").join("main")) {
+        let dir = snapshots.join(revision.trim());
+        if dir.is_dir() {
+            return Some(dir);
+        }
+    }
+    std::fs::read_dir(&snapshots)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_dir())
+        .max_by_key(|e| e.metadata().and_then(|m| m.modified()).ok())
+        .map(|e| e.path())
+}
+
+fn collect_gguf(base: &Path, dir: &Path, repo_id: &str, files: &mut Vec<HfCachedFile>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_gguf(base, &path, repo_id, files)?;
+        } else if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("gguf")) {
+            // 快照中的文件是指向 blobs 的符号链接，解析为实际路径
+            let resolved = std::fs::canonicalize(&path)?;
+            files.push(HfCachedFile {
+                repo_id: repo_id.to_string(),
+                file_name: path.strip_prefix(base).unwrap_or(&path).to_string_lossy().into_owned(),
+                size_bytes: std::fs::metadata(&resolved)?.len(),
+                path: resolved,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("silo-store-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// 只含元数据的最小 GGUF 文件；name 不同则内容（哈希）不同
+    fn write_gguf(path: &Path, name: &str) {
+        fn string(out: &mut Vec<u8>, s: &str) {
+            out.extend((s.len() as u64).to_le_bytes());
+            out.extend(s.as_bytes());
+        }
+        let mut out = Vec::new();
+        out.extend(b"GGUF");
+        out.extend(3u32.to_le_bytes());
+        out.extend(0u64.to_le_bytes());
+        out.extend(3u64.to_le_bytes());
+        string(&mut out, "general.architecture");
+        out.extend(8u32.to_le_bytes());
+        string(&mut out, "llama");
+        string(&mut out, "general.file_type");
+        out.extend(4u32.to_le_bytes());
+        out.extend(15u32.to_le_bytes());
+        string(&mut out, "general.name");
+        out.extend(8u32.to_le_bytes());
+        string(&mut out, name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, out).unwrap();
+    }
+
+    #[test]
+    fn import_copies_file_and_verify_detects_changes() {
+        let dir = temp_dir("import");
+        let source = dir.join("downloads").join("tiny.gguf");
+        write_gguf(&source, "tiny");
+        let mut store = ModelStore::open(dir.join("models")).unwrap();
+
+        let model = store.import_from_path(&source, None).unwrap();
+        assert_eq!(model.name, "tiny");
+        assert_eq!(model.path, dir.join("models").join("tiny.gguf"));
+        assert_eq!(model.quantization.as_deref(), Some("Q4_K_M"));
+        assert_eq!(model.family.as_deref(), Some("llama"));
+        assert!(!dir.join("models").join("tiny.gguf.partial").exists());
+
+        // 仓库中是独立的副本，源文件被修改不影响校验
+        std::fs::write(&source, b"changed").unwrap();
+        assert!(store.verify("tiny").unwrap());
+
+        // 重名、重复内容与非 GGUF 文件都被拒绝
+        write_gguf(&source, "tiny");
+        let err = store.import_from_path(&source, None).unwrap_err().to_string();
+        assert!(err.contains("named 'tiny' is already installed"), "{}", err);
+        let err = store.import_from_path(&source, Some("again")).unwrap_err().to_string();
+        assert!(err.contains("already installed as 'tiny'"), "{}", err);
+        std::fs::write(dir.join("notes.gguf"), b"not a model").unwrap();
+        assert!(store.import_from_path(&dir.join("notes.gguf"), None).is_err());
+        assert!(store.import_from_path(&dir.join("missing.gguf"), None).is_err());
+
+        // 清单持久化
+        let store = ModelStore::open(dir.join("models")).unwrap();
+        assert_eq!(store.list().len(), 1);
+
+        std::fs::write(&model.path, b"corrupted").unwrap();
+        assert!(!store.verify("tiny").unwrap());
+        std::fs::remove_file(&model.path).unwrap();
+        assert!(!store.verify("tiny").unwrap());
+        assert!(store.verify("other").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn delete_removes_only_store_files() {
+        let dir = temp_dir("delete");
+        let mut store = ModelStore::open(dir.join("models")).unwrap();
+        write_gguf(&dir.join("a.gguf"), "a");
+        let model = store.import_from_path(&dir.join("a.gguf"), Some("alpha")).unwrap();
+
+        store.delete("alpha").unwrap();
+        assert!(!model.path.exists());
+        assert!(dir.join("a.gguf").exists());
+        assert!(store.list().is_empty());
+        assert!(ModelStore::open(dir.join("models")).unwrap().list().is_empty());
+        assert!(store.delete("alpha").is_err());
+
+        // 清单中指向仓库外的文件不会被删除
+        store.manifest.models.push(InstalledModel {
+            path: dir.join("a.gguf"),
+            ..model
+        });
+        store.delete("alpha").unwrap();
+        assert!(dir.join("a.gguf").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scans_hf_cache_snapshots_and_imports() {
+        let dir = temp_dir("hf");
+        let cache = dir.join("hub");
+        let repo = cache.join("models--org--Tiny-GGUF");
+        write_gguf(&repo.join("blobs").join("0a1b"), "tiny-q4");
+        write_gguf(&repo.join("blobs").join("2c3d"), "tiny-q8");
+        write_gguf(&repo.join("blobs").join("4e5f"), "old");
+        let link = |snapshot: &str, file: &str, blob: &str| {
+            let path = repo.join("snapshots").join(snapshot).join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::os::unix::fs::symlink(repo.join("blobs").join(blob), path).unwrap();
+        };
+        link("rev2", "q4/tiny-q4.gguf", "0a1b");
+        link("rev2", "tiny-q8.gguf", "2c3d");
+        link("rev1", "old.gguf", "4e5f");
+        std::fs::write(repo.join("snapshots").join("rev2").join("README.md"), "readme").unwrap();
+        std::fs::create_dir_all(repo.join("refs")).unwrap();
+        std::fs::write(repo.join("refs").join("main"), "rev2\n").unwrap();
+        std::fs::create_dir_all(cache.join("datasets--org--data").join("snapshots")).unwrap();
+
+        // 只取 refs/main 指向的快照，符号链接解析为 blob 路径
+        let files = scan_cache_dir(&cache).unwrap();
+        let names: Vec<&str> = files.iter().map(|f| f.file_name.as_str()).collect();
+        assert_eq!(names, ["q4/tiny-q4.gguf", "tiny-q8.gguf"]);
+        assert!(files.iter().all(|f| f.repo_id == "org/Tiny-GGUF"));
+        assert_eq!(files[0].path, std::fs::canonicalize(repo.join("blobs").join("0a1b")).unwrap());
+
+        let mut store = ModelStore::open(dir.join("models")).unwrap();
+        let err = store.import_cached(files.clone(), "org/Tiny-GGUF", None, None).unwrap_err().to_string();
+        assert!(err.contains("specify one of: q4/tiny-q4.gguf, tiny-q8.gguf"), "{}", err);
+        assert!(store.import_cached(files.clone(), "org/Other", None, None).is_err());
+        let model = store
+            .import_cached(files, "org/Tiny-GGUF", Some("q4/tiny-q4.gguf"), None)
+            .unwrap();
+        // 沿用快照中的文件名，而不是 blob 的哈希名
+        assert_eq!(model.name, "tiny-q4");
+        assert_eq!(model.path, dir.join("models").join("tiny-q4.gguf"));
+        assert!(store.verify("tiny-q4").unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
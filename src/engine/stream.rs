@@ -0,0 +1,223 @@
+// 可取消的流式推理
+// 后端通过 StreamSender 推送文本片段，调用方持有 InferenceStream；
+// 取消令牌被触发或 InferenceStream 被丢弃时，后端停止生成并释放连接/计算资源
+
+use crate::engine::context::ContextReport;
+use crate::engine::{FinishReason, InferenceMetrics, InferenceResponse, MetricsTimer, TokenUsage};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// 流式推理事件
+#[derive(Debug, Clone)]
+pub enum StreamEvent {
+    /// 一段增量文本
+    Token(String),
+    /// 生成结束（总是最后一个事件），附带后端上报的 token 用量
+    Finished(FinishReason, Option<TokenUsage>),
+}
+
+/// 流结束时的回调（用于累计后端统计）
+type FinishHook = Box<dyn FnOnce(FinishReason, &InferenceMetrics) + Send>;
+
+/// 调用方持有的流式输出
+pub struct InferenceStream {
+    rx: mpsc::Receiver<StreamEvent>,
+    cancel: CancellationToken,
+    finish_reason: Option<FinishReason>,
+    timer: MetricsTimer,
+    /// 已收到的文本片段数，后端未上报用量时作为生成 token 数
+    pieces: usize,
+    metrics: Option<InferenceMetrics>,
+    /// 发送前对提示词的裁剪报告，结束时并入指标
+    context: Option<ContextReport>,
+    on_finish: Option<FinishHook>,
+}
+
+impl InferenceStream {
+    /// 创建一对发送端/接收端，同时开始计时（后端应在发起请求前创建）
+    /// 使用调用方令牌的子令牌：调用方取消会传递到本次生成，丢弃流只取消本次生成
+    pub fn channel(cancel: CancellationToken) -> (StreamSender, InferenceStream) {
+        let cancel = cancel.child_token();
+        let (tx, rx) = mpsc::channel(100);
+        let sender = StreamSender {
+            tx,
+            cancel: cancel.clone(),
+            usage: None,
+        };
+        let stream = InferenceStream {
+            rx,
+            cancel,
+            finish_reason: None,
+            timer: MetricsTimer::start(),
+            pieces: 0,
+            metrics: None,
+            context: None,
+            on_finish: None,
+        };
+        (sender, stream)
+    }
+
+    /// 下一段文本；流结束时返回 None，此后可通过 finish_reason 获取结束原因
+    pub async fn next_token(&mut self) -> Option<String> {
+        if self.finish_reason.is_some() {
+            return None;
+        }
+        let event = tokio::select! {
+            event = self.rx.recv() => event,
+            _ = self.cancel.cancelled() => None,
+        };
+        match event {
+            Some(StreamEvent::Token(token)) => {
+                self.timer.mark_token();
+                self.pieces += 1;
+                Some(token)
+            }
+            Some(StreamEvent::Finished(reason, usage)) => {
+                self.complete(reason, usage);
+                None
+            }
+            None => {
+                // 后端未发送结束事件就关闭了通道：被取消或异常退出
+                let reason = if self.cancel.is_cancelled() {
+                    FinishReason::Cancelled
+                } else {
+                    FinishReason::Error
+                };
+                self.complete(reason, None);
+                None
+            }
+        }
+    }
+
+    fn complete(&mut self, reason: FinishReason, usage: Option<TokenUsage>) {
+        let mut metrics = self.timer.finish(
+            usage.map(|u| u.prompt_tokens),
+            usage.map_or(self.pieces, |u| u.completion_tokens),
+        );
+        metrics.context = self.context.clone();
+        if let Some(hook) = self.on_finish.take() {
+            hook(reason, &metrics);
+        }
+        self.finish_reason = Some(reason);
+        self.metrics = Some(metrics);
+    }
+
+    /// 注册流结束时的回调
+    pub fn on_finish(mut self, hook: impl FnOnce(FinishReason, &InferenceMetrics) + Send + 'static) -> Self {
+        self.on_finish = Some(Box::new(hook));
+        self
+    }
+
+    /// 附加提示词的裁剪报告
+    pub fn with_context_report(mut self, report: ContextReport) -> Self {
+        self.context = Some(report);
+        self
+    }
+
+    /// 提示词被裁剪时的报告，开始读取前即可用
+    pub fn context_report(&self) -> Option<&ContextReport> {
+        self.context.as_ref()
+    }
+
+    pub fn finish_reason(&self) -> Option<FinishReason> {
+        self.finish_reason
+    }
+
+    /// 流结束后可用
+    pub fn metrics(&self) -> Option<&InferenceMetrics> {
+        self.metrics.as_ref()
+    }
+
+    /// 中止生成
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// 读取全部输出，合并为完整响应（被取消时返回已生成的部分）
+    pub async fn collect(mut self) -> InferenceResponse {
+        let mut tokens = Vec::new();
+        while let Some(token) = self.next_token().await {
+            tokens.push(token);
+        }
+        InferenceResponse {
+            tokens,
+            finish_reason: self.finish_reason.unwrap_or(FinishReason::Error),
+            metrics: self.metrics.take().unwrap_or_default(),
+        }
+    }
+}
+
+impl Drop for InferenceStream {
+    fn drop(&mut self) {
+        // 调用方不再读取，通知后端停止生成
+        self.cancel.cancel();
+        if self.finish_reason.is_none() {
+            self.complete(FinishReason::Cancelled, None);
+        }
+    }
+}
+
+/// 后端持有的发送端
+pub struct StreamSender {
+    tx: mpsc::Sender<StreamEvent>,
+    cancel: CancellationToken,
+    usage: Option<TokenUsage>,
+}
+
+impl StreamSender {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled() || self.tx.is_closed()
+    }
+
+    /// 等待取消（用于 select! 中中断后端的网络读取）
+    pub async fn cancelled(&self) {
+        tokio::select! {
+            _ = self.cancel.cancelled() => {}
+            _ = self.tx.closed() => {}
+        }
+    }
+
+    /// 发送一段文本；已取消时返回 false，后端应停止生成
+    pub async fn send(&self, token: String) -> bool {
+        if self.is_cancelled() {
+            return false;
+        }
+        tokio::select! {
+            result = self.tx.send(StreamEvent::Token(token)) => result.is_ok(),
+            _ = self.cancel.cancelled() => false,
+        }
+    }
+
+    /// 在阻塞线程中发送（CPU 推理）
+    pub fn blocking_send(&self, token: String) -> bool {
+        !self.is_cancelled() && self.tx.blocking_send(StreamEvent::Token(token)).is_ok()
+    }
+
+    /// 记录后端上报的 token 用量，随结束事件一起发送
+    pub fn set_usage(&mut self, usage: TokenUsage) {
+        self.usage = Some(usage);
+    }
+
+    /// 发送结束事件；已取消时结束原因记为 cancelled
+    pub async fn finish(self, reason: FinishReason) {
+        let reason = self.final_reason(reason);
+        let _ = self.tx.send(StreamEvent::Finished(reason, self.usage)).await;
+    }
+
+    pub fn blocking_finish(self, reason: FinishReason) {
+        let reason = self.final_reason(reason);
+        let _ = self.tx.blocking_send(StreamEvent::Finished(reason, self.usage));
+    }
+
+    fn final_reason(&self, reason: FinishReason) -> FinishReason {
+        if self.cancel.is_cancelled() {
+            FinishReason::Cancelled
+        } else {
+            reason
+        }
+    }
+}
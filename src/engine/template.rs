@@ -0,0 +1,301 @@
+// 对话模板 - 将 ChatMessage 序列渲染为模型训练时使用的提示词格式
+// 优先使用模型自带的 Jinja chat_template，缺失或渲染失败时按模型家族选择内置格式
+
+use crate::engine::gguf::GgufModelInfo;
+use crate::engine::{ChatMessage, ChatRole};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// 内置对话格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChatFormat {
+    /// <|im_start|>role ... <|im_end|>
+    ChatMl,
+    /// ChatML，缺少系统消息时补默认系统提示词
+    Qwen,
+    /// <|start_header_id|>role<|end_header_id|> ... <|eot_id|>
+    Llama3,
+    /// [INST] ... [/INST]（Llama 2 / Mistral）
+    Mistral,
+    /// <start_of_turn>user ... <end_of_turn>
+    Gemma,
+}
+
+impl ChatFormat {
+    /// 根据模型架构推断格式；Llama 3 与 Llama 2 同属 llama 架构，以 128k 词表区分
+    pub fn detect(architecture: &str, vocab_size: Option<usize>) -> Self {
+        match architecture {
+            a if a.starts_with("qwen") => ChatFormat::Qwen,
+            a if a.starts_with("gemma") => ChatFormat::Gemma,
+            "mistral" => ChatFormat::Mistral,
+            "llama" if vocab_size.is_some_and(|n| n >= 128_000) => ChatFormat::Llama3,
+            "llama" => ChatFormat::Mistral,
+            _ => ChatFormat::ChatMl,
+        }
+    }
+
+    pub fn render(&self, messages: &[ChatMessage], add_generation_prompt: bool) -> String {
+        match self {
+            ChatFormat::ChatMl => render_chatml(messages, add_generation_prompt, None),
+            ChatFormat::Qwen => render_chatml(messages, add_generation_prompt, Some(QWEN_DEFAULT_SYSTEM)),
+            ChatFormat::Llama3 => render_llama3(messages, add_generation_prompt),
+            ChatFormat::Mistral => render_mistral(messages),
+            ChatFormat::Gemma => render_gemma(messages, add_generation_prompt),
+        }
+    }
+}
+
+const QWEN_DEFAULT_SYSTEM: &str = "You are a helpful assistant.";
+
+/// 模型的对话模板
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatTemplate {
+    pub format: ChatFormat,
+    /// 模型自带的 Jinja 模板（GGUF tokenizer.chat_template 或 tokenizer_config.json）
+    pub jinja: Option<String>,
+    pub bos_token: String,
+    pub eos_token: String,
+}
+
+impl ChatTemplate {
+    pub fn builtin(format: ChatFormat) -> Self {
+        Self {
+            format,
+            jinja: None,
+            bos_token: String::new(),
+            eos_token: String::new(),
+        }
+    }
+
+    /// 由 GGUF 元数据构建；bos/eos 文本来自模型词表
+    pub fn from_model_info(info: &GgufModelInfo, bos_token: Option<&str>, eos_token: Option<&str>) -> Self {
+        Self {
+            format: ChatFormat::detect(&info.architecture, info.vocab_size),
+            jinja: info.chat_template.clone().filter(|t| !t.trim().is_empty()),
+            bos_token: bos_token.unwrap_or_default().to_string(),
+            eos_token: eos_token.unwrap_or_default().to_string(),
+        }
+    }
+
+    /// 由 Hugging Face 模型目录（MLX / safetensors）构建：config.json 的 model_type 决定内置格式，
+    /// Jinja 模板来自 tokenizer_config.json，新版模型单独保存在 chat_template.jinja 中
+    pub fn from_model_dir(dir: &Path) -> Result<Self> {
+        let config: serde_json::Value = match std::fs::read_to_string(dir.join("config.json")) {
+            Ok(text) => serde_json::from_str(&text)?,
+            Err(_) => serde_json::Value::Null,
+        };
+        let fallback = ChatFormat::detect(
+            config["model_type"].as_str().unwrap_or_default(),
+            config["vocab_size"].as_u64().map(|n| n as usize),
+        );
+        let tokenizer_config = dir.join("tokenizer_config.json");
+        let mut template = if tokenizer_config.exists() {
+            Self::from_tokenizer_config(&tokenizer_config, fallback)?
+        } else {
+            Self::builtin(fallback)
+        };
+        if template.jinja.is_none() {
+            template.jinja = std::fs::read_to_string(dir.join("chat_template.jinja"))
+                .ok()
+                .filter(|t| !t.trim().is_empty());
+        }
+        Ok(template)
+    }
+
+    /// 读取 Hugging Face tokenizer_config.json 中的 chat_template 与特殊 token
+    pub fn from_tokenizer_config(path: &Path, fallback: ChatFormat) -> Result<Self> {
+        let config: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        // chat_template 可能是字符串，也可能是 [{name, template}] 列表
+        let jinja = match &config["chat_template"] {
+            serde_json::Value::String(template) => Some(template.clone()),
+            serde_json::Value::Array(templates) => templates
+                .iter()
+                .find(|t| t["name"] == "default")
+                .or_else(|| templates.first())
+                .and_then(|t| t["template"].as_str())
+                .map(str::to_string),
+            _ => None,
+        };
+        // 特殊 token 可能是字符串，也可能是 {"content": "..."} 对象
+        let special_token = |key: &str| {
+            let value = &config[key];
+            value
+                .as_str()
+                .or_else(|| value["content"].as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        Ok(Self {
+            format: fallback,
+            jinja,
+            bos_token: special_token("bos_token"),
+            eos_token: special_token("eos_token"),
+        })
+    }
+
+    /// 渲染对话；add_generation_prompt 为 true 时以助手回合开头结尾，供模型续写
+    pub fn render(&self, messages: &[ChatMessage], add_generation_prompt: bool) -> String {
+        if let Some(source) = &self.jinja {
+            match self.render_jinja(source, messages, add_generation_prompt) {
+                Ok(prompt) => return prompt,
+                Err(e) => tracing::warn!("Chat template rendering failed, falling back to {:?}: {}", self.format, e),
+            }
+        }
+        self.format.render(messages, add_generation_prompt)
+    }
+
+    fn render_jinja(&self, source: &str, messages: &[ChatMessage], add_generation_prompt: bool) -> Result<String> {
+        let mut env = minijinja::Environment::new();
+        // 与 transformers 的 apply_chat_template 保持一致
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+        env.add_function("raise_exception", |message: String| -> Result<String, minijinja::Error> {
+            Err(minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, message))
+        });
+        env.add_function("strftime_now", |format: String| chrono::Local::now().format(&format).to_string());
+        env.add_template("chat", source)?;
+
+        let prompt = env.get_template("chat")?.render(minijinja::context! {
+            messages => messages,
+            add_generation_prompt => add_generation_prompt,
+            bos_token => &self.bos_token,
+            eos_token => &self.eos_token,
+        })?;
+        Ok(prompt)
+    }
+}
+
+fn render_chatml(messages: &[ChatMessage], add_generation_prompt: bool, default_system: Option<&str>) -> String {
+    let mut prompt = String::new();
+    if let Some(system) = default_system
+        && messages.first().is_none_or(|m| m.role != ChatRole::System)
+    {
+        prompt.push_str(&format!("<|im_start|>system\n{}<|im_end|>\n", system));
+    }
+    for message in messages {
+        prompt.push_str(&format!("<|im_start|>{}\n{}<|im_end|>\n", message.role.as_str(), message.content));
+    }
+    if add_generation_prompt {
+        prompt.push_str("<|im_start|>assistant\n");
+    }
+    prompt
+}
+
+fn render_llama3(messages: &[ChatMessage], add_generation_prompt: bool) -> String {
+    let mut prompt = String::from("<|begin_of_text|>");
+    for message in messages {
+        // Llama 3.1 以 ipython 角色承载工具输出
+        let role = match message.role {
+            ChatRole::Tool => "ipython",
+            role => role.as_str(),
+        };
+        prompt.push_str(&format!(
+            "<|start_header_id|>{}<|end_header_id|>\n\n{}<|eot_id|>",
+            role,
+            message.content.trim()
+        ));
+    }
+    if add_generation_prompt {
+        prompt.push_str("<|start_header_id|>assistant<|end_header_id|>\n\n");
+    }
+    prompt
+}
+
+/// Mistral / Gemma 只有用户与助手两种回合：系统消息并入第一个用户回合，工具结果按用户回合处理
+fn fold_turns(messages: &[ChatMessage]) -> Vec<(bool, String)> {
+    let mut turns: Vec<(bool, String)> = Vec::new();
+    let mut system = String::new();
+    for message in messages {
+        let (is_assistant, content) = match message.role {
+            ChatRole::System => {
+                if !system.is_empty() {
+                    system.push_str("\n\n");
+                }
+                system.push_str(message.content.trim());
+                continue;
+            }
+            ChatRole::User => (false, message.content.trim().to_string()),
+            ChatRole::Tool => (
+                false,
+                format!("[{}] {}", message.name.as_deref().unwrap_or("tool"), message.content.trim()),
+            ),
+            ChatRole::Assistant => (true, message.content.trim().to_string()),
+        };
+        match turns.last_mut() {
+            // 连续的同类回合合并
+            Some((last_is_assistant, last)) if *last_is_assistant == is_assistant => {
+                last.push_str("\n\n");
+                last.push_str(&content);
+            }
+            _ => turns.push((is_assistant, content)),
+        }
+    }
+    if !system.is_empty() {
+        match turns.iter_mut().find(|(is_assistant, _)| !is_assistant) {
+            Some((_, first_user)) => *first_user = format!("{}\n\n{}", system, first_user),
+            None => turns.insert(0, (false, system)),
+        }
+    }
+    turns
+}
+
+fn render_mistral(messages: &[ChatMessage]) -> String {
+    let mut prompt = String::from("<s>");
+    for (is_assistant, content) in fold_turns(messages) {
+        if is_assistant {
+            prompt.push_str(&format!(" {}</s>", content));
+        } else {
+            prompt.push_str(&format!("[INST] {} [/INST]", content));
+        }
+    }
+    prompt
+}
+
+fn render_gemma(messages: &[ChatMessage], add_generation_prompt: bool) -> String {
+    let mut prompt = String::from("<bos>");
+    for (is_assistant, content) in fold_turns(messages) {
+        let role = if is_assistant { "model" } else { "user" };
+        prompt.push_str(&format!("<start_of_turn>{}\n{}<end_of_turn>\n", role, content));
+    }
+    if add_generation_prompt {
+        prompt.push_str("<start_of_turn>model\n");
+    }
+    prompt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_template_from_model_dir() {
+        let dir = std::env::temp_dir().join(format!("silo-template-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("config.json"), r#"{"model_type": "llama", "vocab_size": 128256}"#).unwrap();
+
+        // 只有 config.json 时按架构与词表大小选择内置格式
+        let template = ChatTemplate::from_model_dir(&dir).unwrap();
+        assert_eq!(template.format, ChatFormat::Llama3);
+        assert!(template.jinja.is_none());
+
+        std::fs::write(
+            dir.join("tokenizer_config.json"),
+            r#"{"bos_token": {"content": "<s>"}, "eos_token": "</s>",
+                "chat_template": [{"name": "tool_use", "template": "tools"}, {"name": "default", "template": "{{ bos_token }}{% for m in messages %}[{{ m.role }}]{{ m.content }}{% endfor %}"}]}"#,
+        )
+        .unwrap();
+        let template = ChatTemplate::from_model_dir(&dir).unwrap();
+        assert_eq!(template.bos_token, "<s>");
+        assert_eq!(template.eos_token, "</s>");
+        assert_eq!(template.render(&[ChatMessage::user("hi")], false), "<s>[user]hi");
+
+        // 新版模型把模板单独保存在 chat_template.jinja 中
+        std::fs::write(dir.join("tokenizer_config.json"), r#"{"eos_token": "</s>"}"#).unwrap();
+        std::fs::write(dir.join("chat_template.jinja"), "{% for m in messages %}{{ m.content }}{% endfor %}").unwrap();
+        let template = ChatTemplate::from_model_dir(&dir).unwrap();
+        assert_eq!(template.render(&[ChatMessage::user("hi")], false), "hi");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
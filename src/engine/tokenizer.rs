@@ -0,0 +1,87 @@
+// 分词器抽象 - 统一按模型 token 计算长度与截断
+// 可从 GGUF 内嵌词表或 HF tokenizer.json 加载；没有词表的远程后端使用估算
+
+use crate::engine::gguf::GgufFile;
+use crate::engine::llama::tokenizer::GgufTokenizer;
+use anyhow::Result;
+use std::path::Path;
+use std::sync::Arc;
+
+pub trait Tokenizer: Send + Sync {
+    /// 文本的 token 数（不含 BOS 等特殊 token）
+    fn count_tokens(&self, text: &str) -> usize;
+
+    /// 是否为模型的真实词表（估算时为 false）
+    fn is_exact(&self) -> bool {
+        true
+    }
+
+    /// 截取不超过 max_tokens 的最长前缀，截断位置总在字符边界上
+    fn truncate_to_tokens(&self, text: &str, max_tokens: usize) -> String {
+        if self.count_tokens(text) <= max_tokens {
+            return text.to_string();
+        }
+        // 前缀的 token 数随长度单调不减，二分查找字符边界
+        let boundaries: Vec<usize> = text.char_indices().map(|(i, _)| i).chain([text.len()]).collect();
+        let (mut lo, mut hi) = (0, boundaries.len() - 1);
+        while lo < hi {
+            let mid = (lo + hi).div_ceil(2);
+            if self.count_tokens(&text[..boundaries[mid]]) <= max_tokens {
+                lo = mid;
+            } else {
+                hi = mid - 1;
+            }
+        }
+        text[..boundaries[lo]].to_string()
+    }
+}
+
+impl Tokenizer for GgufTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        self.encode(text, false).len()
+    }
+}
+
+/// 没有词表时的估算：中日韩文字每字约 1 个 token，其他文字约 4 个字节 1 个 token
+pub struct EstimateTokenizer;
+
+impl Tokenizer for EstimateTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        let mut tokens = 0;
+        let mut run = 0usize;
+        for c in text.chars() {
+            if is_cjk(c) {
+                tokens += 1 + run.div_ceil(4);
+                run = 0;
+            } else if c.is_whitespace() {
+                tokens += run.div_ceil(4);
+                run = 0;
+            } else {
+                run += c.len_utf8();
+            }
+        }
+        tokens + run.div_ceil(4)
+    }
+
+    fn is_exact(&self) -> bool {
+        false
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32, 0x3000..=0x30FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xAC00..=0xD7AF | 0xF900..=0xFAFF | 0xFF00..=0xFFEF)
+}
+
+/// 加载分词器：GGUF 文件读取内嵌词表，.json 文件按 HF tokenizer.json 解析，
+/// 目录（如 MLX / HF 模型目录）读取其中的 tokenizer.json
+pub fn load(path: &Path) -> Result<Arc<dyn Tokenizer>> {
+    let file = if path.is_dir() { path.join("tokenizer.json") } else { path.to_path_buf() };
+    let tokenizer = if file.extension().is_some_and(|ext| ext == "json") {
+        let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&file)?)?;
+        GgufTokenizer::from_hf_json(&json)?
+    } else {
+        GgufTokenizer::from_gguf(&GgufFile::open(&file)?)?
+    };
+    tracing::info!("Loaded tokenizer from {:?} ({} tokens)", file, tokenizer.vocab_size());
+    Ok(Arc::new(tokenizer))
+}
@@ -3,20 +3,31 @@
 mod agent;
 mod engine;
 mod sandbox;
+mod server;
 mod swarm;
 mod vault;
 
 use agent::{AgentExecutor, AgentTask};
-use engine::{EngineManager, InferenceConfig};
+use engine::EngineManager;
+use engine::backend::{OllamaBackend, OpenAiCompatBackend};
+pub use engine::benchmark::BenchmarkConfig;
+use engine::benchmark::BenchmarkReport;
+pub use engine::context::{ContextConfig, ContextPolicy, ContextReport};
+pub use engine::fallback::BackendEvent;
+pub use engine::{ChatMessage, ChatRole, FinishReason, GenerationParams, InferenceStream, Priority};
+pub use tokio_util::sync::CancellationToken;
+use engine::store::{self as model_store, ModelStore};
 use sandbox::{SandboxConfig, SandboxExecutor};
+pub use server::{ServerConfig, ServerHandle};
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use vault::{Document, VaultDatabase};
+use vault::{Document, DocumentChunker, VaultDatabase};
 
 // 全局状态
 pub struct AppState {
     pub engine: Arc<RwLock<EngineManager>>,
+    pub models: Arc<RwLock<ModelStore>>,
     pub vault: Arc<RwLock<VaultDatabase>>,
     pub sandbox: Arc<RwLock<SandboxExecutor>>,
     pub agent: Arc<RwLock<AgentExecutor>>,
@@ -26,7 +37,82 @@ impl AppState {
     pub async fn new() -> anyhow::Result<Self> {
         // 初始化推理引擎
         let mut engine = EngineManager::new();
-        engine.detect_and_select_backend().await?;
+        if let Ok(endpoint) = std::env::var("SILO_OPENAI_ENDPOINT") {
+            let api_key = std::env::var("SILO_OPENAI_API_KEY").ok();
+            // 服务上的嵌入模型作为专用嵌入后端，向量空间不随对话模型变化
+            if let Ok(model) = std::env::var("SILO_OPENAI_EMBEDDING_MODEL") {
+                let mut backend = OpenAiCompatBackend::new(endpoint.clone()).with_embedding_model(model);
+                if let Some(api_key) = &api_key {
+                    backend = backend.with_api_key(api_key.clone());
+                }
+                if !backend.probe().await {
+                    tracing::warn!("OpenAI-compatible endpoint {} did not respond; embeddings will fail until it is up", endpoint);
+                }
+                engine.set_embedding_backend(Box::new(backend));
+            }
+            engine.set_openai_endpoint(endpoint, api_key, std::env::var("SILO_OPENAI_MODEL").ok());
+        }
+        if let Ok(endpoint) = std::env::var("SILO_OLLAMA_ENDPOINT") {
+            if let Ok(model) = std::env::var("SILO_OLLAMA_EMBEDDING_MODEL") {
+                let mut backend = OllamaBackend::new(endpoint.clone()).with_embedding_model(model);
+                if !backend.probe().await {
+                    tracing::warn!("Ollama endpoint {} did not respond; embeddings will fail until it is up", endpoint);
+                }
+                engine.set_embedding_backend(Box::new(backend));
+            }
+            engine.set_ollama_endpoint(endpoint, std::env::var("SILO_OLLAMA_MODEL").ok());
+        }
+        // 后端插件目录，默认 <data_dir>/silo/plugins
+        let plugin_dir = std::env::var("SILO_PLUGIN_DIR").map(PathBuf::from).unwrap_or_else(|_| {
+            dirs::data_dir()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join("silo")
+                .join("plugins")
+        });
+        engine.set_plugin_dir(plugin_dir);
+        // 本机的基准测试报告，检测后端时按实测速度选择
+        engine.set_benchmark_dir(BenchmarkReport::default_dir());
+        // 提示词前缀 KV 缓存的磁盘上限（MiB）；未设置或为 0 时只缓存在内存中
+        if let Some(cache_mb) = std::env::var("SILO_KV_CACHE_MB").ok().and_then(|v| v.parse::<u64>().ok()) {
+            engine.set_prefix_cache_config(engine::prefix_cache::PrefixCacheConfig {
+                disk_bytes: cache_mb * 1024 * 1024,
+                ..Default::default()
+            });
+        }
+        // SILO_SCRIPT 指定脚本文件或目录时只回放脚本，输出可复现（集成测试）
+        match std::env::var("SILO_SCRIPT") {
+            Ok(script) => {
+                let scripted = engine::scripted::ScriptedBackend::load(std::path::Path::new(&script))?;
+                engine.set_backends(vec![Box::new(scripted)]).await?;
+            }
+            Err(_) => {
+                engine.detect_and_select_backend().await?;
+            }
+        }
+        // SILO_RECORD 指定文件时把对话录制为脚本
+        if let Ok(path) = std::env::var("SILO_RECORD") {
+            engine.record_sessions(path).await?;
+        }
+        // 多模型驻留的内存预算（MiB），默认为物理内存的一半
+        if let Some(budget_mb) = std::env::var("SILO_MODEL_BUDGET_MB").ok().and_then(|v| v.parse::<u64>().ok()) {
+            engine.set_model_budget(budget_mb * 1024 * 1024);
+        }
+
+        // 初始化模型仓库
+        let models = ModelStore::open(ModelStore::default_root())?;
+
+        // SILO_MODEL_PATH 可以是文件路径，也可以是模型仓库中的模型名
+        if let Ok(model) = std::env::var("SILO_MODEL_PATH") {
+            let model_path = match models.get(&model) {
+                Some(installed) => installed.path.clone(),
+                None => PathBuf::from(model),
+            };
+            let config = engine.default_config_for(&model_path)?;
+            engine.initialize(config).await?;
+        }
+
+        // 后台定期探测降级链中的后端，熔断的后端恢复后重新启用
+        engine.spawn_health_checks(std::time::Duration::from_secs(30));
 
         // 初始化向量数据库
         let vault_path = dirs::data_dir()
@@ -34,7 +120,9 @@ impl AppState {
             .join("silo")
             .join("vault");
         std::fs::create_dir_all(&vault_path)?;
-        let vault = VaultDatabase::new(vault_path)?;
+        // 分块大小按当前模型的分词器计算
+        let chunker = DocumentChunker::default().with_tokenizer(engine.tokenizer());
+        let vault = VaultDatabase::new(vault_path)?.with_chunker(chunker);
 
         // 初始化沙箱
         let sandbox_config = SandboxConfig {
@@ -46,7 +134,8 @@ impl AppState {
 
         // 初始化 Agent
         let engine_arc = Arc::new(RwLock::new(engine));
-        let vault_arc = Arc::new(RwLock::new(vault));
+        // 有嵌入模型时按语义向量检索
+        let vault_arc = Arc::new(RwLock::new(vault.with_embedder(engine_arc.clone())));
         let sandbox_arc = Arc::new(RwLock::new(sandbox));
 
         let agent = AgentExecutor::new(
@@ -57,6 +146,7 @@ impl AppState {
 
         Ok(Self {
             engine: engine_arc,
+            models: Arc::new(RwLock::new(models)),
             vault: vault_arc,
             sandbox: sandbox_arc,
             agent: Arc::new(RwLock::new(agent)),
@@ -71,23 +161,310 @@ pub async fn get_backend_type(state: &AppState) -> Result<String, String> {
     Ok(format!("{:?}", engine.current_backend_type()))
 }
 
+pub async fn list_models(state: &AppState) -> Result<serde_json::Value, String> {
+    let engine = state.engine.read().await;
+    let models = engine.list_models().await.map_err(|e| e.to_string())?;
+    Ok(serde_json::to_value(models).unwrap())
+}
+
+pub async fn select_model(state: &AppState, model: String) -> Result<(), String> {
+    let engine = state.engine.read().await;
+    engine.select_model(&model).await.map_err(|e| e.to_string())
+}
+
+/// 多轮对话推理（不经过 Agent 检索与动作解析）
+pub async fn chat(
+    state: &AppState,
+    messages: Vec<ChatMessage>,
+    params: Option<GenerationParams>,
+) -> Result<serde_json::Value, String> {
+    let params = params.unwrap_or_default();
+    ensure_model_resident(state, &params).await?;
+    let engine = state.engine.read().await;
+    let response = engine
+        .chat(&messages, &params)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({
+        "content": response.tokens.join(""),
+        "finish_reason": response.finish_reason,
+        // 提示词超出上下文长度被裁剪时，向用户说明省略了哪些内容
+        "context_notice": response.metrics.context.as_ref().map(|report| report.describe()),
+        "metrics": response.metrics,
+    }))
+}
+
+/// 超出上下文长度时的裁剪策略
+pub async fn get_context_config(state: &AppState) -> Result<serde_json::Value, String> {
+    let engine = state.engine.read().await;
+    serde_json::to_value(engine.context_config()).map_err(|e| e.to_string())
+}
+
+/// 设置裁剪策略（丢弃最早的轮次 / 压缩为摘要 / 丢弃低相关度的检索片段）
+pub async fn set_context_config(state: &AppState, config: ContextConfig) -> Result<(), String> {
+    let engine = state.engine.read().await;
+    engine.set_context_config(config);
+    Ok(())
+}
+
+/// 检测后端时记录的硬件画像（内存、CPU 指令集、GPU）
+pub async fn get_hardware_profile(state: &AppState) -> Result<serde_json::Value, String> {
+    let engine = state.engine.read().await;
+    serde_json::to_value(engine.hardware_profile()).map_err(|e| e.to_string())
+}
+
+/// 已加载的后端插件
+pub async fn list_plugins(state: &AppState) -> Result<serde_json::Value, String> {
+    let engine = state.engine.read().await;
+    serde_json::to_value(engine.plugins()).map_err(|e| e.to_string())
+}
+
+/// 各后端的滚动性能统计（首 token 延迟、生成速度、token 用量）
+pub async fn get_backend_stats(state: &AppState) -> Result<serde_json::Value, String> {
+    let engine = state.engine.read().await;
+    serde_json::to_value(engine.backend_stats()).map_err(|e| e.to_string())
+}
+
+/// 提示词前缀 KV 缓存的命中情况（复用的 token 数、内存与磁盘占用）
+pub async fn get_prefix_cache_stats(state: &AppState) -> Result<serde_json::Value, String> {
+    let engine = state.engine.read().await;
+    serde_json::to_value(engine.prefix_cache_stats().await).map_err(|e| e.to_string())
+}
+
+/// 推理调度队列：各优先级（对话 / Agent / 后台）的排队深度、执行数与等待时间
+pub async fn get_scheduler_stats(state: &AppState) -> Result<serde_json::Value, String> {
+    let engine = state.engine.read().await;
+    serde_json::to_value(engine.scheduler_stats()).map_err(|e| e.to_string())
+}
+
+/// 对各后端与模型运行基准测试，报告写入 <data_dir>/silo/benchmarks（JSON 与 Markdown），下次启动时用于选择后端
+/// models 中的已安装模型先驻留再一并测试
+pub async fn run_benchmark(
+    state: &AppState,
+    config: BenchmarkConfig,
+    models: Vec<String>,
+    cancel: CancellationToken,
+) -> Result<serde_json::Value, String> {
+    for name in models {
+        ensure_model_resident(state, &GenerationParams::default().with_model(name)).await?;
+    }
+    let engine = state.engine.read().await;
+    let report = engine.run_benchmark(&config, cancel).await.map_err(|e| e.to_string())?;
+    report.save(&BenchmarkReport::default_dir()).map_err(|e| e.to_string())?;
+    serde_json::to_value(report).map_err(|e| e.to_string())
+}
+
+/// 本机最近一次的基准测试报告，没有时返回 null
+pub async fn get_benchmark_report(state: &AppState) -> Result<serde_json::Value, String> {
+    let engine = state.engine.read().await;
+    let Some(profile) = engine.hardware_profile() else {
+        return Ok(serde_json::Value::Null);
+    };
+    let report = BenchmarkReport::load(&BenchmarkReport::default_dir(), profile).map_err(|e| e.to_string())?;
+    serde_json::to_value(report).map_err(|e| e.to_string())
+}
+
+/// 降级链中各后端的健康状态（熔断状态、连续失败次数、最近错误）
+pub async fn get_backend_health(state: &AppState) -> Result<serde_json::Value, String> {
+    let engine = state.engine.read().await;
+    serde_json::to_value(engine.backend_health().await).map_err(|e| e.to_string())
+}
+
+/// 订阅后端路由事件（降级、熔断、恢复），用于界面提示当前由哪个后端响应
+pub async fn subscribe_backend_events(state: &AppState) -> tokio::sync::broadcast::Receiver<BackendEvent> {
+    let engine = state.engine.read().await;
+    engine.subscribe_events()
+}
+
+/// 多轮对话流式推理；丢弃返回的流或触发 cancel 即停止生成
+pub async fn chat_stream(
+    state: &AppState,
+    messages: Vec<ChatMessage>,
+    params: Option<GenerationParams>,
+    cancel: CancellationToken,
+) -> Result<InferenceStream, String> {
+    let params = params.unwrap_or_default();
+    ensure_model_resident(state, &params).await?;
+    let engine = state.engine.read().await;
+    engine
+        .chat_stream(&messages, &params, cancel)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+pub async fn inspect_model(path: String) -> Result<serde_json::Value, String> {
+    let info = EngineManager::inspect_model(&PathBuf::from(path)).map_err(|e| e.to_string())?;
+    Ok(serde_json::to_value(info).unwrap())
+}
+
+/// 加载前估算模型的内存占用（权重 + KV 缓存 + 开销）与当前可用内存
+/// model 可以是模型仓库中的模型名，也可以是文件路径；context_size 为空时使用默认上下文
+pub async fn estimate_model_memory(
+    state: &AppState,
+    model: String,
+    context_size: Option<usize>,
+) -> Result<serde_json::Value, String> {
+    let model_path = match state.models.read().await.get(&model) {
+        Some(installed) => installed.path.clone(),
+        None => PathBuf::from(model),
+    };
+    let report = tokio::task::spawn_blocking(move || EngineManager::estimate_memory(&model_path, context_size))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+    serde_json::to_value(report).map_err(|e| e.to_string())
+}
+
+pub async fn list_installed_models(state: &AppState) -> Result<serde_json::Value, String> {
+    let models = state.models.read().await;
+    Ok(serde_json::to_value(models.list()).unwrap())
+}
+
+pub async fn list_hf_cached_models() -> Result<serde_json::Value, String> {
+    let files = model_store::scan_hf_cache().map_err(|e| e.to_string())?;
+    Ok(serde_json::to_value(files).unwrap())
+}
+
+pub async fn import_model(state: &AppState, path: String, name: Option<String>) -> Result<serde_json::Value, String> {
+    // 计算 SHA-256 需要读取整个文件，放到阻塞线程执行
+    let models = state.models.clone();
+    let model = tokio::task::spawn_blocking(move || {
+        models.blocking_write().import_from_path(&PathBuf::from(path), name.as_deref())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())?;
+    Ok(serde_json::to_value(model).unwrap())
+}
+
+pub async fn import_hf_model(
+    state: &AppState,
+    repo_id: String,
+    file_name: Option<String>,
+    name: Option<String>,
+) -> Result<serde_json::Value, String> {
+    let models = state.models.clone();
+    let model = tokio::task::spawn_blocking(move || {
+        models
+            .blocking_write()
+            .import_from_hf_cache(&repo_id, file_name.as_deref(), name.as_deref())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())?;
+    Ok(serde_json::to_value(model).unwrap())
+}
+
+pub async fn verify_model(state: &AppState, name: String) -> Result<bool, String> {
+    let models = state.models.clone();
+    tokio::task::spawn_blocking(move || models.blocking_read().verify(&name))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+pub async fn delete_model(state: &AppState, name: String) -> Result<(), String> {
+    let mut models = state.models.write().await;
+    models.delete(&name).map_err(|e| e.to_string())
+}
+
+/// 加载模型仓库中的模型到当前后端
+pub async fn load_model(state: &AppState, name: String) -> Result<(), String> {
+    let model_path = {
+        let models = state.models.read().await;
+        models
+            .get(&name)
+            .map(|m| m.path.clone())
+            .ok_or_else(|| format!("Model '{}' is not installed", name))?
+    };
+    let mut engine = state.engine.write().await;
+    let config = engine.default_config_for(&model_path).map_err(|e| e.to_string())?;
+    engine.initialize(config).await.map_err(|e| e.to_string())
+}
+
+/// 请求指定的模型尚未驻留时，从模型仓库加载
+async fn ensure_model_resident(state: &AppState, params: &GenerationParams) -> Result<(), String> {
+    match &params.model {
+        Some(model) if !state.engine.read().await.is_model_loaded(model) => preload_model(state, model.clone()).await,
+        _ => Ok(()),
+    }
+}
+
+/// 将模型仓库中的模型以其名称驻留，之后的请求可通过 GenerationParams::model 指定
+pub async fn preload_model(state: &AppState, name: String) -> Result<(), String> {
+    let model_path = {
+        let models = state.models.read().await;
+        models
+            .get(&name)
+            .map(|m| m.path.clone())
+            .ok_or_else(|| format!("Model '{}' is not installed", name))?
+    };
+    let engine = state.engine.read().await;
+    engine.load_model(&name, &model_path).await.map_err(|e| e.to_string())
+}
+
+pub async fn unload_model(state: &AppState, name: String) -> Result<bool, String> {
+    let engine = state.engine.read().await;
+    Ok(engine.unload_model(&name))
+}
+
+/// 当前驻留的模型及其内存占用
+pub async fn list_resident_models(state: &AppState) -> Result<serde_json::Value, String> {
+    let engine = state.engine.read().await;
+    serde_json::to_value(engine.resident_models()).map_err(|e| e.to_string())
+}
+
+/// 将模型仓库中的模型加载为专用的嵌入模型，并重新计算已入库文档的向量
+pub async fn load_embedding_model(state: &AppState, name: String) -> Result<usize, String> {
+    let model_path = {
+        let models = state.models.read().await;
+        models
+            .get(&name)
+            .map(|m| m.path.clone())
+            .ok_or_else(|| format!("Model '{}' is not installed", name))?
+    };
+    {
+        let engine = state.engine.read().await;
+        engine.load_embedding_model(&model_path).await.map_err(|e| e.to_string())?;
+    }
+    let vault = state.vault.read().await;
+    vault.reindex().await.map_err(|e| e.to_string())
+}
+
+/// 批量计算文本向量
+pub async fn embed_texts(state: &AppState, texts: Vec<String>) -> Result<Vec<Vec<f32>>, String> {
+    let engine = state.engine.read().await;
+    engine.embed(&texts).await.map_err(|e| e.to_string())
+}
+
 pub async fn get_vault_stats(state: &AppState) -> Result<serde_json::Value, String> {
     let vault = state.vault.read().await;
     let count = vault.document_count().await;
     Ok(serde_json::json!({ "document_count": count }))
 }
 
+/// 执行 Agent 任务；触发 cancel 可中止正在进行的推理与后续动作
 pub async fn execute_agent_task(
     state: &AppState,
     instruction: String,
     context: Option<String>,
+    cancel: CancellationToken,
 ) -> Result<serde_json::Value, String> {
-    let task = AgentTask { instruction, context };
+    let task = AgentTask {
+        instruction,
+        context,
+        history: vec![],
+    };
     let agent = state.agent.read().await;
-    let response = agent.execute(task).await.map_err(|e: anyhow::Error| e.to_string())?;
+    let response = agent.execute(task, cancel).await.map_err(|e: anyhow::Error| e.to_string())?;
     Ok(serde_json::to_value(response).unwrap())
 }
 
+/// 启动本地 OpenAI 兼容 API 服务（/v1/chat/completions、/v1/completions、/v1/embeddings、/v1/models、/v1/silo/agent）
+pub async fn start_api_server(state: Arc<AppState>, config: ServerConfig) -> Result<ServerHandle, String> {
+    server::serve(state, config).await.map_err(|e| e.to_string())
+}
+
 pub async fn add_document(
     state: &AppState,
     content: String,
@@ -3,7 +3,7 @@
 mod ui;
 
 use gpui::{App, Application, Bounds, Context, CursorStyle, Entity, SharedString, Window, WindowBounds, WindowOptions, div, prelude::*, px, rgb, size};
-use silo_lib::{execute_agent_task, get_backend_type, get_vault_stats, AppState};
+use silo_lib::{execute_agent_task, get_backend_type, get_vault_stats, start_api_server, AppState, CancellationToken, ServerConfig};
 use std::sync::Arc;
 use ui::{key_bindings, TextInput};
 
@@ -31,6 +31,8 @@ struct SiloApp {
     document_count: u64,
     artifacts: Vec<Artifact>,
     isLoading: bool,
+    /// 当前任务的取消令牌，点击“停止”时触发
+    cancel: Option<CancellationToken>,
     error: Option<SharedString>,
 }
 
@@ -67,6 +69,8 @@ fn example_prompt_div(
                 content: input.clone().into(),
             });
             this.isLoading = true;
+            let cancel = CancellationToken::new();
+            this.cancel = Some(cancel.clone());
             cx.notify();
 
             let state = this.state.clone();
@@ -77,7 +81,7 @@ fn example_prompt_div(
                         let rt = tokio::runtime::Runtime::new().unwrap();
                         rt.block_on(async move {
                             if let Some(ref s) = state {
-                                execute_agent_task(s.as_ref(), input, None).await
+                                execute_agent_task(s.as_ref(), input, None, cancel).await
                             } else {
                                 Err("未初始化".into())
                             }
@@ -89,6 +93,7 @@ fn example_prompt_div(
                     if let Ok(view) = root.downcast::<SiloApp>() {
                         view.update(cx, |app, cx| {
                             app.isLoading = false;
+                            app.cancel = None;
                             match result {
                                 Ok(response) => {
                                     let reasoning = response
@@ -96,6 +101,12 @@ fn example_prompt_div(
                                         .and_then(|v| v.as_str())
                                         .unwrap_or("任务完成")
                                         .to_string();
+                                    // 用户点击停止时保留已生成的内容并标注
+                                    let reasoning = if response.get("finish_reason").and_then(|v| v.as_str()) == Some("cancelled") {
+                                        format!("{}\n\n（已停止）", reasoning)
+                                    } else {
+                                        reasoning
+                                    };
                                     app.messages.push(Message {
                                         id: format!("{}", chrono::Utc::now().timestamp_millis()),
                                         role: Role::Assistant,
@@ -165,6 +176,7 @@ impl SiloApp {
             document_count,
             artifacts: vec![],
             isLoading: false,
+            cancel: None,
             error: init_error.map(Into::into),
         }
     }
@@ -456,9 +468,16 @@ impl Render for SiloApp {
                                     .bg(amber)
                                     .text_color(charcoal)
                                     .rounded_sm()
-                                    .child("执行")
+                                    .child(if loading { "停止" } else { "执行" })
                                     .cursor_pointer()
                                     .on_click(cx.listener(move |this, _, window, cx| {
+                                        // 任务进行中时按钮作为停止按钮
+                                        if this.isLoading {
+                                            if let Some(cancel) = &this.cancel {
+                                                cancel.cancel();
+                                            }
+                                            return;
+                                        }
                                         let input = this
                                             .text_input
                                             .update(cx, |ti, cx| {
@@ -478,6 +497,8 @@ impl Render for SiloApp {
                                             content: input.clone().into(),
                                         });
                                         this.isLoading = true;
+                                        let cancel = CancellationToken::new();
+                                        this.cancel = Some(cancel.clone());
                                         cx.notify();
 
             let state = this.state.clone();
@@ -488,7 +509,7 @@ impl Render for SiloApp {
                         let rt = tokio::runtime::Runtime::new().unwrap();
                         rt.block_on(async move {
                             if let Some(ref s) = state {
-                                execute_agent_task(s.as_ref(), input, None).await
+                                execute_agent_task(s.as_ref(), input, None, cancel).await
                             } else {
                                 Err("未初始化".into())
                             }
@@ -500,6 +521,7 @@ impl Render for SiloApp {
                     if let Ok(view) = root.downcast::<SiloApp>() {
                         view.update(cx, |app, cx| {
                             app.isLoading = false;
+                            app.cancel = None;
                             match result {
                                                             Ok(response) => {
                                                                 let reasoning = response
@@ -507,6 +529,12 @@ impl Render for SiloApp {
                                                                     .and_then(|v| v.as_str())
                                                                     .unwrap_or("任务完成")
                                                                     .to_string();
+                                                                // 用户点击停止时保留已生成的内容并标注
+                                                                let reasoning = if response.get("finish_reason").and_then(|v| v.as_str()) == Some("cancelled") {
+                                                                    format!("{}\n\n（已停止）", reasoning)
+                                                                } else {
+                                                                    reasoning
+                                                                };
                                                                 app.messages.push(Message {
                                                                     id: format!(
                                                                         "{}",
@@ -609,6 +637,22 @@ fn main() {
         ),
     };
 
+    // 设置 SILO_API_ADDR 时启动本地 OpenAI 兼容 API 服务；服务在 rt 的工作线程上运行，句柄随进程存活
+    let _api_server = match (&state, ServerConfig::from_env()) {
+        (Some(state), Ok(Some(config))) => match rt.block_on(start_api_server(state.clone(), config)) {
+            Ok(handle) => Some(handle),
+            Err(e) => {
+                tracing::error!("Failed to start API server: {}", e);
+                None
+            }
+        },
+        (_, Err(e)) => {
+            tracing::error!("Invalid API server config: {}", e);
+            None
+        }
+        _ => None,
+    };
+
     Application::new().run(move |cx: &mut App| {
         cx.bind_keys(key_bindings());
         let bounds = Bounds::centered(None, size(px(1400.), px(900.)), cx);
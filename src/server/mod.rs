@@ -0,0 +1,714 @@
+// 本地 OpenAI 兼容 API 服务 - 已经支持 OpenAI API 的编辑器插件与脚本无需新客户端
+// 即可使用 Silo 的本地模型与知识库；默认只监听本机回环地址
+
+pub mod openai;
+
+use crate::AppState;
+use crate::engine::{GenerationParams, InferenceStream};
+use anyhow::Result;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use openai::{
+    AgentRequest, ChatCompletionRequest, CompletionRequest, EmbeddingRequest, OneOrMany, RequestMessage, SamplingOptions, Usage,
+};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+/// 请求未指定本地模型时使用当前后端，对外的模型名
+pub const DEFAULT_MODEL: &str = "silo";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerConfig {
+    pub addr: SocketAddr,
+    /// 设置后请求须携带 Authorization: Bearer <api_key>
+    pub api_key: Option<String>,
+    /// 允许监听非回环地址（局域网内的其他设备可以访问）
+    pub allow_remote: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            addr: SocketAddr::from(([127, 0, 0, 1], 8765)),
+            api_key: None,
+            allow_remote: false,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// 从环境变量读取：SILO_API_ADDR（地址或端口）、SILO_API_KEY、SILO_API_ALLOW_REMOTE
+    /// 未设置 SILO_API_ADDR 时返回 None（不启动服务）
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(addr) = std::env::var("SILO_API_ADDR") else {
+            return Ok(None);
+        };
+        let addr = match addr.parse::<u16>() {
+            Ok(port) => SocketAddr::from(([127, 0, 0, 1], port)),
+            Err(_) => addr.parse().map_err(|e| anyhow::anyhow!("Invalid SILO_API_ADDR '{}': {}", addr, e))?,
+        };
+        Ok(Some(Self {
+            addr,
+            api_key: std::env::var("SILO_API_KEY").ok().filter(|k| !k.is_empty()),
+            allow_remote: std::env::var("SILO_API_ALLOW_REMOTE").is_ok_and(|v| v == "1" || v == "true"),
+        }))
+    }
+}
+
+/// 运行中的服务；丢弃句柄不会停止服务，需调用 shutdown
+pub struct ServerHandle {
+    addr: SocketAddr,
+    shutdown: CancellationToken,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl ServerHandle {
+    /// 实际监听的地址（配置端口为 0 时由系统分配）
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 停止接受新连接，等待进行中的请求结束
+    pub async fn shutdown(self) {
+        self.shutdown.cancel();
+        let _ = self.task.await;
+    }
+}
+
+/// 启动服务
+pub async fn serve(state: Arc<AppState>, config: ServerConfig) -> Result<ServerHandle> {
+    if !config.addr.ip().is_loopback() {
+        if !config.allow_remote {
+            anyhow::bail!("Refusing to listen on non-loopback address {} (set allow_remote to expose the API)", config.addr);
+        }
+        if config.api_key.is_none() {
+            tracing::warn!("API server on {} is reachable from the network without an API key", config.addr);
+        }
+    }
+    let listener = tokio::net::TcpListener::bind(config.addr).await?;
+    let addr = listener.local_addr()?;
+    let app = router(state, config.api_key, addr.ip().is_loopback());
+
+    let shutdown = CancellationToken::new();
+    let token = shutdown.clone();
+    let task = tokio::spawn(async move {
+        let result = axum::serve(listener, app)
+            .with_graceful_shutdown(async move { token.cancelled().await })
+            .await;
+        if let Err(e) = result {
+            tracing::error!("API server stopped: {}", e);
+        }
+    });
+    tracing::info!("OpenAI-compatible API listening on http://{}/v1", addr);
+    Ok(ServerHandle { addr, shutdown, task })
+}
+
+fn router(state: Arc<AppState>, api_key: Option<String>, loopback: bool) -> Router {
+    // Agent 任务可以在本机执行代码：未设置 API 密钥时不开放，否则本机任何进程都能借此执行代码
+    let agent_route = if api_key.is_some() { post(agent) } else { post(agent_disabled) };
+    Router::new()
+        .route("/v1/models", get(list_models))
+        .route("/v1/chat/completions", post(chat_completions))
+        .route("/v1/completions", post(completions))
+        .route("/v1/embeddings", post(embeddings))
+        .route("/v1/silo/agent", agent_route)
+        .layer(middleware::from_fn_with_state(api_key.map(Arc::<str>::from), authorize))
+        .layer(middleware::from_fn_with_state(loopback, check_host))
+        .with_state(state)
+}
+
+/// OpenAI 格式的错误响应
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            message: message.into(),
+        }
+    }
+
+    fn internal(message: impl ToString) -> Self {
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: message.to_string(),
+        }
+    }
+
+    /// 错误响应体；流式响应中途出错时作为 error 事件的数据
+    fn body(&self) -> serde_json::Value {
+        let kind = match self.status {
+            StatusCode::UNAUTHORIZED => "authentication_error",
+            status if status.is_client_error() => "invalid_request_error",
+            _ => "server_error",
+        };
+        serde_json::json!({
+            "error": { "message": self.message, "type": kind, "code": null },
+        })
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body())).into_response()
+    }
+}
+
+async fn authorize(State(api_key): State<Option<Arc<str>>>, request: Request, next: Next) -> Response {
+    if let Some(key) = &api_key {
+        let provided = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        if provided != Some(&**key) {
+            return ApiError {
+                status: StatusCode::UNAUTHORIZED,
+                message: "Invalid API key".to_string(),
+            }
+            .into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// 只监听回环地址时拒绝 Host 不是本机的请求，防止网页通过 DNS 重绑定访问本地服务
+async fn check_host(State(loopback): State<bool>, request: Request, next: Next) -> Response {
+    let host = request.headers().get(header::HOST).and_then(|v| v.to_str().ok());
+    if loopback && host.is_some_and(|host| !is_loopback_host(host)) {
+        return ApiError {
+            status: StatusCode::FORBIDDEN,
+            message: "Host not allowed".to_string(),
+        }
+        .into_response();
+    }
+    next.run(request).await
+}
+
+/// Host 头（可带端口）是否指向本机：localhost、127.0.0.0/8 或 [::1]
+fn is_loopback_host(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+    };
+    name.eq_ignore_ascii_case("localhost") || name.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// 请求体解析失败时返回 OpenAI 格式的错误，而不是 axum 默认的纯文本
+fn parse<T>(body: Result<Json<T>, JsonRejection>) -> Result<T, ApiError> {
+    body.map(|Json(value)| value).map_err(|e| ApiError::bad_request(e.body_text()))
+}
+
+/// 请求中的 model 是模型仓库中已安装（或已驻留）的模型时按需驻留并使用它；
+/// 其他名称（包括 "silo"）由当前后端降级链处理
+async fn resolve_model(state: &AppState, model: Option<&str>) -> Result<Option<String>, ApiError> {
+    let Some(model) = model.filter(|m| !m.is_empty() && *m != DEFAULT_MODEL) else {
+        return Ok(None);
+    };
+    let known = state.models.read().await.get(model).is_some() || state.engine.read().await.is_model_loaded(model);
+    if !known {
+        return Ok(None);
+    }
+    crate::ensure_model_resident(state, &GenerationParams::default().with_model(model))
+        .await
+        .map_err(ApiError::internal)?;
+    Ok(Some(model.to_string()))
+}
+
+async fn params_for(state: &AppState, options: &SamplingOptions) -> Result<GenerationParams, ApiError> {
+    let model = resolve_model(state, options.model.as_deref()).await?;
+    options.to_params(model).map_err(ApiError::bad_request)
+}
+
+async fn list_models(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let mut ids = vec![DEFAULT_MODEL.to_string()];
+    ids.extend(state.models.read().await.list().iter().map(|m| m.name.clone()));
+    ids.extend(state.engine.read().await.resident_models().into_iter().map(|m| m.id));
+    let mut seen = std::collections::HashSet::new();
+    ids.retain(|id| seen.insert(id.clone()));
+    let data: Vec<_> = ids
+        .into_iter()
+        .map(|id| serde_json::json!({ "id": id, "object": "model", "created": 0, "owned_by": "silo" }))
+        .collect();
+    Json(serde_json::json!({ "object": "list", "data": data }))
+}
+
+async fn chat_completions(
+    State(state): State<Arc<AppState>>,
+    body: Result<Json<ChatCompletionRequest>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let request = parse(body)?;
+    let constraint = request.constraint();
+    let messages = request
+        .messages
+        .into_iter()
+        .map(RequestMessage::into_chat_message)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(ApiError::bad_request)?;
+    if messages.is_empty() {
+        return Err(ApiError::bad_request("messages must not be empty"));
+    }
+    let mut params = params_for(&state, &request.options).await?;
+    params.constraint = constraint;
+    let format = ChunkFormat::new(ResponseKind::Chat, &request.options);
+
+    let engine = state.engine.read().await;
+    if request.options.stream {
+        let stream = engine
+            .chat_stream(&messages, &params, CancellationToken::new())
+            .await
+            .map_err(ApiError::internal)?;
+        return sse(stream, format, request.options.include_usage()).await;
+    }
+    let response = engine.chat(&messages, &params).await.map_err(ApiError::internal)?;
+    let Some(finish_reason) = openai::finish_reason(response.finish_reason) else {
+        return Err(ApiError::internal("Generation failed"));
+    };
+    let mut body = serde_json::json!({
+        "id": format.id,
+        "object": "chat.completion",
+        "created": format.created,
+        "model": format.model,
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": response.tokens.concat() },
+            "finish_reason": finish_reason,
+        }],
+        "usage": Usage::from_metrics(&response.metrics),
+    });
+    add_context_notice(&mut body, &response.metrics);
+    Ok(Json(body).into_response())
+}
+
+/// 文本补全：提示词作为单条用户消息，经模型的对话模板渲染
+async fn completions(
+    State(state): State<Arc<AppState>>,
+    body: Result<Json<CompletionRequest>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let request = parse(body)?;
+    let prompt = match request.prompt {
+        OneOrMany::One(prompt) => prompt,
+        OneOrMany::Many(mut prompts) if prompts.len() == 1 => prompts.remove(0),
+        OneOrMany::Many(_) => return Err(ApiError::bad_request("Only a single prompt is supported")),
+    };
+    let params = params_for(&state, &request.options).await?;
+    let messages = [crate::engine::ChatMessage::user(prompt)];
+    let format = ChunkFormat::new(ResponseKind::Completion, &request.options);
+
+    let engine = state.engine.read().await;
+    if request.options.stream {
+        let stream = engine
+            .chat_stream(&messages, &params, CancellationToken::new())
+            .await
+            .map_err(ApiError::internal)?;
+        return sse(stream, format, request.options.include_usage()).await;
+    }
+    let response = engine.chat(&messages, &params).await.map_err(ApiError::internal)?;
+    let Some(finish_reason) = openai::finish_reason(response.finish_reason) else {
+        return Err(ApiError::internal("Generation failed"));
+    };
+    let mut body = serde_json::json!({
+        "id": format.id,
+        "object": "text_completion",
+        "created": format.created,
+        "model": format.model,
+        "choices": [{
+            "index": 0,
+            "text": response.tokens.concat(),
+            "logprobs": null,
+            "finish_reason": finish_reason,
+        }],
+        "usage": Usage::from_metrics(&response.metrics),
+    });
+    add_context_notice(&mut body, &response.metrics);
+    Ok(Json(body).into_response())
+}
+
+async fn embeddings(
+    State(state): State<Arc<AppState>>,
+    body: Result<Json<EmbeddingRequest>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let request = parse(body)?;
+    let base64 = match request.encoding_format.as_deref() {
+        None | Some("float") => false,
+        Some("base64") => true,
+        Some(other) => return Err(ApiError::bad_request(format!("Unsupported encoding_format: {}", other))),
+    };
+    let texts = request.input.into_vec();
+    if texts.is_empty() {
+        return Err(ApiError::bad_request("input must not be empty"));
+    }
+
+    let engine = state.engine.read().await;
+    let vectors = engine.embed(&texts).await.map_err(ApiError::internal)?;
+    let model = engine.embedding_model().await.or(request.model).unwrap_or_else(|| DEFAULT_MODEL.to_string());
+    let prompt_tokens: usize = texts.iter().map(|t| engine.count_tokens(t)).sum();
+    let data: Vec<_> = vectors
+        .iter()
+        .enumerate()
+        .map(|(index, vector)| {
+            let embedding = if base64 {
+                serde_json::json!(openai::encode_base64(vector))
+            } else {
+                serde_json::json!(vector)
+            };
+            serde_json::json!({ "object": "embedding", "index": index, "embedding": embedding })
+        })
+        .collect();
+    Ok(Json(serde_json::json!({
+        "object": "list",
+        "data": data,
+        "model": model,
+        "usage": { "prompt_tokens": prompt_tokens, "total_tokens": prompt_tokens },
+    }))
+    .into_response())
+}
+
+/// 执行 Agent 任务（知识库检索 + 推理 + 动作执行）；客户端断开时取消任务
+async fn agent(
+    State(state): State<Arc<AppState>>,
+    body: Result<Json<AgentRequest>, JsonRejection>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let request = parse(body)?;
+    let cancel = CancellationToken::new();
+    let _guard = cancel.clone().drop_guard();
+    let response = crate::execute_agent_task(&state, request.instruction, request.context, cancel)
+        .await
+        .map_err(ApiError::internal)?;
+    Ok(Json(response))
+}
+
+async fn agent_disabled() -> ApiError {
+    ApiError {
+        status: StatusCode::FORBIDDEN,
+        message: "The agent endpoint requires an API key (set SILO_API_KEY)".to_string(),
+    }
+}
+
+/// 提示词被裁剪时附带说明（OpenAI 格式之外的扩展字段）
+fn add_context_notice(body: &mut serde_json::Value, metrics: &crate::engine::InferenceMetrics) {
+    if let Some(report) = &metrics.context {
+        body["context_notice"] = serde_json::json!(report.describe());
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ResponseKind {
+    Chat,
+    Completion,
+}
+
+/// 流式响应中各事件的格式
+struct ChunkFormat {
+    kind: ResponseKind,
+    id: String,
+    created: i64,
+    model: String,
+}
+
+impl ChunkFormat {
+    fn new(kind: ResponseKind, options: &SamplingOptions) -> Self {
+        let prefix = match kind {
+            ResponseKind::Chat => "chatcmpl",
+            ResponseKind::Completion => "cmpl",
+        };
+        Self {
+            kind,
+            id: format!("{}-{}", prefix, uuid::Uuid::new_v4().simple()),
+            created: chrono::Utc::now().timestamp(),
+            model: options.model.clone().unwrap_or_else(|| DEFAULT_MODEL.to_string()),
+        }
+    }
+
+    fn chunk(&self, text: Option<&str>, finish_reason: Option<&str>) -> serde_json::Value {
+        let choice = match self.kind {
+            ResponseKind::Chat => {
+                let delta = match text {
+                    Some(text) => serde_json::json!({ "content": text }),
+                    None if finish_reason.is_none() => serde_json::json!({ "role": "assistant", "content": "" }),
+                    None => serde_json::json!({}),
+                };
+                serde_json::json!({ "index": 0, "delta": delta, "finish_reason": finish_reason })
+            }
+            ResponseKind::Completion => {
+                serde_json::json!({ "index": 0, "text": text.unwrap_or(""), "logprobs": null, "finish_reason": finish_reason })
+            }
+        };
+        self.envelope(vec![choice])
+    }
+
+    fn usage(&self, usage: Usage) -> serde_json::Value {
+        let mut chunk = self.envelope(vec![]);
+        chunk["usage"] = serde_json::json!(usage);
+        chunk
+    }
+
+    fn envelope(&self, choices: Vec<serde_json::Value>) -> serde_json::Value {
+        let object = match self.kind {
+            ResponseKind::Chat => "chat.completion.chunk",
+            ResponseKind::Completion => "text_completion",
+        };
+        serde_json::json!({
+            "id": self.id,
+            "object": object,
+            "created": self.created,
+            "model": self.model,
+            "choices": choices,
+        })
+    }
+}
+
+/// 推理流转换为 SSE：每段文本一个事件，最后是结束原因、（可选）用量与 [DONE]
+/// 第一段文本之前就失败时返回 HTTP 错误；开始输出后失败则发送 error 事件并结束，不发送 [DONE]
+/// 客户端断开后发送失败，推理流随之丢弃并停止生成
+async fn sse(mut stream: InferenceStream, format: ChunkFormat, include_usage: bool) -> Result<Response, ApiError> {
+    let first = stream.next_token().await;
+    if first.is_none() && stream.finish_reason().and_then(openai::finish_reason).is_none() {
+        return Err(ApiError::internal("Generation failed"));
+    }
+    let (tx, rx) = tokio::sync::mpsc::channel::<Event>(32);
+    tokio::spawn(async move {
+        let event = |value: serde_json::Value| Event::default().data(value.to_string());
+        if matches!(format.kind, ResponseKind::Chat) && tx.send(event(format.chunk(None, None))).await.is_err() {
+            return;
+        }
+        if let Some(token) = first {
+            if tx.send(event(format.chunk(Some(&token), None))).await.is_err() {
+                return;
+            }
+            while let Some(token) = stream.next_token().await {
+                if tx.send(event(format.chunk(Some(&token), None))).await.is_err() {
+                    return;
+                }
+            }
+        }
+        let Some(reason) = stream.finish_reason().and_then(openai::finish_reason) else {
+            let error = ApiError::internal("Generation failed");
+            let _ = tx.send(Event::default().event("error").data(error.body().to_string())).await;
+            return;
+        };
+        let _ = tx.send(event(format.chunk(None, Some(reason)))).await;
+        if include_usage && let Some(metrics) = stream.metrics() {
+            let _ = tx.send(event(format.usage(Usage::from_metrics(metrics)))).await;
+        }
+        let _ = tx.send(Event::default().data("[DONE]")).await;
+    });
+    let events = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|event| (Ok::<_, Infallible>(event), rx))
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::AgentExecutor;
+    use crate::engine::scripted::{PromptMatch, ScriptEntry, ScriptResponse, ScriptedBackend};
+    use crate::engine::store::ModelStore;
+    use crate::engine::{EngineManager, FinishReason};
+    use crate::sandbox::{SandboxConfig, SandboxExecutor};
+    use crate::vault::VaultDatabase;
+    use axum::body::Body;
+    use tokio::sync::RwLock;
+    use tower::ServiceExt;
+
+    fn entry(prompt: &str, tokens: &[&str], finish_reason: FinishReason) -> ScriptEntry {
+        ScriptEntry {
+            matcher: PromptMatch::Exact(prompt.to_string()),
+            response: ScriptResponse::Tokens(tokens.iter().map(|t| t.to_string()).collect()),
+            finish_reason,
+            usage: None,
+        }
+    }
+
+    /// 只有脚本后端的 AppState，模型仓库与知识库放在临时目录中
+    async fn state(name: &str) -> Arc<AppState> {
+        let dir = std::env::temp_dir().join(format!("silo-server-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut engine = EngineManager::new();
+        engine
+            .set_backends(vec![Box::new(ScriptedBackend::new(vec![
+                entry("hello", &["Hi", " there"], FinishReason::Stop),
+                entry("broken", &[], FinishReason::Error),
+                entry("crash", &["Partial"], FinishReason::Error),
+            ]))])
+            .await
+            .unwrap();
+        let engine = Arc::new(RwLock::new(engine));
+        let vault = Arc::new(RwLock::new(VaultDatabase::new(dir.join("vault")).unwrap()));
+        let sandbox = Arc::new(RwLock::new(
+            SandboxExecutor::new(SandboxConfig {
+                memory_limit: 64 * 1024 * 1024,
+                timeout_seconds: 5,
+                allowed_files: vec![],
+            })
+            .unwrap(),
+        ));
+        let agent = AgentExecutor::new(engine.clone(), vault.clone(), sandbox.clone());
+        Arc::new(AppState {
+            engine,
+            models: Arc::new(RwLock::new(ModelStore::open(dir.join("models")).unwrap())),
+            vault,
+            sandbox,
+            agent: Arc::new(RwLock::new(agent)),
+        })
+    }
+
+    fn post_json(uri: &str, body: serde_json::Value) -> Request {
+        axum::http::Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn chat_request(prompt: &str, stream: bool) -> Request {
+        post_json(
+            "/v1/chat/completions",
+            serde_json::json!({ "messages": [{ "role": "user", "content": prompt }], "stream": stream }),
+        )
+    }
+
+    fn get(uri: &str) -> Request {
+        axum::http::Request::builder().uri(uri).body(Body::empty()).unwrap()
+    }
+
+    async fn send(app: &Router, request: Request) -> (StatusCode, String) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn loopback_hosts() {
+        for host in ["localhost", "LOCALHOST:8765", "127.0.0.1", "127.0.0.1:8765", "127.1.2.3:80", "[::1]", "[::1]:8765"] {
+            assert!(is_loopback_host(host), "{}", host);
+        }
+        for host in ["example.com", "evil.localhost.example:8765", "192.168.1.10:8765", "[2001:db8::1]:8765", "0.0.0.0", ""] {
+            assert!(!is_loopback_host(host), "{}", host);
+        }
+    }
+
+    /// SSE 响应体中各 data 事件的内容；error 事件以 "error:" 前缀标出
+    fn sse_events(body: &str) -> Vec<String> {
+        let mut events = Vec::new();
+        let mut kind = None;
+        for line in body.lines() {
+            if let Some(name) = line.strip_prefix("event: ") {
+                kind = Some(name.to_string());
+            } else if let Some(data) = line.strip_prefix("data: ") {
+                events.push(match kind.take() {
+                    Some(kind) => format!("{}:{}", kind, data),
+                    None => data.to_string(),
+                });
+            }
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn chat_completion_and_models() {
+        let app = router(state("chat").await, None, true);
+
+        let (status, body) = send(&app, chat_request("hello", false)).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["object"], "chat.completion");
+        assert_eq!(body["choices"][0]["message"]["content"], "Hi there");
+        assert_eq!(body["choices"][0]["finish_reason"], "stop");
+        assert_eq!(body["usage"]["completion_tokens"], 2);
+
+        let (status, body) = send(&app, get("/v1/models")).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["data"][0]["id"], DEFAULT_MODEL);
+
+        // 生成失败返回 HTTP 错误，而不是 finish_reason 为 error 的响应
+        let (status, body) = send(&app, chat_request("broken", false)).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(body.contains("server_error"), "{}", body);
+
+        // 无法解析的请求体返回 OpenAI 格式的错误
+        let (status, body) = send(&app, post_json("/v1/chat/completions", serde_json::json!({ "messages": 1 }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("invalid_request_error"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn streams_chat_as_sse() {
+        let app = router(state("stream").await, None, true);
+
+        let (status, body) = send(&app, chat_request("hello", true)).await;
+        assert_eq!(status, StatusCode::OK);
+        let events = sse_events(&body);
+        assert_eq!(events.last().map(String::as_str), Some("[DONE]"));
+        let chunks: Vec<serde_json::Value> = events[..events.len() - 1]
+            .iter()
+            .map(|e| serde_json::from_str(e).unwrap())
+            .collect();
+        assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+        let text: String = chunks
+            .iter()
+            .filter_map(|c| c["choices"][0]["delta"]["content"].as_str())
+            .collect();
+        assert_eq!(text, "Hi there");
+        assert_eq!(chunks.last().unwrap()["choices"][0]["finish_reason"], "stop");
+
+        // 第一段文本之前失败：HTTP 错误
+        let (status, body) = send(&app, chat_request("broken", true)).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(body.contains("Generation failed"), "{}", body);
+
+        // 输出中途失败：error 事件，之后没有结束原因与 [DONE]
+        let (status, body) = send(&app, chat_request("crash", true)).await;
+        assert_eq!(status, StatusCode::OK);
+        let events = sse_events(&body);
+        let error = events.last().unwrap().strip_prefix("error:").expect("last event is an error");
+        let error: serde_json::Value = serde_json::from_str(error).unwrap();
+        assert_eq!(error["error"]["type"], "server_error");
+        assert!(events.iter().any(|e| e.contains("Partial")));
+        assert!(!events.iter().any(|e| e == "[DONE]" || e.contains("\"finish_reason\":\"")));
+    }
+
+    #[tokio::test]
+    async fn rejects_unauthorized_requests() {
+        // 设置 API 密钥后必须携带 Bearer 令牌
+        let app = router(state("auth").await, Some("secret".to_string()), true);
+        let (status, body) = send(&app, get("/v1/models")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(body.contains("authentication_error"), "{}", body);
+        let mut request = get("/v1/models");
+        request.headers_mut().insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        assert_eq!(send(&app, request).await.0, StatusCode::OK);
+
+        // 未设置 API 密钥时不开放 Agent
+        let app = router(state("agent").await, None, true);
+        let (status, _) = send(&app, post_json("/v1/silo/agent", serde_json::json!({ "instruction": "ls" }))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // 只监听回环地址时拒绝外部 Host
+        let mut request = get("/v1/models");
+        request.headers_mut().insert(header::HOST, "evil.example".parse().unwrap());
+        let (status, body) = send(&app, request).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(body.contains("Host not allowed"), "{}", body);
+        let mut request = get("/v1/models");
+        request.headers_mut().insert(header::HOST, "127.0.0.1:8765".parse().unwrap());
+        assert_eq!(send(&app, request).await.0, StatusCode::OK);
+    }
+}
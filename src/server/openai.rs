@@ -0,0 +1,238 @@
+// OpenAI API 的请求与响应格式，以及与引擎类型之间的转换
+
+use crate::engine::{ChatMessage, ChatRole, FinishReason, GenerationParams, InferenceMetrics, OutputConstraint};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// 字符串或字符串数组（stop、input、prompt 等字段）
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            OneOrMany::One(s) => vec![s],
+            OneOrMany::Many(v) => v,
+        }
+    }
+}
+
+/// 消息内容：纯文本，或多段内容（只取其中的文本段）
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ContentPart {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RequestMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: Option<MessageContent>,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+impl RequestMessage {
+    pub fn into_chat_message(self) -> Result<ChatMessage, String> {
+        let role = match self.role.as_str() {
+            "system" | "developer" => ChatRole::System,
+            "user" => ChatRole::User,
+            "assistant" => ChatRole::Assistant,
+            "tool" | "function" => ChatRole::Tool,
+            other => return Err(format!("Unsupported message role: {}", other)),
+        };
+        let content = match self.content {
+            None => String::new(),
+            Some(MessageContent::Text(text)) => text,
+            Some(MessageContent::Parts(parts)) => {
+                if parts.iter().any(|p| p.kind != "text") {
+                    return Err("Only text content parts are supported".to_string());
+                }
+                parts.into_iter().filter_map(|p| p.text).collect::<Vec<_>>().join("\n")
+            }
+        };
+        let mut message = ChatMessage::new(role, content);
+        message.name = self.name;
+        Ok(message)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StreamOptions {
+    #[serde(default)]
+    pub include_usage: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JsonSchemaFormat {
+    #[serde(default)]
+    pub schema: Option<serde_json::Value>,
+}
+
+/// chat/completions 与 completions 共用的采样参数
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SamplingOptions {
+    #[serde(default)]
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<usize>,
+    pub max_completion_tokens: Option<usize>,
+    pub stop: Option<OneOrMany>,
+    pub seed: Option<u64>,
+    /// 键为 token id（OpenAI 以字符串表示）
+    #[serde(default)]
+    pub logit_bias: HashMap<String, f32>,
+    /// llama-server / vLLM 的扩展字段
+    pub top_k: Option<usize>,
+    pub repetition_penalty: Option<f32>,
+    pub n: Option<usize>,
+    #[serde(default)]
+    pub stream: bool,
+    pub stream_options: Option<StreamOptions>,
+}
+
+impl SamplingOptions {
+    /// 转换为生成参数；resident_model 为请求指定的本地模型（None 表示使用当前后端）
+    pub fn to_params(&self, resident_model: Option<String>) -> Result<GenerationParams, String> {
+        if self.n.is_some_and(|n| n != 1) {
+            return Err("Only n = 1 is supported".to_string());
+        }
+        let defaults = GenerationParams::default();
+        let mut logit_bias = HashMap::new();
+        for (token, bias) in &self.logit_bias {
+            let id = token.parse::<u32>().map_err(|_| format!("Invalid logit_bias token id: {}", token))?;
+            logit_bias.insert(id, *bias);
+        }
+        Ok(GenerationParams {
+            model: resident_model,
+            max_tokens: self.max_completion_tokens.or(self.max_tokens),
+            temperature: self.temperature.unwrap_or(defaults.temperature),
+            top_p: self.top_p.unwrap_or(defaults.top_p),
+            top_k: self.top_k,
+            repetition_penalty: self.repetition_penalty.unwrap_or(defaults.repetition_penalty),
+            seed: self.seed,
+            stop: self.stop.clone().map(OneOrMany::into_vec).unwrap_or_default(),
+            logit_bias,
+            ..defaults
+        })
+    }
+
+    pub fn include_usage(&self) -> bool {
+        self.stream_options.as_ref().is_some_and(|o| o.include_usage)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatCompletionRequest {
+    pub messages: Vec<RequestMessage>,
+    #[serde(default)]
+    pub response_format: Option<ResponseFormat>,
+    #[serde(flatten)]
+    pub options: SamplingOptions,
+}
+
+impl ChatCompletionRequest {
+    pub fn constraint(&self) -> Option<OutputConstraint> {
+        match &self.response_format {
+            None | Some(ResponseFormat::Text) => None,
+            Some(ResponseFormat::JsonObject) => Some(OutputConstraint::Json),
+            Some(ResponseFormat::JsonSchema { json_schema }) => Some(match &json_schema.schema {
+                Some(schema) => OutputConstraint::JsonSchema { schema: schema.clone() },
+                None => OutputConstraint::Json,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CompletionRequest {
+    pub prompt: OneOrMany,
+    #[serde(flatten)]
+    pub options: SamplingOptions,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmbeddingRequest {
+    pub input: OneOrMany,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub encoding_format: Option<String>,
+}
+
+/// /v1/silo/agent 的请求
+#[derive(Debug, Clone, Deserialize)]
+pub struct AgentRequest {
+    pub instruction: String,
+    #[serde(default)]
+    pub context: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub total_tokens: usize,
+}
+
+impl Usage {
+    pub fn from_metrics(metrics: &InferenceMetrics) -> Self {
+        let prompt_tokens = metrics.prompt_tokens.unwrap_or(0);
+        Self {
+            prompt_tokens,
+            completion_tokens: metrics.completion_tokens,
+            total_tokens: prompt_tokens + metrics.completion_tokens,
+        }
+    }
+}
+
+/// OpenAI 的 finish_reason；被取消时按 stop 上报
+/// 生成失败没有对应的 finish_reason，返回 None，由调用方报告错误
+pub fn finish_reason(reason: FinishReason) -> Option<&'static str> {
+    match reason {
+        FinishReason::Length => Some("length"),
+        FinishReason::Error => None,
+        FinishReason::Stop | FinishReason::StopSequence | FinishReason::Cancelled => Some("stop"),
+    }
+}
+
+/// 向量按小端 f32 编码为 base64（encoding_format = "base64"）
+pub fn encode_base64(vector: &[f32]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let bytes: Vec<u8> = vector.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
@@ -1,45 +1,76 @@
 // 文档分块器 - 将长文档分割成适合向量化的块
+// 块大小按 token 计算（嵌入模型的输入上限也是 token 数），中文没有空格分词也能正确切分
+
+use crate::engine::tokenizer::{EstimateTokenizer, Tokenizer};
+use std::sync::Arc;
 
 pub struct DocumentChunker {
     chunk_size: usize,
     chunk_overlap: usize,
+    tokenizer: Arc<dyn Tokenizer>,
 }
 
 impl DocumentChunker {
     pub fn new(chunk_size: usize, chunk_overlap: usize) -> Self {
         Self {
-            chunk_size,
-            chunk_overlap,
+            chunk_size: chunk_size.max(1),
+            chunk_overlap: chunk_overlap.min(chunk_size / 2),
+            tokenizer: Arc::new(EstimateTokenizer),
         }
     }
     
-    /// 将文本分割成块
+    /// 按指定分词器计算 token 数（默认按字符估算）
+    pub fn with_tokenizer(mut self, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+    
+    /// 将文本分割成不超过 chunk_size 个 token 的块，相邻块重叠约 chunk_overlap 个 token
     pub fn chunk_text(&self, text: &str) -> Vec<String> {
         let mut chunks = Vec::new();
-        let words: Vec<&str> = text.split_whitespace().collect();
-        
-        if words.is_empty() {
-            return chunks;
-        }
+        let mut rest = text.trim();
         
-        let mut start = 0;
-        while start < words.len() {
-            let end = (start + self.chunk_size).min(words.len());
-            let chunk = words[start..end].join(" ");
-            chunks.push(chunk);
-            
-            if end >= words.len() {
+        while !rest.is_empty() {
+            let window = self.tokenizer.truncate_to_tokens(rest, self.chunk_size);
+            if window.len() == rest.len() {
+                chunks.push(rest.to_string());
                 break;
             }
+            // 尽量在句子或空白处断开，但不让块缩到一半以下
+            let end = natural_break(&window).filter(|&end| end >= window.len() / 2).unwrap_or(window.len());
+            // 单个字符就超出上限时也要前进
+            let end = if end == 0 { rest.chars().next().map_or(rest.len(), char::len_utf8) } else { end };
+            let chunk = &rest[..end];
+            chunks.push(chunk.trim().to_string());
             
-            // 重叠处理
-            start = end.saturating_sub(self.chunk_overlap);
+            // 重叠处理：下一块从本块末尾约 chunk_overlap 个 token 处开始，且至少前进半块
+            let next = self.overlap_start(chunk).max(floor_char_boundary(chunk, end / 2));
+            rest = rest[next.min(end)..].trim_start();
         }
         
         chunks
     }
     
-    /// 按段落分割（更智能的分块方式）
+    /// 块末尾不超过 chunk_overlap 个 token 的最长后缀的起始位置
+    fn overlap_start(&self, chunk: &str) -> usize {
+        if self.chunk_overlap == 0 {
+            return chunk.len();
+        }
+        let boundaries: Vec<usize> = chunk.char_indices().map(|(i, _)| i).skip(1).collect();
+        // 后缀越短 token 越少，二分查找满足上限的最靠前的位置
+        let (mut lo, mut hi) = (0, boundaries.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.tokenizer.count_tokens(&chunk[boundaries[mid]..]) <= self.chunk_overlap {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+        boundaries.get(lo).copied().unwrap_or(chunk.len())
+    }
+    
+    /// 按段落分割（更智能的分块方式）；超长段落再按 token 切分
     pub fn chunk_by_paragraphs(&self, text: &str) -> Vec<String> {
         let paragraphs: Vec<&str> = text
             .split("\n\n")
@@ -48,21 +79,27 @@ impl DocumentChunker {
         
         let mut chunks = Vec::new();
         let mut current_chunk = String::new();
+        let mut current_tokens = 0;
         
         for paragraph in paragraphs {
-            let para_words: Vec<&str> = paragraph.split_whitespace().collect();
+            let para_tokens = self.tokenizer.count_tokens(paragraph);
             
-            if current_chunk.split_whitespace().count() + para_words.len() > self.chunk_size {
+            if current_tokens + para_tokens > self.chunk_size {
                 if !current_chunk.is_empty() {
                     chunks.push(current_chunk.trim().to_string());
+                    current_chunk.clear();
+                    current_tokens = 0;
                 }
-                current_chunk = paragraph.to_string();
-            } else {
-                if !current_chunk.is_empty() {
-                    current_chunk.push_str("\n\n");
+                if para_tokens > self.chunk_size {
+                    chunks.extend(self.chunk_text(paragraph));
+                    continue;
                 }
-                current_chunk.push_str(paragraph);
             }
+            if !current_chunk.is_empty() {
+                current_chunk.push_str("\n\n");
+            }
+            current_chunk.push_str(paragraph);
+            current_tokens += para_tokens;
         }
         
         if !current_chunk.is_empty() {
@@ -73,8 +110,89 @@ impl DocumentChunker {
     }
 }
 
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// 最后一个句末标点或空白之后的位置
+fn natural_break(text: &str) -> Option<usize> {
+    text.char_indices()
+        .rev()
+        .find(|(_, c)| matches!(c, '。' | '！' | '？' | '；' | '.' | '!' | '?' | ';' | '\n') || c.is_whitespace())
+        .map(|(i, c)| i + c.len_utf8())
+}
+
 impl Default for DocumentChunker {
     fn default() -> Self {
-        Self::new(500, 50) // 默认 500 词，50 词重叠
+        Self::new(500, 50) // 默认 500 token，50 token 重叠
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 每个字节算一个 token，多字节字符单独就能超出上限
+    struct ByteTokenizer;
+
+    impl Tokenizer for ByteTokenizer {
+        fn count_tokens(&self, text: &str) -> usize {
+            text.len()
+        }
+    }
+
+    #[test]
+    fn splits_cjk_without_spaces() {
+        // 没有空白与标点时按 token 上限硬切，块之间不丢字
+        let chunks = DocumentChunker::new(4, 0).chunk_text("一二三四五六七八九十");
+        assert_eq!(chunks, ["一二三四", "五六七八", "九十"]);
+
+        // 有句末标点时在标点之后断开
+        let chunks = DocumentChunker::new(6, 0).chunk_text("第一句。第二句很长");
+        assert_eq!(chunks, ["第一句。", "第二句很长"]);
+    }
+
+    #[test]
+    fn cuts_at_token_limit_without_natural_break() {
+        let chunks = DocumentChunker::new(2, 0).chunk_text("abcdefghijklmnopqrstuvwxyz");
+        assert_eq!(chunks, ["abcdefgh", "ijklmnop", "qrstuvwx", "yz"]);
+
+        // 断点会让块缩到一半以下时忽略断点
+        let chunks = DocumentChunker::new(2, 0).chunk_text("a bcdefghijklmnop");
+        assert_eq!(chunks, ["a bcde", "fghijklm", "nop"]);
+    }
+
+    #[test]
+    fn overlap_is_capped_at_half_chunk() {
+        let text = "一二三四五六七八九十";
+        let expected = ["一二三四", "三四五六", "五六七八", "七八九十"];
+        assert_eq!(DocumentChunker::new(4, 2).chunk_text(text), expected);
+        // 重叠不小于半块时按半块处理，每块仍然前进
+        assert_eq!(DocumentChunker::new(4, 4).chunk_text(text), expected);
+        assert_eq!(DocumentChunker::new(4, 100).chunk_text(text), expected);
+
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(20);
+        let chunks = DocumentChunker::new(8, 100).chunk_text(&text);
+        let tokenizer = EstimateTokenizer;
+        assert!(chunks.iter().all(|c| !c.is_empty() && tokenizer.count_tokens(c) <= 8));
+        assert!(chunks.len() < tokenizer.count_tokens(&text));
+        assert!(text.trim_end().ends_with(chunks.last().unwrap().as_str()));
+    }
+
+    #[test]
+    fn single_char_over_limit_still_advances() {
+        let chunker = DocumentChunker::new(2, 1).with_tokenizer(Arc::new(ByteTokenizer));
+        assert_eq!(chunker.chunk_text("中文ab"), ["中", "文", "ab"]);
+    }
+
+    #[test]
+    fn long_paragraph_is_split_between_neighbours() {
+        let long = "一二三四五六七八九十";
+        let text = format!("短段落\n\n{}\n\n尾段\n\n结束", long);
+        let chunks = DocumentChunker::new(4, 0).chunk_by_paragraphs(&text);
+        assert_eq!(chunks, ["短段落", "一二三四", "五六七八", "九十", "尾段\n\n结束"]);
     }
 }
@@ -1,7 +1,8 @@
 // LanceDB 向量数据库封装
 // 目前使用内存存储作为临时实现，后续集成 LanceDB
 
-use crate::vault::{Document, SearchResult, DocumentChunker};
+use crate::engine::Priority;
+use crate::vault::{Document, SearchResult, DocumentChunker, Embedder, HashedEmbedder};
 use anyhow::Result;
 use std::path::PathBuf;
 use std::collections::HashMap;
@@ -14,8 +15,10 @@ struct DocumentChunk {
     document_id: String,
     content: String,
     chunk_index: usize,
-    // 简单的特征向量（词频向量）
-    features: Vec<f32>,
+    // 哈希词袋向量，总是存在
+    lexical: Vec<f32>,
+    // 嵌入模型生成的向量及其向量空间标识
+    embedding: Option<(String, Vec<f32>)>,
 }
 
 pub struct VaultDatabase {
@@ -24,6 +27,7 @@ pub struct VaultDatabase {
     documents: Arc<RwLock<HashMap<String, Document>>>,
     chunks: Arc<RwLock<HashMap<String, Vec<DocumentChunk>>>>,
     chunker: DocumentChunker,
+    embedder: Option<Arc<dyn Embedder>>,
 }
 
 impl VaultDatabase {
@@ -35,39 +39,37 @@ impl VaultDatabase {
             documents: Arc::new(RwLock::new(HashMap::new())),
             chunks: Arc::new(RwLock::new(HashMap::new())),
             chunker: DocumentChunker::default(),
+            embedder: None,
         })
     }
     
-    /// 生成简单的文本特征向量（词频向量）
-    fn extract_features(text: &str) -> Vec<f32> {
-        use std::collections::HashMap;
-        
-        let words: Vec<String> = text
-            .to_lowercase()
-            .split_whitespace()
-            .map(|w| w.chars().filter(|c| c.is_alphanumeric()).collect())
-            .filter(|w: &String| !w.is_empty())
-            .collect();
-        
-        let total_words = words.len().max(1);
-        let mut word_freq: HashMap<String, usize> = HashMap::new();
-        
-        for word in &words {
-            *word_freq.entry(word.clone()).or_insert(0) += 1;
+    /// 替换分块器（如按当前模型的分词器计算块大小）
+    pub fn with_chunker(mut self, chunker: DocumentChunker) -> Self {
+        self.chunker = chunker;
+        self
+    }
+    
+    /// 使用嵌入模型计算语义向量；嵌入模型不可用时退回哈希词袋向量
+    pub fn with_embedder(mut self, embedder: Arc<dyn Embedder>) -> Self {
+        self.embedder = Some(embedder);
+        self
+    }
+    
+    /// 用当前的嵌入模型计算向量，返回向量空间标识与向量；不可用或失败时返回 None
+    async fn embed(&self, texts: &[String], priority: Priority) -> Option<(String, Vec<Vec<f32>>)> {
+        let embedder = self.embedder.as_ref()?;
+        let model = embedder.model_id().await?;
+        match embedder.embed(texts, priority).await {
+            Ok(vectors) if vectors.len() == texts.len() => Some((model, vectors)),
+            Ok(vectors) => {
+                tracing::warn!("Embedder returned {} vectors for {} texts", vectors.len(), texts.len());
+                None
+            }
+            Err(e) => {
+                tracing::warn!("Embedding with {} failed, using lexical vectors: {}", model, e);
+                None
+            }
         }
-        
-        // 创建固定大小的特征向量（使用最常见的词）
-        let mut features: Vec<(String, f32)> = word_freq
-            .into_iter()
-            .map(|(word, count)| (word, count as f32 / total_words as f32))
-            .collect();
-        
-        features.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
-        features.truncate(100); // 取前 100 个最常见的词
-        
-        // 归一化
-        let sum: f32 = features.iter().map(|(_, f)| f * f).sum::<f32>().sqrt().max(1e-10);
-        features.iter().map(|(_, f)| f / sum).collect()
     }
     
     /// 计算两个特征向量的余弦相似度
@@ -96,16 +98,21 @@ impl VaultDatabase {
         
         // 分块处理
         let chunks = self.chunker.chunk_by_paragraphs(&document.content);
+        // 入库属于后台任务，不抢占交互式对话
+        let (space, mut vectors) = match self.embed(&chunks, Priority::Background).await {
+            Some((space, vectors)) => (Some(space), vectors.into_iter().map(Some).collect()),
+            None => (None, vec![None; chunks.len()]),
+        };
         let mut document_chunks = Vec::new();
         
         for (idx, chunk_text) in chunks.iter().enumerate() {
-            let features = Self::extract_features(chunk_text);
             let chunk = DocumentChunk {
                 id: format!("{}_chunk_{}", document.id, idx),
                 document_id: document.id.clone(),
                 content: chunk_text.clone(),
                 chunk_index: idx,
-                features,
+                lexical: HashedEmbedder::embed(chunk_text),
+                embedding: space.clone().zip(vectors[idx].take()),
             };
             document_chunks.push(chunk);
         }
@@ -119,45 +126,62 @@ impl VaultDatabase {
         Ok(())
     }
     
-    /// 搜索相似文档（使用特征向量相似度）
+    /// 搜索相似文档：有查询向量空间中向量的文档按语义相似度排序，其余文档按哈希词袋向量单独排序并排在其后。
+    /// 两种余弦相似度的尺度不同，不放在同一个排序中比较
     pub async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>> {
-        let query_features = Self::extract_features(query);
+        let query_lexical = HashedEmbedder::embed(query);
+        let query_embedding = self
+            .embed(&[query.to_string()], Priority::Interactive)
+            .await
+            .and_then(|(space, mut vectors)| vectors.pop().map(|v| (space, v)));
         let chunks_map = self.chunks.read().await;
         let docs = self.documents.read().await;
         
         // 计算每个文档的最高相似度（基于最佳匹配块）
-        let mut doc_similarities: Vec<(String, f32, String)> = Vec::new();
+        let mut semantic: Vec<(String, f32)> = Vec::new();
+        let mut lexical: Vec<(String, f32)> = Vec::new();
         
         for (doc_id, chunks) in chunks_map.iter() {
-            let mut max_similarity = 0.0;
-            let mut best_chunk_content = String::new();
-            
-            for chunk in chunks {
-                let similarity = Self::cosine_similarity(&query_features, &chunk.features);
-                if similarity > max_similarity {
-                    max_similarity = similarity;
-                    best_chunk_content = chunk.content.clone();
-                }
-            }
-            
-            if max_similarity > 0.1 { // 阈值过滤
-                doc_similarities.push((doc_id.clone(), max_similarity, best_chunk_content));
+            let semantic_similarity = query_embedding.as_ref().and_then(|(query_space, query_vector)| {
+                chunks
+                    .iter()
+                    .filter_map(|chunk| chunk.embedding.as_ref().filter(|(space, _)| space == query_space))
+                    .map(|(_, vector)| Self::cosine_similarity(query_vector, vector))
+                    .reduce(f32::max)
+            });
+            let (ranking, similarity) = match semantic_similarity {
+                Some(similarity) => (&mut semantic, similarity),
+                None => (
+                    &mut lexical,
+                    chunks
+                        .iter()
+                        .map(|chunk| Self::cosine_similarity(&query_lexical, &chunk.lexical))
+                        .fold(0.0, f32::max),
+                ),
+            };
+            if similarity > 0.1 { // 阈值过滤
+                ranking.push((doc_id.clone(), similarity));
             }
         }
         
-        // 按相似度排序
-        doc_similarities.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
-        doc_similarities.truncate(limit);
+        // 两个列表各自按相似度排序
+        for ranking in [&mut semantic, &mut lexical] {
+            ranking.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        }
         
         // 构建搜索结果
-        let results: Vec<SearchResult> = doc_similarities
+        let results: Vec<SearchResult> = semantic
             .into_iter()
-            .filter_map(|(doc_id, similarity, _)| {
+            .map(|(doc_id, similarity)| (doc_id, similarity, true))
+            .chain(lexical.into_iter().map(|(doc_id, similarity)| (doc_id, similarity, false)))
+            .filter_map(|(doc_id, similarity, is_semantic)| {
                 docs.get(&doc_id).map(|doc| SearchResult {
                     document: doc.clone(),
                     similarity,
+                    semantic: is_semantic,
                 })
             })
+            .take(limit)
             .collect();
         
         tracing::info!("Search for '{}' returned {} results (best similarity: {:.3})", 
@@ -165,16 +189,54 @@ impl VaultDatabase {
         Ok(results)
     }
     
+    /// 用当前的嵌入模型重新计算不在其向量空间中的块（更换嵌入模型后调用），返回更新的块数
+    pub async fn reindex(&self) -> Result<usize> {
+        let Some(embedder) = &self.embedder else { return Ok(0) };
+        let Some(space) = embedder.model_id().await else { return Ok(0) };
+        let stale: Vec<(String, usize, String)> = {
+            let chunks_map = self.chunks.read().await;
+            chunks_map
+                .values()
+                .flatten()
+                .filter(|chunk| chunk.embedding.as_ref().is_none_or(|(s, _)| *s != space))
+                .map(|chunk| (chunk.document_id.clone(), chunk.chunk_index, chunk.content.clone()))
+                .collect()
+        };
+        if stale.is_empty() {
+            return Ok(0);
+        }
+        
+        let texts: Vec<String> = stale.iter().map(|(_, _, content)| content.clone()).collect();
+        let Some((space, vectors)) = self.embed(&texts, Priority::Background).await else {
+            anyhow::bail!("Embedding model {} is not available", space);
+        };
+        let mut chunks_map = self.chunks.write().await;
+        let mut updated = 0;
+        for ((document_id, chunk_index, _), vector) in stale.into_iter().zip(vectors) {
+            // 期间文档可能已被删除或替换
+            if let Some(chunk) = chunks_map
+                .get_mut(&document_id)
+                .and_then(|chunks| chunks.iter_mut().find(|c| c.chunk_index == chunk_index))
+            {
+                chunk.embedding = Some((space.clone(), vector));
+                updated += 1;
+            }
+        }
+        tracing::info!("Re-embedded {} chunk(s) with {}", updated, space);
+        Ok(updated)
+    }
+    
     /// 获取文档
     pub async fn get_document(&self, id: &str) -> Result<Option<Document>> {
         let docs = self.documents.read().await;
         Ok(docs.get(id).cloned())
     }
     
-    /// 删除文档
+    /// 删除文档及其分块
     pub async fn delete_document(&self, id: &str) -> Result<()> {
         let mut docs = self.documents.write().await;
         docs.remove(id);
+        self.chunks.write().await.remove(id);
         tracing::info!("Deleted document from vault: {}", id);
         Ok(())
     }
@@ -191,3 +253,121 @@ impl VaultDatabase {
         Ok(docs.values().cloned().collect())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::DocumentMetadata;
+    use std::sync::Mutex;
+    use tokio::sync::oneshot;
+
+    /// 可切换向量空间的假嵌入模型：提到宠物的文本指向同一方向，其余文本指向另一方向
+    #[derive(Default)]
+    struct FakeEmbedder {
+        space: Mutex<Option<String>>,
+        /// 设置后 embed 先通知开始，等待放行后才返回
+        gate: Mutex<Option<(oneshot::Sender<()>, oneshot::Receiver<()>)>>,
+    }
+
+    impl FakeEmbedder {
+        fn set_space(&self, space: Option<&str>) {
+            *self.space.lock().unwrap() = space.map(str::to_string);
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Embedder for FakeEmbedder {
+        async fn model_id(&self) -> Option<String> {
+            self.space.lock().unwrap().clone()
+        }
+
+        async fn embed(&self, texts: &[String], _priority: Priority) -> Result<Vec<Vec<f32>>> {
+            let gate = self.gate.lock().unwrap().take();
+            if let Some((started, release)) = gate {
+                let _ = started.send(());
+                let _ = release.await;
+            }
+            Ok(texts
+                .iter()
+                .map(|t| if t.contains("宠物") || t.contains('猫') { vec![1.0, 0.0] } else { vec![0.0, 1.0] })
+                .collect())
+        }
+    }
+
+    fn document(id: &str, content: &str) -> Document {
+        Document {
+            id: id.to_string(),
+            content: content.to_string(),
+            metadata: DocumentMetadata {
+                file_path: None,
+                mime_type: None,
+                created_at: chrono::Utc::now(),
+                tags: vec![],
+            },
+        }
+    }
+
+    fn database(name: &str, embedder: Arc<FakeEmbedder>) -> VaultDatabase {
+        let dir = std::env::temp_dir().join(format!("silo-vault-{}-{}", name, std::process::id()));
+        VaultDatabase::new(dir).unwrap().with_embedder(embedder)
+    }
+
+    fn ids(results: &[SearchResult]) -> Vec<(&str, bool)> {
+        results.iter().map(|r| (r.document.id.as_str(), r.semantic)).collect()
+    }
+
+    #[tokio::test]
+    async fn semantic_matches_rank_before_lexical() {
+        let embedder = Arc::new(FakeEmbedder::default());
+        let db = database("search", embedder.clone());
+
+        // 嵌入模型不可用时入库的文档只有词袋向量
+        db.add_document(document("lexical", "猫 cat 很可爱")).await.unwrap();
+        embedder.set_space(Some("m1"));
+        db.add_document(document("semantic", "宠物护理指南")).await.unwrap();
+        db.add_document(document("other", "季度财务报表")).await.unwrap();
+
+        // 词袋相似度更高的文档也排在语义匹配之后；语义上不相关的文档被阈值过滤
+        let results = db.search("猫", 10).await.unwrap();
+        assert_eq!(ids(&results), [("semantic", true), ("lexical", false)]);
+        assert!(results[1].similarity > 0.1);
+        assert_eq!(ids(&db.search("猫", 1).await.unwrap()), [("semantic", true)]);
+
+        // 换成新的向量空间后旧向量不参与比较，全部按词袋排序
+        embedder.set_space(Some("m2"));
+        assert_eq!(ids(&db.search("猫", 10).await.unwrap()), [("lexical", false)]);
+        assert_eq!(db.reindex().await.unwrap(), 3);
+        assert_eq!(db.reindex().await.unwrap(), 0);
+        let results = db.search("猫", 10).await.unwrap();
+        assert!(results.iter().all(|r| r.semantic));
+        assert_eq!(results.len(), 2);
+
+        // 没有嵌入模型时 reindex 什么也不做
+        embedder.set_space(None);
+        assert_eq!(db.reindex().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn reindex_skips_chunks_deleted_while_embedding() {
+        let embedder = Arc::new(FakeEmbedder::default());
+        let db = Arc::new(database("reindex", embedder.clone()));
+        db.add_document(document("kept", "宠物医院")).await.unwrap();
+        db.add_document(document("deleted", "猫粮")).await.unwrap();
+
+        embedder.set_space(Some("m1"));
+        let (started_tx, started) = oneshot::channel();
+        let (release, release_rx) = oneshot::channel();
+        *embedder.gate.lock().unwrap() = Some((started_tx, release_rx));
+        let task = tokio::spawn({
+            let db = db.clone();
+            async move { db.reindex().await }
+        });
+        started.await.unwrap();
+        db.delete_document("deleted").await.unwrap();
+        release.send(()).unwrap();
+
+        assert_eq!(task.await.unwrap().unwrap(), 1);
+        assert_eq!(db.document_count().await, 1);
+        assert_eq!(ids(&db.search("猫", 10).await.unwrap()), [("kept", true)]);
+    }
+}
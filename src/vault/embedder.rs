@@ -0,0 +1,129 @@
+// 文本向量化 - 优先使用推理引擎的嵌入模型；没有可用的嵌入模型时
+// 使用特征哈希的词袋向量，只能做关键词匹配，但维度固定、不同文本之间可以比较
+
+use crate::engine::{EngineManager, Priority};
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+#[async_trait]
+pub trait Embedder: Send + Sync {
+    /// 当前的向量空间标识，None 表示暂时无法生成向量
+    async fn model_id(&self) -> Option<String>;
+
+    /// 批量计算文本向量，返回顺序与输入一致
+    async fn embed(&self, texts: &[String], priority: Priority) -> Result<Vec<Vec<f32>>>;
+}
+
+#[async_trait]
+impl Embedder for RwLock<EngineManager> {
+    async fn model_id(&self) -> Option<String> {
+        self.read().await.embedding_model().await
+    }
+
+    async fn embed(&self, texts: &[String], priority: Priority) -> Result<Vec<Vec<f32>>> {
+        self.read().await.embed_with_priority(texts, priority).await
+    }
+}
+
+/// 哈希词袋向量的维度
+pub const HASHED_DIM: usize = 512;
+
+/// 特征哈希的词袋向量：英文等按单词切分，中日韩文字按单字与相邻二字切分
+pub struct HashedEmbedder;
+
+impl HashedEmbedder {
+    pub fn embed(text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0f32; HASHED_DIM];
+        for term in terms(text) {
+            // 高位决定符号，减少哈希冲突带来的偏差
+            let hash = fnv1a(term.as_bytes());
+            let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+            vector[(hash % HASHED_DIM as u64) as usize] += sign;
+        }
+        let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|v| *v /= norm);
+        }
+        vector
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32, 0x3040..=0x30FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xAC00..=0xD7AF | 0xF900..=0xFAFF)
+}
+
+/// 切分为词项
+fn terms(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut word = String::new();
+    let mut prev_cjk: Option<char> = None;
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if is_cjk(c) {
+            if !word.is_empty() {
+                terms.push(std::mem::take(&mut word));
+            }
+            terms.push(c.to_string());
+            if let Some(prev) = prev_cjk {
+                terms.push(format!("{}{}", prev, c));
+            }
+            prev_cjk = Some(c);
+            continue;
+        }
+        prev_cjk = None;
+        if c.is_alphanumeric() {
+            word.push(c);
+        } else if !word.is_empty() {
+            terms.push(std::mem::take(&mut word));
+        }
+    }
+    if !word.is_empty() {
+        terms.push(word);
+    }
+    terms
+}
+
+/// FNV-1a：结果不随 Rust 版本变化，已存储的向量保持有效
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cosine(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(x, y)| x * y).sum()
+    }
+
+    #[test]
+    fn splits_words_and_cjk_unigrams_bigrams() {
+        assert_eq!(
+            terms("Hello, 世界和平 OK-2024"),
+            ["hello", "世", "界", "世界", "和", "界和", "平", "和平", "ok", "2024"]
+        );
+        // 非中日韩字符打断二字组合；假名也按单字切分
+        assert_eq!(terms("中a文"), ["中", "a", "文"]);
+        assert_eq!(terms("カナ"), ["カ", "ナ", "カナ"]);
+        assert!(terms(" ,.! ").is_empty());
+    }
+
+    #[test]
+    fn hashed_vectors_are_normalized_and_stable() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+
+        let vector = HashedEmbedder::embed("本地知识库 search");
+        assert_eq!(vector.len(), HASHED_DIM);
+        assert!((cosine(&vector, &vector) - 1.0).abs() < 1e-5);
+        assert_eq!(vector, HashedEmbedder::embed("本地知识库 SEARCH"));
+        assert!(HashedEmbedder::embed("").iter().all(|v| *v == 0.0));
+
+        // 共享词项越多越相似
+        let query = HashedEmbedder::embed("知识库");
+        let related = cosine(&query, &HashedEmbedder::embed("本地知识库检索"));
+        let unrelated = cosine(&query, &HashedEmbedder::embed("weather forecast"));
+        assert!(related > 0.5, "{}", related);
+        assert!(related > unrelated);
+    }
+}
@@ -7,9 +7,11 @@ use std::path::PathBuf;
 pub mod database;
 pub mod sync;
 pub mod chunker;
+pub mod embedder;
 
 pub use database::*;
 pub use chunker::*;
+pub use embedder::*;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Document {
@@ -30,4 +32,7 @@ pub struct DocumentMetadata {
 pub struct SearchResult {
     pub document: Document,
     pub similarity: f32,
+    /// similarity 来自嵌入模型的向量空间；为 false 时是哈希词袋向量的相似度，两者不可比较
+    #[serde(default)]
+    pub semantic: bool,
 }
@@ -0,0 +1,130 @@
+// 脚本化后端的集成测试：通过 SILO_SCRIPT / SILO_RECORD 启动 AppState，回放 fixtures 中的脚本并检查录制文件
+
+use silo_lib::{AppState, CancellationToken, ChatMessage, FinishReason};
+use std::path::{Path, PathBuf};
+
+/// AppState::new 从环境变量读取配置，构建时逐个进行
+static ENV_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures").join(name)
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("silo-scripted-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+async fn app(script: &Path, record: Option<&Path>) -> AppState {
+    let _guard = ENV_LOCK.lock().await;
+    // SAFETY: 环境变量只在持有 ENV_LOCK 时修改，AppState::new 读取完成后才释放
+    unsafe {
+        // 模型仓库与知识库目录放在临时目录中，不影响本机数据
+        std::env::set_var("XDG_DATA_HOME", std::env::temp_dir().join(format!("silo-scripted-data-{}", std::process::id())));
+        std::env::set_var("SILO_SCRIPT", script);
+        match record {
+            Some(path) => std::env::set_var("SILO_RECORD", path),
+            None => std::env::remove_var("SILO_RECORD"),
+        }
+    }
+    AppState::new().await.unwrap()
+}
+
+async fn chat(state: &AppState, prompt: &str) -> Result<serde_json::Value, String> {
+    silo_lib::chat(state, vec![ChatMessage::user(prompt)], None).await
+}
+
+async fn stream_tokens(state: &AppState, prompt: &str) -> (Vec<String>, Option<FinishReason>) {
+    let mut stream = silo_lib::chat_stream(state, vec![ChatMessage::user(prompt)], None, CancellationToken::new())
+        .await
+        .unwrap();
+    let mut tokens = Vec::new();
+    while let Some(token) = stream.next_token().await {
+        tokens.push(token);
+    }
+    (tokens, stream.finish_reason())
+}
+
+#[tokio::test]
+async fn replays_demo_script() {
+    let state = app(&fixture("demo.json"), None).await;
+    assert_eq!(silo_lib::get_backend_type(&state).await.unwrap(), "Scripted");
+
+    // any_of
+    let reply = chat(&state, "你好").await.unwrap();
+    assert_eq!(
+        reply["content"],
+        "你好！我是 Silo AI，一个隐私优先的本地 Agent 操作系统。我可以帮助你完成各种任务，同时确保你的数据完全保留在本地。"
+    );
+    assert_eq!(reply["finish_reason"], "stop");
+
+    // all_of 嵌套 any_of
+    let reply = chat(&state, "帮我列出当前目录的文件").await.unwrap();
+    assert!(reply["content"].as_str().unwrap().starts_with("用户需要列出当前目录下的文件"));
+
+    // 兜底条目替换 {prompt}
+    let reply = chat(&state, "  随便说点什么 ").await.unwrap();
+    assert!(reply["content"].as_str().unwrap().starts_with("我理解你的指令：\"随便说点什么\"。"));
+
+    // 流式回放：中文逐字、英文按单词切分
+    let (tokens, reason) = stream_tokens(&state, "hello").await;
+    assert_eq!(reason, Some(FinishReason::Stop));
+    assert_eq!(&tokens[..3], ["你", "好", "！"]);
+    assert!(tokens.contains(&"Silo ".to_string()));
+    assert_eq!(tokens.concat(), chat(&state, "hello").await.unwrap()["content"]);
+}
+
+#[tokio::test]
+async fn replays_script_directory() {
+    let state = app(&fixture("scripts"), None).await;
+
+    // 02_chat.json：正则匹配，录制格式的 token 序列、结束原因与用量原样回放
+    let (tokens, reason) = stream_tokens(&state, "天气如何？").await;
+    assert_eq!(tokens, ["今天", "晴", "，", "适合散步。"]);
+    assert_eq!(reason, Some(FinishReason::Length));
+    let reply = chat(&state, "天气怎么样").await.unwrap();
+    assert_eq!(reply["finish_reason"], "length");
+    assert_eq!(reply["metrics"]["prompt_tokens"], 12);
+    assert_eq!(reply["metrics"]["completion_tokens"], 4);
+
+    // 01_agent.json 排在 02_chat.json 的兜底条目之前
+    let response = silo_lib::execute_agent_task(&state, "帮我总结笔记".to_string(), None, CancellationToken::new())
+        .await
+        .unwrap();
+    assert_eq!(response["reasoning"], "先在知识库中检索笔记，再总结要点。");
+    assert_eq!(response["actions"][0]["SearchQuery"]["query"], "笔记");
+
+    let reply = chat(&state, "别的问题").await.unwrap();
+    assert_eq!(reply["content"], "脚本未覆盖：别的问题");
+}
+
+#[tokio::test]
+async fn records_and_replays_session() {
+    let dir = temp_dir("record");
+    let recorded = dir.join("session.json");
+    let state = app(&fixture("scripts"), Some(&recorded)).await;
+    let weather = chat(&state, "天气怎么样").await.unwrap();
+    let (tokens, _) = stream_tokens(&state, "讲个笑话").await;
+    // 同一提示词只保留最近一次
+    chat(&state, "讲个笑话").await.unwrap();
+    drop(state);
+
+    let entries: Vec<serde_json::Value> = serde_json::from_str(&std::fs::read_to_string(&recorded).unwrap()).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["match"], serde_json::json!({ "exact": "天气怎么样" }));
+    assert_eq!(entries[0]["response"], serde_json::json!(["今天", "晴", "，", "适合散步。"]));
+    assert_eq!(entries[0]["finish_reason"], "length");
+    assert_eq!(entries[0]["usage"], serde_json::json!({ "prompt_tokens": 12, "completion_tokens": 4 }));
+    assert_eq!(entries[1]["match"], serde_json::json!({ "exact": "讲个笑话" }));
+    assert_eq!(entries[1]["response"], serde_json::json!(tokens));
+
+    // 只回放录制的脚本：输出与录制时一致，未录制的提示词没有匹配
+    let state = app(&recorded, None).await;
+    assert_eq!(chat(&state, "天气怎么样").await.unwrap()["content"], weather["content"]);
+    assert_eq!(stream_tokens(&state, "讲个笑话").await.0, tokens);
+    let err = chat(&state, "别的问题").await.unwrap_err();
+    assert!(err.contains("No scripted response matches"), "{}", err);
+    let _ = std::fs::remove_dir_all(&dir);
+}